@@ -0,0 +1,31 @@
+// With the ffi feature, compiles the C harness of tests/ffi.rs into a static
+// library with the system C compiler ($CC, or cc). Nothing to do without it,
+// so the rest of the crate doesn't need a C compiler.
+
+use std::env;
+use std::path::PathBuf;
+use std::process::Command;
+
+fn run(command: &mut Command) {
+    let status = command.status().unwrap_or_else(|e| panic!("failed to run {:?}: {}", command, e));
+    assert!(status.success(), "{:?} failed with {}", command, status);
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=include/hrst.h");
+    println!("cargo:rerun-if-changed=tests/ffi/harness.c");
+    println!("cargo:rerun-if-env-changed=CC");
+    if env::var_os("CARGO_FEATURE_FFI").is_none() {
+        return;
+    }
+
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").expect("cargo sets OUT_DIR"));
+    let object = out_dir.join("harness.o");
+    let compiler = env::var("CC").unwrap_or_else(|_| String::from("cc"));
+    run(Command::new(compiler)
+        .args(["-std=c99", "-Wall", "-Wextra", "-Werror", "-Iinclude", "-c", "tests/ffi/harness.c", "-o"])
+        .arg(&object));
+    run(Command::new("ar").arg("crs").arg(out_dir.join("libhrst_harness.a")).arg(&object));
+    println!("cargo:rustc-link-search=native={}", out_dir.display());
+}
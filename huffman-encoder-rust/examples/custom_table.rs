@@ -0,0 +1,41 @@
+// Builds a table from one sample of text and encodes many small messages with
+// it, instead of counting each of them. Every message still carries the table
+// in its header, so any of them decodes on its own. Every line of a file, or
+// of a few sentences when there is none, is a message:
+//
+//     cargo run --example custom_table -- notes.txt
+
+use std::error::Error;
+
+use huffman_encoder::{decode_bytes, encode_bytes_with, EncoderOptions, FrequencyTable, HuffmanTree};
+
+const SAMPLE: &str = "the quick brown fox jumps over the lazy dog\n\
+    a huffman code gives the most common bytes the shortest codes\n\
+    messages from the same source share one table\n";
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let training = match std::env::args_os().nth(1) {
+        Some(path) => std::fs::read(path)?,
+        None => SAMPLE.as_bytes().to_vec(),
+    };
+
+    // The messages are lines of the sample, so all of their bytes have a code.
+    // A byte the sample doesn't have fails with UnknownSymbol.
+    let mut frequencies = FrequencyTable::new();
+    frequencies.add(&training);
+    let Some(tree) = HuffmanTree::from_frequencies(&frequencies) else {
+        return Err("nothing to train on".into());
+    };
+    let options = EncoderOptions::builder().encoding_table(tree.codes()).build()?;
+
+    let mut total = 0;
+    let messages: Vec<&[u8]> = training.split(|&byte| byte == b'\n').filter(|line| !line.is_empty()).collect();
+    for message in &messages {
+        let encoded = encode_bytes_with(message, &options)?;
+        assert!(decode_bytes(&encoded)? == *message, "the roundtrip changed a message");
+        total += encoded.len();
+    }
+    let codes = options.encoding_table().map_or(0, |table| table.len());
+    println!("{} messages encoded into {} bytes with one table of {} codes", messages.len(), total, codes);
+    Ok(())
+}
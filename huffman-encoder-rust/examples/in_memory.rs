@@ -0,0 +1,26 @@
+// Encodes a file, or a sentence when there is none, in memory and decodes it
+// again:
+//
+//     cargo run --example in_memory -- notes.txt
+
+use std::error::Error;
+
+use huffman_encoder::{decode_bytes, encode_bytes};
+
+const SAMPLE: &[u8] = b"this is an example of a huffman tree, encoded and decoded in memory";
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let data = match std::env::args_os().nth(1) {
+        Some(path) => std::fs::read(path)?,
+        None => SAMPLE.to_vec(),
+    };
+
+    let encoded = encode_bytes(&data)?;
+    let decoded = decode_bytes(&encoded)?;
+    assert!(decoded == data, "the roundtrip changed the data");
+
+    // The header with the table counts too, so tiny inputs get larger
+    let ratio = encoded.len() as f64 / data.len().max(1) as f64;
+    println!("{} bytes -> {} bytes, ratio {:.3}", data.len(), encoded.len(), ratio);
+    Ok(())
+}
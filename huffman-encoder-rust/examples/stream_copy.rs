@@ -0,0 +1,32 @@
+// Encodes a file into another with io::copy through a HuffmanWriter, then
+// decodes that into a third through a HuffmanReader, without holding any of
+// them in memory as a whole:
+//
+//     cargo run --example stream_copy -- notes.txt notes.huff notes.decoded
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+
+use huffman_encoder::{HuffmanReader, HuffmanWriter};
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<_> = std::env::args_os().skip(1).collect();
+    let [input, encoded, decoded] = &args[..] else {
+        return Err("usage: stream_copy INPUT ENCODED DECODED".into());
+    };
+
+    // buffered() picks the table of the data once it is finished, like encode
+    let mut writer = HuffmanWriter::buffered(BufWriter::new(File::create(encoded)?));
+    let read = io::copy(&mut File::open(input)?, &mut writer)?;
+    writer.finish()?.flush()?;
+
+    let mut reader = HuffmanReader::new(BufReader::new(File::open(encoded)?))?;
+    let mut output = BufWriter::new(File::create(decoded)?);
+    let written = io::copy(&mut reader, &mut output)?;
+    output.flush()?;
+
+    let encoded_size = std::fs::metadata(encoded)?.len();
+    println!("{} bytes -> {} bytes -> {} bytes", read, encoded_size, written);
+    Ok(())
+}
@@ -0,0 +1,70 @@
+// The exports of src/wasm.rs as functions on Uint8Arrays. Every call copies
+// the input into the module's memory and the output back out of it, so the
+// results stay valid after the next call.
+//
+//     const hrst = await load(await (await fetch("hrst.wasm")).arrayBuffer());
+//     const encoded = hrst.encode(new TextEncoder().encode("abracadabra"));
+
+// hrst_status of include/hrst.h, by value
+const STATUS = [
+  "ok",
+  "null pointer",
+  "allocation failed",
+  "panic",
+  "io error",
+  "invalid magic",
+  "unsupported version",
+  "truncated header",
+  "corrupt header",
+  "truncated data",
+  "corrupt data",
+  "checksum mismatch",
+  "unknown symbol",
+  "invalid table",
+  "trailing garbage",
+  "output too large",
+  "usage",
+  "cancelled",
+];
+
+export class HrstError extends Error {
+  constructor(status) {
+    super(STATUS[status] ?? `status ${status}`);
+    this.status = status;
+  }
+}
+
+export async function load(wasmBytes) {
+  const { instance } = await WebAssembly.instantiate(wasmBytes, {});
+  const exports = instance.exports;
+
+  function call(name, input) {
+    const pointer = input.length > 0 ? exports.hrst_alloc(input.length) : 0;
+    if (input.length > 0 && pointer === 0) {
+      throw new HrstError(2);
+    }
+    try {
+      new Uint8Array(exports.memory.buffer, pointer, input.length).set(input);
+      const status = exports[name](pointer, input.length);
+      if (status !== 0) {
+        throw new HrstError(status);
+      }
+      const output = exports.hrst_js_output();
+      const length = exports.hrst_js_output_length();
+      // The memory may have grown during the call, so the view is taken now
+      const copy = new Uint8Array(exports.memory.buffer, output, length).slice();
+      exports.hrst_free(output, length);
+      return copy;
+    } finally {
+      exports.hrst_free(pointer, input.length);
+    }
+  }
+
+  return {
+    encode: (data) => call("hrst_js_encode", data),
+    decode: (encoded) => call("hrst_js_decode", encoded),
+    // { length, encodedSize, entropy, codes: [{ byte, count, code: "1011" }] }
+    // for the table encode would pick for `data`
+    tableInfo: (data) => JSON.parse(new TextDecoder().decode(call("hrst_js_table_info", data))),
+  };
+}
@@ -0,0 +1,25 @@
+// Headless check of the module and hrst.js, with node:
+//
+//     node examples/wasm-demo/roundtrip.mjs examples/wasm-demo/hrst.wasm
+
+import { readFile } from "node:fs/promises";
+import assert from "node:assert/strict";
+import { load, HrstError } from "./hrst.js";
+
+const hrst = await load(await readFile(process.argv[2] ?? new URL("hrst.wasm", import.meta.url)));
+
+const samples = [
+  new Uint8Array(0),
+  new TextEncoder().encode("abracadabra"),
+  Uint8Array.from({ length: 100000 }, (_, i) => (i * i) % 251),
+];
+for (const data of samples) {
+  const encoded = hrst.encode(data);
+  assert.deepEqual(hrst.decode(encoded), data);
+  assert.equal(hrst.tableInfo(data).encodedSize, encoded.length);
+}
+
+const info = hrst.tableInfo(new TextEncoder().encode("abracadabra"));
+assert.deepEqual(info.codes[0], { byte: 97, count: 5, code: "0" });
+assert.throws(() => hrst.decode(new TextEncoder().encode("HUFF")), (e) => e instanceof HrstError && e.status === 5);
+console.log("roundtrip ok");
@@ -0,0 +1,84 @@
+/*
+ * C interface of the huffman-encoder library, built with the ffi feature:
+ *
+ *     cargo rustc --release --lib --features ffi --crate-type cdylib
+ *
+ * or with --crate-type staticlib, see `make ffi`. Everything works on buffers
+ * in memory and gives the same bytes and errors as `huffman encode` and
+ * `huffman decode`.
+ */
+
+#ifndef HRST_H
+#define HRST_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/*
+ * What a call returns. The errors from HRST_ERROR_IO on are the variants of
+ * HuffmanError; an error in a later stream of concatenated data has the code
+ * of what went wrong in it. New codes are only ever added at the end.
+ */
+typedef enum {
+    HRST_OK = 0,
+    /* `out` or `out_len` is null, or `in` is null with a length */
+    HRST_ERROR_NULL_POINTER = 1,
+    /* The output couldn't be allocated */
+    HRST_ERROR_ALLOCATION = 2,
+    /* A bug in the library, please report it */
+    HRST_ERROR_PANIC = 3,
+    HRST_ERROR_IO = 4,
+    /* The input doesn't start with "HRST" */
+    HRST_ERROR_INVALID_MAGIC = 5,
+    HRST_ERROR_UNSUPPORTED_VERSION = 6,
+    HRST_ERROR_TRUNCATED_HEADER = 7,
+    HRST_ERROR_CORRUPT_HEADER = 8,
+    HRST_ERROR_TRUNCATED_DATA = 9,
+    HRST_ERROR_CORRUPT_DATA = 10,
+    HRST_ERROR_CHECKSUM_MISMATCH = 11,
+    HRST_ERROR_UNKNOWN_SYMBOL = 12,
+    HRST_ERROR_INVALID_TABLE = 13,
+    /* Something that isn't a stream after the last one */
+    HRST_ERROR_TRAILING_GARBAGE = 14,
+    HRST_ERROR_OUTPUT_TOO_LARGE = 15,
+    HRST_ERROR_USAGE = 16,
+    /* Not returned yet, the C API has no way to cancel a call */
+    HRST_ERROR_CANCELLED = 17,
+    /* A stream of a method this version can't decode */
+    HRST_ERROR_UNSUPPORTED_METHOD = 18,
+    /* Not returned yet, the C API takes no table files */
+    HRST_ERROR_TABLE_MISMATCH = 19
+} hrst_status;
+
+/*
+ * Encodes the `in_len` bytes at `in` into a single stream, exactly the bytes
+ * `huffman encode` writes for a file with the same contents. `in` may be null
+ * when `in_len` is 0.
+ *
+ * On HRST_OK, `*out` points to `*out_len` bytes that the caller owns and
+ * releases with hrst_free. On any error `*out` is null and `*out_len` is 0.
+ */
+hrst_status hrst_encode(const uint8_t *in, size_t in_len, uint8_t **out, size_t *out_len);
+
+/*
+ * Decodes anything `huffman decode` accepts: every version of the format and
+ * concatenated streams. Same arguments and ownership as hrst_encode. Data that
+ * decodes to nothing gives HRST_OK with a null `*out` and `*out_len` of 0.
+ */
+hrst_status hrst_decode(const uint8_t *in, size_t in_len, uint8_t **out, size_t *out_len);
+
+/*
+ * Releases an output of hrst_encode or hrst_decode, with the length it came
+ * with. Does nothing when `data` is null.
+ */
+void hrst_free(uint8_t *data, size_t len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* HRST_H */
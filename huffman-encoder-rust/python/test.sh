@@ -0,0 +1,10 @@
+#!/bin/sh
+# Builds the hrst module into the current virtualenv and runs its tests:
+#
+#     python -m venv .venv && . .venv/bin/activate
+#     pip install maturin pytest
+#     python/test.sh
+set -e
+cd "$(dirname "$0")/.."
+maturin develop --release
+pytest python/tests "$@"
@@ -0,0 +1,74 @@
+# The hrst module, run with python/test.sh
+
+import threading
+
+import pytest
+
+import hrst
+
+
+def test_roundtrip():
+    for data in [b"", b"a", b"abracadabra", bytes(range(256)) * 10]:
+        assert hrst.decode(hrst.encode(data)) == data
+
+
+def test_encode_writes_what_the_binary_writes():
+    encoded = hrst.encode(b"abracadabra")
+    assert encoded[:4] == b"HRST"
+    assert hrst.decode(encoded + hrst.encode(b" again")) == b"abracadabra again"
+
+
+def test_table():
+    assert hrst.table(b"abracadabra") == {
+        ord("a"): ("0", 1),
+        ord("b"): ("100", 3),
+        ord("c"): ("101", 3),
+        ord("d"): ("110", 3),
+        ord("r"): ("111", 3),
+    }
+    assert hrst.table(b"") == {}
+
+
+def test_errors_are_named_after_the_variant():
+    encoded = hrst.encode(b"abracadabra")
+    with pytest.raises(hrst.InvalidMagic):
+        hrst.decode(b"HUFF is the C version")
+    with pytest.raises(hrst.TruncatedData):
+        hrst.decode(encoded[:-1])
+    with pytest.raises(hrst.TrailingGarbage, match="1 bytes of trailing garbage"):
+        hrst.decode(encoded + b"!")
+    # A flipped bit that still decodes, so only the checksum notices
+    corrupt = bytearray(encoded)
+    corrupt[-2] ^= 0x01
+    with pytest.raises(hrst.HuffmanError) as error:
+        hrst.decode(bytes(corrupt))
+    assert type(error.value).__name__ == "ChecksumMismatch"
+    assert issubclass(hrst.ChecksumMismatch, hrst.HuffmanError)
+    assert issubclass(hrst.HuffmanError, Exception)
+
+
+def test_only_bytes():
+    with pytest.raises(TypeError):
+        hrst.encode("text")
+
+
+def test_other_threads_run_during_a_large_encode():
+    data = b"the huffman tree " * (4 * 1024 * 1024)
+    ticks = []
+    done = threading.Event()
+
+    def tick():
+        while not done.is_set():
+            ticks.append(1)
+            done.wait(0.001)
+
+    thread = threading.Thread(target=tick)
+    thread.start()
+    try:
+        encoded = hrst.encode(data)
+        decoded = hrst.decode(encoded)
+    finally:
+        done.set()
+        thread.join()
+    assert decoded == data
+    assert len(ticks) > 1
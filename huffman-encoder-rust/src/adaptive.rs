@@ -0,0 +1,416 @@
+// Adaptive Huffman coding (FGK, after Faller, Gallager and Knuth) for
+// `encode --adaptive`: one pass over the data and no table in the header. The
+// encoder and the decoder start from the same tree and update it the same way
+// after every symbol, so the codes follow the counts of the data seen so far.
+// A byte's first occurrence is sent as the code of the NYT ("not yet
+// transmitted") leaf followed by the byte itself, and the data ends with the
+// END symbol, sent the same way.
+
+use alloc::format;
+
+use crate::bitio::{BitReader, BitWriter};
+use crate::error::{HuffmanError, HuffmanResult};
+use crate::frequency::FrequencyTable;
+use crate::header::{Header, TRAILER_SIZE};
+use crate::io::{BufRead, ErrorKind, Read, Result as IoResult, Write};
+use crate::options::DecoderOptions;
+use crate::progress::Progress;
+use crate::report::StreamSizes;
+
+// Every byte and END. After NYT a symbol takes SYMBOL_BITS bits.
+const SYMBOLS: usize = 257;
+const END: u16 = 256;
+const SYMBOL_BITS: u8 = 9;
+
+// A leaf for every symbol and one for NYT, and the nodes joining them
+const NODES: usize = 2 * (SYMBOLS + 1) - 1;
+const ROOT: u16 = NODES as u16 - 1;
+const NONE: u16 = u16::MAX;
+
+#[derive(Clone, Copy)]
+struct Node {
+    weight: u64,
+    parent: u16,
+    // NONE in leaves, which have a symbol instead (NONE for NYT)
+    children: [u16; 2],
+    symbol: u16,
+}
+
+const EMPTY: Node = Node { weight: 0, parent: NONE, children: [NONE; 2], symbol: NONE };
+
+// The nodes are kept in the order of the sibling property: their index only
+// goes down from the root, and the weights never go up with it. A node and its
+// sibling are next to each other, and NYT is always the lowest, with weight 0.
+// The indices below NYT are not used yet.
+pub(crate) struct AdaptiveTree {
+    nodes: [Node; NODES],
+    // Where the leaf of each symbol is, NONE until it occurs
+    leaves: [u16; SYMBOLS],
+    nyt: u16,
+}
+
+impl AdaptiveTree {
+    // Only NYT, with an empty code
+    pub(crate) fn new() -> Self {
+        AdaptiveTree { nodes: [EMPTY; NODES], leaves: [NONE; SYMBOLS], nyt: ROOT }
+    }
+
+    fn is_leaf(&self, node: u16) -> bool {
+        self.nodes[node as usize].children[0] == NONE
+    }
+
+    // Writes the code of `symbol` and returns its length in bits, then updates
+    // the tree for it
+    pub(crate) fn encode(&mut self, symbol: u16, writer: &mut BitWriter<impl Write>) -> IoResult<u64> {
+        let leaf = self.leaves[symbol as usize];
+        let mut length = self.write_path(if leaf == NONE { self.nyt } else { leaf }, writer)?;
+        if leaf == NONE {
+            writer.write_bits((symbol as u32) << (32 - SYMBOL_BITS as u32), SYMBOL_BITS)?;
+            length += SYMBOL_BITS as u64;
+        }
+        if symbol != END {
+            self.update(symbol);
+        }
+        Ok(length)
+    }
+
+    // The bits from the root down to `node`: 0 for the first child, 1 for the
+    // second. Paths can be longer than the 32 bits write_bits takes, they are
+    // collected bottom up and written in pieces.
+    fn write_path(&self, mut node: u16, writer: &mut BitWriter<impl Write>) -> IoResult<u64> {
+        let mut path = [0u8; NODES];
+        let mut depth = 0;
+        while node != ROOT {
+            let parent = self.nodes[node as usize].parent;
+            path[depth] = (self.nodes[parent as usize].children[1] == node) as u8;
+            depth += 1;
+            node = parent;
+        }
+        for piece in path[..depth].rchunks(32) {
+            let bits = piece.iter().rev().fold(0u32, |bits, &bit| bits << 1 | bit as u32);
+            writer.write_bits(bits << (32 - piece.len() as u32), piece.len() as u8)?;
+        }
+        Ok(depth as u64)
+    }
+
+    // Reads the next symbol and updates the tree for it, None for END
+    pub(crate) fn decode(&mut self, reader: &mut BitReader<impl BufRead>) -> HuffmanResult<Option<u8>> {
+        let mut node = ROOT;
+        while !self.is_leaf(node) {
+            let bit = reader.read_bits(1)?.ok_or_else(|| truncated("in the middle of a code"))?;
+            node = self.nodes[node as usize].children[bit as usize];
+        }
+        let symbol = match node == self.nyt {
+            true => {
+                let symbol = reader.read_bits(SYMBOL_BITS)?.ok_or_else(|| truncated("in the middle of a new symbol"))? as u16;
+                // The encoder only escapes symbols that have no leaf yet
+                if symbol > END || self.leaves[symbol as usize] != NONE {
+                    return Err(HuffmanError::CorruptData(format!("invalid new symbol {} in encoded data", symbol)));
+                }
+                symbol
+            }
+            false => self.nodes[node as usize].symbol,
+        };
+        if symbol == END {
+            return Ok(None);
+        }
+        self.update(symbol);
+        Ok(Some(symbol as u8))
+    }
+
+    // Whether `byte` occurred
+    pub(crate) fn has(&self, byte: u8) -> bool {
+        self.leaves[byte as usize] != NONE
+    }
+
+    // Adds one to the weight of the leaf of `symbol` and of every node above
+    // it. Before each increment the node trades places with the highest node
+    // of the same weight, unless that is its parent, which keeps the order:
+    // the node is then the last of its weight and can grow by one.
+    fn update(&mut self, symbol: u16) {
+        let mut node = match self.leaves[symbol as usize] {
+            NONE => self.split_nyt(symbol),
+            leaf => leaf,
+        };
+        loop {
+            let leader = self.leader(node);
+            if leader != node && leader != self.nodes[node as usize].parent {
+                self.swap(node, leader);
+                node = leader;
+            }
+            self.nodes[node as usize].weight += 1;
+            if node == ROOT {
+                break;
+            }
+            node = self.nodes[node as usize].parent;
+        }
+    }
+
+    // NYT becomes a node with a new NYT and a leaf for `symbol` under it, the
+    // next two indices down. Returns the new leaf.
+    fn split_nyt(&mut self, symbol: u16) -> u16 {
+        let parent = self.nyt;
+        let (nyt, leaf) = (parent - 2, parent - 1);
+        self.nodes[nyt as usize] = Node { parent, ..EMPTY };
+        self.nodes[leaf as usize] = Node { parent, symbol, ..EMPTY };
+        self.nodes[parent as usize].children = [nyt, leaf];
+        self.nodes[parent as usize].symbol = NONE;
+        self.leaves[symbol as usize] = leaf;
+        self.nyt = nyt;
+        leaf
+    }
+
+    // The highest node with the weight of `node`, which are all next to each
+    // other
+    fn leader(&self, node: u16) -> u16 {
+        let weight = self.nodes[node as usize].weight;
+        let mut leader = node;
+        while leader < ROOT && self.nodes[leader as usize + 1].weight == weight {
+            leader += 1;
+        }
+        leader
+    }
+
+    // Trades the places of two nodes with the same weight, neither above the
+    // other, taking their subtrees along
+    fn swap(&mut self, a: u16, b: u16) {
+        let (parent_a, parent_b) = (self.nodes[a as usize].parent, self.nodes[b as usize].parent);
+        self.nodes.swap(a as usize, b as usize);
+        self.nodes[a as usize].parent = parent_a;
+        self.nodes[b as usize].parent = parent_b;
+        for node in [a, b] {
+            let Node { children, symbol, .. } = self.nodes[node as usize];
+            if children[0] != NONE {
+                for child in children {
+                    self.nodes[child as usize].parent = node;
+                }
+            } else if symbol != NONE {
+                self.leaves[symbol as usize] = node;
+            } else {
+                self.nyt = node;
+            }
+        }
+    }
+}
+
+fn truncated(place: &str) -> HuffmanError {
+    HuffmanError::TruncatedData(format!("data ends {}", place))
+}
+
+// Encoding
+////////////////////////////////////////////////////////////////////////////////
+
+// Writes all of `input` as one adaptive stream, as it is read: the header, the
+// codes, END and the trailer. Nothing has to be known up front, so this works
+// the same on a pipe. Returns the length of the input and the sizes of the
+// stream. `header` is Header::adaptive() with the checksum the trailer has
+// and the info of the input, if any. `progress` gets the position in the
+// input, and `frequencies` the counts of the bytes.
+pub(crate) fn encode_adaptive(
+    mut input: impl Read,
+    mut output: impl Write,
+    buffer_size: usize,
+    header: &Header,
+    progress: &mut Progress,
+    mut frequencies: Option<&mut FrequencyTable>,
+) -> HuffmanResult<(u64, StreamSizes)> {
+    header.write_to(&mut output)?;
+    let mut tree = AdaptiveTree::new();
+    let mut bit_writer = BitWriter::new(&mut output, buffer_size)?;
+    let mut buffer = alloc::vec![0u8; buffer_size];
+    let mut crc = header.checksum_kind.start();
+    let mut length = 0u64;
+    let mut bits = 0u64;
+
+    loop {
+        let chunk = match input.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => &buffer[..read],
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        crc.update(chunk);
+        if let Some(frequencies) = &mut frequencies {
+            frequencies.add(chunk);
+        }
+        for &byte in chunk {
+            bits += tree.encode(byte as u16, &mut bit_writer)?;
+        }
+        length += chunk.len() as u64;
+        progress.update(length)?;
+    }
+    bits += tree.encode(END, &mut bit_writer)?;
+    let padding_bits = bit_writer.finish()?;
+    output.write_all(&length.to_le_bytes())?;
+    output.write_all(&crc.finish().to_le_bytes())?;
+    progress.end(length)?;
+
+    let header_bytes = header.serialized_len() + TRAILER_SIZE;
+    Ok((length, StreamSizes { header_bytes, payload_bytes: bits.div_ceil(8), padding_bits }))
+}
+
+// Decoding
+////////////////////////////////////////////////////////////////////////////////
+
+// Decodes the adaptive stream that `reader` is at, after its header, up to
+// END, and checks it against the trailer. The fields the trailer has are
+// filled in into `header`, so it describes the stream like one with a table.
+// Returns the bits the payload took and which bytes occurred. `reader` may
+// have been read past the trailer, the stream ends serialized_len(),
+// payload_length and trailer_len() bytes after it starts.
+pub(crate) fn decode_adaptive(
+    reader: impl BufRead,
+    mut output: impl Write,
+    header: &mut Header,
+    options: &DecoderOptions,
+    progress: &mut Progress,
+) -> HuffmanResult<(u64, [bool; 256])> {
+    let mut tree = AdaptiveTree::new();
+    let mut bit_reader = BitReader::new(reader, 0)?;
+    let mut crc = header.checksum_kind.verifier(options.verify_checksum);
+    let mut decoded = 0u64;
+    while let Some(byte) = tree.decode(&mut bit_reader)? {
+        decoded += 1;
+        if let Some(max) = options.max_output_size.filter(|&max| decoded > max) {
+            return Err(HuffmanError::OutputTooLarge { length: None, max });
+        }
+        output.write_all(&[byte])?;
+        if let Some(crc) = &mut crc {
+            crc.update(&[byte]);
+        }
+        progress.update(bit_reader.bits_read / 8)?;
+    }
+
+    let bits = bit_reader.bits_read;
+    let (padding_bits, padding) = bit_reader.unread_bits();
+    if padding != 0 {
+        return Err(HuffmanError::CorruptData(format!("padding isn't zero ({:#04x})", padding)));
+    }
+    bit_reader.read_bits(padding_bits)?;
+    let mut trailer = [0u8; TRAILER_SIZE as usize];
+    for byte in &mut trailer {
+        *byte = bit_reader.read_bits(8)?.ok_or_else(|| truncated("in the trailer"))? as u8;
+    }
+    let length = u64::from_le_bytes(trailer[..8].try_into().expect("8 bytes"));
+    let checksum = u32::from_le_bytes(trailer[8..].try_into().expect("4 bytes"));
+    if length != decoded {
+        return Err(HuffmanError::CorruptData(
+            format!("length doesn't match the data ({} bytes declared, {} decoded)", length, decoded),
+        ));
+    }
+    if let Some(actual) = crc.map(|crc| crc.finish()).filter(|&actual| actual != checksum) {
+        return Err(HuffmanError::ChecksumMismatch { expected: checksum, actual });
+    }
+
+    header.original_length = Some(length);
+    header.checksum = Some(checksum);
+    header.payload_length = Some(bits.div_ceil(8));
+    header.padding_bits = padding_bits;
+    Ok((bits, core::array::from_fn(|byte| tree.has(byte as u8))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{text_data, Rng};
+
+    impl AdaptiveTree {
+        // The sibling property, and that every node weighs what its children do
+        fn check(&self) {
+            for node in self.nyt..ROOT {
+                let Node { weight, parent, .. } = self.nodes[node as usize];
+                assert!(weight <= self.nodes[node as usize + 1].weight, "node {} outweighs the next one", node);
+                assert!(parent > node && parent != NONE, "node {} is above its parent", node);
+            }
+            for node in self.nyt..=ROOT {
+                let Node { weight, children, symbol, .. } = self.nodes[node as usize];
+                if children[0] != NONE {
+                    let sum: u64 = children.iter().map(|&child| self.nodes[child as usize].weight).sum();
+                    assert_eq!(weight, sum, "node {}", node);
+                    assert!(children.iter().all(|&child| self.nodes[child as usize].parent == node));
+                } else if symbol != NONE {
+                    assert_eq!(self.leaves[symbol as usize], node);
+                } else {
+                    assert_eq!(node, self.nyt);
+                }
+            }
+        }
+    }
+
+    fn encode_symbols(data: &[u8]) -> (Vec<u8>, u64) {
+        let mut encoded = Vec::new();
+        let mut writer = BitWriter::new(&mut encoded, 4096).unwrap();
+        let mut tree = AdaptiveTree::new();
+        let mut bits = 0;
+        for &byte in data {
+            bits += tree.encode(byte as u16, &mut writer).unwrap();
+            tree.check();
+        }
+        bits += tree.encode(END, &mut writer).unwrap();
+        writer.finish().unwrap();
+        (encoded, bits)
+    }
+
+    fn decode_symbols(encoded: &[u8]) -> HuffmanResult<Vec<u8>> {
+        let mut reader = BitReader::new(encoded, 0).unwrap();
+        let mut tree = AdaptiveTree::new();
+        let mut decoded = Vec::new();
+        while let Some(byte) = tree.decode(&mut reader)? {
+            decoded.push(byte);
+        }
+        Ok(decoded)
+    }
+
+    #[test]
+    fn test_tree_keeps_its_order() {
+        let mut rng = Rng(0xF6C0);
+        for data in [b"abracadabra".to_vec(), text_data(&mut rng, 5000), (0..=255).cycle().take(3000).collect()] {
+            let (encoded, bits) = encode_symbols(&data);
+            assert_eq!(encoded.len() as u64, bits.div_ceil(8));
+            assert_eq!(decode_symbols(&encoded).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_first_codes() {
+        // a: only NYT, so the byte; b: NYT is 0 then; a: 1; END: NYT is 00
+        let (encoded, bits) = encode_symbols(b"aba");
+        assert_eq!(bits, 9 + 1 + 9 + 1 + 2 + 9);
+        let expected = "001100001".to_string() + "0" + "001100010" + "1" + "00" + "100000000";
+        let written: String = encoded.iter().map(|byte| format!("{:08b}", byte)).collect();
+        assert_eq!(&written[..bits as usize], expected);
+    }
+
+    #[test]
+    fn test_codes_longer_than_32_bits() {
+        // Fibonacci counts make the deepest tree there is, with NYT at the
+        // bottom, so END takes more than 32 bits after 33 bytes
+        let (mut previous, mut count) = (0usize, 1usize);
+        let mut data = Vec::new();
+        for byte in 0..33u8 {
+            data.extend(core::iter::repeat_n(byte, count));
+            (previous, count) = (count, previous + count);
+        }
+        let mut encoded = Vec::new();
+        let mut writer = BitWriter::new(&mut encoded, 4096).unwrap();
+        let mut tree = AdaptiveTree::new();
+        for &byte in &data {
+            tree.encode(byte as u16, &mut writer).unwrap();
+        }
+        tree.check();
+        assert!(tree.encode(END, &mut writer).unwrap() > 32 + SYMBOL_BITS as u64);
+        writer.finish().unwrap();
+        assert!(decode_symbols(&encoded).unwrap() == data);
+    }
+
+    #[test]
+    fn test_decode_rejects() {
+        // END right away, then a byte escaped twice
+        assert_eq!(decode_symbols(&[0b1000_0000, 0]).unwrap(), b"");
+        let (encoded, _) = encode_symbols(b"a");
+        assert!(matches!(decode_symbols(&encoded[..1]), Err(HuffmanError::TruncatedData(_))));
+        let twice = [0b0011_0000, 0b1000_1100, 0b0010_0000];
+        assert!(matches!(decode_symbols(&twice), Err(HuffmanError::CorruptData(_))));
+        assert!(matches!(decode_symbols(&[0xFF, 0x80]), Err(HuffmanError::CorruptData(_))));
+    }
+}
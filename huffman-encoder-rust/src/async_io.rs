@@ -0,0 +1,355 @@
+// HuffmanWriter and HuffmanReader for tokio, on AsyncWrite and AsyncBufRead.
+// They share WriterState and ReaderState with the adapters of stream, so the
+// bytes and the errors are the same, and only the IO around them differs.
+//
+// Everything lives in the adapter between polls, nothing in a future, so a
+// read or write that is dropped before it completes loses nothing: the next
+// one picks up where it stopped.
+
+use std::future::poll_fn;
+use std::io::{ErrorKind, Result as IoResult};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use tokio::io::{AsyncBufRead, AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::error::HuffmanResult;
+use crate::stream::{ReaderState, WriterState};
+use crate::table::EncodingTable;
+
+// Encodes everything written to it into `output`, like HuffmanWriter:
+// poll_flush() ends the current stream, and poll_shutdown() ends the last one
+// and shuts `output` down. finish() ends the last one and hands back
+// `output` instead.
+//
+// A drop can't wait for `output`, so dropping the writer without one of them
+// loses the current stream.
+pub struct AsyncHuffmanWriter<W> {
+    state: WriterState,
+    output: W,
+    // Ended streams that `output` didn't take yet
+    pending: Vec<u8>,
+    pending_start: usize,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncHuffmanWriter<W> {
+    // Same as HuffmanWriter::new
+    pub fn new(output: W, encoding_table: EncodingTable) -> IoResult<Self> {
+        Ok(Self::with_state(output, WriterState::new(encoding_table)?))
+    }
+
+    // Same as HuffmanWriter::buffered
+    pub fn buffered(output: W) -> Self {
+        Self::with_state(output, WriterState::buffered())
+    }
+
+    fn with_state(output: W, state: WriterState) -> Self {
+        AsyncHuffmanWriter { state, output, pending: Vec::new(), pending_start: 0 }
+    }
+
+    fn end_stream(&mut self) -> HuffmanResult<()> {
+        let stream = self.state.end_stream()?;
+        self.pending.extend_from_slice(&stream);
+        Ok(())
+    }
+
+    // Writes out the ended streams
+    fn poll_pending(&mut self, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        while self.pending_start < self.pending.len() {
+            let written = ready!(Pin::new(&mut self.output).poll_write(cx, &self.pending[self.pending_start..]))?;
+            if written == 0 {
+                return Poll::Ready(Err(ErrorKind::WriteZero.into()));
+            }
+            self.pending_start += written;
+        }
+        self.pending.clear();
+        self.pending_start = 0;
+        Poll::Ready(Ok(()))
+    }
+
+    // Ends the last stream and flushes it out. No stream is ended twice when
+    // this is polled again.
+    fn poll_end(&mut self, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        if self.state.needs_last_stream() {
+            self.end_stream()?;
+        }
+        ready!(self.poll_pending(cx))?;
+        Pin::new(&mut self.output).poll_flush(cx)
+    }
+
+    // Ends the last stream and returns the writer it went to
+    pub async fn finish(mut self) -> HuffmanResult<W> {
+        poll_fn(|cx| self.poll_end(cx)).await?;
+        Ok(self.output)
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for AsyncHuffmanWriter<W> {
+    // Ended streams go out first, so a slow `output` slows down the writes
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<IoResult<usize>> {
+        let writer = self.get_mut();
+        ready!(writer.poll_pending(cx))?;
+        Poll::Ready(Ok(writer.state.write(buf)?))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        let writer = self.get_mut();
+        if writer.state.is_pending() {
+            writer.end_stream()?;
+        }
+        ready!(writer.poll_pending(cx))?;
+        Pin::new(&mut writer.output).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        let writer = self.get_mut();
+        ready!(writer.poll_end(cx))?;
+        Pin::new(&mut writer.output).poll_shutdown(cx)
+    }
+}
+
+// Decodes its input as it is read, like HuffmanReader
+pub struct AsyncHuffmanReader<R> {
+    reader: R,
+    state: ReaderState,
+}
+
+impl<R: AsyncBufRead + Unpin> AsyncHuffmanReader<R> {
+    pub fn new(reader: R) -> IoResult<Self> {
+        Ok(AsyncHuffmanReader { reader, state: ReaderState::new() })
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> AsyncRead for AsyncHuffmanReader<R> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<IoResult<()>> {
+        let this = self.get_mut();
+        loop {
+            let decoded = this.state.decode(buf.initialize_unfilled())?;
+            if decoded > 0 || !this.state.wants_input() {
+                buf.advance(decoded);
+                return Poll::Ready(Ok(()));
+            }
+            let input = ready!(Pin::new(&mut this.reader).poll_fill_buf(cx))?;
+            if input.is_empty() {
+                this.state.end_input();
+            } else {
+                let taken = this.state.feed(input);
+                Pin::new(&mut this.reader).consume(taken);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{decode_bytes, encode_bytes};
+    use crate::error::HuffmanError;
+    use crate::stream::{HuffmanReader, HuffmanWriter};
+    use crate::table::Code;
+    use crate::test_util::{text_data, Rng};
+    use crate::tree::HuffmanTree;
+    use crate::FrequencyTable;
+    use std::io::{self, Read, Write};
+    use std::time::Duration;
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::time::timeout;
+
+    // Long enough for anything that isn't stuck
+    const DEADLINE: Duration = Duration::from_secs(10);
+
+    fn table_for(data: &[u8]) -> EncodingTable {
+        let mut frequencies = FrequencyTable::new();
+        frequencies.add(data);
+        HuffmanTree::from_frequencies(&frequencies).unwrap().codes()
+    }
+
+    // What HuffmanWriter writes for `data` in `chunks`, flushed after
+    // `flush_after` of them
+    fn sync_encode(data: &[u8], chunk_size: usize, flush_after: usize, table: Option<EncodingTable>) -> Vec<u8> {
+        let mut writer = match table {
+            Some(table) => HuffmanWriter::new(Vec::new(), table).unwrap(),
+            None => HuffmanWriter::buffered(Vec::new()),
+        };
+        for (i, chunk) in data.chunks(chunk_size).enumerate() {
+            writer.write_all(chunk).unwrap();
+            if i + 1 == flush_after {
+                writer.flush().unwrap();
+            }
+        }
+        writer.finish().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_writer_matches_the_sync_writer() {
+        let data = text_data(&mut Rng(0xA5E7), 200_000);
+        for table in [None, Some(table_for(&data))] {
+            // Through a small pipe, so the writer has to wait for the other end
+            let (output, mut input) = duplex(64);
+            let mut writer = match table.clone() {
+                Some(table) => AsyncHuffmanWriter::new(output, table).unwrap(),
+                None => AsyncHuffmanWriter::buffered(output),
+            };
+            let chunks = data.clone();
+            let writing = tokio::spawn(async move {
+                for (i, chunk) in chunks.chunks(1000).enumerate() {
+                    writer.write_all(chunk).await.unwrap();
+                    if i == 49 {
+                        writer.flush().await.unwrap();
+                    }
+                }
+                writer.shutdown().await.unwrap();
+            });
+            let mut encoded = Vec::new();
+            input.read_to_end(&mut encoded).await.unwrap();
+            writing.await.unwrap();
+            assert!(encoded == sync_encode(&data, 1000, 50, table), "with a table: {}", encoded.len());
+            assert!(decode_bytes(&encoded).unwrap() == data);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_finish_returns_the_output() {
+        let writer = AsyncHuffmanWriter::buffered(Vec::new());
+        assert_eq!(writer.finish().await.unwrap(), encode_bytes(b"").unwrap());
+
+        let mut writer = AsyncHuffmanWriter::new(Vec::new(), table_for(b"ab")).unwrap();
+        writer.write_all(b"abba").await.unwrap();
+        let error = writer.write_all(b"c").await.unwrap_err();
+        let attached = error.get_ref().and_then(|e| e.downcast_ref::<HuffmanError>());
+        assert!(matches!(attached, Some(HuffmanError::UnknownSymbol { byte: b'c', offset: 4 })), "{:?}", error);
+        assert_eq!(decode_bytes(&writer.finish().await.unwrap()).unwrap(), b"abba");
+
+        let overlapping: EncodingTable = [
+            (b'a', Code { bits: 0, length: 1 }),
+            (b'b', Code { bits: 0, length: 2 }),
+        ].into_iter().collect();
+        assert!(AsyncHuffmanWriter::new(Vec::new(), overlapping).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_roundtrip_through_a_pipe() {
+        let data = text_data(&mut Rng(0xD0E7), 300_000);
+        let (output, input) = duplex(4096);
+        let mut writer = AsyncHuffmanWriter::new(output, table_for(&data)).unwrap();
+        let chunks = data.clone();
+        let writing = tokio::spawn(async move {
+            for chunk in chunks.chunks(7_000) {
+                writer.write_all(chunk).await.unwrap();
+                // A stream per chunk
+                writer.flush().await.unwrap();
+            }
+            writer.shutdown().await.unwrap();
+        });
+
+        let mut reader = AsyncHuffmanReader::new(BufReader::with_capacity(100, input)).unwrap();
+        let mut decoded = Vec::new();
+        timeout(DEADLINE, reader.read_to_end(&mut decoded)).await.unwrap().unwrap();
+        writing.await.unwrap();
+        assert!(decoded == data);
+    }
+
+    #[tokio::test]
+    async fn test_reader_errors_match_the_sync_reader() {
+        let encoded = [encode_bytes(b"first block").unwrap(), encode_bytes(b"second block").unwrap()].concat();
+        let mut checksum = encoded.clone();
+        *checksum.last_mut().unwrap() ^= 0x80;
+        let cases = [
+            ("magic", b"HRSX".to_vec()),
+            ("empty", Vec::new()),
+            ("truncated", encoded[..encoded.len() - 3].to_vec()),
+            ("garbage", [&encoded[..], b"junk"].concat()),
+            ("checksum", checksum),
+            ("valid", encoded),
+        ];
+        for (name, input) in cases {
+            let mut expected = Vec::new();
+            let expected = HuffmanReader::new(&input[..]).unwrap().read_to_end(&mut expected).map(|_| expected);
+            // A byte at a time, the slowest way the input can come in
+            let mut reader = AsyncHuffmanReader::new(BufReader::with_capacity(1, &input[..])).unwrap();
+            let mut decoded = Vec::new();
+            let result = reader.read_to_end(&mut decoded).await.map(|_| decoded);
+            match (result, expected) {
+                (Ok(decoded), Ok(expected)) => assert_eq!(decoded, expected, "{}", name),
+                (Err(error), Err(expected)) => {
+                    assert_eq!(error.kind(), expected.kind(), "{}", name);
+                    assert_eq!(error.to_string(), expected.to_string(), "{}", name);
+                }
+                (result, expected) => panic!("{}: {:?} instead of {:?}", name, result, expected),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_reads_lose_nothing() {
+        let data = text_data(&mut Rng(0xCA4C), 50_000);
+        let encoded = encode_bytes(&data).unwrap();
+        let (mut output, input) = duplex(encoded.len());
+        let mut reader = AsyncHuffmanReader::new(BufReader::new(input)).unwrap();
+        let mut decoded = Vec::new();
+        let mut buffer = [0u8; 1000];
+
+        // Half of the input, then reads that wait for the rest and are given up
+        let (first, second) = encoded.split_at(encoded.len() / 2);
+        output.write_all(first).await.unwrap();
+        while let Ok(read) = timeout(Duration::from_millis(20), reader.read(&mut buffer)).await {
+            decoded.extend_from_slice(&buffer[..read.unwrap()]);
+        }
+        assert!(!decoded.is_empty() && decoded.len() < data.len());
+
+        output.write_all(second).await.unwrap();
+        drop(output);
+        timeout(DEADLINE, reader.read_to_end(&mut decoded)).await.unwrap().unwrap();
+        assert!(decoded == data);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_writes_lose_nothing() {
+        let data = text_data(&mut Rng(0xCA4D), 100_000);
+        let (output, mut input) = duplex(256);
+        let mut writer = AsyncHuffmanWriter::buffered(output);
+        writer.write_all(&data).await.unwrap();
+        // The flush fills the pipe and has to wait for the reader, so it is
+        // given up part way
+        assert!(timeout(Duration::from_millis(20), writer.flush()).await.is_err());
+
+        let reading = tokio::spawn(async move {
+            let mut encoded = Vec::new();
+            input.read_to_end(&mut encoded).await.unwrap();
+            encoded
+        });
+        writer.write_all(b" and more").await.unwrap();
+        timeout(DEADLINE, writer.shutdown()).await.unwrap().unwrap();
+        drop(writer);
+        let encoded = reading.await.unwrap();
+        assert!(decode_bytes(&encoded).unwrap() == [&data[..], b" and more"].concat());
+    }
+
+    #[tokio::test]
+    async fn test_dropping_either_end_mid_stream_doesnt_hang() {
+        let data = text_data(&mut Rng(0xD40F), 100_000);
+        let encoded = encode_bytes(&data).unwrap();
+
+        // The reader goes away part way, the other end gets an error
+        let (mut output, input) = duplex(512);
+        let mut reader = AsyncHuffmanReader::new(BufReader::new(input)).unwrap();
+        let writing = tokio::spawn(async move { output.write_all(&encoded).await });
+        let mut buffer = [0u8; 100];
+        reader.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..], &data[..100]);
+        drop(reader);
+        let error = timeout(DEADLINE, writing).await.unwrap().unwrap().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::BrokenPipe);
+
+        // The writer goes away with its stream unfinished, the reader sees
+        // the input end without a stream
+        let (output, input) = duplex(512);
+        let mut writer = AsyncHuffmanWriter::buffered(output);
+        writer.write_all(&data).await.unwrap();
+        drop(writer);
+        let mut reader = AsyncHuffmanReader::new(BufReader::new(input)).unwrap();
+        let mut decoded = Vec::new();
+        let error = timeout(DEADLINE, reader.read_to_end(&mut decoded)).await.unwrap().unwrap_err();
+        assert_eq!(error.to_string(), decode_bytes(b"").unwrap_err().to_string());
+    }
+}
@@ -0,0 +1,564 @@
+// Writing and reading the encoded data a code at a time
+
+use alloc::boxed::Box;
+use alloc::vec;
+
+use crate::io::{BufRead, Result as IoResult, ErrorKind, Write};
+
+use crate::table::Code;
+
+// Owns the writer it writes to, pass a `&mut` to keep using the writer after
+// finish(), or take it back with into_inner()
+pub struct BitWriter<W: Write> {
+    // Pending bits, left aligned: the next bit to go out is the top bit. Whole
+    // bytes are moved to the buffer right away, so fewer than 8 are left
+    // between calls.
+    accumulator: u64,
+    bits_filled: u32,
+    // Completed bytes, written out once the buffer is full
+    buffer: Box<[u8]>,
+    buffered: usize,
+    // Only None once into_inner() has taken it
+    output: Option<W>,
+}
+
+impl<W: Write> BitWriter<W> {
+    pub fn new(output: W, buffer_size: usize) -> IoResult<Self> {
+        Ok(BitWriter {
+            accumulator: 0,
+            bits_filled: 0,
+            buffer: vec![0; buffer_size].into_boxed_slice(),
+            buffered: 0,
+            output: Some(output),
+        })
+    }
+
+    // Writes the top `length` bits of `bits`, so a code 0b101 of length 3 is
+    // passed as 0b101 << 29
+    pub fn write_bits(&mut self, bits: u32, length: u8) -> IoResult<()> {
+        if length == 0 {
+            return Ok(());
+        }
+        // Anything past the length is not part of the code
+        let code = bits & (u32::MAX << (32 - length as u32));
+
+        // At most 7 + 32 bits are used, so the code always fits. Example with
+        // 3 pending bits, writing 0b11 (length 2):
+        //   accumulator = 0b101000...
+        //   code << 32 >> 3 = 0b000110...
+        //   accumulator = 0b101110..., 5 bits filled
+        self.accumulator |= (code as u64) << 32 >> self.bits_filled;
+        self.bits_filled += length as u32;
+
+        while self.bits_filled >= 8 {
+            self.buffer[self.buffered] = (self.accumulator >> 56) as u8;
+            self.buffered += 1;
+            self.accumulator <<= 8;
+            self.bits_filled -= 8;
+            if self.buffered == self.buffer.len() {
+                self.write_buffer()?;
+            }
+        }
+        Ok(())
+    }
+
+    // Same as write_bits with the two halves of the code
+    #[inline]
+    pub fn write_code(&mut self, code: &Code) -> IoResult<()> {
+        self.write_bits(code.bits, code.length)
+    }
+
+    // Moves up to `count` bits from `reader` to the output, wherever the two
+    // are in their bytes, and returns how many there were. Copying the payload
+    // of a stream after another one this way leaves out the padding between
+    // them.
+    pub fn copy_bits(&mut self, reader: &mut BitReader<impl BufRead>, count: u64) -> IoResult<u64> {
+        let mut copied = 0;
+        while copied < count {
+            let wanted = (count - copied).min(32) as u8;
+            let (bits, available) = reader.peek_bits(wanted)?;
+            if available == 0 {
+                break;
+            }
+            reader.consume(available);
+            self.write_bits(bits << (32 - wanted as u32), available)?;
+            copied += available as u64;
+        }
+        Ok(copied)
+    }
+
+    // Empties the buffer even when writing fails, so there is always room for
+    // the next byte. The error is reported and the output is lost anyway.
+    fn write_buffer(&mut self) -> IoResult<()> {
+        let result = match &mut self.output {
+            Some(output) => output.write_all(&self.buffer[..self.buffered]),
+            None => Ok(()),
+        };
+        self.buffered = 0;
+        result
+    }
+
+    // Moves the pending bits to the buffer as a last byte, padded with zeros
+    fn push_pending_bits(&mut self) {
+        if self.bits_filled > 0 {
+            // There is always room, a full buffer is written out right away
+            self.buffer[self.buffered] = (self.accumulator >> 56) as u8;
+            self.buffered += 1;
+            self.accumulator = 0;
+            self.bits_filled = 0;
+        }
+    }
+
+    // Writes out the pending bits, padding them to a full byte, and returns the
+    // number of padded bits. Only hands the data to the file, durability is up
+    // to the caller (see OutputFile::commit).
+    fn flush(&mut self) -> IoResult<u8> {
+        // No padding when the output ended on a byte boundary
+        let padding_bits = ((8 - self.bits_filled) % 8) as u8;
+        self.push_pending_bits();
+        self.write_buffer()?;
+        Ok(padding_bits)
+    }
+
+    // Flushes the remaining bits and returns the number of padding bits. Taking
+    // self means nothing can be written after the padding is known.
+    pub fn finish(mut self) -> IoResult<u8> {
+        self.flush()
+    }
+
+    // Like finish(), also handing back the writer
+    pub fn into_inner(mut self) -> IoResult<(W, u8)> {
+        let padding_bits = self.flush()?;
+        let output = self.output.take().expect("the writer is only taken here");
+        Ok((output, padding_bits))
+    }
+}
+
+// A writer that goes out of scope without finish() would silently drop the
+// buffered bytes and up to 7 bits. Write them out anyway; errors can't be
+// reported from here, which is why finish() should be preferred.
+impl<W: Write> Drop for BitWriter<W> {
+    fn drop(&mut self) {
+        self.push_pending_bits();
+        let _ = self.write_buffer();
+    }
+}
+
+// Reads straight from the buffer of the reader, so it only consumes the bytes
+// it loads and the reader can carry on after the encoded data. Pass a `&mut`
+// to keep the reader, or take it back with into_inner().
+pub struct BitReader<R: BufRead> {
+    reader: R,
+    // Bit buffer, left aligned: the next bit is the top bit
+    bits: u64,
+    bits_available: u32,
+    // Set once the last byte of the input is in `bits`
+    at_end: bool,
+    padding_bits: u8,
+    pub(crate) bits_read: u64,
+    // Bytes taken from the reader, some of them may not be read yet
+    pub(crate) bytes_loaded: u64,
+}
+
+impl<R: BufRead> BitReader<R> {
+    pub fn new(reader: R, padding_bits: u8) -> IoResult<Self> {
+        Ok(BitReader {
+            reader,
+            bits: 0,
+            bits_available: 0,
+            at_end: false,
+            padding_bits,
+            bits_read: 0,
+            bytes_loaded: 0,
+        })
+    }
+
+    // Whether the reader has nothing left, retrying interrupted reads
+    fn at_end_of_input(&mut self) -> IoResult<bool> {
+        loop {
+            match self.reader.fill_buf() {
+                Ok(buffer) => return Ok(buffer.is_empty()),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // Tops up the bit buffer with whole bytes. Only the last byte has padding,
+    // so after taking everything the reader had buffered this looks ahead to
+    // find out whether that was the last one. The padding is never made
+    // available.
+    fn refill(&mut self) -> IoResult<()> {
+        while self.bits_available <= 56 && !self.at_end {
+            let (taken, buffered) = match self.reader.fill_buf() {
+                Ok(buffer) => {
+                    let taken = buffer.len().min(((64 - self.bits_available) / 8) as usize);
+                    for &byte in &buffer[..taken] {
+                        self.bits |= (byte as u64) << (56 - self.bits_available);
+                        self.bits_available += 8;
+                    }
+                    (taken, buffer.len())
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            self.reader.consume(taken);
+            self.bytes_loaded += taken as u64;
+            if buffered == 0 {
+                // An empty input
+                self.at_end = true;
+            } else if taken == buffered && self.at_end_of_input()? {
+                self.bits_available -= self.padding_bits as u32;
+                self.at_end = true;
+            }
+        }
+        Ok(())
+    }
+
+    // The next `count` bits (at most 32) right aligned, without consuming them,
+    // and how many of them are really there. Past the end of the data the
+    // missing bits read as zero.
+    #[inline]
+    pub fn peek_bits(&mut self, count: u8) -> IoResult<(u32, u8)> {
+        let count = count as u32;
+        if self.bits_available < count {
+            self.refill()?;
+        }
+        let available = count.min(self.bits_available);
+        if available == 0 {
+            return Ok((0, 0));
+        }
+        // Top `available` bits, shifted back up to `count` bits wide
+        let bits = (self.bits >> (64 - available)) << (count - available);
+        Ok((bits as u32, available as u8))
+    }
+
+    // Skips `count` bits, which have to be available according to peek_bits
+    #[inline]
+    pub fn consume(&mut self, count: u8) {
+        debug_assert!(count as u32 <= self.bits_available);
+        self.bits <<= count;
+        self.bits_available -= count as u32;
+        self.bits_read += count as u64;
+    }
+
+    // Reads the next `count` bits (at most 32), right aligned with the first
+    // one at the top. None when fewer than `count` are left, in which case
+    // nothing is consumed. Like peek_bits the padding is never returned.
+    pub fn read_bits(&mut self, count: u8) -> IoResult<Option<u32>> {
+        assert!(count <= 32, "at most 32 bits at a time, not {}", count);
+        let (bits, available) = self.peek_bits(count)?;
+        if available < count {
+            return Ok(None);
+        }
+        self.consume(count);
+        Ok(Some(bits))
+    }
+
+    #[cfg(test)]
+    pub fn read_bit(&mut self) -> IoResult<Option<bool>> {
+        Ok(self.read_bits(1)?.map(|bit| bit == 1))
+    }
+
+    // The bits of the current byte that haven't been read yet, as a count and
+    // their value. Nothing is left at a byte boundary. Data starts on a byte
+    // boundary and whole bytes are loaded, so the rest of the byte is always at
+    // the top of the bit buffer, padding included.
+    pub fn unread_bits(&self) -> (u8, u8) {
+        let count = ((8 - self.bits_read % 8) % 8) as u8;
+        if count == 0 {
+            return (0, 0);
+        }
+        (count, (self.bits >> (64 - count as u32)) as u8)
+    }
+
+    // For the stream readers, which feed the reader while it is decoding
+    #[cfg(feature = "std")]
+    pub(crate) fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    // The reader, positioned after the bytes loaded so far
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::codec::DEFAULT_BUFFER_SIZE;
+    use crate::test_util::{bench, report_throughput, temp_path, Rng, MIB};
+    use std::fs::{self, File};
+    use std::io::{BufReader, Cursor, Read};
+
+    #[test]
+    fn test_bit_writer_finish_returns_padding() {
+        let path = temp_path("finish");
+        let mut file = File::create(&path).unwrap();
+        let mut writer = BitWriter::new(&mut file, DEFAULT_BUFFER_SIZE).unwrap();
+        writer.write_bits(0b1010_0000 << 24, 3).unwrap();
+        assert_eq!(writer.finish().unwrap(), 5);
+        drop(file);
+
+        assert_eq!(fs::read(&path).unwrap(), [0b1010_0000]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_bit_writer_drop_writes_pending_bits() {
+        let path = temp_path("drop");
+        let mut file = File::create(&path).unwrap();
+        {
+            let mut writer = BitWriter::new(&mut file, DEFAULT_BUFFER_SIZE).unwrap();
+            writer.write_bits(0xFF << 24, 8).unwrap();
+            writer.write_bits(0b1100_0000 << 24, 2).unwrap();
+            // Dropped without finish()
+        }
+        drop(file);
+
+        assert_eq!(fs::read(&path).unwrap(), [0xFF, 0b1100_0000]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_bit_writer_output_spanning_several_buffers() {
+        let data: Vec<u8> = (0..3 * DEFAULT_BUFFER_SIZE + 1).map(|i| (i * 7) as u8).collect();
+        let mut output = Vec::new();
+        let mut writer = BitWriter::new(&mut output, DEFAULT_BUFFER_SIZE).unwrap();
+        for &byte in &data {
+            writer.write_bits((byte as u32) << 24, 8).unwrap();
+        }
+        writer.write_bits(0b1000_0000 << 24, 1).unwrap();
+        assert_eq!(writer.finish().unwrap(), 7);
+
+        assert_eq!(output.len(), data.len() + 1);
+        assert_eq!(output[..data.len()], data[..]);
+        assert_eq!(output[data.len()], 0b1000_0000);
+    }
+
+    // Writes the (bits, length) codes and reads them back bit by bit
+    fn check_bit_symmetry(codes: &[(u32, u8)]) {
+        let mut buffer = Vec::new();
+        let mut writer = BitWriter::new(&mut buffer, DEFAULT_BUFFER_SIZE).unwrap();
+        for &(bits, length) in codes {
+            writer.write_bits(bits, length).unwrap();
+        }
+        let padding_bits = writer.finish().unwrap();
+
+        let total_bits: usize = codes.iter().map(|&(_, length)| length as usize).sum();
+        assert_eq!(buffer.len(), total_bits.div_ceil(8), "codes: {:?}", codes);
+        assert_eq!(padding_bits as usize, buffer.len() * 8 - total_bits, "codes: {:?}", codes);
+
+        let mut reader = BitReader::new(&buffer[..], padding_bits).unwrap();
+        for &(bits, length) in codes {
+            for i in 0..length {
+                let expected = (bits >> (31 - i)) & 1 == 1;
+                assert_eq!(reader.read_bit().unwrap(), Some(expected), "codes: {:?}", codes);
+            }
+        }
+        assert_eq!(reader.read_bit().unwrap(), None, "codes: {:?}", codes);
+        assert_eq!(reader.read_bit().unwrap(), None, "codes: {:?}", codes);
+        assert_eq!(reader.bits_read as usize, total_bits);
+
+        // The same codes again, a whole code at a time, from a reader that
+        // holds a single byte, so the end is only found by looking ahead
+        let input = BufReader::with_capacity(1, &buffer[..]);
+        let mut reader = BitReader::new(input, padding_bits).unwrap();
+        for &(bits, length) in codes {
+            let expected = (bits >> (32 - length as u32), length);
+            assert_eq!(reader.peek_bits(length).unwrap(), expected, "codes: {:?}", codes);
+            reader.consume(length);
+        }
+        assert_eq!(reader.peek_bits(32).unwrap(), (0, 0), "codes: {:?}", codes);
+        assert_eq!(reader.bits_read as usize, total_bits);
+    }
+
+    #[test]
+    fn test_bit_io_edge_cases() {
+        let ones = |length: u8| (u32::MAX << (32 - length as u32), length);
+        check_bit_symmetry(&[]);
+        for length in [1, 7, 8, 9, 15, 16, 17, 31, 32] {
+            check_bit_symmetry(&[ones(length)]);
+            check_bit_symmetry(&[(0, length)]);
+        }
+        // 8 bits in odd pieces, 9 and 16 across several codes
+        check_bit_symmetry(&[ones(3), (0, 5)]);
+        check_bit_symmetry(&[ones(4), ones(4), (0, 1)]);
+        check_bit_symmetry(&[(0, 7), ones(9)]);
+    }
+
+    #[test]
+    fn test_bit_reader_peek_past_the_padding() {
+        let data = [0b1100_1010, 0b1011_1111];
+        let mut reader = BitReader::new(&data[..], 4).unwrap();
+        assert_eq!(reader.peek_bits(2).unwrap(), (0b11, 2));
+        assert_eq!(reader.peek_bits(16).unwrap(), (0b1100_1010_1011_0000, 12));
+        reader.consume(10);
+        // The padding bits are not available, and read as zero even when set
+        assert_eq!(reader.peek_bits(8).unwrap(), (0b1100_0000, 2));
+        assert_eq!(reader.unread_bits(), (6, 0b11_1111));
+        reader.consume(2);
+        assert_eq!(reader.peek_bits(8).unwrap(), (0, 0));
+        assert_eq!(reader.read_bit().unwrap(), None);
+    }
+
+    #[test]
+    fn test_bit_writer_into_inner_returns_the_writer() {
+        let mut writer = BitWriter::new(Vec::new(), DEFAULT_BUFFER_SIZE).unwrap();
+        writer.write_bits(0b1011 << 28, 4).unwrap();
+        writer.write_bits(0xAB << 24, 8).unwrap();
+        let (output, padding_bits) = writer.into_inner().unwrap();
+        assert_eq!(output, [0b1011_1010, 0b1011_0000]);
+        assert_eq!(padding_bits, 4);
+    }
+
+    // The bits of `data` without its padding, one bool per bit
+    fn bits_of(data: &[u8], padding_bits: u8) -> Vec<bool> {
+        let mut bits: Vec<bool> = data.iter().flat_map(|&byte| (0..8).map(move |i| byte >> (7 - i) & 1 == 1)).collect();
+        bits.truncate(bits.len() - padding_bits as usize);
+        bits
+    }
+
+    #[test]
+    fn test_copy_bits_after_any_number_of_bits() {
+        let mut rng = Rng(0xC0B1);
+        let source: Vec<u8> = (0..1000).map(|_| rng.next() as u8).collect();
+        for (written, padding_bits) in [(0, 0), (1, 3), (7, 0), (8, 7), (13, 5)] {
+            let mut output = Vec::new();
+            let mut writer = BitWriter::new(&mut output, 16).unwrap();
+            writer.write_bits(u32::MAX, written).unwrap();
+            let mut reader = BitReader::new(BufReader::with_capacity(3, &source[..]), padding_bits).unwrap();
+            let available = (source.len() * 8) as u64 - padding_bits as u64;
+            assert_eq!(writer.copy_bits(&mut reader, u64::MAX).unwrap(), available);
+            let padding = writer.finish().unwrap();
+
+            let mut expected = vec![true; written as usize];
+            expected.extend(bits_of(&source, padding_bits));
+            assert_eq!(bits_of(&output, padding), expected, "after {} bits", written);
+        }
+
+        // Fewer than there are, leaving the rest to read
+        let mut reader = BitReader::new(&source[..], 0).unwrap();
+        let mut output = Vec::new();
+        let mut writer = BitWriter::new(&mut output, 16).unwrap();
+        assert_eq!(writer.copy_bits(&mut reader, 100).unwrap(), 100);
+        assert_eq!(writer.finish().unwrap(), 4);
+        assert_eq!(bits_of(&output, 4), bits_of(&source, 0)[..100]);
+        assert_eq!(reader.read_bits(4).unwrap(), Some((source[12] & 0xF) as u32));
+    }
+
+    #[test]
+    fn test_bit_reader_leaves_the_rest_of_the_reader() {
+        // Two bytes of bits followed by something else, like the next stream
+        let mut input = Cursor::new(vec![0b1111_0000, 0b1010_0000, b'n', b'e', b'x', b't']);
+        let mut reader = BitReader::new((&mut input).take(2), 4).unwrap();
+        assert_eq!(reader.peek_bits(16).unwrap(), (0b1111_0000_1010_0000, 12));
+        reader.consume(12);
+        assert_eq!(reader.peek_bits(1).unwrap(), (0, 0));
+
+        let mut rest = String::new();
+        reader.into_inner().into_inner().read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "next");
+    }
+
+    #[test]
+    fn test_bit_io_random_codes() {
+        let mut rng = Rng(0xB175);
+        for case in 0..5000 {
+            // Mostly short codes, the wide ones less often
+            let max_length = if case % 10 == 0 { 32 } else { 8 };
+            let codes: Vec<(u32, u8)> = (0..rng.below(40))
+                .map(|_| {
+                    let length = 1 + rng.below(max_length) as u8;
+                    let bits = (rng.next() as u32) & (u32::MAX << (32 - length as u32));
+                    (bits, length)
+                })
+                .collect();
+            check_bit_symmetry(&codes);
+        }
+    }
+
+    #[test]
+    fn test_read_bits_at_the_end() {
+        let data = [0b1011_0110, 0b1100_0000];
+        let mut reader = BitReader::new(&data[..], 6).unwrap();
+        assert_eq!(reader.read_bits(0).unwrap(), Some(0));
+        assert_eq!(reader.read_bits(3).unwrap(), Some(0b101));
+        // Only 7 bits are left, the padding isn't data
+        assert_eq!(reader.read_bits(8).unwrap(), None);
+        assert_eq!(reader.read_bits(7).unwrap(), Some(0b101_1011));
+        assert_eq!(reader.read_bits(1).unwrap(), None);
+        assert_eq!(reader.read_bit().unwrap(), None);
+    }
+
+    // Codes written with write_code and single bits with write_bits, read back
+    // with any mix of read_bits and read_bit, all give the same bits
+    #[test]
+    fn test_bit_io_mixed_calls() {
+        let mut rng = Rng(0x313D);
+        for _ in 0..2000 {
+            let mut expected = Vec::new();
+            let mut output = Vec::new();
+            let mut writer = BitWriter::new(&mut output, DEFAULT_BUFFER_SIZE).unwrap();
+            for _ in 0..rng.below(30) {
+                if rng.below(3) == 0 {
+                    let bit = rng.below(2) as u32;
+                    writer.write_bits(bit << 31, 1).unwrap();
+                    expected.push(bit == 1);
+                } else {
+                    let length = 1 + rng.below(32) as u8;
+                    let code = Code { bits: (rng.next() as u32) & (u32::MAX << (32 - length as u32)), length };
+                    writer.write_code(&code).unwrap();
+                    expected.extend((0..length).map(|i| (code.bits >> (31 - i)) & 1 == 1));
+                }
+            }
+            let padding_bits = writer.finish().unwrap();
+
+            let input = BufReader::with_capacity(1 + rng.below(4), &output[..]);
+            let mut reader = BitReader::new(input, padding_bits).unwrap();
+            let mut read = Vec::new();
+            while read.len() < expected.len() {
+                if rng.below(2) == 0 {
+                    read.push(reader.read_bit().unwrap().unwrap());
+                } else {
+                    let count = rng.below(33).min(expected.len() - read.len()) as u8;
+                    let bits = reader.read_bits(count).unwrap().unwrap();
+                    read.extend((0..count).map(|i| (bits >> (count - 1 - i)) & 1 == 1));
+                }
+            }
+            assert_eq!(read, expected);
+            assert_eq!(reader.read_bits(1).unwrap(), None);
+            assert_eq!(reader.read_bit().unwrap(), None);
+        }
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_bit_writer_write_bits() {
+        let mut rng = Rng(0xB175);
+        let codes: Vec<(u32, u8)> = (0..MIB)
+            .map(|_| {
+                let length = 1 + rng.below(16) as u8;
+                ((rng.next() as u32) & (u32::MAX << (32 - length as u32)), length)
+            })
+            .collect();
+        let output_bits: usize = codes.iter().map(|&(_, length)| length as usize).sum();
+
+        let elapsed = bench(|| {
+            let mut output = Vec::with_capacity(output_bits / 8 + 1);
+            let mut writer = BitWriter::new(&mut output, DEFAULT_BUFFER_SIZE).unwrap();
+            for &(bits, length) in &codes {
+                writer.write_bits(bits, length).unwrap();
+            }
+            writer.finish().unwrap();
+            std::hint::black_box(output);
+        });
+        report_throughput("BitWriter::write_bits", output_bits / 8, elapsed);
+    }
+}
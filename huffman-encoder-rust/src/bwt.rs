@@ -0,0 +1,225 @@
+// The Burrows-Wheeler transform and move-to-front, for `encode --bwt`, like
+// bzip2 does before its codes. The transform sorts the suffixes of a block and
+// takes the byte before each one, which groups the bytes that come before the
+// same context: text turns into long stretches of a few bytes. Move-to-front
+// then turns those into small numbers, mostly zeros, which get short codes.
+//
+// The suffixes are sorted as if the block ended in a byte smaller than all
+// others. Its place in the output, the primary index, isn't a byte of the
+// output, it goes in the header, so the transform keeps the length.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::mem;
+
+use crate::error::{HuffmanError, HuffmanResult};
+
+// The most a block can hold, the suffixes are u32
+pub(crate) const MAX_BLOCK_SIZE: u64 = u32::MAX as u64;
+
+// The start of every suffix of `data`, in sorted order. Prefix doubling: the
+// suffixes are sorted by their first k bytes, then by the first 2k from the
+// ranks of both halves, with two counting sorts, until every rank is distinct.
+fn suffix_array(data: &[u8]) -> Vec<u32> {
+    let n = data.len();
+    let mut counts = vec![0u32; n.max(256) + 2];
+    // Ranks from 1, 0 is past the end, which sorts first
+    let mut rank: Vec<u32> = data.iter().map(|&byte| byte as u32 + 1).collect();
+    let mut suffixes = vec![0u32; n];
+    let mut by_second = Vec::with_capacity(n);
+    let mut next_rank = vec![0u32; n];
+    let mut classes = 257;
+    for &byte in data {
+        counts[byte as usize + 1] += 1;
+    }
+    sort_by_rank(&(0..n as u32).collect::<Vec<u32>>(), &rank, &mut counts[..classes], &mut suffixes);
+
+    let mut k = 1;
+    while k < n {
+        // By the rank of the second half: the suffixes that end in it first,
+        // then the others in the order of their second halves
+        by_second.clear();
+        by_second.extend((n - k..n).map(|i| i as u32));
+        by_second.extend(suffixes.iter().filter(|&&i| i as usize >= k).map(|&i| i - k as u32));
+        counts[..classes].fill(0);
+        for &r in &rank {
+            counts[r as usize] += 1;
+        }
+        sort_by_rank(&by_second, &rank, &mut counts[..classes], &mut suffixes);
+
+        let second = |i: u32| rank.get(i as usize + k).copied().unwrap_or(0);
+        let mut current = 1;
+        next_rank[suffixes[0] as usize] = current;
+        for pair in suffixes.windows(2) {
+            if rank[pair[0] as usize] != rank[pair[1] as usize] || second(pair[0]) != second(pair[1]) {
+                current += 1;
+            }
+            next_rank[pair[1] as usize] = current;
+        }
+        mem::swap(&mut rank, &mut next_rank);
+        if current as usize == n {
+            break;
+        }
+        classes = current as usize + 1;
+        k *= 2;
+    }
+    suffixes
+}
+
+// Stable counting sort of `order` by `rank` into `sorted`, `counts` has the
+// number of every rank
+fn sort_by_rank(order: &[u32], rank: &[u32], counts: &mut [u32], sorted: &mut [u32]) {
+    let mut start = 0;
+    for count in counts.iter_mut() {
+        (*count, start) = (start, start + *count);
+    }
+    for &i in order {
+        let slot = &mut counts[rank[i as usize] as usize];
+        sorted[*slot as usize] = i;
+        *slot += 1;
+    }
+}
+
+// The transform of `data` and its primary index, the row of the end of the
+// block: 1 to the length, 0 for an empty block
+pub(crate) fn burrows_wheeler_transform(data: &[u8]) -> (Vec<u8>, u32) {
+    let Some(&last) = data.last() else {
+        return (Vec::new(), 0);
+    };
+    // The suffix that is only the end comes first, the last byte is before it
+    let mut transformed = Vec::with_capacity(data.len());
+    transformed.push(last);
+    let mut primary_index = 0;
+    for (row, &start) in suffix_array(data).iter().enumerate() {
+        match start {
+            0 => primary_index = row as u32 + 1,
+            _ => transformed.push(data[start as usize - 1]),
+        }
+    }
+    (transformed, primary_index)
+}
+
+// The inverse of burrows_wheeler_transform. Each row leads to the row of the
+// suffix one byte longer, which the counts of the bytes tell; following them
+// from the first row gives the block back to front, ending at the primary
+// index. Damaged data can lead there early, or never.
+pub(crate) fn inverse_burrows_wheeler_transform(transformed: &[u8], primary_index: u32) -> HuffmanResult<Vec<u8>> {
+    let n = transformed.len();
+    let primary_index = primary_index as usize;
+    if n == 0 {
+        return Ok(Vec::new());
+    }
+    if !(1..=n).contains(&primary_index) {
+        return Err(HuffmanError::CorruptData(format!("primary index {} of a block of {} bytes", primary_index, n)));
+    }
+    // The byte of every row, the row of the end has none
+    let byte_at = |row: usize| transformed[row - (row > primary_index) as usize];
+
+    // The rows that start with a byte start after the end and all smaller bytes
+    let mut starts = [0usize; 256];
+    for &byte in transformed {
+        starts[byte as usize] += 1;
+    }
+    let mut start = 1;
+    for count in starts.iter_mut() {
+        (*count, start) = (start, start + *count);
+    }
+    let mut next = vec![0u32; n + 1];
+    for row in (0..=n).filter(|&row| row != primary_index) {
+        let slot = &mut starts[byte_at(row) as usize];
+        next[row] = *slot as u32;
+        *slot += 1;
+    }
+
+    let mut data = vec![0u8; n];
+    let mut row = 0;
+    for i in (0..n).rev() {
+        if row == primary_index {
+            return Err(HuffmanError::CorruptData(format!("the transformed block ends after {} of {} bytes", n - 1 - i, n)));
+        }
+        data[i] = byte_at(row);
+        row = next[row] as usize;
+    }
+    if row != primary_index {
+        return Err(HuffmanError::CorruptData(String::from("the transformed block doesn't end at its primary index")));
+    }
+    Ok(data)
+}
+
+// Replaces every byte with its place in a list of all bytes, most recent first
+pub(crate) fn move_to_front(data: &mut [u8]) {
+    let mut recent: [u8; 256] = core::array::from_fn(|i| i as u8);
+    for byte in data.iter_mut() {
+        let position = recent.iter().position(|&b| b == *byte).unwrap_or(0);
+        recent.copy_within(..position, 1);
+        recent[0] = *byte;
+        *byte = position as u8;
+    }
+}
+
+pub(crate) fn undo_move_to_front(data: &mut [u8]) {
+    let mut recent: [u8; 256] = core::array::from_fn(|i| i as u8);
+    for byte in data.iter_mut() {
+        let position = *byte as usize;
+        let value = recent[position];
+        recent.copy_within(..position, 1);
+        recent[0] = value;
+        *byte = value;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{random_data, text_data, Rng};
+
+    #[test]
+    fn test_suffix_array_sorts_the_suffixes() {
+        assert_eq!(suffix_array(b"banana"), [5, 3, 1, 0, 4, 2]);
+        let mut rng = Rng(0xB37);
+        for data in [text_data(&mut rng, 2000), random_data(&mut rng, 2000), vec![7; 1000], b"ab".repeat(500)] {
+            let suffixes = suffix_array(&data);
+            assert!(suffixes.windows(2).all(|pair| data[pair[0] as usize..] < data[pair[1] as usize..]));
+        }
+    }
+
+    #[test]
+    fn test_transform_of_banana() {
+        assert_eq!(burrows_wheeler_transform(b"banana"), (b"annbaa".to_vec(), 4));
+        assert_eq!(inverse_burrows_wheeler_transform(b"annbaa", 4).unwrap(), b"banana");
+        // Another index leads back too early or not at all
+        assert!(inverse_burrows_wheeler_transform(b"annbaa", 2).is_err());
+        assert!(inverse_burrows_wheeler_transform(b"annbaa", 7).is_err());
+    }
+
+    #[test]
+    fn test_transforms_roundtrip() {
+        let mut rng = Rng(0xB38);
+        let inputs = [
+            Vec::new(),
+            b"a".to_vec(),
+            b"abracadabra".to_vec(),
+            (0..=255).collect(),
+            (0..=255).rev().cycle().take(5000).collect(),
+            vec![0; 10_000],
+            text_data(&mut rng, 50_000),
+            random_data(&mut rng, 50_000),
+        ];
+        for data in inputs {
+            let (mut transformed, primary_index) = burrows_wheeler_transform(&data);
+            assert_eq!(transformed.len(), data.len());
+            move_to_front(&mut transformed);
+            undo_move_to_front(&mut transformed);
+            assert!(inverse_burrows_wheeler_transform(&transformed, primary_index).unwrap() == data);
+        }
+    }
+
+    #[test]
+    fn test_move_to_front() {
+        let mut data = b"aaabbba".to_vec();
+        move_to_front(&mut data);
+        assert_eq!(data, [97, 0, 0, 98, 0, 0, 1]);
+    }
+}
@@ -0,0 +1,131 @@
+// Stopping an encode or a decode from another thread, like the Stop button of
+// a GUI or a request that went away:
+//
+//     let cancel = CancelToken::new();
+//     let options = EncoderOptions::builder().cancel(cancel.clone()).build()?;
+//     // On another thread
+//     cancel.cancel();
+
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::error::{HuffmanError, HuffmanResult};
+
+// Shared by its clones, cancelling one cancels all of them. The operations
+// check it where they report progress, every PROGRESS_STEP of input, and fail
+// with HuffmanError::Cancelled. A token stays cancelled.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn check(&self) -> HuffmanResult<()> {
+        match self.is_cancelled() {
+            true => Err(HuffmanError::Cancelled),
+            false => Ok(()),
+        }
+    }
+}
+
+// Tokens are equal when they are clones of each other
+impl PartialEq for CancelToken {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{decode_bytes_with, encode_bytes_with};
+    use crate::options::{DecoderOptions, EncoderOptions};
+    use crate::progress::ProgressSink;
+    use crate::test_util::{text_data, Rng, MIB};
+    use std::sync::mpsc::{channel, Receiver, Sender};
+    use std::sync::Mutex;
+    use std::thread;
+
+    // Once past `at`, asks another thread to cancel and waits until it did,
+    // so the operation is in the middle of its input when it is cancelled
+    struct CancelFromThread {
+        at: u64,
+        ask: Mutex<Sender<()>>,
+        done: Mutex<Receiver<()>>,
+    }
+
+    impl ProgressSink for CancelFromThread {
+        fn on_bytes(&self, processed: u64, _total: Option<u64>) {
+            if processed >= self.at && self.ask.lock().unwrap().send(()).is_ok() {
+                self.done.lock().unwrap().recv().unwrap();
+            }
+        }
+    }
+
+    // The sink and the thread holding `token`, which ends once it cancelled
+    fn cancel_from_thread(token: &CancelToken, at: u64) -> (Arc<CancelFromThread>, thread::JoinHandle<()>) {
+        let (ask, asked) = channel();
+        let (done_sender, done) = channel();
+        let token = token.clone();
+        let handle = thread::spawn(move || {
+            asked.recv().unwrap();
+            token.cancel();
+            done_sender.send(()).unwrap();
+        });
+        (Arc::new(CancelFromThread { at, ask: Mutex::new(ask), done: Mutex::new(done) }), handle)
+    }
+
+    #[test]
+    fn test_cancel_from_another_thread() {
+        let data = text_data(&mut Rng(0xCA9C), 8 * MIB);
+        let encoded = encode_bytes_with(&data, &EncoderOptions::default()).unwrap();
+
+        let token = CancelToken::new();
+        let (sink, handle) = cancel_from_thread(&token, 2 * MIB as u64);
+        let options = EncoderOptions::builder().progress(sink).cancel(token.clone()).build().unwrap();
+        assert!(matches!(encode_bytes_with(&data, &options), Err(HuffmanError::Cancelled)));
+        handle.join().unwrap();
+        assert!(token.is_cancelled());
+
+        let token = CancelToken::new();
+        let (sink, handle) = cancel_from_thread(&token, 2 * MIB as u64);
+        let options = DecoderOptions::builder().progress(sink).cancel(token).build().unwrap();
+        assert!(matches!(decode_bytes_with(&encoded, &options), Err(HuffmanError::Cancelled)));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_cancelled_before_the_start() {
+        let token = CancelToken::new();
+        token.cancel();
+        let options = EncoderOptions::builder().cancel(token.clone()).build().unwrap();
+        assert!(matches!(encode_bytes_with(b"", &options), Err(HuffmanError::Cancelled)));
+        let encoded = encode_bytes_with(b"abracadabra", &EncoderOptions::default()).unwrap();
+        let options = DecoderOptions::builder().cancel(token).build().unwrap();
+        assert!(matches!(decode_bytes_with(&encoded, &options), Err(HuffmanError::Cancelled)));
+
+        // Without a token, or with one nobody cancelled, nothing changes
+        let options = DecoderOptions::builder().cancel(CancelToken::new()).build().unwrap();
+        assert_eq!(decode_bytes_with(&encoded, &options).unwrap(), b"abracadabra");
+    }
+
+    #[test]
+    fn test_tokens_are_equal_to_their_clones() {
+        let token = CancelToken::new();
+        assert_eq!(token.clone(), token);
+        assert_ne!(CancelToken::new(), token);
+        let options = EncoderOptions::builder().cancel(token.clone()).build().unwrap();
+        assert_eq!(options, EncoderOptions::builder().cancel(token).build().unwrap());
+        assert_ne!(options, EncoderOptions::default());
+    }
+}
@@ -0,0 +1,513 @@
+// The checksums stored in the header. A stream names the algorithm of its
+// checksum with a ChecksumKind, and every algorithm is a Checksum, so another
+// one only needs an id and an implementation.
+
+use alloc::boxed::Box;
+
+// A checksum of data fed to it in pieces, the same however it is split
+pub trait Checksum: Send {
+    fn update(&mut self, data: &[u8]);
+
+    // The checksum of everything so far, in the 32 bits of the header
+    fn finish(&self) -> u32;
+}
+
+// The algorithm of the checksum of a stream, like --checksum
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChecksumKind {
+    #[default]
+    Crc32,
+    // The lower 32 bits of XXH3-64, faster than CRC-32 on large inputs
+    Xxh3,
+    // Nothing is computed, the field is 0 and decoding doesn't verify it
+    None,
+}
+
+impl ChecksumKind {
+    pub const ALL: [ChecksumKind; 3] = [ChecksumKind::Crc32, ChecksumKind::Xxh3, ChecksumKind::None];
+
+    // Its id in the header
+    pub fn id(self) -> u8 {
+        match self {
+            ChecksumKind::Crc32 => 0,
+            ChecksumKind::Xxh3 => 1,
+            ChecksumKind::None => 2,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        ChecksumKind::ALL.into_iter().find(|kind| kind.id() == id)
+    }
+
+    // For --checksum and the messages, "crc32", "xxh3" or "none"
+    pub fn name(self) -> &'static str {
+        match self {
+            ChecksumKind::Crc32 => "crc32",
+            ChecksumKind::Xxh3 => "xxh3",
+            ChecksumKind::None => "none",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        ChecksumKind::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+
+    // A checksum of nothing yet
+    pub fn start(self) -> Box<dyn Checksum> {
+        match self {
+            ChecksumKind::Crc32 => Box::new(Crc32::new()),
+            ChecksumKind::Xxh3 => Box::new(Xxh3::new()),
+            ChecksumKind::None => Box::new(NoChecksum),
+        }
+    }
+
+    // The checksum decoding computes, None when it doesn't verify one
+    pub(crate) fn verifier(self, verify_checksum: bool) -> Option<Box<dyn Checksum>> {
+        (verify_checksum && self != ChecksumKind::None).then(|| self.start())
+    }
+}
+
+// The checksum of --checksum none, always 0
+struct NoChecksum;
+
+impl Checksum for NoChecksum {
+    fn update(&mut self, _data: &[u8]) {}
+
+    fn finish(&self) -> u32 {
+        0
+    }
+}
+
+// CRC-32 as used by zlib and PNG (reflected, polynomial 0xEDB88320)
+pub struct Crc32 {
+    value: u32,
+}
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut value = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            value = if value & 1 == 1 { (value >> 1) ^ 0xEDB8_8320 } else { value >> 1 };
+            bit += 1;
+        }
+        table[i] = value;
+        i += 1;
+    }
+    table
+};
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Crc32 { value: 0xFFFF_FFFF }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.value = CRC32_TABLE[((self.value ^ byte as u32) & 0xFF) as usize] ^ (self.value >> 8);
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.value
+    }
+
+    // The checksum of two pieces of data one after the other, from the
+    // checksums of each and the length of the second, without the data. As in
+    // zlib's crc32_combine: appending `second_length` zero bytes to the first
+    // is a linear map on its CRC, applied by squaring the map for one zero bit.
+    pub fn combine(first: u32, second: u32, second_length: u64) -> u32 {
+        if second_length == 0 {
+            return first;
+        }
+        // The map for one zero bit, then for two and for four
+        let mut odd = [0u32; 32];
+        odd[0] = 0xEDB8_8320;
+        for (n, row) in odd.iter_mut().enumerate().skip(1) {
+            *row = 1 << (n - 1);
+        }
+        let mut even = [0u32; 32];
+        gf2_square(&mut even, &odd);
+        gf2_square(&mut odd, &even);
+
+        // The first squaring is the map for a zero byte, the length is a
+        // number of bytes
+        let mut crc = first;
+        let mut length = second_length;
+        loop {
+            gf2_square(&mut even, &odd);
+            if length & 1 == 1 {
+                crc = gf2_times(&even, crc);
+            }
+            length >>= 1;
+            if length == 0 {
+                break;
+            }
+            gf2_square(&mut odd, &even);
+            if length & 1 == 1 {
+                crc = gf2_times(&odd, crc);
+            }
+            length >>= 1;
+            if length == 0 {
+                break;
+            }
+        }
+        crc ^ second
+    }
+}
+
+// A 32x32 matrix over GF(2) times a vector, `matrix[i]` is what bit i maps to
+fn gf2_times(matrix: &[u32; 32], mut vector: u32) -> u32 {
+    let mut sum = 0;
+    let mut row = 0;
+    while vector != 0 {
+        if vector & 1 == 1 {
+            sum ^= matrix[row];
+        }
+        vector >>= 1;
+        row += 1;
+    }
+    sum
+}
+
+fn gf2_square(square: &mut [u32; 32], matrix: &[u32; 32]) {
+    for (row, &column) in square.iter_mut().zip(matrix) {
+        *row = gf2_times(matrix, column);
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Crc32::new()
+    }
+}
+
+impl Checksum for Crc32 {
+    fn update(&mut self, data: &[u8]) {
+        Crc32::update(self, data)
+    }
+
+    fn finish(&self) -> u32 {
+        Crc32::finish(self)
+    }
+}
+
+// XXH3-64 with the default secret and seed 0, the same as XXH3_64bits of
+// xxHash 0.8. Inputs up to 240 bytes are hashed as a whole, so they are kept;
+// longer ones go through 8 accumulators a stripe of 64 bytes at a time. The
+// last stripe, which is the last 64 bytes even when they overlap the one
+// before, is hashed differently, so a full stripe waits until more data comes.
+pub struct Xxh3 {
+    acc: [u64; 8],
+    length: u64,
+    // All of the input while it is short
+    head: [u8; MIDSIZE_MAX],
+    // The stripes consumed so far and the last of them
+    stripes: u64,
+    last: [u8; STRIPE_LEN],
+    pending: [u8; STRIPE_LEN],
+    pending_len: usize,
+}
+
+const PRIME32_1: u64 = 0x9E37_79B1;
+const PRIME32_2: u64 = 0x85EB_CA77;
+const PRIME32_3: u64 = 0xC2B2_AE3D;
+const PRIME64_1: u64 = 0x9E37_79B1_85EB_CA87;
+const PRIME64_2: u64 = 0xC2B2_AE3D_27D4_EB4F;
+const PRIME64_3: u64 = 0x1656_67B1_9E37_79F9;
+const PRIME64_4: u64 = 0x85EB_CA77_C2B2_AE63;
+const PRIME64_5: u64 = 0x27D4_EB2F_1656_67C5;
+const PRIME_MX1: u64 = 0x1656_6791_9E37_79F9;
+const PRIME_MX2: u64 = 0x9FB2_1C65_1E98_DF25;
+
+const STRIPE_LEN: usize = 64;
+const MIDSIZE_MAX: usize = 240;
+// The secret moves on this much for every stripe of a block
+const SECRET_CONSUME_RATE: usize = 8;
+const STRIPES_PER_BLOCK: u64 = ((SECRET.len() - STRIPE_LEN) / SECRET_CONSUME_RATE) as u64;
+const SECRET_LAST_ACC_START: usize = 7;
+const SECRET_MERGE_ACCS_START: usize = 11;
+const SECRET_MIDSIZE_START: usize = 3;
+const SECRET_MIDSIZE_LAST: usize = 136 - 17;
+
+const SECRET: [u8; 192] = [
+    0xb8, 0xfe, 0x6c, 0x39, 0x23, 0xa4, 0x4b, 0xbe, 0x7c, 0x01, 0x81, 0x2c, 0xf7, 0x21, 0xad, 0x1c,
+    0xde, 0xd4, 0x6d, 0xe9, 0x83, 0x90, 0x97, 0xdb, 0x72, 0x40, 0xa4, 0xa4, 0xb7, 0xb3, 0x67, 0x1f,
+    0xcb, 0x79, 0xe6, 0x4e, 0xcc, 0xc0, 0xe5, 0x78, 0x82, 0x5a, 0xd0, 0x7d, 0xcc, 0xff, 0x72, 0x21,
+    0xb8, 0x08, 0x46, 0x74, 0xf7, 0x43, 0x24, 0x8e, 0xe0, 0x35, 0x90, 0xe6, 0x81, 0x3a, 0x26, 0x4c,
+    0x3c, 0x28, 0x52, 0xbb, 0x91, 0xc3, 0x00, 0xcb, 0x88, 0xd0, 0x65, 0x8b, 0x1b, 0x53, 0x2e, 0xa3,
+    0x71, 0x64, 0x48, 0x97, 0xa2, 0x0d, 0xf9, 0x4e, 0x38, 0x19, 0xef, 0x46, 0xa9, 0xde, 0xac, 0xd8,
+    0xa8, 0xfa, 0x76, 0x3f, 0xe3, 0x9c, 0x34, 0x3f, 0xf9, 0xdc, 0xbb, 0xc7, 0xc7, 0x0b, 0x4f, 0x1d,
+    0x8a, 0x51, 0xe0, 0x4b, 0xcd, 0xb4, 0x59, 0x31, 0xc8, 0x9f, 0x7e, 0xc9, 0xd9, 0x78, 0x73, 0x64,
+    0xea, 0xc5, 0xac, 0x83, 0x34, 0xd3, 0xeb, 0xc3, 0xc5, 0x81, 0xa0, 0xff, 0xfa, 0x13, 0x63, 0xeb,
+    0x17, 0x0d, 0xdd, 0x51, 0xb7, 0xf0, 0xda, 0x49, 0xd3, 0x16, 0x55, 0x26, 0x29, 0xd4, 0x68, 0x9e,
+    0x2b, 0x16, 0xbe, 0x58, 0x7d, 0x47, 0xa1, 0xfc, 0x8f, 0xf8, 0xb8, 0xd1, 0x7a, 0xd0, 0x31, 0xce,
+    0x45, 0xcb, 0x3a, 0x8f, 0x95, 0x16, 0x04, 0x28, 0xaf, 0xd7, 0xfb, 0xca, 0xbb, 0x4b, 0x40, 0x7e,
+];
+
+impl Xxh3 {
+    pub fn new() -> Self {
+        Xxh3 {
+            acc: [PRIME32_3, PRIME64_1, PRIME64_2, PRIME64_3, PRIME64_4, PRIME32_2, PRIME64_5, PRIME32_1],
+            length: 0,
+            head: [0; MIDSIZE_MAX],
+            stripes: 0,
+            last: [0; STRIPE_LEN],
+            pending: [0; STRIPE_LEN],
+            pending_len: 0,
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        if self.length <= MIDSIZE_MAX as u64 {
+            let start = self.length as usize;
+            let fits = data.len().min(MIDSIZE_MAX - start);
+            self.head[start..start + fits].copy_from_slice(&data[..fits]);
+            self.length += fits as u64;
+            data = &data[fits..];
+            if data.is_empty() {
+                return;
+            }
+            // Too long for the short hashes from here on
+            let head = self.head;
+            self.stream(&head);
+        }
+        self.length += data.len() as u64;
+        self.stream(data);
+    }
+
+    // The whole 64 bits
+    pub fn finish_64(&self) -> u64 {
+        if self.length <= MIDSIZE_MAX as u64 {
+            return hash_short(&self.head[..self.length as usize]);
+        }
+        let mut acc = self.acc;
+        let mut stripe = [0u8; STRIPE_LEN];
+        let pending = self.pending_len;
+        stripe[..STRIPE_LEN - pending].copy_from_slice(&self.last[pending..]);
+        stripe[STRIPE_LEN - pending..].copy_from_slice(&self.pending[..pending]);
+        accumulate(&mut acc, &stripe, &SECRET[SECRET.len() - STRIPE_LEN - SECRET_LAST_ACC_START..]);
+        merge_accs(&acc, &SECRET[SECRET_MERGE_ACCS_START..], self.length.wrapping_mul(PRIME64_1))
+    }
+
+    fn stream(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            if self.pending_len == STRIPE_LEN {
+                let stripe = self.pending;
+                self.consume(&stripe);
+                self.pending_len = 0;
+            }
+            if self.pending_len == 0 {
+                while data.len() > STRIPE_LEN {
+                    let (stripe, rest) = data.split_at(STRIPE_LEN);
+                    self.consume(stripe.try_into().expect("a stripe"));
+                    data = rest;
+                }
+            }
+            let take = (STRIPE_LEN - self.pending_len).min(data.len());
+            self.pending[self.pending_len..self.pending_len + take].copy_from_slice(&data[..take]);
+            self.pending_len += take;
+            data = &data[take..];
+        }
+    }
+
+    // A stripe that isn't the last one, the accumulators are scrambled after
+    // every block of them
+    fn consume(&mut self, stripe: &[u8; STRIPE_LEN]) {
+        let in_block = (self.stripes % STRIPES_PER_BLOCK) as usize;
+        accumulate(&mut self.acc, stripe, &SECRET[in_block * SECRET_CONSUME_RATE..]);
+        self.stripes += 1;
+        if self.stripes.is_multiple_of(STRIPES_PER_BLOCK) {
+            scramble(&mut self.acc, &SECRET[SECRET.len() - STRIPE_LEN..]);
+        }
+        self.last = *stripe;
+    }
+}
+
+impl Default for Xxh3 {
+    fn default() -> Self {
+        Xxh3::new()
+    }
+}
+
+impl Checksum for Xxh3 {
+    fn update(&mut self, data: &[u8]) {
+        Xxh3::update(self, data)
+    }
+
+    fn finish(&self) -> u32 {
+        self.finish_64() as u32
+    }
+}
+
+fn read_32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes(bytes[..4].try_into().expect("4 bytes"))
+}
+
+fn read_64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes[..8].try_into().expect("8 bytes"))
+}
+
+fn fold_64(a: u64, b: u64) -> u64 {
+    let product = a as u128 * b as u128;
+    product as u64 ^ (product >> 64) as u64
+}
+
+fn avalanche(mut h: u64) -> u64 {
+    h ^= h >> 37;
+    h = h.wrapping_mul(PRIME_MX1);
+    h ^ (h >> 32)
+}
+
+fn xxh64_avalanche(mut h: u64) -> u64 {
+    h ^= h >> 33;
+    h = h.wrapping_mul(PRIME64_2);
+    h ^= h >> 29;
+    h = h.wrapping_mul(PRIME64_3);
+    h ^ (h >> 32)
+}
+
+fn mix_16(input: &[u8], secret: &[u8]) -> u64 {
+    fold_64(read_64(input) ^ read_64(secret), read_64(&input[8..]) ^ read_64(&secret[8..]))
+}
+
+// The hashes of inputs up to 240 bytes, one for every range of lengths
+fn hash_short(input: &[u8]) -> u64 {
+    let n = input.len();
+    let length = n as u64;
+    match n {
+        0 => xxh64_avalanche(read_64(&SECRET[56..]) ^ read_64(&SECRET[64..])),
+        1..=3 => {
+            let combined = (input[0] as u32) << 16 | (input[n / 2] as u32) << 24 | input[n - 1] as u32 | (n as u32) << 8;
+            xxh64_avalanche(combined as u64 ^ (read_32(&SECRET) ^ read_32(&SECRET[4..])) as u64)
+        }
+        4..=8 => {
+            let keyed = (read_32(&input[n - 4..]) as u64 | (read_32(input) as u64) << 32) ^ (read_64(&SECRET[8..]) ^ read_64(&SECRET[16..]));
+            let mut h = keyed ^ keyed.rotate_left(49) ^ keyed.rotate_left(24);
+            h = h.wrapping_mul(PRIME_MX2);
+            h ^= (h >> 35).wrapping_add(length);
+            h = h.wrapping_mul(PRIME_MX2);
+            h ^ (h >> 28)
+        }
+        9..=16 => {
+            let low = read_64(input) ^ read_64(&SECRET[24..]) ^ read_64(&SECRET[32..]);
+            let high = read_64(&input[n - 8..]) ^ read_64(&SECRET[40..]) ^ read_64(&SECRET[48..]);
+            avalanche(length.wrapping_add(low.swap_bytes()).wrapping_add(high).wrapping_add(fold_64(low, high)))
+        }
+        // 16 bytes from the front and 16 from the back for every 32
+        17..=128 => {
+            let mut acc = length.wrapping_mul(PRIME64_1);
+            for i in 0..=(n - 1) / 32 {
+                acc = acc.wrapping_add(mix_16(&input[16 * i..], &SECRET[32 * i..]));
+                acc = acc.wrapping_add(mix_16(&input[n - 16 * (i + 1)..], &SECRET[32 * i + 16..]));
+            }
+            avalanche(acc)
+        }
+        _ => {
+            let mut acc = length.wrapping_mul(PRIME64_1);
+            for i in 0..8 {
+                acc = acc.wrapping_add(mix_16(&input[16 * i..], &SECRET[16 * i..]));
+            }
+            acc = avalanche(acc);
+            for i in 8..n / 16 {
+                acc = acc.wrapping_add(mix_16(&input[16 * i..], &SECRET[16 * (i - 8) + SECRET_MIDSIZE_START..]));
+            }
+            avalanche(acc.wrapping_add(mix_16(&input[n - 16..], &SECRET[SECRET_MIDSIZE_LAST..])))
+        }
+    }
+}
+
+fn accumulate(acc: &mut [u64; 8], stripe: &[u8], secret: &[u8]) {
+    for i in 0..8 {
+        let data = read_64(&stripe[8 * i..]);
+        let keyed = data ^ read_64(&secret[8 * i..]);
+        acc[i ^ 1] = acc[i ^ 1].wrapping_add(data);
+        acc[i] = acc[i].wrapping_add((keyed & 0xFFFF_FFFF) * (keyed >> 32));
+    }
+}
+
+fn scramble(acc: &mut [u64; 8], secret: &[u8]) {
+    for (i, acc) in acc.iter_mut().enumerate() {
+        *acc ^= *acc >> 47;
+        *acc ^= read_64(&secret[8 * i..]);
+        *acc = acc.wrapping_mul(PRIME32_1);
+    }
+}
+
+fn merge_accs(acc: &[u64; 8], secret: &[u8], start: u64) -> u64 {
+    let mut result = start;
+    for i in 0..4 {
+        result = result.wrapping_add(fold_64(acc[2 * i] ^ read_64(&secret[16 * i..]), acc[2 * i + 1] ^ read_64(&secret[16 * i + 8..])));
+    }
+    avalanche(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn crc32(data: &[u8]) -> u32 {
+        let mut crc = Crc32::new();
+        crc.update(data);
+        crc.finish()
+    }
+
+    #[test]
+    fn test_combine_is_the_checksum_of_both() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        let data: Vec<u8> = (0..3000u32).map(|i| (i * 7 % 251) as u8).collect();
+        for split in [0, 1, 2, 3, 8, 1000, 2999, 3000] {
+            let (first, second) = data.split_at(split);
+            let combined = Crc32::combine(crc32(first), crc32(second), second.len() as u64);
+            assert_eq!(combined, crc32(&data), "split at {}", split);
+        }
+    }
+
+    #[test]
+    fn test_xxh3_matches_xxhash() {
+        // XXH3_64bits of xxHash 0.8.1, across the lengths of every hash and
+        // the blocks of the long one
+        let data: Vec<u8> = (0..5000u32).map(|i| (i * 7 % 251) as u8).collect();
+        for (length, expected) in [
+            (0, 0x2d06_8005_38d3_94c2), (1, 0xc44b_dff4_074e_ecdb), (3, 0xc348_9259_e968_ad9e),
+            (4, 0xd3d6_0c15_1901_4e89), (8, 0xb88d_ee77_f6bf_6980), (9, 0x0368_8dca_d730_d826),
+            (16, 0x9da2_3836_adf2_be1e), (17, 0xf34c_3c9c_f5a1_12d1), (32, 0x99cb_9ad0_f1a1_1fbe),
+            (33, 0xc077_b454_92d2_9cde), (100, 0x1023_ae92_e631_eac5), (128, 0x4c65_9b74_5f43_5148),
+            (129, 0xb852_22b8_3902_b6e5), (200, 0xd120_16b5_3c95_65ba), (240, 0xd6df_bf67_b067_5b54),
+            (241, 0xc614_c8c3_5753_48c1), (256, 0xf418_6dc1_63a3_283c), (1024, 0xf22e_f3dc_84ff_47ea),
+            (1025, 0x43f8_69a8_3769_a435), (1088, 0x40d7_1f03_7cd0_c530), (2047, 0xec5a_2387_2cc1_3f94),
+            (5000, 0xce29_a194_60ee_1bfc),
+        ] {
+            let mut xxh3 = Xxh3::new();
+            xxh3.update(&data[..length]);
+            assert_eq!(xxh3.finish_64(), expected, "{} bytes", length);
+            // However the data comes in
+            for piece in [1, 7, 64, 65, 239, 1000] {
+                let mut xxh3 = Xxh3::new();
+                data[..length].chunks(piece).for_each(|chunk| xxh3.update(chunk));
+                assert_eq!(xxh3.finish_64(), expected, "{} bytes in pieces of {}", length, piece);
+            }
+        }
+    }
+
+    #[test]
+    fn test_checksum_kinds() {
+        for kind in ChecksumKind::ALL {
+            assert_eq!(ChecksumKind::from_id(kind.id()), Some(kind));
+            assert_eq!(ChecksumKind::from_name(kind.name()), Some(kind));
+        }
+        assert_eq!(ChecksumKind::from_id(3), None);
+        assert_eq!(ChecksumKind::from_name("md5"), None);
+        let finish = |kind: ChecksumKind| {
+            let mut checksum = kind.start();
+            checksum.update(b"123456789");
+            checksum.finish()
+        };
+        assert_eq!(finish(ChecksumKind::Crc32), 0xCBF4_3926);
+        assert_eq!(finish(ChecksumKind::None), 0);
+        assert!(ChecksumKind::None.verifier(true).is_none());
+        assert!(ChecksumKind::Xxh3.verifier(false).is_none());
+    }
+}
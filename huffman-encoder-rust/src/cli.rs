@@ -0,0 +1,3036 @@
+// The commands of the binary and their options
+
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Cursor, Error, IsTerminal, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Once, OnceLock, PoisonError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use log::{debug, info, log_enabled, warn, Level, LevelFilter, Log, Metadata, Record};
+
+use crate::cancel::CancelToken;
+use crate::checksum::{ChecksumKind, Crc32, Xxh3};
+use crate::config::{load_settings, Setting, Value};
+use crate::codec::{decode_single_stream, decode_stream, encode_block_with_progress, encode_stream, merge_streams, next_header, upgrade_stream, ReadSeek, DEFAULT_BUFFER_SIZE};
+use crate::error::{HuffmanError, HuffmanResult, IoContext};
+use crate::freq_cache::{freq_cache_filename, freq_cache_key, load_freq_cache, store_freq_cache, CacheKey};
+use crate::frequency::{calculate_frequencies_parallel, FrequencyTable, calculate_frequencies_with_progress, PARALLEL_RANGE_SIZE};
+use crate::header::{decode_header, read_u32, FileInfo, Header, MAGIC, VERSION};
+use crate::hints::{advise, open_sequential, Advice};
+use crate::output::{ensure_distinct_output, preallocate, sibling_filename, sync_parent, OutputFile};
+use crate::estimate::{entropy_bits_per_byte, estimate_encoded_size, estimate_range_size};
+use crate::options::{DecoderOptions, EncoderOptions, Filter, Method, MAX_BUFFER_SIZE, MIN_BUFFER_SIZE};
+use crate::pipeline::{PipelinedReader, PIPELINE_BUFFER_SIZE, PIPELINE_THRESHOLD};
+use crate::progress::{NoProgress, Phase, Progress, ProgressSink, Report, SharedSink};
+use crate::report::{ChecksumStatus, DecodeReport, EncodeReport, Stopwatch};
+use crate::style::Style;
+use crate::throttle::{Limited, RateLimiter};
+pub use crate::prompt::{Overwrite, Prompt, TerminalPrompt};
+pub use crate::style::init_colors;
+use crate::table::{escaped_codes, EncodingTable};
+use crate::template::{normalized, today, InputFacts};
+pub use crate::template::OutputTemplate;
+use crate::tree::{symbol_label, HuffmanTree};
+
+// Option Parsing
+////////////////////////////////////////////////////////////////////////////////
+
+// Exit code of any other failure, like a missing input or bad options
+pub const EXIT_FAILURE: i32 = 1;
+// Exit code when --ignore-errors salvaged only part of the data
+pub const EXIT_PARTIAL: i32 = 2;
+// Exit code when the input is damaged or not an encoded file
+pub const EXIT_CORRUPT: i32 = 3;
+// Exit code when the output would be over --max-output-size
+pub const EXIT_TOO_LARGE: i32 = 4;
+// Exit code when Ctrl-C stopped the command, the one a shell gives a process
+// killed by SIGINT
+pub const EXIT_CANCELLED: i32 = 130;
+
+// Decoding refuses to write more than this unless told otherwise, so a small
+// crafted file can't fill up the disk.
+const DEFAULT_MAX_OUTPUT_SIZE: u64 = 16 * 1024 * 1024 * 1024;
+
+// Inputs up to this size are read into memory once instead of twice from disk
+const DEFAULT_MEMORY_LIMIT: u64 = 8 * 1024 * 1024;
+
+#[derive(Clone)]
+pub struct Options {
+    pub command: String,
+    pub input_filename: PathBuf,
+    pub output_filename: PathBuf,
+    // Whether output_filename is the -o of a single input
+    pub output_given: bool,
+    // Names the outputs after their inputs, see template.rs
+    pub output_template: Option<OutputTemplate>,
+    pub max_output_size: Option<u64>,
+    pub ignore_errors: bool,
+    pub fsync: bool,
+    pub threads: usize,
+    pub block_size: Option<u64>,
+    pub memory_limit: u64,
+    pub drop_cache: bool,
+    pub buffer_size: usize,
+    pub compare: bool,
+    pub freq_cache: bool,
+    // Bytes a second that encode and decode read and write at most, see
+    // throttle.rs
+    pub limit_rate: Option<u64>,
+    // What the library reports on stderr, see init_logging
+    pub log_level: LevelFilter,
+    pub progress: bool,
+    // Print the report of encode or decode on stdout, as JSON with --json
+    pub stats: bool,
+    pub json: bool,
+    // What the table command writes, see --format
+    pub table_format: TableFormat,
+    // The table encode uses for every block instead of its own
+    pub table_file: Option<PathBuf>,
+    // Encode in one pass without a table, see adaptive.rs
+    pub adaptive: bool,
+    // Run-length encode before the codes, see rle.rs
+    pub rle: bool,
+    // Bytes per symbol, 2 codes pairs of bytes
+    pub symbol_width: u8,
+    // Code words and other tokens, see tokens.rs
+    pub tokens: bool,
+    // The Burrows-Wheeler transform and move-to-front first, see bwt.rs
+    pub bwt: bool,
+    // A table for every previous byte, see context.rs
+    pub context: bool,
+    // Codes from a table or range coding, see range.rs
+    pub method: Method,
+    // A filter before the codes and its bytes per sample, see filter.rs
+    pub filter: Option<Filter>,
+    pub stride: u8,
+    // The algorithm of the checksum of encode, see checksum.rs
+    pub checksum: ChecksumKind,
+    // The name and the modification time encode keeps of its input, from
+    // --stdin-name and --stdin-mtime
+    pub file_info: FileInfo,
+    // The inputs after the first one, which merge appends to it and encode
+    // and decode work on one after the other
+    pub more_inputs: Vec<PathBuf>,
+    // Test every encoded file under a directory, see test
+    pub recursive: bool,
+    // Whether messages get colors, see style.rs
+    pub color: ColorChoice,
+    // Replace existing outputs without asking
+    pub force: bool,
+    // Remove the input of encode once it is encoded
+    pub rm: bool,
+    // Check the output of encode before anything is removed, see
+    // paranoid_encode
+    pub paranoid: bool,
+    // Hard-link the outputs of inputs encode already had, see encode_inputs
+    pub dedupe: bool,
+    // Give outputs the modification time of their input, see preserved_mtime
+    pub preserve_timestamps: bool,
+    // Asks before an existing output is replaced, see prompt.rs. None
+    // replaces it, see ask_overwrite.
+    pub overwrite: Option<Arc<Mutex<Overwrite>>>,
+    // Stops the commands with Cancelled, see cancel_on_ctrl_c
+    pub cancel: Option<CancelToken>,
+}
+
+impl Options {
+    // The options of decode_stream, parse_args already checked them
+    fn decoder_options(&self, progress: Option<Arc<dyn ProgressSink>>) -> DecoderOptions {
+        DecoderOptions {
+            max_output_size: self.max_output_size,
+            verify_checksum: true,
+            threads: self.threads,
+            buffer_size: self.buffer_size,
+            progress: SharedSink(progress),
+            cancel: self.cancel.clone(),
+        }
+    }
+
+    // The first input and the ones after it
+    pub fn inputs(&self) -> Vec<PathBuf> {
+        let mut inputs = vec![self.input_filename.clone()];
+        inputs.extend(self.more_inputs.iter().cloned());
+        inputs
+    }
+
+    // A new bar for every command with --progress, otherwise the status line
+    // of status_on_signal once its handler is there
+    fn progress_sink(&self) -> Option<Arc<dyn ProgressSink>> {
+        match self.progress {
+            true => Some(Arc::new(ProgressBar::new())),
+            false => STATUS_HANDLER.is_completed().then(|| Arc::new(StatusOnSignal::default()) as Arc<dyn ProgressSink>),
+        }
+    }
+}
+
+// --color: always, never, or only on a terminal without NO_COLOR
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+// The files of the table command: the entries of a header after their count,
+// or the text of EncodingTable::to_text()
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TableFormat {
+    Binary,
+    Text,
+}
+
+// A flag of the command line. parse_args, print_usage and the completion
+// scripts all go by FLAGS, so a flag can't be parsed without being documented
+// or completed.
+struct Flag {
+    short: Option<&'static str>,
+    long: &'static str,
+    // The name of its value in the usage, None for a flag without one. A FILE
+    // completes to files.
+    value: Option<&'static str>,
+    // The values it takes, for completion, empty when it takes any
+    choices: &'static [&'static str],
+    help: &'static str,
+}
+
+// The commands, in the order of the usage
+const COMMANDS: &[(&str, &str)] = &[
+    ("encode", "Encode a file using Huffman compression"),
+    ("decode", "Decode a Huffman-encoded file"),
+    ("repair", "Salvage the readable data of a damaged file into a new one"),
+    ("bench", "Measure the compression ratio and speed on a file"),
+    ("table", "Write the code table encode would use for a file"),
+    ("merge", "Join encoded files with the same table into one, without decoding"),
+    ("upgrade", "Rewrite encoded files of an old version in the current one, in place"),
+    ("analyze", "Show the entropy of a file and what each --method would make of it"),
+    ("test", "Check that an encoded file decodes, or all of those under a directory"),
+    ("ratio", "Add up the original and encoded sizes of the files under a directory"),
+    ("selftest", "Check that encoding and decoding work on this machine"),
+    ("completions", "Write the completion script of a shell (bash, zsh or fish) to stdout"),
+];
+
+// The shells `completions` writes a script for
+const SHELLS: &[&str] = &["bash", "zsh", "fish"];
+
+const fn flag(short: Option<&'static str>, long: &'static str, help: &'static str) -> Flag {
+    Flag { short, long, value: None, choices: &[], help }
+}
+
+const fn with_value(long: &'static str, value: &'static str, choices: &'static [&'static str], help: &'static str) -> Flag {
+    Flag { short: None, long, value: Some(value), choices, help }
+}
+
+const FLAGS: &[Flag] = &[
+    Flag { short: Some("-o"), long: "--output", value: Some("FILE"), choices: &[], help: "Output file (default: <input>.encoded/.decoded)" },
+    with_value("--output-template", "T", &[], "Name each output after its input, like '{dir}/{stem}.{date}.huf'"),
+    with_value("--max-output-size", "N", &["none"], "Abort decoding past N bytes, or 'none' (default: 16 GiB)"),
+    flag(None, "--ignore-errors", "Keep what could be decoded from a damaged file"),
+    flag(None, "--fsync", "Make sure the output is on disk before finishing"),
+    with_value("--threads", "N", &[], "Threads counting or decoding blocks (default: one per CPU)"),
+    with_value("--block-size", "N", &[], "Encode in independent blocks of N bytes, like 64K or 1M"),
+    with_value("--memory-limit", "N", &[], "Encode files up to N bytes from memory, like 512M (default: 8 MiB)"),
+    flag(None, "--drop-cache", "Let the OS drop the input from its cache once read"),
+    with_value("--limit-rate", "N", &[], "Read and write at most N bytes a second, like 20M, in encode and decode"),
+    with_value("--buffer-size", "N", &[], "Read and write in chunks of N bytes, 4K to 64M (default: 64K)"),
+    flag(None, "--compare", "Also bench gzip (needs the compare-flate2 feature)"),
+    flag(None, "--freq-cache", "Keep the byte counts next to the input, for encoding it again"),
+    flag(Some("-h"), "--help", "Show this help message"),
+    flag(Some("-v"), "--verbose", "Also show the code tables, headers and padding"),
+    flag(Some("-q"), "--quiet", "Only show warnings and errors"),
+    flag(None, "--progress", "Show how far encoding or decoding got on stderr"),
+    flag(None, "--stats", "Print the sizes and times of encode or decode on stdout"),
+    flag(None, "--json", "The same as --stats, as a JSON object"),
+    with_value("--format", "F", &["binary", "text"], "What table writes: binary (.huft, default) or text (.huft.txt)"),
+    with_value("--table-file", "FILE", &[], "Encode with the table in FILE, binary or text (*.txt), or check decode has it"),
+    flag(None, "--adaptive", "Encode in one pass with codes that adapt, without a table"),
+    flag(None, "--rle", "Run-length encode before the codes, for data with long runs"),
+    with_value("--symbol-width", "N", &["1", "2"], "Code bytes (1, default) or pairs of bytes (2), for UTF-16 or 16-bit audio"),
+    flag(None, "--tokens", "Code whole words and runs of whitespace, for text"),
+    flag(None, "--bwt", "Burrows-Wheeler transform and move-to-front first, in 900 kB blocks"),
+    flag(None, "--context", "Code every byte with a table for the byte before it, for text"),
+    with_value("--method", "M", &["huffman", "range"], "Code with a table (huffman, default) or range coding (range)"),
+    with_value("--filter", "F", &["delta", "xor-prev"], "Code the differences (delta) or XOR (xor-prev) of every byte and the one before"),
+    with_value("--stride", "N", &[], "Bytes per sample of --filter, the distance to the byte before (default: 1)"),
+    with_value("--checksum", "ALG", &["crc32", "xxh3", "none"], "Checksum of encode: crc32 (default), xxh3 (faster) or none"),
+    with_value("--stdin-name", "NAME", &[], "Name encode keeps of its input, like a pipe, for decode to name the output after"),
+    with_value("--stdin-mtime", "TS", &[], "Modification time encode keeps of its input, in seconds since 1970, for decode to give the output"),
+    flag(Some("-r"), "--recursive", "Test every encoded file under the directory given"),
+    flag(Some("-f"), "--force", "Overwrite existing outputs without asking"),
+    flag(None, "--rm", "Remove the input once it is encoded"),
+    flag(None, "--paranoid", "Sync the output and decode it again to check it before removing anything"),
+    flag(None, "--dedupe", "Link the output of an input that is the same as an earlier one instead of encoding it again"),
+    flag(None, "--preserve-timestamps", "Give the output the modification time of the input"),
+    with_value("--color", "WHEN", &["auto", "always", "never"], "Color messages: auto (on a terminal, default), always or never"),
+    flag(None, "--no-config", "Ignore the config files (~/.config/huff/config.toml, ./.huff.toml)"),
+];
+
+// The flags a config file can't set, they only make sense on the command line
+const NOT_CONFIGURABLE: &[&str] = &["--output", "--help", "--no-config", "--stdin-name", "--stdin-mtime"];
+
+// The commands without an input, their flags come right after them
+const NO_INPUT: &[&str] = &["selftest"];
+
+pub fn takes_input(command: &OsStr) -> bool {
+    !NO_INPUT.iter().any(|name| command == *name)
+}
+
+// Where the flags of `args` start, after the command and its input
+fn first_flag(args: &[OsString]) -> usize {
+    match args.get(1).is_some_and(|command| !takes_input(command)) {
+        true => 2,
+        false => 3,
+    }
+}
+
+fn find_flag(arg: &OsString) -> Option<&'static Flag> {
+    FLAGS.iter().find(|flag| arg == flag.long || flag.short.is_some_and(|short| arg == short))
+}
+
+// Flags that aren't known are an error, a typo shouldn't quietly run with the
+// defaults
+pub fn parse_args(args: &[OsString]) -> HuffmanResult<Options> {
+    let command = args[1].to_string_lossy().into_owned();
+    let input_filename = match takes_input(&args[1]) {
+        true => PathBuf::from(&args[2]),
+        false => PathBuf::new(),
+    };
+    let mut output = None;
+    let mut max_output_size = Some(DEFAULT_MAX_OUTPUT_SIZE);
+    let mut ignore_errors = false;
+    let mut fsync = false;
+    let mut drop_cache = false;
+    let mut buffer_size = DEFAULT_BUFFER_SIZE;
+    let mut compare = false;
+    let mut freq_cache = false;
+    let mut limit_rate = None;
+    let mut threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let mut block_size = None;
+    let mut memory_limit = DEFAULT_MEMORY_LIMIT;
+    let mut log_level = LevelFilter::Info;
+    let mut progress = false;
+    let mut stats = false;
+    let mut json = false;
+    let mut table_format = TableFormat::Binary;
+    let mut adaptive = false;
+    let mut rle = false;
+    let mut symbol_width = 1;
+    let mut tokens = false;
+    let mut bwt = false;
+    let mut context = false;
+    let mut method = Method::Huffman;
+    let mut filter = None;
+    let mut stride = 1;
+    let mut checksum = ChecksumKind::Crc32;
+    let mut file_info = FileInfo::default();
+    let mut table_file = None;
+    let mut more_inputs = Vec::new();
+    let mut recursive = false;
+    let mut color = ColorChoice::Auto;
+    let mut force = false;
+    let mut output_template = None;
+    let mut rm = false;
+    let mut paranoid = false;
+    let mut dedupe = false;
+    let mut preserve_timestamps = false;
+
+    let mut i = first_flag(args);
+    while i < args.len() {
+        let Some(flag) = find_flag(&args[i]) else {
+            let arg = args[i].to_string_lossy();
+            if arg.starts_with('-') {
+                return Err(HuffmanError::Usage(format!("unknown option '{}'", arg)));
+            }
+            if matches!(command.as_str(), "merge" | "encode" | "decode" | "upgrade") {
+                more_inputs.push(PathBuf::from(&args[i]));
+            }
+            i += 1;
+            continue;
+        };
+        // The value of a flag that takes one is the next argument
+        let raw_value = match flag.value {
+            Some(_) => match args.get(i + 1) {
+                Some(value) => {
+                    i += 1;
+                    Some(value)
+                }
+                None => {
+                    eprintln!("Error: Missing value for {}", flag.long);
+                    exit(1);
+                }
+            },
+            None => None,
+        };
+        let value = raw_value.map(|v| v.to_string_lossy()).unwrap_or_default();
+        let invalid = |expected: &str| -> ! {
+            eprintln!("Error: Invalid {} '{}'{}", flag.long, value, expected);
+            exit(1);
+        };
+        match flag.long {
+            "--output" => output = raw_value.map(PathBuf::from),
+            "--output-template" => {
+                output_template = match OutputTemplate::parse(&value) {
+                    Ok(template) => Some(template),
+                    Err(e) => invalid(&format!(", {}", e)),
+                }
+            }
+            "--max-output-size" => {
+                max_output_size = match value.parse::<u64>() {
+                    _ if value == "none" => None,
+                    Ok(bytes) => Some(bytes),
+                    Err(_) => invalid(""),
+                }
+            }
+            "--threads" => {
+                threads = match value.parse::<usize>() {
+                    Ok(n) if n > 0 => n,
+                    _ => invalid(""),
+                }
+            }
+            "--block-size" => {
+                block_size = match parse_size(&value) {
+                    Some(bytes) if bytes > 0 => Some(bytes),
+                    _ => invalid(""),
+                }
+            }
+            "--memory-limit" => {
+                memory_limit = match parse_size(&value) {
+                    Some(bytes) => bytes,
+                    None => invalid(""),
+                }
+            }
+            "--buffer-size" => {
+                buffer_size = match parse_size(&value) {
+                    Some(bytes) if (MIN_BUFFER_SIZE as u64..=MAX_BUFFER_SIZE as u64).contains(&bytes) => bytes as usize,
+                    _ => invalid(", expected 4K to 64M"),
+                }
+            }
+            "--limit-rate" => {
+                limit_rate = match parse_size(&value) {
+                    Some(bytes) if bytes > 0 => Some(bytes),
+                    _ => invalid(", expected bytes a second, like 512K or 20M"),
+                }
+            }
+            "--ignore-errors" => ignore_errors = true,
+            "--fsync" => fsync = true,
+            "--drop-cache" => drop_cache = true,
+            "--compare" => compare = true,
+            "--freq-cache" => freq_cache = true,
+            // main() already printed the usage
+            "--help" => {}
+            "--verbose" => log_level = LevelFilter::Debug,
+            "--quiet" => log_level = LevelFilter::Warn,
+            "--progress" => progress = true,
+            "--stats" => stats = true,
+            "--json" => {
+                stats = true;
+                json = true;
+            }
+            "--format" => {
+                table_format = match &*value {
+                    "binary" => TableFormat::Binary,
+                    "text" => TableFormat::Text,
+                    _ => invalid(", expected binary or text"),
+                }
+            }
+            "--table-file" => table_file = raw_value.map(PathBuf::from),
+            "--adaptive" => adaptive = true,
+            "--rle" => rle = true,
+            "--symbol-width" => {
+                symbol_width = match value.parse::<u8>() {
+                    Ok(width @ (1 | 2)) => width,
+                    _ => invalid(", expected 1 or 2"),
+                }
+            }
+            "--tokens" => tokens = true,
+            "--bwt" => bwt = true,
+            "--context" => context = true,
+            "--method" => {
+                method = match &*value {
+                    "huffman" => Method::Huffman,
+                    "range" => Method::Range,
+                    _ => invalid(", expected huffman or range"),
+                }
+            }
+            "--checksum" => {
+                checksum = ChecksumKind::from_name(&value).unwrap_or_else(|| invalid(", expected crc32, xxh3 or none"));
+            }
+            "--stdin-name" => {
+                let name = raw_value.map(|name| name_bytes(name)).unwrap_or_default();
+                if name.is_empty() || name.len() > u16::MAX as usize {
+                    invalid(", expected a name of 1 to 65535 bytes");
+                }
+                file_info.name = Some(name);
+            }
+            "--stdin-mtime" => {
+                file_info.mtime = Some(value.parse().unwrap_or_else(|_| invalid(", expected seconds since 1970, like 1700000000")));
+            }
+            "--filter" => {
+                filter = match &*value {
+                    "delta" => Some(Filter::Delta),
+                    "xor-prev" => Some(Filter::XorPrev),
+                    _ => invalid(", expected delta or xor-prev"),
+                }
+            }
+            "--stride" => {
+                stride = match value.parse::<u8>() {
+                    Ok(stride) if stride > 0 => stride,
+                    _ => invalid(", expected 1 to 255"),
+                }
+            }
+            "--recursive" => recursive = true,
+            "--force" => force = true,
+            "--rm" => rm = true,
+            "--paranoid" => paranoid = true,
+            "--dedupe" => dedupe = true,
+            "--preserve-timestamps" => preserve_timestamps = true,
+            "--color" => {
+                color = match &*value {
+                    "auto" => ColorChoice::Auto,
+                    "always" => ColorChoice::Always,
+                    "never" => ColorChoice::Never,
+                    _ => invalid(", expected auto, always or never"),
+                }
+            }
+            // config_args() already left the config files out
+            "--no-config" => {}
+            long => unreachable!("{} is in FLAGS but not parsed", long),
+        }
+        i += 1;
+    }
+
+    let output_given = output.is_some();
+    let output_filename = match output {
+        Some(path) => path,
+        None => output_name(&command, &input_filename, table_format, output_template.as_ref(), &today()),
+    };
+
+    Ok(Options { command, input_filename, output_filename, output_given, output_template, max_output_size, ignore_errors, fsync, threads, block_size, memory_limit, drop_cache, buffer_size, compare, freq_cache, limit_rate, log_level, progress, stats, json, table_format, table_file, adaptive, rle, symbol_width, tokens, bwt, context, method, filter, stride, checksum, file_info, more_inputs, recursive, color, force, rm, paranoid, dedupe, preserve_timestamps, overwrite: None, cancel: None })
+}
+
+// `args` with the flags of the config files (see config.rs) right after the
+// input, so that the ones on the command line come later and win. An unknown
+// key isn't an error, the warnings about them are returned for once logging
+// is set up.
+pub fn config_args(args: &[OsString]) -> HuffmanResult<(Vec<OsString>, Vec<String>)> {
+    if args.iter().skip(1).any(|arg| arg == "--no-config") {
+        return Ok((args.to_vec(), Vec::new()));
+    }
+    let (flags, warnings) = config_flags(&load_settings()?)?;
+    let mut args = args.to_vec();
+    let at = args.len().min(first_flag(&args));
+    args.splice(at..at, flags);
+    Ok((args, warnings))
+}
+
+// The flags of `settings` and the warnings about the keys that aren't flags
+fn config_flags(settings: &[Setting]) -> HuffmanResult<(Vec<OsString>, Vec<String>)> {
+    let mut flags = Vec::new();
+    let mut warnings = Vec::new();
+    for setting in settings {
+        let long = format!("--{}", setting.key);
+        let Some(flag) = FLAGS.iter().find(|flag| flag.long == long && !NOT_CONFIGURABLE.contains(&flag.long)) else {
+            warnings.push(format!("{}: unknown key '{}'", setting.origin, setting.key));
+            continue;
+        };
+        let value = match (flag.value, &setting.value) {
+            (None, Value::Bool(set)) => {
+                if *set {
+                    flags.push(OsString::from(flag.long));
+                }
+                continue;
+            }
+            (None, _) => return Err(HuffmanError::Usage(format!("{}: {} is true or false", setting.origin, setting.key))),
+            (Some(_), Value::Bool(_)) => {
+                return Err(HuffmanError::Usage(format!("{}: {} takes a value, not true or false", setting.origin, setting.key)));
+            }
+            (Some(_), Value::Integer(n)) => n.to_string(),
+            (Some(_), Value::String(text)) => text.clone(),
+        };
+        flags.extend([OsString::from(flag.long), OsString::from(value)]);
+    }
+    Ok((flags, warnings))
+}
+
+// A number of bytes, optionally followed by K, M or G for KiB, MiB or GiB
+fn parse_size(value: &str) -> Option<u64> {
+    let (digits, multiplier) = match value.char_indices().last()? {
+        (i, 'k' | 'K') => (&value[..i], 1 << 10),
+        (i, 'm' | 'M') => (&value[..i], 1 << 20),
+        (i, 'g' | 'G') => (&value[..i], 1 << 30),
+        _ => (value, 1),
+    };
+    digits.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+// Appends .encoded, .decoded, .repaired, .merged or .huft(.txt) to the input path, or nothing for
+// upgrade, which replaces its input. Works on the raw OsStr so paths that aren't valid UTF-8 survive
+// untouched.
+fn default_output_filename(command: &str, input_filename: &Path, table_format: TableFormat) -> PathBuf {
+    let suffix = match (command, table_format) {
+        ("decode", _) => ".decoded",
+        ("repair", _) => ".repaired",
+        ("merge", _) => ".merged",
+        ("upgrade", _) => "",
+        ("table", TableFormat::Binary) => ".huft",
+        ("table", TableFormat::Text) => ".huft.txt",
+        _ => ".encoded",
+    };
+    let mut filename = input_filename.as_os_str().to_owned();
+    filename.push(suffix);
+    PathBuf::from(filename)
+}
+
+// The name of --output-template for `input`, or the default one without it
+fn output_name(command: &str, input_filename: &Path, table_format: TableFormat, template: Option<&OutputTemplate>, date: &str) -> PathBuf {
+    let Some(template) = template else {
+        return default_output_filename(command, input_filename, table_format);
+    };
+    // An input that can't be read fails the command later, with its own error
+    let size = fs::metadata(input_filename).map_or(0, |metadata| metadata.len());
+    template.expand(input_filename, &InputFacts { date, size })
+}
+
+// The bytes of a name given on the command line, as they are on Unix
+fn name_bytes(name: &OsStr) -> Vec<u8> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        name.as_bytes().to_vec()
+    }
+    #[cfg(not(unix))]
+    name.to_string_lossy().into_owned().into_bytes()
+}
+
+// The output decode names after the name the first stream keeps of the input,
+// next to the input, or None for an input without one or that isn't a file.
+// Only the last component of the name counts, so it can't put the output
+// anywhere else, and one that isn't the name of a file, like "..", is left
+// out with a warning.
+fn restored_name(input_filename: &Path) -> Option<PathBuf> {
+    if !fs::metadata(input_filename).is_ok_and(|metadata| metadata.is_file()) {
+        return None;
+    }
+    let mut reader = BufReader::new(File::open(input_filename).ok()?);
+    let name = decode_header(&mut reader).ok()?.info.name?;
+    let last = name.rsplit(|&byte| byte == b'/' || byte == b'\\').next().unwrap_or_default();
+    if last.is_empty() || last == b"." || last == b".." || last.contains(&0) {
+        warn!("'{}' keeps the name '{}', which isn't one of a file", input_filename.display(), String::from_utf8_lossy(&name));
+        return None;
+    }
+    #[cfg(unix)]
+    let last = {
+        use std::os::unix::ffi::OsStrExt;
+        OsStr::from_bytes(last).to_os_string()
+    };
+    #[cfg(not(unix))]
+    let last = OsString::from(String::from_utf8_lossy(last).into_owned());
+    Some(input_filename.with_file_name(last))
+}
+
+pub fn print_usage(program_name: &str) {
+    println!("Usage: {} <command> <input_file> [options]", program_name);
+    println!("\nCommands:");
+    for (name, help) in COMMANDS {
+        println!("  {:<11} {}", name, help);
+    }
+    println!("\nOptions:");
+    for flag in FLAGS {
+        let names = match flag.short {
+            Some(short) => format!("{}, {}", short, flag.long),
+            None => flag.long.to_string(),
+        };
+        let usage = match flag.value {
+            Some(value) => format!("{} {}", names, value),
+            None => names,
+        };
+        println!("  {:<20} {}", usage, flag.help);
+    }
+    println!("\nExamples:");
+    println!("  {} encode test.txt", program_name);
+    println!("  {} encode test.txt -o compressed.huf", program_name);
+    println!("  cat test.txt | {} encode /dev/stdin --adaptive -o test.huf", program_name);
+    println!("  {} decode test.txt.encoded -o restored.txt", program_name);
+    println!("  {} table test.txt --format text", program_name);
+    println!("  {} merge a.huf b.huf -o ab.huf", program_name);
+    println!("  {} test /backups --recursive", program_name);
+}
+
+// Completion
+////////////////////////////////////////////////////////////////////////////////
+
+// Writes the completion script of `shell` for the binary, from COMMANDS and
+// FLAGS: the commands first, then files, and after a flag its values, files
+// for a FILE
+pub fn completions(shell: &str, program_name: &str) -> HuffmanResult<()> {
+    let name = Path::new(program_name).file_name().map_or(program_name.into(), |name| name.to_string_lossy());
+    let script = match shell {
+        "bash" => bash_completion(&name),
+        "zsh" => zsh_completion(&name),
+        "fish" => fish_completion(&name),
+        _ => return Err(HuffmanError::Usage(format!("Unknown shell '{}', expected bash, zsh or fish", shell))),
+    };
+    io::stdout().write_all(script.as_bytes())?;
+    Ok(())
+}
+
+// Every name of the flags, long and short, separated by spaces
+fn flag_names(flags: &[&Flag]) -> String {
+    let names: Vec<&str> = flags.iter().flat_map(|flag| flag.short.into_iter().chain([flag.long])).collect();
+    names.join(" ")
+}
+
+fn bash_completion(name: &str) -> String {
+    let function = format!("_{}", name.replace(|c: char| !c.is_ascii_alphanumeric(), "_"));
+    let commands: Vec<&str> = COMMANDS.iter().map(|(command, _)| *command).collect();
+    let mut script = format!("# bash completion of {}, source it or put it in bash-completion's directory\n", name);
+    script += &format!("{}() {{\n", function);
+    script += "    local cur=\"${COMP_WORDS[COMP_CWORD]}\" prev=\"${COMP_WORDS[COMP_CWORD-1]}\"\n";
+    script += "    if [ \"$COMP_CWORD\" -eq 1 ]; then\n";
+    script += &format!("        COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))\n", commands.join(" "));
+    script += "        return\n    fi\n";
+    script += "    if [ \"$COMP_CWORD\" -eq 2 ] && [ \"${COMP_WORDS[1]}\" = completions ]; then\n";
+    script += &format!("        COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))\n", SHELLS.join(" "));
+    script += "        return\n    fi\n";
+    script += "    case \"$prev\" in\n";
+    let with_value: Vec<&Flag> = FLAGS.iter().filter(|flag| flag.value.is_some()).collect();
+    let files: Vec<&Flag> = with_value.iter().copied().filter(|flag| flag.value == Some("FILE")).collect();
+    script += &format!("        {})\n            COMPREPLY=($(compgen -f -- \"$cur\"))\n            return\n            ;;\n", flag_names(&files).replace(' ', "|"));
+    for flag in with_value.iter().filter(|flag| !flag.choices.is_empty()) {
+        script += &format!("        {})\n            COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))\n            return\n            ;;\n", flag.long, flag.choices.join(" "));
+    }
+    let free: Vec<&Flag> = with_value.iter().copied().filter(|flag| flag.value != Some("FILE") && flag.choices.is_empty()).collect();
+    script += &format!("        {})\n            return\n            ;;\n", flag_names(&free).replace(' ', "|"));
+    script += "    esac\n";
+    script += "    if [[ \"$cur\" == -* ]]; then\n";
+    script += &format!("        COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))\n", flag_names(&FLAGS.iter().collect::<Vec<_>>()));
+    script += "    else\n        COMPREPLY=($(compgen -f -- \"$cur\"))\n    fi\n}\n";
+    script += &format!("complete -o filenames -F {} {}\n", function, name);
+    script
+}
+
+// Help in the brackets of _arguments, which end at ] and whose values start
+// after a :
+fn zsh_help(help: &str) -> String {
+    help.replace('\'', "'\\''").replace('[', "\\[").replace(']', "\\]").replace(':', "\\:")
+}
+
+fn zsh_completion(name: &str) -> String {
+    let function = format!("_{}", name.replace(|c: char| !c.is_ascii_alphanumeric(), "_"));
+    let mut script = format!("#compdef {}\n\n{}() {{\n    local -a commands\n    commands=(\n", name, function);
+    for (command, help) in COMMANDS {
+        script += &format!("        '{}:{}'\n", command, help.replace('\'', "'\\''"));
+    }
+    script += "    )\n    local state\n    _arguments \\\n";
+    for flag in FLAGS {
+        let value = match flag.value {
+            Some("FILE") => String::from(":file:_files"),
+            Some(value) if !flag.choices.is_empty() => format!(":{}:({})", value, flag.choices.join(" ")),
+            Some(value) => format!(":{}: ", value),
+            None => String::new(),
+        };
+        for name in flag.short.into_iter().chain([flag.long]) {
+            script += &format!("        '{}[{}]{}' \\\n", name, zsh_help(flag.help), value);
+        }
+    }
+    script += "        '1:command:->command' \\\n        '*:file:->file'\n";
+    script += "    case $state in\n";
+    script += "        command) _describe 'command' commands ;;\n";
+    script += &format!("        file) [[ $words[2] == completions ]] && _values 'shell' {} || _files ;;\n", SHELLS.join(" "));
+    script += &format!("    esac\n}}\n\n{} \"$@\"\n", function);
+    script
+}
+
+fn fish_completion(name: &str) -> String {
+    let quote = |text: &str| format!("'{}'", text.replace('\\', "\\\\").replace('\'', "\\'"));
+    let mut script = format!("# fish completion of {}, put it in ~/.config/fish/completions/{}.fish\n", name, name);
+    for (command, help) in COMMANDS {
+        script += &format!("complete -c {} -n __fish_use_subcommand -f -a {} -d {}\n", name, command, quote(help));
+    }
+    script += &format!("complete -c {} -n '__fish_seen_subcommand_from completions' -f -a {}\n", name, quote(&SHELLS.join(" ")));
+    for flag in FLAGS {
+        let mut line = format!("complete -c {}", name);
+        if let Some(short) = flag.short {
+            line += &format!(" -s {}", &short[1..]);
+        }
+        line += &format!(" -l {}", &flag.long[2..]);
+        match flag.value {
+            Some("FILE") => line += " -r -F",
+            Some(_) if !flag.choices.is_empty() => line += &format!(" -x -a {}", quote(&flag.choices.join(" "))),
+            Some(_) => line += " -x",
+            None => {}
+        }
+        script += &format!("{} -d {}\n", line, quote(flag.help));
+    }
+    script
+}
+
+// Logging
+////////////////////////////////////////////////////////////////////////////////
+
+// Everything the library reports goes through the log crate, so stdout only
+// ever gets data. The binary shows it on stderr, warnings and errors with the
+// prefixes they always had.
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let style = Style::stderr();
+        let mut stderr = io::stderr().lock();
+        let _ = match record.level() {
+            Level::Error => writeln!(stderr, "{} {}", style.red("Error:"), record.args()),
+            Level::Warn => writeln!(stderr, "{} {}", style.yellow("WARNING:"), record.args()),
+            _ => writeln!(stderr, "{}", record.args()),
+        };
+    }
+
+    fn flush(&self) {
+        let _ = io::stderr().flush();
+    }
+}
+
+// The error a command failed with, the last thing it prints
+pub fn print_error(error: &HuffmanError) {
+    eprintln!("{} {}", Style::stderr().red("Error:"), error);
+}
+
+// Info by default, debug with --verbose and warnings with --quiet. Does
+// nothing when the program already set a logger of its own.
+pub fn init_logging(level: LevelFilter) {
+    static LOGGER: StderrLogger = StderrLogger;
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+// Progress
+////////////////////////////////////////////////////////////////////////////////
+
+// Redraws of the progress bar are at least this far apart
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+const PROGRESS_BAR_WIDTH: usize = 30;
+
+// The bar of --progress, a line on stderr that is drawn over again. It is
+// removed at the end, and when it is dropped because the command failed, so
+// the messages after it get a line of their own.
+struct ProgressBar {
+    state: Mutex<BarState>,
+}
+
+struct BarState {
+    phase: Option<Phase>,
+    // Length of the line on the screen, 0 when there is none
+    drawn: usize,
+    // None draws the next update right away
+    last_draw: Option<Instant>,
+    // When the phase started and where, for its rate
+    phase_start: Option<(Instant, u64)>,
+}
+
+impl ProgressBar {
+    fn new() -> Self {
+        ProgressBar { state: Mutex::new(BarState { phase: None, drawn: 0, last_draw: None, phase_start: None }) }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BarState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+// Like `Encoding   42% [############                  ] 12.0 of 28.6 MiB`,
+// or without the bar when the total isn't known, and `, 20.0 MiB/s` once the
+// rate in bytes a second is known, which shows what --limit-rate leaves
+fn progress_line(phase: Option<Phase>, processed: u64, total: Option<u64>, rate: Option<f64>) -> String {
+    let label = phase.map_or(String::new(), |phase| format!("{:?}", phase));
+    let mib = |bytes: u64| bytes as f64 / (1024.0 * 1024.0);
+    let rate = rate.map_or(String::new(), |rate| format!(", {:.1} MiB/s", rate / (1024.0 * 1024.0)));
+    let line = match total {
+        Some(total) => {
+            let fraction = if total == 0 { 1.0 } else { processed.min(total) as f64 / total as f64 };
+            let filled = (fraction * PROGRESS_BAR_WIDTH as f64) as usize;
+            format!("{:<8} {:>3}% [{:<width$}] {:.1} of {:.1} MiB",
+                label, (fraction * 100.0) as u32, "#".repeat(filled), mib(processed), mib(total), width = PROGRESS_BAR_WIDTH)
+        }
+        None => format!("{:<8} {:.1} MiB", label, mib(processed)),
+    };
+    line + &rate
+}
+
+fn redraw(state: &mut BarState, line: &str) {
+    let mut stderr = io::stderr().lock();
+    // Spaces over what is left of a longer line
+    let _ = write!(stderr, "\r{:<width$}", line, width = state.drawn);
+    let _ = stderr.flush();
+    state.drawn = line.len();
+}
+
+impl ProgressSink for ProgressBar {
+    fn on_phase_start(&self, phase: Phase) {
+        let mut state = self.lock();
+        state.phase = Some(phase);
+        state.last_draw = None;
+        state.phase_start = None;
+    }
+
+    // The end of a phase is always drawn, so it shows at 100%
+    fn on_bytes(&self, processed: u64, total: Option<u64>) {
+        let mut state = self.lock();
+        let now = Instant::now();
+        let recent = state.last_draw.is_some_and(|last| now - last < PROGRESS_INTERVAL);
+        if recent && total != Some(processed) {
+            return;
+        }
+        let (started, start) = *state.phase_start.get_or_insert((now, processed));
+        let elapsed = (now - started).as_secs_f64();
+        let rate = (elapsed >= PROGRESS_INTERVAL.as_secs_f64()).then(|| processed.saturating_sub(start) as f64 / elapsed);
+        let line = progress_line(state.phase, processed, total, rate);
+        redraw(&mut state, &line);
+        state.last_draw = Some(now);
+    }
+
+    fn on_finish(&self, _report: &Report) {
+        let mut state = self.lock();
+        if state.drawn > 0 {
+            redraw(&mut state, "");
+            let _ = write!(io::stderr(), "\r");
+        }
+    }
+}
+
+impl Drop for ProgressBar {
+    fn drop(&mut self) {
+        self.on_finish(&Report::default());
+    }
+}
+
+// Ctrl-C
+////////////////////////////////////////////////////////////////////////////////
+
+static CTRL_C: OnceLock<CancelToken> = OnceLock::new();
+
+// A token that the first Ctrl-C cancels, so the command stops at its next
+// check and removes what it wrote, instead of leaving a temporary output and
+// a lock behind. A second Ctrl-C exits right away, for a command that is stuck
+// waiting for its input.
+pub fn cancel_on_ctrl_c() -> CancelToken {
+    static HANDLER: Once = Once::new();
+    let token = CTRL_C.get_or_init(CancelToken::new);
+    HANDLER.call_once(install_ctrl_c_handler);
+    token.clone()
+}
+
+#[cfg(unix)]
+fn install_ctrl_c_handler() {
+    const SIGINT: i32 = 2;
+    extern "C" {
+        fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+        fn _exit(status: i32) -> !;
+    }
+    // Only an atomic store or _exit, which are safe in a signal handler
+    extern "C" fn on_sigint(_signum: i32) {
+        match CTRL_C.get() {
+            Some(token) if !token.is_cancelled() => token.cancel(),
+            _ => unsafe { _exit(EXIT_CANCELLED) },
+        }
+    }
+    unsafe { signal(SIGINT, on_sigint) };
+}
+
+#[cfg(windows)]
+fn install_ctrl_c_handler() {
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn SetConsoleCtrlHandler(handler: unsafe extern "system" fn(u32) -> i32, add: i32) -> i32;
+    }
+    // Returning 0 passes the second one on to the default handler, which exits
+    unsafe extern "system" fn on_ctrl_c(_event: u32) -> i32 {
+        match CTRL_C.get() {
+            Some(token) if !token.is_cancelled() => {
+                token.cancel();
+                1
+            }
+            _ => 0,
+        }
+    }
+    unsafe { SetConsoleCtrlHandler(on_ctrl_c, 1) };
+}
+
+#[cfg(not(any(unix, windows)))]
+fn install_ctrl_c_handler() {}
+
+// Status on a signal
+////////////////////////////////////////////////////////////////////////////////
+
+static STATUS_HANDLER: Once = Once::new();
+// Set by the signal, and taken by the next report of the command
+static STATUS_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+// Like dd: `kill -USR1 <pid>` makes the command print a line on stderr with
+// how far it got, without --progress. The BSDs and macOS also have SIGINFO
+// for it, which Ctrl-T sends. The line comes with the next report of the
+// loops, at most a megabyte of input later.
+pub fn status_on_signal() {
+    STATUS_HANDLER.call_once(install_status_handler);
+}
+
+#[cfg(unix)]
+fn install_status_handler() {
+    // SIGUSR1, which is 10 on Linux but for MIPS, SPARC and Alpha
+    #[cfg(all(any(target_os = "linux", target_os = "android"), not(any(target_arch = "mips", target_arch = "mips64", target_arch = "sparc64"))))]
+    const SIGNALS: &[i32] = &[10];
+    // SIGUSR1 and SIGINFO
+    #[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd", target_os = "netbsd", target_os = "openbsd", target_os = "dragonfly"))]
+    const SIGNALS: &[i32] = &[30, 29];
+    #[cfg(not(any(
+        all(any(target_os = "linux", target_os = "android"), not(any(target_arch = "mips", target_arch = "mips64", target_arch = "sparc64"))),
+        target_os = "macos", target_os = "ios", target_os = "freebsd", target_os = "netbsd", target_os = "openbsd", target_os = "dragonfly",
+    )))]
+    const SIGNALS: &[i32] = &[];
+    extern "C" {
+        fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+    }
+    // Only an atomic store, which is safe in a signal handler
+    extern "C" fn on_status(_signum: i32) {
+        STATUS_REQUESTED.store(true, Ordering::Relaxed);
+    }
+    for &signum in SIGNALS {
+        unsafe { signal(signum, on_status) };
+    }
+}
+
+#[cfg(not(unix))]
+fn install_status_handler() {}
+
+// The sink of a command without --progress, which only keeps track of the
+// phase until the signal asks for a line
+#[derive(Default)]
+struct StatusOnSignal {
+    state: Mutex<StatusState>,
+}
+
+#[derive(Default)]
+struct StatusState {
+    phase: Option<Phase>,
+    // When the phase started and where, from its first report
+    phase_start: Option<(Instant, u64)>,
+}
+
+impl ProgressSink for StatusOnSignal {
+    fn on_phase_start(&self, phase: Phase) {
+        *self.state.lock().unwrap_or_else(PoisonError::into_inner) = StatusState { phase: Some(phase), phase_start: None };
+    }
+
+    fn on_bytes(&self, processed: u64, total: Option<u64>) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let now = Instant::now();
+        let (started, start) = *state.phase_start.get_or_insert((now, processed));
+        if STATUS_REQUESTED.swap(false, Ordering::Relaxed) {
+            eprintln!("{}", status_line(state.phase, processed, total, processed.saturating_sub(start), now - started));
+        }
+    }
+}
+
+// Like `Encoding: 12.0 of 28.6 MiB (42%), 20.0 MiB/s, 1m 05s left`, from the
+// `done` bytes of the phase in `elapsed`. What isn't known is left out.
+fn status_line(phase: Option<Phase>, processed: u64, total: Option<u64>, done: u64, elapsed: Duration) -> String {
+    let mib = |bytes: f64| bytes / (1024.0 * 1024.0);
+    let mut line = match phase {
+        Some(phase) => format!("{:?}: ", phase),
+        None => String::new(),
+    };
+    line += &match total {
+        Some(total) => {
+            let percent = (processed.min(total) * 100).checked_div(total).unwrap_or(100);
+            format!("{:.1} of {:.1} MiB ({}%)", mib(processed as f64), mib(total as f64), percent)
+        }
+        None => format!("{:.1} MiB", mib(processed as f64)),
+    };
+    let seconds = elapsed.as_secs_f64();
+    if done > 0 && seconds > 0.0 {
+        let rate = done as f64 / seconds;
+        line += &format!(", {:.1} MiB/s", mib(rate));
+        if let Some(total) = total {
+            let left = (total.saturating_sub(processed) as f64 / rate).round() as u64;
+            line += &match left {
+                0..60 => format!(", {}s left", left),
+                60..3600 => format!(", {}m {:02}s left", left / 60, left % 60),
+                _ => format!(", {}h {:02}m left", left / 3600, left % 3600 / 60),
+            };
+        }
+    }
+    line
+}
+
+// Stats
+////////////////////////////////////////////////////////////////////////////////
+
+// A value of --stats, which JSON quotes when it is text
+enum Stat {
+    Count(u64),
+    Decimal(f64),
+    Text(&'static str),
+}
+
+impl fmt::Display for Stat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stat::Count(count) => write!(f, "{}", count),
+            Stat::Decimal(value) => write!(f, "{:.6}", value),
+            Stat::Text(text) => f.write_str(text),
+        }
+    }
+}
+
+fn encode_stats(report: &EncodeReport) -> Vec<(&'static str, Stat)> {
+    vec![
+        ("input_bytes", Stat::Count(report.input_bytes)),
+        ("output_bytes", Stat::Count(report.output_bytes)),
+        ("streams", Stat::Count(report.streams)),
+        ("header_bytes", Stat::Count(report.header_bytes)),
+        ("payload_bits", Stat::Count(report.payload_bits)),
+        ("padding_bits", Stat::Count(report.padding_bits)),
+        ("distinct_symbols", Stat::Count(report.distinct_symbols as u64)),
+        ("entropy", Stat::Decimal(report.entropy)),
+        ("counting_seconds", Stat::Decimal(report.counting_time.as_secs_f64())),
+        ("encoding_seconds", Stat::Decimal(report.encoding_time.as_secs_f64())),
+    ]
+}
+
+fn decode_stats(report: &DecodeReport) -> Vec<(&'static str, Stat)> {
+    let checksum = match report.checksum {
+        ChecksumStatus::Verified => "verified",
+        ChecksumStatus::NotVerified => "not verified",
+        ChecksumStatus::Missing => "missing",
+    };
+    vec![
+        ("input_bytes", Stat::Count(report.input_bytes)),
+        ("output_bytes", Stat::Count(report.output_bytes)),
+        ("streams", Stat::Count(report.streams)),
+        ("header_bytes", Stat::Count(report.header_bytes)),
+        ("payload_bits", Stat::Count(report.payload_bits)),
+        ("padding_bits", Stat::Count(report.padding_bits)),
+        ("distinct_symbols", Stat::Count(report.distinct_symbols as u64)),
+        ("checksum", Stat::Text(checksum)),
+        ("decoding_seconds", Stat::Decimal(report.decoding_time.as_secs_f64())),
+    ]
+}
+
+// One `name: value` line per stat, or a single line of JSON. Names and texts
+// need no escaping.
+fn stats_text(stats: &[(&str, Stat)], json: bool) -> String {
+    if !json {
+        return stats.iter().map(|(name, value)| format!("{}: {}\n", name, value)).collect();
+    }
+    let fields: Vec<String> = stats.iter()
+        .map(|(name, value)| match value {
+            Stat::Text(_) => format!("\"{}\": \"{}\"", name, value),
+            _ => format!("\"{}\": {}", name, value),
+        })
+        .collect();
+    format!("{{{}}}\n", fields.join(", "))
+}
+
+fn print_stats(stats: &[(&str, Stat)], json: bool) {
+    print!("{}", stats_text(stats, json));
+}
+
+// Commands
+////////////////////////////////////////////////////////////////////////////////
+
+fn log_encoding_table(encoding_table: &EncodingTable) {
+    if !log_enabled!(Level::Debug) {
+        return;
+    }
+    for (character, code) in encoding_table {
+        debug!("Char '{}' - encoding: {:#b}, length: {}", symbol_label(character), code.bits, code.length);
+    }
+}
+
+// The decoded size of the file starting with `first`, when every stream in it
+// declares both its original and its payload length. None as soon as one
+// doesn't or something else follows, decoding reports those.
+fn declared_output_length(input_filename: &Path, first: &Header) -> Option<u64> {
+    let mut reader = BufReader::new(File::open(input_filename).ok()?);
+    let file_size = reader.get_ref().metadata().ok()?.len();
+    let (mut length, mut payload_length, mut size) = (first.original_length?, first.payload_length?, first.serialized_len());
+    let mut start = 0u64;
+    let mut total = 0u64;
+    loop {
+        total = total.checked_add(length)?;
+        let end = start.checked_add(size)?.checked_add(payload_length)?;
+        match next_header(&mut reader, end, file_size) {
+            Ok(Some(next)) => {
+                length = next.original_length?;
+                payload_length = next.payload_length?;
+                size = next.serialized_len();
+                start = end;
+            }
+            Ok(None) => return Some(total),
+            Err(_) => return None,
+        }
+    }
+}
+
+// The exit code for a command that failed with `error`
+pub fn exit_code(error: &HuffmanError) -> i32 {
+    if error.is_corrupt() {
+        return EXIT_CORRUPT;
+    }
+    match error.root() {
+        HuffmanError::OutputTooLarge { .. } => EXIT_TOO_LARGE,
+        HuffmanError::Cancelled => EXIT_CANCELLED,
+        _ => EXIT_FAILURE,
+    }
+}
+
+// Outcome of a command that didn't fail outright
+pub enum Status {
+    Complete,
+    // Only part of the data could be recovered, see --ignore-errors
+    Partial,
+}
+
+// What asks before outputs are overwritten: a person at the terminal, unless
+// --force or --quiet say not to. None without a terminal, where an existing
+// output is refused unless --force says to replace it, see may_write.
+pub fn ask_overwrite(opts: &Options) -> Option<Arc<Mutex<Overwrite>>> {
+    let interactive = io::stdin().is_terminal() && !opts.force && opts.log_level != LevelFilter::Warn;
+    interactive.then(|| Arc::new(Mutex::new(Overwrite::new(Box::new(TerminalPrompt)))))
+}
+
+// Whether the command may write `output_filename`, see Options::overwrite. A
+// no at the prompt is not a failure, the command just doesn't do anything.
+// Nobody to ask fails on an existing output, unless --force was given.
+fn may_write(output_filename: &Path, opts: &Options) -> HuffmanResult<bool> {
+    if let Some(overwrite) = &opts.overwrite {
+        let allowed = overwrite.lock().unwrap_or_else(PoisonError::into_inner).allow(output_filename)?;
+        if !allowed {
+            info!("Not overwriting '{}'", output_filename.display());
+            return Ok(false);
+        }
+    } else if !opts.force && output_filename.symlink_metadata().is_ok() {
+        return Err(HuffmanError::Io(Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("output '{}' exists already, use -f to overwrite it", output_filename.display()),
+        )));
+    }
+    // A template like '{dir}/compressed/{name}' names directories that aren't
+    // there yet
+    if opts.output_template.is_some() && !opts.output_given {
+        if let Some(dir) = output_filename.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).with_context(|| format!("failed to create directory '{}'", dir.display()))?;
+        }
+    }
+    Ok(true)
+}
+
+// Every input of the command with its output: the -o of a single input, or
+// the name of --output-template or the default one. Fails before anything is
+// written when two inputs would get the same output, or one would be written
+// over another input.
+pub fn outputs(opts: &Options) -> HuffmanResult<Vec<(PathBuf, PathBuf)>> {
+    let inputs = opts.inputs();
+    if opts.output_given && inputs.len() > 1 {
+        return Err(HuffmanError::Usage(String::from("-o names the output of a single input, use --output-template for several")));
+    }
+    let date = today();
+    let pairs: Vec<(PathBuf, PathBuf)> = inputs
+        .into_iter()
+        .map(|input| {
+            // The output of decode is named after the input encode had, when
+            // it kept its name
+            let restored = match opts.command == "decode" && opts.output_template.is_none() {
+                true => restored_name(&input),
+                false => None,
+            };
+            let output = match (opts.output_given, restored) {
+                (true, _) => opts.output_filename.clone(),
+                (false, Some(restored)) => restored,
+                (false, None) => output_name(&opts.command, &input, opts.table_format, opts.output_template.as_ref(), &date),
+            };
+            (input, output)
+        })
+        .collect();
+    let inputs: HashMap<PathBuf, &Path> = pairs.iter().map(|(input, _)| (normalized(input), input.as_path())).collect();
+    let mut written: HashMap<PathBuf, &Path> = HashMap::new();
+    for (input, output) in &pairs {
+        let key = normalized(output);
+        if let Some(other) = written.insert(key.clone(), input) {
+            return Err(HuffmanError::Usage(format!(
+                "'{}' and '{}' would both be written to '{}'",
+                other.display(),
+                input.display(),
+                output.display(),
+            )));
+        }
+        if let Some(other) = inputs.get(&key).filter(|other| **other != input.as_path()) {
+            return Err(HuffmanError::Usage(format!("the output of '{}' would be written over the input '{}'", input.display(), other.display())));
+        }
+    }
+    Ok(pairs)
+}
+
+// Runs `command` on every input of encode or decode in turn, see outputs. The
+// first failure stops the batch; Partial when any of them was.
+pub fn each_input(opts: &Options, command: impl Fn(&Path, &Path, &Options) -> HuffmanResult<Status>) -> HuffmanResult<Status> {
+    let mut status = Status::Complete;
+    for (input, output) in outputs(opts)? {
+        if let Status::Partial = command(&input, &output, opts)? {
+            status = Status::Partial;
+        }
+    }
+    Ok(status)
+}
+
+pub fn encode(input_filename: &Path, output_filename: &Path, opts: &Options) -> HuffmanResult<()> {
+    ensure_distinct_output(input_filename, output_filename)?;
+    if !may_write(output_filename, opts)? {
+        return Ok(());
+    }
+    let input = input_filename.display();
+    let output = output_filename.display();
+
+    let own_codes = opts.adaptive || opts.rle || opts.symbol_width != 1 || opts.tokens || opts.bwt || opts.context
+        || opts.method != Method::Huffman || opts.filter.is_some() || opts.stride != 1;
+    if own_codes && opts.table_file.is_some() {
+        return Err(HuffmanError::Usage(String::from(
+            "--adaptive, --rle, --symbol-width, --tokens, --bwt, --context, --method and --filter pick their own codes, --table-file can't be used with them",
+        )));
+    }
+    let table_file = match &opts.table_file {
+        Some(path) => Some(read_table_file(path)
+            .with_context(|| format!("failed to read table '{}'", path.display()))?),
+        None => None,
+    };
+    let table_id = table_file.as_ref().and_then(|table_file| table_file.id);
+    let fixed_table = table_file.map(|table_file| table_file.encoding_table);
+    let own_codes_or_info = own_codes || !opts.file_info.is_empty() || table_id.is_some();
+
+    // Compute frequencies, huffman tree and encoding table
+    let mut input_file = open_sequential(input_filename)
+        .with_context(|| format!("failed to open input '{}'", input))?;
+    let metadata = input_file.metadata()
+        .with_context(|| format!("failed to read input '{}'", input))?;
+    // The library writes the info, see EncoderOptionsBuilder::file_info
+    if own_codes_or_info {
+        return encode_with_options(&input_file, &metadata, input_filename, output_filename, fixed_table, table_id, opts);
+    }
+    // Reading in parallel would get around the limit, so it reads and writes
+    // in one thread
+    let limiter = opts.limit_rate.map(RateLimiter::new);
+    let limiter = limiter.as_ref();
+    let max_threads = if limiter.is_some() { 1 } else { opts.threads };
+
+    // The cache holds the counts of a whole file, blocks are counted each time
+    let cache_filename = freq_cache_filename(input_filename);
+    let cache_key = match opts.freq_cache && metadata.is_file() && opts.block_size.is_none() {
+        true => freq_cache_key(&input_file).ok(),
+        false => None,
+    };
+    let mut cached_frequencies = cache_key.as_ref().and_then(|key| load_freq_cache(&cache_filename, key));
+    // Windows moves the file position when reading at an offset
+    if cache_key.is_some() {
+        input_file.rewind()
+            .with_context(|| format!("failed to read input '{}'", input))?;
+    }
+
+    // Pipes and devices can't be rewound for the second pass, so their data is
+    // kept in memory instead. Small files are too, which saves reading them
+    // twice.
+    let spool = if metadata.is_file() && metadata.len() > opts.memory_limit {
+        None
+    } else {
+        let mut data = Vec::new();
+        Limited::new(&mut input_file, limiter).read_to_end(&mut data)
+            .with_context(|| format!("failed to read input '{}'", input))?;
+        Some(data)
+    };
+
+    // Every block is counted, gets its own table and is encoded as a stream
+    // of its own. Without --block-size the whole input is one block.
+    let size = spool.as_ref().map_or(metadata.len(), |data| data.len() as u64);
+    let block_size = opts.block_size.unwrap_or(u64::MAX);
+    let mut output_file = OutputFile::create(output_filename, &metadata.permissions())
+        .with_context(|| format!("failed to create output '{}'", output))?;
+    let mut spool_reader;
+    let mut file_reader = Limited::new(&input_file, limiter);
+    let reader: &mut dyn ReadSeek = match &spool {
+        Some(data) => {
+            spool_reader = Cursor::new(&data[..]);
+            &mut spool_reader
+        }
+        None => &mut file_reader,
+    };
+
+    let progress_sink = SharedSink(opts.progress_sink());
+    let mut progress = Progress::new(progress_sink.get(), opts.cancel.as_ref(), Some(size));
+    let mut report = EncodeReport::default();
+    let mut counts = FrequencyTable::new();
+    let mut stopwatch = Stopwatch::start();
+    let mut start = 0;
+    loop {
+        let expected = block_size.min(size.saturating_sub(start));
+        let threads = (max_threads as u64).min(expected / PARALLEL_RANGE_SIZE) as usize;
+        let frequencies = match cached_frequencies.take() {
+            Some(frequencies) => {
+                info!("Frequencies from cache '{}'", cache_filename.display());
+                frequencies
+            }
+            None => {
+                progress.start_phase(Phase::Counting, start)?;
+                // The threads don't report progress, only the end of the range is
+                let frequencies = match &spool {
+                    None if threads > 1 && cfg!(any(unix, windows)) => {
+                        calculate_frequencies_parallel(&input_file, start..start + expected, threads, opts.buffer_size)
+                            .map_err(HuffmanError::from)
+                            .and_then(|frequencies| progress.end(frequencies.total()).map(|()| frequencies))
+                    }
+                    _ => reader.seek(SeekFrom::Start(start)).map_err(HuffmanError::from).and_then(|_| {
+                        calculate_frequencies_with_progress((&mut *reader).take(block_size), opts.buffer_size, &mut progress)
+                    }),
+                }.with_context(|| format!("failed to read input '{}'", input))?;
+                // Only counts of the input as it was when the key was taken
+                let unchanged = |key: &CacheKey| frequencies.total() == key.size
+                    && freq_cache_key(&input_file).is_ok_and(|now| now == *key);
+                if let Some(key) = cache_key.as_ref().filter(|key| unchanged(key)) {
+                    if let Err(e) = store_freq_cache(&cache_filename, key, &frequencies) {
+                        warn!("failed to write frequency cache '{}': {}", cache_filename.display(), e);
+                    }
+                }
+                frequencies
+            }
+        };
+        // The counts still give the length of the block with a fixed table
+        let encoding_table = match (&fixed_table, HuffmanTree::from_frequencies(&frequencies)) {
+            (Some(encoding_table), _) => encoding_table.clone(),
+            (None, Some(tree)) => tree.codes(),
+            (None, None) => EncodingTable::new(),
+        };
+        log_encoding_table(&encoding_table);
+        counts.merge(&frequencies);
+        stopwatch.lap(&mut report.counting_time);
+
+        // Exactly the bytes that were counted, in case the input changed size
+        let length = frequencies.total();
+        let pipelined = spool.is_none() && max_threads > 1 && length >= PIPELINE_THRESHOLD;
+        progress.start_phase(Phase::Encoding, start)?;
+        let stream = reader.seek(SeekFrom::Start(start))
+            .map_err(HuffmanError::from)
+            .and_then(|_| if pipelined {
+                std::thread::scope(|scope| {
+                    let block = PipelinedReader::spawn(scope, (&input_file).take(length), PIPELINE_BUFFER_SIZE);
+                    encode_block_with_progress(block, &mut Limited::new(&mut output_file.file, limiter), length, &encoding_table, opts.buffer_size, opts.checksum, &mut progress)
+                })
+            } else {
+                let block = (&mut *reader).take(length);
+                encode_block_with_progress(block, &mut Limited::new(&mut output_file.file, limiter), length, &encoding_table, opts.buffer_size, opts.checksum, &mut progress)
+            })
+            .with_context(|| format!("failed to encode '{}' into '{}'", input, output))?;
+        stopwatch.lap(&mut report.encoding_time);
+        debug!("Padding bits: {}", stream.padding_bits);
+        report.input_bytes += length;
+        report.add_stream(&stream);
+
+        start += length;
+        if length < block_size || start >= size {
+            break;
+        }
+    }
+    if opts.drop_cache {
+        advise(&input_file, Advice::DontNeed);
+    }
+    output_file.commit(opts.fsync)
+        .with_context(|| format!("failed to save output '{}'", output))?;
+
+    report.set_frequencies(&counts);
+    progress.finish(report.progress());
+    info!("Encoding successful");
+    if opts.stats {
+        print_stats(&encode_stats(&report), opts.json);
+    }
+    Ok(())
+}
+
+// What the encode command does for an input: encode it and with --rm remove
+// it afterwards, or all of --paranoid. The question about an existing output
+// comes first, so a no leaves the input alone as well.
+pub fn encode_input(input_filename: &Path, output_filename: &Path, opts: &Options) -> HuffmanResult<()> {
+    encode_if_allowed(input_filename, output_filename, opts).map(|_| ())
+}
+
+// encode_input, which says whether the output was written
+fn encode_if_allowed(input_filename: &Path, output_filename: &Path, opts: &Options) -> HuffmanResult<bool> {
+    ensure_distinct_output(input_filename, output_filename)?;
+    if opts.rm || opts.paranoid {
+        ensure_regular_file(input_filename)?;
+    }
+    if !may_write(output_filename, opts)? {
+        return Ok(false);
+    }
+    // Asked once, here
+    let opts = Options { overwrite: None, force: true, ..opts.clone() };
+    // Before --rm or --paranoid remove the input
+    let mtime = match fs::metadata(input_filename) {
+        Ok(metadata) => preserved_mtime(&metadata, &opts),
+        Err(_) => None,
+    };
+    if opts.paranoid {
+        paranoid_encode(input_filename, output_filename, &opts)?;
+        set_mtime(output_filename, mtime)?;
+        return Ok(true);
+    }
+    encode(input_filename, output_filename, &opts)?;
+    set_mtime(output_filename, mtime)?;
+    remove_input(input_filename, &opts)?;
+    Ok(true)
+}
+
+// With --rm, removes the input once its output is there
+fn remove_input(input_filename: &Path, opts: &Options) -> HuffmanResult<()> {
+    if opts.rm {
+        fs::remove_file(input_filename)
+            .with_context(|| format!("failed to remove input '{}'", input_filename.display()))?;
+    }
+    Ok(())
+}
+
+// Encodes every input like each_input. With --dedupe the regular files are
+// hashed first, their length and XXH3-64, and the output of one that is the
+// same as an input already encoded is a hard link to the output of that one,
+// or a copy where a link can't be made, instead of the same bytes encoded
+// again. The options are the same for all of them, so the outputs would be
+// too. Only outputs that were written count, not the ones left alone, and
+// the end says how many inputs were skipped and how many bytes of input that
+// saved encoding.
+pub fn encode_inputs(opts: &Options) -> HuffmanResult<Status> {
+    if !opts.file_info.is_empty() && !opts.more_inputs.is_empty() {
+        return Err(HuffmanError::Usage(String::from("--stdin-name and --stdin-mtime are of a single input")));
+    }
+    if !opts.dedupe {
+        return each_input(opts, |input, output, opts| encode_input(input, output, opts).map(|()| Status::Complete));
+    }
+    let mut encoded: HashMap<(u64, u64), PathBuf> = HashMap::new();
+    let (mut skipped, mut saved) = (0u64, 0u64);
+    for (input, output) in outputs(opts)? {
+        let fingerprint = fingerprint(&input, opts)
+            .with_context(|| format!("failed to read input '{}'", input.display()))?;
+        let earlier = fingerprint.and_then(|key| encoded.get(&key));
+        match (earlier, fingerprint) {
+            (Some(earlier), Some((length, _))) => {
+                if link_output(&input, earlier, &output, opts)? {
+                    skipped += 1;
+                    saved += length;
+                }
+            }
+            _ => {
+                if encode_if_allowed(&input, &output, opts)? {
+                    if let Some(key) = fingerprint {
+                        encoded.insert(key, output);
+                    }
+                }
+            }
+        }
+    }
+    if skipped > 0 {
+        info!("Skipped {} duplicate inputs, {} bytes not encoded again", skipped, saved);
+    }
+    Ok(Status::Complete)
+}
+
+// The length and the XXH3-64 of a regular file, None for a pipe or a device,
+// which can't be read twice
+fn fingerprint(input_filename: &Path, opts: &Options) -> HuffmanResult<Option<(u64, u64)>> {
+    if !fs::metadata(input_filename).is_ok_and(|metadata| metadata.is_file()) {
+        return Ok(None);
+    }
+    let mut input_file = open_sequential(input_filename)?;
+    let mut buffer = vec![0; opts.buffer_size];
+    let mut hash = Xxh3::new();
+    let mut length = 0;
+    loop {
+        if let Some(cancel) = &opts.cancel {
+            cancel.check()?;
+        }
+        let read = match input_file.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        hash.update(&buffer[..read]);
+        length += read as u64;
+    }
+    Ok(Some((length, hash.finish_64())))
+}
+
+// The output of the duplicate `input_filename`, linked to `earlier`, the
+// output of the same bytes. With --preserve-timestamps a link would share the
+// time of the earlier output, so an input with another time gets a copy with
+// its own. Says whether it was written, like encode_if_allowed.
+fn link_output(input_filename: &Path, earlier: &Path, output_filename: &Path, opts: &Options) -> HuffmanResult<bool> {
+    ensure_distinct_output(input_filename, output_filename)?;
+    if !may_write(output_filename, opts)? {
+        return Ok(false);
+    }
+    let output = output_filename.display();
+    if output_filename.symlink_metadata().is_ok() {
+        fs::remove_file(output_filename).with_context(|| format!("failed to replace output '{}'", output))?;
+    }
+    let mtime = fs::metadata(input_filename).ok().and_then(|metadata| preserved_mtime(&metadata, opts));
+    let same_time = mtime.is_none() || fs::metadata(earlier).and_then(|metadata| metadata.modified()).ok() == mtime;
+    let sync = opts.fsync || opts.paranoid;
+    if !same_time || fs::hard_link(earlier, output_filename).is_err() {
+        fs::copy(earlier, output_filename)
+            .and_then(|_| if sync { File::open(output_filename)?.sync_all() } else { Ok(()) })
+            .with_context(|| format!("failed to copy '{}' to '{}'", earlier.display(), output))?;
+    }
+    if sync {
+        sync_parent(output_filename).with_context(|| format!("failed to sync output '{}'", output))?;
+    }
+    match same_time {
+        true => info!("'{}' is a duplicate, '{}' is linked to '{}'", input_filename.display(), output, earlier.display()),
+        false => {
+            set_mtime(output_filename, mtime)?;
+            info!("'{}' is a duplicate with another time, '{}' is a copy of '{}'", input_filename.display(), output, earlier.display());
+        }
+    }
+    remove_input(input_filename, opts)?;
+    Ok(true)
+}
+
+// --rm and --paranoid read or remove the input afterwards, which a pipe or a
+// device can't take. A missing input is left to encode to report.
+fn ensure_regular_file(input_filename: &Path) -> HuffmanResult<()> {
+    match fs::metadata(input_filename) {
+        Ok(metadata) if !metadata.is_file() => Err(HuffmanError::Usage(format!(
+            "--rm and --paranoid need a file, '{}' isn't one",
+            input_filename.display(),
+        ))),
+        _ => Ok(()),
+    }
+}
+
+// --paranoid, for data that can't be lost, one step after the other: keep an
+// existing output as <output>.bak, encode with --fsync, decode the output
+// again and compare it with the input as it is on disk, and only then remove
+// the backup and, with --rm, the input. The error names the step that
+// failed, and the input and the backup are still there after it; an output
+// that didn't decode to the input is removed.
+fn paranoid_encode(input_filename: &Path, output_filename: &Path, opts: &Options) -> HuffmanResult<()> {
+    let input = input_filename.display();
+    let output = output_filename.display();
+    let backup = match output_filename.symlink_metadata() {
+        Ok(_) => {
+            let mut backup = output_filename.as_os_str().to_owned();
+            backup.push(".bak");
+            let backup = PathBuf::from(backup);
+            fault("backup")
+                .and_then(|()| back_up(output_filename, &backup))
+                .with_context(|| format!("--paranoid stopped at backing up '{}' to '{}'", output, backup.display()))?;
+            Some(backup)
+        }
+        Err(_) => None,
+    };
+    let kept = || match &backup {
+        Some(backup) => format!(", the previous '{}' is kept as '{}'", output, backup.display()),
+        None => String::new(),
+    };
+
+    let encode_opts = Options { fsync: true, rm: false, paranoid: false, ..opts.clone() };
+    fault("encode")
+        .and_then(|()| encode(input_filename, output_filename, &encode_opts))
+        .with_context(|| format!("--paranoid stopped at encoding '{}'{}", input, kept()))?;
+
+    let verified = fault("verify").and_then(|()| verify_output(input_filename, output_filename, opts));
+    if verified.is_err() {
+        let _ = fs::remove_file(output_filename);
+    }
+    verified.with_context(|| format!("--paranoid stopped at verifying '{}', it is removed{}", output, kept()))?;
+    info!("Verified that '{}' decodes to '{}'", output, input);
+
+    if let Some(backup) = &backup {
+        fault("remove-backup")
+            .and_then(|()| Ok(fs::remove_file(backup)?))
+            .with_context(|| format!("--paranoid stopped at removing the backup '{}'", backup.display()))?;
+    }
+    if opts.rm {
+        fault("remove-input")
+            .and_then(|()| Ok(fs::remove_file(input_filename).and_then(|()| sync_parent(input_filename))?))
+            .with_context(|| format!("--paranoid stopped at removing the input '{}'", input))?;
+    }
+    Ok(())
+}
+
+// A hard link to the output where it is possible, a copy where not, on disk
+// before the output is replaced. Never over a file that is there already.
+fn back_up(output_filename: &Path, backup: &Path) -> HuffmanResult<()> {
+    if backup.symlink_metadata().is_ok() {
+        return Err(HuffmanError::Io(Error::new(io::ErrorKind::AlreadyExists, "the backup exists already")));
+    }
+    if fs::hard_link(output_filename, backup).is_err() {
+        fs::copy(output_filename, backup)?;
+        File::open(backup)?.sync_all()?;
+    }
+    Ok(sync_parent(backup)?)
+}
+
+// Decodes the output and compares the checksum and the length of what it
+// decodes to with those of the input, read again from the disk
+fn verify_output(input_filename: &Path, output_filename: &Path, opts: &Options) -> HuffmanResult<()> {
+    let mut original = ChecksumWriter::default();
+    io::copy(&mut File::open(input_filename)?, &mut original)?;
+    let mut reader = BufReader::with_capacity(opts.buffer_size, File::open(output_filename)?);
+    let header = decode_header(&mut reader)?;
+    let mut decoded = ChecksumWriter::default();
+    decode_stream(&mut reader, &mut decoded, header, &opts.decoder_options(None))?;
+    if (decoded.crc.finish(), decoded.length) != (original.crc.finish(), original.length) {
+        return Err(HuffmanError::CorruptData(format!(
+            "it decodes to {} bytes with checksum {:08x}, the input has {} bytes with checksum {:08x}",
+            decoded.length, decoded.crc.finish(), original.length, original.crc.finish(),
+        )));
+    }
+    Ok(())
+}
+
+// Keeps only the checksum and the length of what is written to it
+#[derive(Default)]
+struct ChecksumWriter {
+    crc: Crc32,
+    length: u64,
+}
+
+impl Write for ChecksumWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.crc.update(data);
+        self.length += data.len() as u64;
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Fails the step of --paranoid that HUFF_FAULT names, so the tests can see
+// what a failure there leaves behind. Release builds never fail this way.
+fn fault(step: &str) -> HuffmanResult<()> {
+    match cfg!(debug_assertions) && std::env::var_os("HUFF_FAULT").is_some_and(|fault| fault == step) {
+        true => Err(HuffmanError::Io(Error::other(format!("fault injected at {}", step)))),
+        false => Ok(()),
+    }
+}
+
+// encode --adaptive, --rle, --symbol-width, --tokens, --bwt, --context,
+// --method and --filter, through encode_stream.
+// --adaptive reads the input once as it comes, so a pipe is encoded without
+// keeping it in memory; the others work on a block at a time in memory.
+fn encode_with_options(
+    input_file: &File,
+    metadata: &fs::Metadata,
+    input_filename: &Path,
+    output_filename: &Path,
+    fixed_table: Option<EncodingTable>,
+    table_id: Option<u64>,
+    opts: &Options,
+) -> HuffmanResult<()> {
+    let input = input_filename.display();
+    let output = output_filename.display();
+    let mut builder = EncoderOptions::builder()
+        .adaptive(opts.adaptive)
+        .rle(opts.rle)
+        .symbol_width(opts.symbol_width)
+        .tokens(opts.tokens)
+        .bwt(opts.bwt)
+        .context(opts.context)
+        .method(opts.method)
+        .stride(opts.stride)
+        .checksum(opts.checksum)
+        .file_info(FileInfo { table_id, ..opts.file_info.clone() })
+        .buffer_size(opts.buffer_size);
+    if let Some(encoding_table) = fixed_table {
+        builder = builder.encoding_table(encoding_table);
+    }
+    if let Some(filter) = opts.filter {
+        builder = builder.filter(filter);
+    }
+    if let Some(block_size) = opts.block_size {
+        builder = builder.block_size(block_size);
+    }
+    if let Some(progress) = opts.progress_sink() {
+        builder = builder.progress(progress);
+    }
+    if let Some(cancel) = &opts.cancel {
+        builder = builder.cancel(cancel.clone());
+    }
+    let options = builder.build()?;
+
+    let limiter = opts.limit_rate.map(RateLimiter::new);
+    let mut output_file = OutputFile::create(output_filename, &metadata.permissions())
+        .with_context(|| format!("failed to create output '{}'", output))?;
+    let mut output_writer = Limited::new(&mut output_file.file, limiter.as_ref());
+    let report = encode_stream(Limited::new(input_file, limiter.as_ref()), &mut output_writer, &options)
+        .with_context(|| format!("failed to encode '{}' into '{}'", input, output))?;
+    if opts.drop_cache {
+        advise(input_file, Advice::DontNeed);
+    }
+    output_file.commit(opts.fsync)
+        .with_context(|| format!("failed to save output '{}'", output))?;
+
+    info!("Encoding successful");
+    if opts.stats {
+        print_stats(&encode_stats(&report), opts.json);
+    }
+    Ok(())
+}
+
+pub fn decode(input_filename: &Path, output_filename: &Path, opts: &Options) -> HuffmanResult<Status> {
+    ensure_distinct_output(input_filename, output_filename)?;
+    if !may_write(output_filename, opts)? {
+        return Ok(Status::Complete);
+    }
+    let input = input_filename.display();
+    let output = output_filename.display();
+
+    // Decode Header
+    let input_file = open_sequential(input_filename)
+        .with_context(|| format!("failed to open input '{}'", input))?;
+    let metadata = input_file.metadata()
+        .with_context(|| format!("failed to read input '{}'", input))?;
+    let permissions = metadata.permissions();
+    let limiter = opts.limit_rate.map(RateLimiter::new);
+    let mut reader = BufReader::with_capacity(opts.buffer_size, Limited::new(input_file, limiter.as_ref()));
+    let header = decode_header(&mut reader)
+        .with_context(|| format!("failed to read header of '{}'", input))?;
+    if let Some(path) = &opts.table_file {
+        check_table_file(&header, path)
+            .with_context(|| format!("failed to decode '{}'", input))?;
+    }
+    let mtime = header.info.mtime;
+    // The time kept in the header wins over the one of the encoded file
+    let input_mtime = if mtime.is_none() { preserved_mtime(&metadata, opts) } else { None };
+    debug!("Header - version: {}, entries: {}, padding: {}",
+        header.version, header.num_entries, header.padding_bits
+    );
+    log_encoding_table(&header.encoding_table);
+
+    // All streams together when they say how long they are, otherwise at least
+    // the first one
+    let declared_length = declared_output_length(input_filename, &header);
+    if let (Some(length), Some(max)) = (declared_length.or(header.original_length), opts.max_output_size) {
+        if length > max {
+            return Err(HuffmanError::Context {
+                context: format!("failed to decode '{}'", input),
+                source: Box::new(HuffmanError::OutputTooLarge { length: Some(length), max }),
+            });
+        }
+    }
+
+    // Write decoded data to output_file
+    let mut output_file = OutputFile::create(output_filename, &permissions)
+        .with_context(|| format!("failed to create output '{}'", output))?;
+    if let Some(length) = declared_length {
+        preallocate(&output_file.file, length)
+            .with_context(|| format!("failed to reserve {} bytes for output '{}'", length, output))?;
+    }
+    let original_length = declared_length.or(header.original_length);
+    // The options hold the only reference to the bar, which is removed when
+    // they are dropped, before any message about the result
+    let options = opts.decoder_options(opts.progress_sink());
+    let result = decode_stream(&mut reader, &mut Limited::new(&mut output_file.file, limiter.as_ref()), header, &options)
+        .with_context(|| format!("failed to decode '{}' into '{}'", input, output));
+    drop(options);
+    if opts.drop_cache {
+        advise(reader.get_ref().get_ref(), Advice::DontNeed);
+    }
+    // Drops the part of the preallocation that wasn't decoded into
+    let written = output_file.file.stream_position()?;
+    output_file.file.set_len(written)?;
+
+    // Without commit() the output file is removed again, so a failure doesn't
+    // leave a partially decoded file behind
+    match result {
+        Ok(report) => {
+            // The time encode kept of its input, after the last write
+            if let Some(mtime) = mtime.and_then(system_time) {
+                output_file.file.set_modified(mtime)
+                    .with_context(|| format!("failed to set the modification time of output '{}'", output))?;
+            }
+            output_file.commit(opts.fsync)
+                .with_context(|| format!("failed to save output '{}'", output))?;
+            set_mtime(output_filename, input_mtime)?;
+            info!("Decoding successful");
+            if opts.stats {
+                print_stats(&decode_stats(&report), opts.json);
+            }
+            Ok(Status::Complete)
+        }
+        // Limits and Ctrl-C are not data errors, so they are never ignored
+        Err(e) if opts.ignore_errors && !matches!(e.root(), HuffmanError::OutputTooLarge { .. } | HuffmanError::Cancelled) => {
+            let recovered = written;
+            output_file.commit(opts.fsync)
+                .with_context(|| format!("failed to save output '{}'", output))?;
+            let expected = match original_length {
+                Some(length) => format!("{} expected", length),
+                None => String::from("expected size unknown"),
+            };
+            warn!("{}", e);
+            warn!("recovered only {} bytes ({}) into '{}'", recovered, expected, output);
+            Ok(Status::Partial)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+// With --preserve-timestamps, the modification time of an input for its
+// output, so tools that compare times see the two as the same. None for a
+// pipe or a device, whose time says nothing about the data.
+fn preserved_mtime(metadata: &fs::Metadata, opts: &Options) -> Option<SystemTime> {
+    match opts.preserve_timestamps && metadata.is_file() {
+        true => metadata.modified().ok(),
+        false => None,
+    }
+}
+
+// Gives a saved output the time of preserved_mtime. It comes after the rename
+// of OutputFile::commit, which would otherwise be the last change to the
+// output, and only the owner of a file can set its time, which it is.
+fn set_mtime(output_filename: &Path, mtime: Option<SystemTime>) -> HuffmanResult<()> {
+    let Some(mtime) = mtime else {
+        return Ok(());
+    };
+    File::open(output_filename)
+        .and_then(|output_file| output_file.set_modified(mtime))
+        .with_context(|| format!("failed to set the modification time of output '{}'", output_filename.display()))
+}
+
+// Seconds since the Unix epoch as a time, None for one too far off for it
+fn system_time(seconds: i64) -> Option<SystemTime> {
+    let offset = Duration::from_secs(seconds.unsigned_abs());
+    match seconds < 0 {
+        true => UNIX_EPOCH.checked_sub(offset),
+        false => UNIX_EPOCH.checked_add(offset),
+    }
+}
+
+// Writes the table encode would use for the whole input, as a file for
+// --table-file
+pub fn table(input_filename: &Path, output_filename: &Path, opts: &Options) -> HuffmanResult<()> {
+    ensure_distinct_output(input_filename, output_filename)?;
+    if !may_write(output_filename, opts)? {
+        return Ok(());
+    }
+    let input = input_filename.display();
+    let output = output_filename.display();
+
+    let input_file = open_sequential(input_filename)
+        .with_context(|| format!("failed to open input '{}'", input))?;
+    let metadata = input_file.metadata()
+        .with_context(|| format!("failed to read input '{}'", input))?;
+    let frequencies = calculate_frequencies_with_progress(&input_file, opts.buffer_size, &mut Progress::new(&NoProgress, opts.cancel.as_ref(), None))
+        .with_context(|| format!("failed to read input '{}'", input))?;
+    // The table is for other data, which can have bytes this input hasn't
+    let encoding_table = escaped_codes(&frequencies);
+    log_encoding_table(&encoding_table);
+
+    let mut output_file = OutputFile::create(output_filename, &metadata.permissions())
+        .with_context(|| format!("failed to create output '{}'", output))?;
+    let table_file = TableFile { encoding_table, id: Some(new_table_id()) };
+    write_table_file(&table_file, opts.table_format, &mut output_file.file)
+        .and_then(|()| output_file.commit(opts.fsync))
+        .with_context(|| format!("failed to save output '{}'", output))?;
+    info!("Table of {} codes written", table_file.encoding_table.len());
+    Ok(())
+}
+
+// The table of a file of the table command, and the random id it was saved
+// with. Encoding with it keeps the id in the first stream, and decode
+// --table-file checks the data has the id and the codes of the file it is
+// given, so data can't be decoded with the wrong table without an error.
+// Tables saved before there were ids have none.
+//
+// A binary table is
+//   "HUFT" | id: u64 | hash: u64 | num_entries: u32 | entries
+//          | (escape_length: u8 | escape_bits: u32)?
+// with the entries of a header, and the hash the XXH3-64 of num_entries, the
+// entries and the escape code. Without "HUFT" it is a table from before,
+// which starts at num_entries and has no escape code. A text table has the id
+// and the hash on the first line, `# table <id> <hash>` in hex, which older
+// versions read as a comment.
+struct TableFile {
+    encoding_table: EncodingTable,
+    id: Option<u64>,
+}
+
+const TABLE_MAGIC: &[u8; 4] = b"HUFT";
+const TABLE_COMMENT: &str = "# table ";
+
+// A new table id, from the random keys std gives every HashMap and the time
+fn new_table_id() -> u64 {
+    use std::hash::{BuildHasher, Hasher};
+    let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+    hasher.write_u128(SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos());
+    hasher.finish()
+}
+
+// The number of entries, the entries and the escape code, as a binary table
+// has them
+fn table_bytes(encoding_table: &EncodingTable) -> Vec<u8> {
+    let mut bytes = (encoding_table.len() as u32).to_le_bytes().to_vec();
+    encoding_table.serialize(&mut bytes)
+        .and_then(|()| encoding_table.write_escape(&mut bytes))
+        .expect("writing to a Vec can't fail");
+    bytes
+}
+
+// The same hash for a binary and a text table with the same codes
+fn table_hash(encoding_table: &EncodingTable) -> u64 {
+    let mut hash = Xxh3::new();
+    hash.update(&table_bytes(encoding_table));
+    hash.finish_64()
+}
+
+fn write_table_file(table_file: &TableFile, format: TableFormat, writer: &mut impl Write) -> io::Result<()> {
+    let encoding_table = &table_file.encoding_table;
+    match (format, table_file.id) {
+        (TableFormat::Binary, Some(id)) => {
+            writer.write_all(TABLE_MAGIC)?;
+            writer.write_all(&id.to_le_bytes())?;
+            writer.write_all(&table_hash(encoding_table).to_le_bytes())?;
+            writer.write_all(&table_bytes(encoding_table))
+        }
+        (TableFormat::Binary, None) => writer.write_all(&table_bytes(encoding_table)),
+        (TableFormat::Text, Some(id)) => {
+            writeln!(writer, "{}{:016x} {:016x}", TABLE_COMMENT, id, table_hash(encoding_table))?;
+            writer.write_all(encoding_table.to_text().as_bytes())
+        }
+        (TableFormat::Text, None) => writer.write_all(encoding_table.to_text().as_bytes()),
+    }
+}
+
+// Text when the name ends in .txt, like the .huft.txt of --format text. Either
+// way the table has to be a prefix code. A table whose codes don't match its
+// hash was changed after it was saved, it is still read, without its id.
+fn read_table_file(path: &Path) -> HuffmanResult<TableFile> {
+    let data = fs::read(path)?;
+    let (encoding_table, saved) = match path.extension().is_some_and(|extension| extension == "txt") {
+        true => {
+            let text = std::str::from_utf8(&data)
+                .map_err(|_| HuffmanError::InvalidTable(String::from("the text isn't UTF-8")))?;
+            (EncodingTable::from_text(text)?, text.lines().next().and_then(parse_table_comment))
+        }
+        false => {
+            let mut reader = &data[..];
+            let saved = match reader.strip_prefix(TABLE_MAGIC) {
+                Some(mut rest) => {
+                    let id = u64::from_le_bytes(read_field_of(&mut rest, "table id")?);
+                    let hash = u64::from_le_bytes(read_field_of(&mut rest, "table hash")?);
+                    reader = rest;
+                    Some((id, hash))
+                }
+                None => None,
+            };
+            let num_entries = read_u32(&mut reader, &mut 0, "number of entries")?;
+            let mut encoding_table = EncodingTable::read_entries(&mut reader, &mut 4, num_entries, 1)?;
+            if saved.is_some() && !reader.is_empty() {
+                encoding_table = encoding_table.with_escape(EncodingTable::read_escape(&mut reader, &mut 0)?);
+            }
+            if !reader.is_empty() {
+                return Err(HuffmanError::InvalidTable(format!("{} bytes after the entries", reader.len())));
+            }
+            encoding_table.validate()?;
+            (encoding_table, saved)
+        }
+    };
+    let id = match saved {
+        Some((id, hash)) if hash == table_hash(&encoding_table) => Some(id),
+        Some((id, _)) => {
+            warn!("table {:016x} in '{}' was changed after it was saved, its id is left out", id, path.display());
+            None
+        }
+        None => None,
+    };
+    Ok(TableFile { encoding_table, id })
+}
+
+// The id and the hash of `# table <id> <hash>`
+fn parse_table_comment(line: &str) -> Option<(u64, u64)> {
+    let (id, hash) = line.trim().strip_prefix(TABLE_COMMENT)?.split_once(' ')?;
+    Some((u64::from_str_radix(id, 16).ok()?, u64::from_str_radix(hash.trim(), 16).ok()?))
+}
+
+// N bytes of a binary table, which ends too soon without them
+fn read_field_of<const N: usize>(reader: &mut &[u8], field: &str) -> HuffmanResult<[u8; N]> {
+    match reader.split_first_chunk::<N>() {
+        Some((bytes, rest)) => {
+            *reader = rest;
+            Ok(*bytes)
+        }
+        None => Err(HuffmanError::InvalidTable(format!("the file ends in the {}", field))),
+    }
+}
+
+// decode --table-file: the first stream has to have the id of the table file
+// and the same codes, before anything is decoded
+fn check_table_file(header: &Header, path: &Path) -> HuffmanResult<()> {
+    let table_file = read_table_file(path)
+        .with_context(|| format!("failed to read table '{}'", path.display()))?;
+    let path = path.display();
+    let Some(id) = table_file.id else {
+        return Err(HuffmanError::TableMismatch(format!("'{}' has no table id, save it again with the table command", path)));
+    };
+    match header.info.table_id {
+        None => Err(HuffmanError::TableMismatch(format!("the data wasn't encoded with a table file, '{}' is table {:016x}", path, id))),
+        Some(encoded_with) if encoded_with != id => Err(HuffmanError::TableMismatch(format!(
+            "the data was encoded with table {:016x}, '{}' is table {:016x}", encoded_with, path, id,
+        ))),
+        Some(_) if table_hash(&header.encoding_table) != table_hash(&table_file.encoding_table) => Err(HuffmanError::TableMismatch(
+            format!("the data has other codes than table {:016x} in '{}'", id, path),
+        )),
+        Some(_) => Ok(()),
+    }
+}
+
+// Appends the encoded files after the first one to it, into a single stream
+// that decodes to all of them one after the other. They need the same table,
+// like files encoded with the same --table-file, see merge_streams.
+pub fn merge(input_filenames: &[PathBuf], output_filename: &Path, opts: &Options) -> HuffmanResult<()> {
+    if input_filenames.len() < 2 {
+        return Err(HuffmanError::Usage(String::from("merge needs at least two inputs")));
+    }
+    if !may_write(output_filename, opts)? {
+        return Ok(());
+    }
+    let output = output_filename.display();
+    let mut inputs = Vec::with_capacity(input_filenames.len());
+    for input_filename in input_filenames {
+        ensure_distinct_output(input_filename, output_filename)?;
+        let input_file = File::open(input_filename)
+            .with_context(|| format!("failed to open input '{}'", input_filename.display()))?;
+        inputs.push(BufReader::with_capacity(opts.buffer_size, input_file));
+    }
+    let permissions = inputs[0].get_ref().metadata()
+        .with_context(|| format!("failed to read input '{}'", input_filenames[0].display()))?
+        .permissions();
+
+    let mut output_file = OutputFile::create(output_filename, &permissions)
+        .with_context(|| format!("failed to create output '{}'", output))?;
+    let header = merge_streams(&mut inputs, &mut output_file.file, opts.buffer_size)
+        .with_context(|| format!("failed to merge into '{}'", output))?;
+    output_file.commit(opts.fsync)
+        .with_context(|| format!("failed to save output '{}'", output))?;
+    info!("Merged {} files, which decode to {} bytes", input_filenames.len(), header.original_length.unwrap_or(0));
+    Ok(())
+}
+
+// Rewrites a file of an old version in the current one, in place unless -o
+// names another output. A single stream of version 0, 1 or 2 keeps its
+// payload, see upgrade_stream, and old streams followed by others are decoded
+// and encoded again. Either way the output has to decode to what the input
+// does before it replaces anything, and it gets the permissions and the
+// modification time of the input. A file in the current version is left
+// alone, or copied to -o as it is.
+pub fn upgrade(input_filename: &Path, output_filename: &Path, opts: &Options) -> HuffmanResult<Status> {
+    let in_place = input_filename == output_filename;
+    if !in_place {
+        ensure_distinct_output(input_filename, output_filename)?;
+        if !may_write(output_filename, opts)? {
+            return Ok(Status::Complete);
+        }
+    }
+    let input = input_filename.display();
+    let output = output_filename.display();
+    // Nothing is written while checking, so the size limit doesn't apply
+    let check_options = DecoderOptions { max_output_size: None, ..opts.decoder_options(None) };
+
+    let input_file = File::open(input_filename)
+        .with_context(|| format!("failed to open input '{}'", input))?;
+    let metadata = input_file.metadata()
+        .with_context(|| format!("failed to read input '{}'", input))?;
+    let mut reader = BufReader::with_capacity(opts.buffer_size, input_file);
+    let header = decode_header(&mut reader)
+        .with_context(|| format!("failed to read header of '{}'", input))?;
+    let mut original = ChecksumWriter::default();
+    let decoded = decode_stream(&mut reader, &mut original, header.clone(), &check_options)
+        .with_context(|| format!("failed to decode '{}'", input))?;
+    if header.version >= VERSION && in_place {
+        info!("'{}' is version {} already, nothing to upgrade", input, header.version);
+        return Ok(Status::Complete);
+    }
+
+    let mut output_file = OutputFile::create(output_filename, &metadata.permissions())
+        .with_context(|| format!("failed to create output '{}'", output))?;
+    let reencoded = header.version < VERSION && decoded.streams > 1;
+    if header.version >= VERSION {
+        io::copy(&mut File::open(input_filename)?, &mut output_file.file)
+            .with_context(|| format!("failed to copy '{}' to '{}'", input, output))?;
+    } else if !reencoded {
+        let mut reader = BufReader::with_capacity(opts.buffer_size, File::open(input_filename)?);
+        upgrade_stream(&mut reader, &mut output_file.file, &decoded, original.crc.finish(), opts.buffer_size)
+            .with_context(|| format!("failed to upgrade '{}' into '{}'", input, output))?;
+    } else {
+        reencode(input_filename, &metadata.permissions(), &mut output_file, opts)
+            .with_context(|| format!("failed to encode '{}' again into '{}'", input, output))?;
+    }
+
+    let mut reader = BufReader::with_capacity(opts.buffer_size, File::open(output_file.temp_filename())?);
+    let upgraded = decode_header(&mut reader)?;
+    let mut roundtrip = ChecksumWriter::default();
+    let verified = decode_stream(&mut reader, &mut roundtrip, upgraded.clone(), &check_options).and_then(|_| {
+        match (roundtrip.crc.finish(), roundtrip.length) == (original.crc.finish(), original.length) {
+            true => Ok(()),
+            false => Err(HuffmanError::CorruptData(format!(
+                "it decodes to {} bytes with checksum {:08x}, the input to {} bytes with checksum {:08x}",
+                roundtrip.length, roundtrip.crc.finish(), original.length, original.crc.finish(),
+            ))),
+        }
+    });
+    verified.with_context(|| format!("the upgrade of '{}' doesn't decode to what it does, nothing is written", input))?;
+    output_file.commit(opts.fsync)
+        .with_context(|| format!("failed to save output '{}'", output))?;
+    set_mtime(output_filename, metadata.modified().ok())?;
+
+    match (header.version >= VERSION, reencoded) {
+        (true, _) => info!("'{}' is version {} already, copied it to '{}'", input, header.version, output),
+        (false, false) => info!("Upgraded '{}' from version {} to version {}, keeping its payload", input, header.version, upgraded.version),
+        (false, true) => info!("Upgraded '{}' from version {} to version {}, encoding it again", input, header.version, upgraded.version),
+    }
+    Ok(Status::Complete)
+}
+
+// The data of the file encoded again into `output_file`, with the --checksum
+// and --block-size of the command. It is decoded next to the output first,
+// into a file with the permissions of the input like any output, which is
+// removed again.
+fn reencode(input_filename: &Path, permissions: &fs::Permissions, output_file: &mut OutputFile, opts: &Options) -> HuffmanResult<()> {
+    let mut reader = BufReader::with_capacity(opts.buffer_size, File::open(input_filename)?);
+    let header = decode_header(&mut reader)?;
+    let mut decoded_file = OutputFile::create(&sibling_filename(output_file.temp_filename(), ".decoded"), permissions)?;
+    let mut writer = BufWriter::with_capacity(opts.buffer_size, &mut decoded_file.file);
+    decode_stream(&mut reader, &mut writer, header, &opts.decoder_options(None))?;
+    writer.flush()?;
+    drop(writer);
+
+    let mut builder = EncoderOptions::builder().checksum(opts.checksum).buffer_size(opts.buffer_size);
+    if let Some(block_size) = opts.block_size {
+        builder = builder.block_size(block_size);
+    }
+    encode_stream(File::open(decoded_file.temp_filename())?, &mut output_file.file, &builder.build()?)?;
+    Ok(())
+}
+
+// Decodes whatever is readable and encodes it again into a valid file. The
+// streams of the file, like the blocks of --block-size, are decoded one at a
+// time: one that fails its checksum or is otherwise damaged is left out as a
+// whole and the ones after it are kept, while one cut short by the end of the
+// file keeps what it decoded. The bytes of the original data that are missing
+// are reported by their offsets in it. A damaged header ends it, since the
+// streams after it can't be found.
+pub fn repair(input_filename: &Path, output_filename: &Path, opts: &Options) -> HuffmanResult<Status> {
+    ensure_distinct_output(input_filename, output_filename)?;
+    if !may_write(output_filename, opts)? {
+        return Ok(Status::Complete);
+    }
+    // Asked once, here
+    let opts = &Options { overwrite: None, force: true, ..opts.clone() };
+
+    let input = input_filename.display();
+    let input_file = File::open(input_filename)
+        .with_context(|| format!("failed to open input '{}'", input))?;
+    let metadata = input_file.metadata()?;
+    let file_size = metadata.len();
+    let mut reader = BufReader::with_capacity(opts.buffer_size, input_file);
+    let mut header = decode_header(&mut reader)
+        .with_context(|| format!("failed to read header of '{}'", input))?;
+    let total = declared_output_length(input_filename, &header);
+
+    // The data that could be decoded, in a file with the permissions of the
+    // input that is removed again
+    let mut salvaged = OutputFile::create(&sibling_filename(output_filename, ".salvaged"), &metadata.permissions())?;
+    let options = DecoderOptions { max_output_size: None, ..opts.decoder_options(None) };
+    let mut missing = Vec::new();
+    // Where the stream starts in the file and its data in the original
+    let (mut start, mut offset) = (0u64, 0u64);
+    let unreadable = loop {
+        let written = salvaged.file.stream_position()?;
+        let length = header.original_length;
+        let payload_length = match decode_single_stream(&mut reader, &mut salvaged.file, &mut header, &options) {
+            // Version 0 streams have no length and run to the end of the file
+            Ok((_, decoded)) if header.original_length.is_none() => {
+                offset += decoded;
+                None
+            }
+            Ok((bits, decoded)) => {
+                offset += decoded;
+                Some(header.payload_length.unwrap_or(bits.div_ceil(8)))
+            }
+            Err(e) if matches!(e.root(), HuffmanError::Cancelled) => return Err(e),
+            Err(e) => {
+                warn!("Stream at byte {} of '{}' is damaged: {}", start, input, e);
+                // A stream cut short decoded its start correctly, what a
+                // damaged one decoded can't be trusted
+                let kept = match e.root() {
+                    HuffmanError::TruncatedData(_) => salvaged.file.stream_position()? - written,
+                    _ => 0,
+                };
+                salvaged.file.set_len(written + kept)?;
+                salvaged.file.seek(SeekFrom::Start(written + kept))?;
+                if let Some(length) = length {
+                    missing.push((offset + kept, offset + length));
+                    offset += length;
+                }
+                // Without a payload length the next stream can't be found
+                length.and(header.payload_length)
+            }
+        };
+        let Some(payload_length) = payload_length else {
+            break None;
+        };
+        let end = start + header.serialized_len() + payload_length + header.trailer_len();
+        match next_header(&mut reader, end, file_size) {
+            Ok(Some(next)) => {
+                header = next;
+                start = end;
+            }
+            Ok(None) => break None,
+            Err(e) => break Some(e),
+        }
+    };
+
+    let recovered = salvaged.file.stream_position()?;
+    encode(salvaged.temp_filename(), output_filename, opts)?;
+    drop(salvaged);
+
+    for (from, to) in &missing {
+        warn!("Missing bytes {}..{} of the original data", from, to);
+    }
+    if let Some(e) = unreadable {
+        warn!("Missing the data after byte {}, the rest of '{}' can't be read: {}", offset, input, e);
+        return Ok(Status::Partial);
+    }
+    match total {
+        _ if !missing.is_empty() => Ok(Status::Partial),
+        Some(length) if recovered < length => {
+            warn!("Missing bytes {}..{} of the original data", recovered, length);
+            Ok(Status::Partial)
+        }
+        Some(_) => {
+            info!("Nothing is missing, all {} bytes were recovered", recovered);
+            Ok(Status::Complete)
+        }
+        None => {
+            info!("Recovered {} bytes, this file doesn't record how many are missing", recovered);
+            Ok(Status::Complete)
+        }
+    }
+}
+
+// Testing
+////////////////////////////////////////////////////////////////////////////////
+
+// Decodes the file, or with --recursive every encoded file under the
+// directory, without writing anything: the headers, every stream of
+// concatenated files and the checksums. Each file gets a line as it is done,
+// and the summary on stdout ends with the files that failed, which fail the
+// command. Under a directory only the files that start like an encoded file
+// are tested, the rest are skipped.
+pub fn test(input_filename: &Path, opts: &Options) -> HuffmanResult<()> {
+    let input = input_filename.display();
+    let metadata = fs::metadata(input_filename)
+        .with_context(|| format!("failed to open input '{}'", input))?;
+    let mut files = Vec::new();
+    let mut skipped = 0;
+    match (metadata.is_dir(), opts.recursive) {
+        (true, true) => {
+            let mut found = Vec::new();
+            walk(input_filename, &mut found)?;
+            for path in found {
+                match is_encoded(&path) {
+                    true => files.push(path),
+                    false => skipped += 1,
+                }
+            }
+        }
+        (true, false) => {
+            return Err(HuffmanError::Usage(format!("'{}' is a directory, test it with --recursive", input)));
+        }
+        (false, _) => files.push(input_filename.to_path_buf()),
+    }
+
+    let style = Style::stderr();
+    let mut failed = Vec::new();
+    for (i, path) in files.iter().enumerate() {
+        let counter = format!("[{}/{}]", i + 1, files.len());
+        match test_file(path, opts) {
+            Ok(report) => info!(
+                "{} {}: {}, {} bytes in {} streams",
+                counter, path.display(), style.green("OK"), report.output_bytes, report.streams,
+            ),
+            // Ctrl-C stops the whole run
+            Err(e) if matches!(e.root(), HuffmanError::Cancelled) => return Err(e),
+            Err(e) => {
+                warn!("{} {}: {}, {}", counter, path.display(), style.red("FAILED"), e);
+                failed.push(path);
+            }
+        }
+    }
+
+    let style = Style::stdout();
+    println!("Passed: {}", style.bold(&(files.len() - failed.len()).to_string()));
+    match failed.len() {
+        0 => println!("Failed: {}", style.bold("0")),
+        count => println!("Failed: {}", style.red(&count.to_string())),
+    }
+    if skipped > 0 {
+        println!("Skipped: {} (not encoded files)", style.bold(&skipped.to_string()));
+    }
+    for path in &failed {
+        println!("  {}", style.red(&path.display().to_string()));
+    }
+    match failed.len() {
+        0 => Ok(()),
+        count => Err(HuffmanError::CorruptData(format!("{} of {} files failed the test", count, files.len()))),
+    }
+}
+
+// The files under `dir` and its subdirectories, in order. Links to
+// directories aren't followed, so a link back up can't loop.
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> HuffmanResult<()> {
+    let mut entries = fs::read_dir(dir)
+        .and_then(|entries| entries.map(|entry| entry.map(|entry| entry.path())).collect::<io::Result<Vec<_>>>())
+        .with_context(|| format!("failed to read directory '{}'", dir.display()))?;
+    entries.sort();
+    for path in entries {
+        let file_type = fs::symlink_metadata(&path)
+            .with_context(|| format!("failed to read '{}'", path.display()))?
+            .file_type();
+        if file_type.is_dir() {
+            walk(&path, files)?;
+        } else if fs::metadata(&path).is_ok_and(|metadata| metadata.is_file()) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+// Whether the file starts with the magic of a header. Files that can't be
+// read are tested, so they are reported.
+fn is_encoded(path: &Path) -> bool {
+    let mut magic = [0u8; 4];
+    match File::open(path).and_then(|mut file| file.read_exact(&mut magic)) {
+        Ok(()) => &magic == MAGIC,
+        Err(e) => e.kind() != io::ErrorKind::UnexpectedEof,
+    }
+}
+
+// The errors don't name the file, the line of the file does
+fn test_file(path: &Path, opts: &Options) -> HuffmanResult<DecodeReport> {
+    let mut reader = BufReader::with_capacity(opts.buffer_size, open_sequential(path)?);
+    let header = decode_header(&mut reader)?;
+    let options = opts.decoder_options(opts.progress_sink());
+    decode_stream(&mut reader, &mut io::sink(), header, &options)
+}
+
+// Ratios
+////////////////////////////////////////////////////////////////////////////////
+
+// The encoded files of a directory, or some of them
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct Savings {
+    files: u64,
+    original: u64,
+    compressed: u64,
+}
+
+impl Savings {
+    fn add(&mut self, original: u64, compressed: u64) {
+        self.files += 1;
+        self.original += original;
+        self.compressed += compressed;
+    }
+
+    // The share of the original that encoding saved, negative when the
+    // files grew
+    fn saved(&self) -> String {
+        match self.original {
+            0 => String::from("-"),
+            original => format!("{:.1}%", 100.0 - self.compressed as f64 * 100.0 / original as f64),
+        }
+    }
+}
+
+// Prints how much encoding saved under a directory, like du: for every
+// directory the original and the encoded sizes of the encoded files in it and
+// its subdirectories, and the total. Only the headers are read, the original
+// size from them and the encoded size from the length of the file. Files
+// without their original size, like adaptive ones that only store it at the
+// end, are counted apart, and so are files that aren't encoded.
+pub fn ratio(input_filename: &Path, opts: &Options) -> HuffmanResult<()> {
+    let metadata = fs::metadata(input_filename)
+        .with_context(|| format!("failed to open input '{}'", input_filename.display()))?;
+    let mut files = Vec::new();
+    match metadata.is_dir() {
+        true => walk(input_filename, &mut files)?,
+        false => files.push(input_filename.to_path_buf()),
+    }
+
+    let mut directories: HashMap<PathBuf, Savings> = HashMap::new();
+    let mut unknown = Savings::default();
+    let mut not_encoded = 0u64;
+    for path in &files {
+        if let Some(cancel) = &opts.cancel {
+            cancel.check()?;
+        }
+        if !is_encoded(path) {
+            not_encoded += 1;
+            continue;
+        }
+        let compressed = fs::metadata(path).map_or(0, |metadata| metadata.len());
+        let original = match original_size(path, opts) {
+            Ok(original) => original,
+            Err(e) => {
+                warn!("{}: {}", path.display(), e);
+                None
+            }
+        };
+        let Some(original) = original else {
+            unknown.add(0, compressed);
+            continue;
+        };
+        // The file counts for every directory up to the one given
+        let mut dir = match metadata.is_dir() {
+            true => path.parent(),
+            false => Some(path.as_path()),
+        };
+        while let Some(current) = dir {
+            directories.entry(current.to_path_buf()).or_default().add(original, compressed);
+            if current == input_filename {
+                break;
+            }
+            dir = current.parent();
+        }
+    }
+
+    let total = directories.get(input_filename).copied().unwrap_or_default();
+    let mut directories: Vec<(PathBuf, Savings)> = directories.into_iter().collect();
+    directories.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let style = Style::stdout();
+    println!("{:>14} {:>14} {:>7}  Directory", "Original", "Encoded", "Saved");
+    for (dir, savings) in &directories {
+        println!("{:>14} {:>14} {:>7}  {}", savings.original, savings.compressed, savings.saved(), dir.display());
+    }
+    println!(
+        "Total: {} bytes in {} files encoded to {} bytes, {} saved",
+        total.original, total.files, total.compressed, style.bold(&total.saved()),
+    );
+    if unknown.files > 0 {
+        println!("Unknown original size: {} files, {} bytes", unknown.files, unknown.compressed);
+    }
+    if not_encoded > 0 {
+        println!("Not encoded: {} files", not_encoded);
+    }
+    Ok(())
+}
+
+// The original size the headers of the file declare, None when one of its
+// streams doesn't
+fn original_size(path: &Path, opts: &Options) -> HuffmanResult<Option<u64>> {
+    let mut reader = BufReader::with_capacity(opts.buffer_size, File::open(path)?);
+    let header = decode_header(&mut reader)?;
+    Ok(declared_output_length(path, &header))
+}
+
+// Self-test
+////////////////////////////////////////////////////////////////////////////////
+
+// The flags of a case of selftest and what they change in the options
+type Variant = (&'static str, fn(&mut Options));
+
+// The options selftest encodes every input with, on top of the defaults
+const SELFTEST_VARIANTS: &[Variant] = &[
+    ("default", |_| {}),
+    ("--block-size 4K", |opts| opts.block_size = Some(4096)),
+    ("--adaptive", |opts| opts.adaptive = true),
+    ("--rle", |opts| opts.rle = true),
+    ("--symbol-width 2", |opts| opts.symbol_width = 2),
+    ("--tokens", |opts| opts.tokens = true),
+    ("--bwt", |opts| opts.bwt = true),
+    ("--context", |opts| opts.context = true),
+    ("--method range", |opts| opts.method = Method::Range),
+    ("--filter delta", |opts| opts.filter = Some(Filter::Delta)),
+    ("--checksum xxh3", |opts| opts.checksum = ChecksumKind::Xxh3),
+    ("--checksum none", |opts| opts.checksum = ChecksumKind::None),
+];
+
+// The inputs of selftest, small enough that all of it takes a moment
+fn selftest_inputs() -> Vec<(&'static str, Vec<u8>)> {
+    // xorshift, the same data on every machine
+    let mut state = 0x5E1F_7E57u32;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state
+    };
+    const WORDS: &[&str] = &["the", "of", "and", "a", "to", "in", "is", "code", "table", "tree", "bits", "huffman", "encoded"];
+    let mut text = Vec::new();
+    while text.len() < 16 * 1024 {
+        text.extend_from_slice(WORDS[next() as usize % WORDS.len()].as_bytes());
+        text.push(if next() % 12 == 0 { b'\n' } else { b' ' });
+    }
+    let random: Vec<u8> = (0..16 * 1024).map(|_| next() as u8).collect();
+    let mut runs = Vec::new();
+    while runs.len() < 16 * 1024 {
+        let byte = b"ab\0\xff"[next() as usize % 4];
+        runs.extend(std::iter::repeat_n(byte, 1 + next() as usize % 300));
+    }
+    vec![("text", text), ("random", random), ("runs", runs), ("empty", Vec::new()), ("single-byte", vec![b'x'])]
+}
+
+// A directory of its own under the temporary one, removed with everything in
+// it when dropped, also when selftest fails or is cancelled
+struct ScratchDir(PathBuf);
+
+impl ScratchDir {
+    fn new() -> HuffmanResult<Self> {
+        let path = std::env::temp_dir().join(format!("huff-selftest-{}", std::process::id()));
+        // What a run killed before it could clean up left behind
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir(&path).with_context(|| format!("failed to create '{}'", path.display()))?;
+        Ok(ScratchDir(path))
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+// Checks that this build works on this machine: every input of
+// selftest_inputs goes through encode and decode with every variant, files
+// in a scratch directory like any other, and what comes out must have the
+// length and the XXH3 of what went in. Prints PASS or FAIL for each and fails
+// when one did.
+pub fn selftest(opts: &Options) -> HuffmanResult<()> {
+    let dir = ScratchDir::new()?;
+    // Only the options of the machine, not the ones of encode a config file
+    // may set
+    let defaults = Options {
+        block_size: None, table_file: None, adaptive: false, rle: false, symbol_width: 1, tokens: false, bwt: false,
+        context: false, method: Method::Huffman, filter: None, stride: 1, checksum: ChecksumKind::Crc32,
+        progress: false, stats: false, json: false, fsync: false, rm: false, paranoid: false, limit_rate: None,
+        output_template: None, more_inputs: Vec::new(), overwrite: None, force: true, ..opts.clone()
+    };
+    // Without the lines encode and decode log for every case
+    let log_level = log::max_level();
+    log::set_max_level(log_level.min(LevelFilter::Warn));
+    let result = run_selftest(&dir.0, &defaults);
+    log::set_max_level(log_level);
+    let (passed, failed) = result?;
+
+    let style = Style::stdout();
+    println!("Passed: {}", style.bold(&passed.to_string()));
+    match failed {
+        0 => println!("Failed: {}", style.bold("0")),
+        count => println!("Failed: {}", style.red(&count.to_string())),
+    }
+    match failed {
+        0 => Ok(()),
+        count => Err(HuffmanError::CorruptData(format!("{} of {} cases failed the self-test", count, passed + count))),
+    }
+}
+
+// The number of cases that passed and failed
+fn run_selftest(dir: &Path, defaults: &Options) -> HuffmanResult<(usize, usize)> {
+    let style = Style::stdout();
+    let (mut passed, mut failed) = (0, 0);
+    for (name, data) in selftest_inputs() {
+        let input = dir.join(name);
+        fs::write(&input, &data).with_context(|| format!("failed to write '{}'", input.display()))?;
+        let expected = xxh3(&data);
+        for (variant, apply) in SELFTEST_VARIANTS {
+            if let Some(cancel) = &defaults.cancel {
+                cancel.check()?;
+            }
+            let mut opts = defaults.clone();
+            apply(&mut opts);
+            let encoded = dir.join(format!("{}.encoded", name));
+            let decoded = dir.join(format!("{}.decoded", name));
+            let outcome = encode(&input, &encoded, &opts)
+                .and_then(|()| decode(&encoded, &decoded, &opts))
+                .and_then(|_| Ok(fs::read(&decoded)?))
+                .and_then(|output| match (output.len() == data.len(), xxh3(&output) == expected) {
+                    (true, true) => Ok(()),
+                    (false, _) => Err(HuffmanError::CorruptData(format!("{} bytes decoded, {} expected", output.len(), data.len()))),
+                    (true, false) => Err(HuffmanError::CorruptData(String::from("the decoded data differs"))),
+                });
+            match outcome {
+                Ok(()) => {
+                    println!("{} {:<11} {}", style.green("PASS"), name, variant);
+                    passed += 1;
+                }
+                Err(e) if matches!(e.root(), HuffmanError::Cancelled) => return Err(e),
+                Err(e) => {
+                    println!("{} {:<11} {}: {}", style.red("FAIL"), name, variant, e);
+                    failed += 1;
+                }
+            }
+            let _ = fs::remove_file(&encoded);
+            let _ = fs::remove_file(&decoded);
+        }
+    }
+    Ok((passed, failed))
+}
+
+fn xxh3(data: &[u8]) -> u64 {
+    let mut hash = Xxh3::new();
+    hash.update(data);
+    hash.finish_64()
+}
+
+// Analysis
+////////////////////////////////////////////////////////////////////////////////
+
+// Prints the entropy of the file and the sizes encode would write with a table
+// and with --method range, worked out from the byte counts without encoding
+pub fn analyze(input_filename: &Path, opts: &Options) -> HuffmanResult<()> {
+    let input = input_filename.display();
+    let input_file = open_sequential(input_filename)
+        .with_context(|| format!("failed to open input '{}'", input))?;
+    let frequencies = calculate_frequencies_with_progress(&input_file, opts.buffer_size, &mut Progress::new(&NoProgress, opts.cancel.as_ref(), None))
+        .with_context(|| format!("failed to read input '{}'", input))?;
+    let encoding_table = HuffmanTree::from_frequencies(&frequencies).map(|tree| tree.codes()).unwrap_or_default();
+    let huffman = estimate_encoded_size(&frequencies, &encoding_table);
+    let range = estimate_range_size(&frequencies);
+
+    println!("{:<8} {:>12}", "Bytes", frequencies.total());
+    println!("{:<8} {:>12.4} bits per byte", "Entropy", entropy_bits_per_byte(&frequencies));
+    println!("{:<8} {:>12} bytes", "huffman", huffman);
+    // What range coding saves, or costs on data where the codes are as good
+    let gain = 100.0 * (huffman as f64 - range as f64) / huffman as f64;
+    match range < huffman {
+        true => println!("{:<8} {:>12} bytes, {:.1}% smaller", "range", range, gain),
+        false => println!("{:<8} {:>12} bytes, {:.1}% larger", "range", range, -gain),
+    }
+    Ok(())
+}
+
+// Benchmarking
+////////////////////////////////////////////////////////////////////////////////
+
+struct CodecResult {
+    name: &'static str,
+    encoded_size: u64,
+    encode_time: std::time::Duration,
+    decode_time: std::time::Duration,
+}
+
+// Encodes and decodes the file once with every codec, between files in the
+// temp directory, and prints the ratio and speed of each
+pub fn bench_codecs(input_filename: &Path, opts: &Options) -> HuffmanResult<()> {
+    if opts.compare && !cfg!(feature = "compare-flate2") {
+        return Err(HuffmanError::Usage(String::from("--compare needs a build with the compare-flate2 feature")));
+    }
+    let input = input_filename.display();
+    let size = fs::metadata(input_filename)
+        .with_context(|| format!("failed to read input '{}'", input))?
+        .len();
+    let scratch = |suffix: &str| std::env::temp_dir().join(format!("huffman-bench-{}{}", std::process::id(), suffix));
+    let (encoded, decoded) = (scratch(".encoded"), scratch(".decoded"));
+
+    let run_all = || -> HuffmanResult<Vec<CodecResult>> {
+        let mut results = Vec::new();
+        let mut add = |result: CodecResult| -> HuffmanResult<()> {
+            // A codec that loses data has no business in the table
+            let decoded_size = fs::metadata(&decoded)?.len();
+            if decoded_size != size {
+                return Err(Error::other(format!("{} decoded {} of {} bytes", result.name, decoded_size, size)).into());
+            }
+            results.push(result);
+            Ok(())
+        };
+        add(bench_huffman(input_filename, &encoded, &decoded, opts)?)?;
+        add(bench_adaptive(input_filename, &encoded, &decoded, opts)?)?;
+        #[cfg(feature = "compare-flate2")]
+        if opts.compare {
+            add(bench_gzip(input_filename, &encoded, &decoded, opts)?)?;
+        }
+        Ok(results)
+    };
+    let results = run_all();
+    let _ = fs::remove_file(&encoded);
+    let _ = fs::remove_file(&decoded);
+    let results = results.with_context(|| format!("failed to bench '{}'", input))?;
+
+    let throughput = |time: std::time::Duration| size as f64 / time.as_secs_f64() / 1e6;
+    println!("{:<8} {:>12} {:>8} {:>12} {:>12}", "Codec", "Size", "Ratio", "Encode MB/s", "Decode MB/s");
+    for result in results {
+        println!("{:<8} {:>12} {:>8.4} {:>12.1} {:>12.1}",
+            result.name,
+            result.encoded_size,
+            result.encoded_size as f64 / size.max(1) as f64,
+            throughput(result.encode_time),
+            throughput(result.decode_time),
+        );
+    }
+    Ok(())
+}
+
+fn bench_huffman(input_filename: &Path, encoded: &Path, decoded: &Path, opts: &Options) -> HuffmanResult<CodecResult> {
+    let start = std::time::Instant::now();
+    let input_file = open_sequential(input_filename)?;
+    // Nothing to show while timing, but Ctrl-C still stops it
+    let mut progress = Progress::new(&NoProgress, opts.cancel.as_ref(), None);
+    let frequencies = calculate_frequencies_with_progress(&input_file, opts.buffer_size, &mut progress)?;
+    let encoding_table = match HuffmanTree::from_frequencies(&frequencies) {
+        Some(tree) => tree.codes(),
+        None => EncodingTable::new(),
+    };
+    let mut output_file = File::create(encoded)?;
+    (&input_file).seek(SeekFrom::Start(0))?;
+    encode_block_with_progress(&input_file, &mut output_file, frequencies.total(), &encoding_table, opts.buffer_size, opts.checksum, &mut progress)?;
+    let encode_time = start.elapsed();
+    let encoded_size = output_file.metadata()?.len();
+    let decode_time = bench_decode(encoded, decoded, opts)?;
+    Ok(CodecResult { name: "huffman", encoded_size, encode_time, decode_time })
+}
+
+// The same with --adaptive, for its ratio next to the one of a table
+fn bench_adaptive(input_filename: &Path, encoded: &Path, decoded: &Path, opts: &Options) -> HuffmanResult<CodecResult> {
+    let start = std::time::Instant::now();
+    let mut builder = EncoderOptions::builder().adaptive(true).buffer_size(opts.buffer_size);
+    if let Some(cancel) = &opts.cancel {
+        builder = builder.cancel(cancel.clone());
+    }
+    let mut output_file = File::create(encoded)?;
+    encode_stream(open_sequential(input_filename)?, &mut output_file, &builder.build()?)?;
+    let encode_time = start.elapsed();
+    let encoded_size = output_file.metadata()?.len();
+    let decode_time = bench_decode(encoded, decoded, opts)?;
+    Ok(CodecResult { name: "adaptive", encoded_size, encode_time, decode_time })
+}
+
+fn bench_decode(encoded: &Path, decoded: &Path, opts: &Options) -> HuffmanResult<std::time::Duration> {
+    let start = std::time::Instant::now();
+    let mut reader = BufReader::with_capacity(opts.buffer_size, open_sequential(encoded)?);
+    let header = decode_header(&mut reader)?;
+    let options = DecoderOptions { max_output_size: None, ..opts.decoder_options(None) };
+    decode_stream(&mut reader, &mut File::create(decoded)?, header, &options)?;
+    Ok(start.elapsed())
+}
+
+// Buffered in the same chunks as the Huffman codec, so both spend the same on
+// reads and writes
+#[cfg(feature = "compare-flate2")]
+fn bench_gzip(input_filename: &Path, encoded: &Path, decoded: &Path, opts: &Options) -> HuffmanResult<CodecResult> {
+    use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+    use std::io::BufWriter;
+
+    let start = std::time::Instant::now();
+    let mut input = BufReader::with_capacity(opts.buffer_size, open_sequential(input_filename)?);
+    let output = BufWriter::with_capacity(opts.buffer_size, File::create(encoded)?);
+    let mut encoder = GzEncoder::new(output, Compression::default());
+    std::io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?.flush()?;
+    let encode_time = start.elapsed();
+    let encoded_size = fs::metadata(encoded)?.len();
+
+    let start = std::time::Instant::now();
+    let mut decoder = GzDecoder::new(BufReader::with_capacity(opts.buffer_size, open_sequential(encoded)?));
+    let mut output = BufWriter::with_capacity(opts.buffer_size, File::create(decoded)?);
+    std::io::copy(&mut decoder, &mut output)?;
+    output.flush()?;
+    let decode_time = start.elapsed();
+
+    Ok(CodecResult { name: "gzip", encoded_size, encode_time, decode_time })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::sibling_filename;
+    use crate::test_util::{bench, capture_logs, temp_path, text_data, Rng};
+    use std::thread;
+
+    #[test]
+    fn test_config_flags() {
+        let setting = |key: &str, value| Setting { key: key.to_string(), value, origin: format!("config.toml:{}", key.len()) };
+        let (flags, warnings) = config_flags(&[
+            setting("stats", Value::Bool(true)),
+            setting("force", Value::Bool(false)),
+            setting("threads", Value::Integer(4)),
+            setting("method", Value::String(String::from("range"))),
+            setting("suffix", Value::String(String::from(".huf"))),
+            setting("output", Value::String(String::from("always.huf"))),
+        ])
+        .unwrap();
+        assert_eq!(flags, ["--stats", "--threads", "4", "--method", "range"]);
+        assert_eq!(warnings, ["config.toml:6: unknown key 'suffix'", "config.toml:6: unknown key 'output'"]);
+
+        let error = config_flags(&[setting("stats", Value::String(String::from("yes")))]).unwrap_err();
+        assert_eq!(error.to_string(), "config.toml:5: stats is true or false");
+        let error = config_flags(&[setting("threads", Value::Bool(true))]).unwrap_err();
+        assert_eq!(error.to_string(), "config.toml:7: threads takes a value, not true or false");
+    }
+
+    #[test]
+    fn test_status_line() {
+        let mib = 1024 * 1024;
+        let second = Duration::from_secs(1);
+        assert_eq!(
+            status_line(Some(Phase::Encoding), 12 * mib, Some(52 * mib), 8 * mib, 2 * second),
+            "Encoding: 12.0 of 52.0 MiB (23%), 4.0 MiB/s, 10s left",
+        );
+        assert_eq!(
+            status_line(Some(Phase::Decoding), mib, Some(1000 * mib), mib, second),
+            "Decoding: 1.0 of 1000.0 MiB (0%), 1.0 MiB/s, 16m 39s left",
+        );
+        assert_eq!(status_line(Some(Phase::Counting), 0, Some(0), 0, Duration::ZERO), "Counting: 0.0 of 0.0 MiB (100%)");
+        // A pipe doesn't say how long it is
+        assert_eq!(status_line(Some(Phase::Encoding), 3 * mib, None, 3 * mib, 3 * second), "Encoding: 3.0 MiB, 1.0 MiB/s");
+        assert_eq!(status_line(None, mib, Some(20000 * mib), mib, second), "1.0 of 20000.0 MiB (0%), 1.0 MiB/s, 5h 33m left");
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("4096"), Some(4096));
+        assert_eq!(parse_size("64K"), Some(64 * 1024));
+        assert_eq!(parse_size("64k"), Some(64 * 1024));
+        assert_eq!(parse_size("1M"), Some(1024 * 1024));
+        assert_eq!(parse_size("2G"), Some(2 * 1024 * 1024 * 1024));
+        for invalid in ["", "K", "1.5M", "-1K", "12KB", "64 K", "99999999999999999999G"] {
+            assert_eq!(parse_size(invalid), None, "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_progress_line() {
+        let mib = 1024 * 1024;
+        assert_eq!(
+            progress_line(Some(Phase::Encoding), 3 * mib, Some(10 * mib), None),
+            "Encoding  30% [#########                     ] 3.0 of 10.0 MiB",
+        );
+        assert_eq!(
+            progress_line(Some(Phase::Counting), 0, Some(0), None),
+            "Counting 100% [##############################] 0.0 of 0.0 MiB",
+        );
+        assert_eq!(progress_line(Some(Phase::Decoding), mib / 2, None, None), "Decoding 0.5 MiB");
+        assert_eq!(progress_line(Some(Phase::Decoding), mib / 2, None, Some(20.0 * mib as f64)), "Decoding 0.5 MiB, 20.0 MiB/s");
+    }
+
+    #[test]
+    fn test_commands_log_instead_of_printing() {
+        let input = temp_path("logged.txt");
+        let encoded = temp_path("logged.encoded");
+        let decoded = temp_path("logged.decoded");
+        fs::write(&input, b"abracadabra").unwrap();
+        let args = |command: &str, input: &Path, output: &Path| -> Vec<OsString> {
+            vec!["huffman".into(), command.into(), input.into(), "-o".into(), output.into(), "-f".into()]
+        };
+        let message = |level, text: &str| (level, String::from(text));
+
+        let (result, records) = capture_logs(|| encode(&input, &encoded, &parse_args(&args("encode", &input, &encoded)).unwrap()));
+        result.unwrap();
+        // 23 bits of codes: a is 0, the other four 3 bits long
+        assert_eq!(records.iter().filter(|(level, text)| *level == Level::Debug && text.starts_with("Char ")).count(), 5);
+        assert!(records.contains(&message(Level::Debug, "Char 'a' - encoding: 0b0, length: 1")), "{:?}", records);
+        assert_eq!(records[5..], [message(Level::Debug, "Padding bits: 1"), message(Level::Info, "Encoding successful")]);
+
+        let (result, records) = capture_logs(|| decode(&encoded, &decoded, &parse_args(&args("decode", &encoded, &decoded)).unwrap()));
+        assert!(matches!(result, Ok(Status::Complete)));
+        assert_eq!(records[0], message(Level::Debug, "Header - version: 3, entries: 5, padding: 1"));
+        assert_eq!(records.last(), Some(&message(Level::Info, "Decoding successful")));
+        assert_eq!(fs::read(&decoded).unwrap(), b"abracadabra");
+
+        // A salvaged decode warns about what it lost
+        let data = fs::read(&encoded).unwrap();
+        fs::write(&encoded, &data[..data.len() - 1]).unwrap();
+        let mut salvage_args = args("decode", &encoded, &decoded);
+        salvage_args.push("--ignore-errors".into());
+        let (result, records) = capture_logs(|| decode(&encoded, &decoded, &parse_args(&salvage_args).unwrap()));
+        assert!(matches!(result, Ok(Status::Partial)));
+        let warnings: Vec<&String> = records.iter().filter(|(level, _)| *level == Level::Warn).map(|(_, text)| text).collect();
+        assert_eq!(warnings.len(), 2, "{:?}", records);
+        assert!(warnings[1].starts_with("recovered only"), "{:?}", warnings);
+
+        for path in [input, encoded, decoded] {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    #[test]
+    fn test_cancelled_commands_leave_nothing_behind() {
+        let input = temp_path("cancelled.txt");
+        let output = temp_path("cancelled.encoded");
+        fs::write(&input, text_data(&mut Rng(0xC7C), 32 * 1024 * 1024)).unwrap();
+        let args: Vec<OsString> = vec!["huffman".into(), "encode".into(), (&input).into(), "-o".into(), (&output).into()];
+        let mut opts = parse_args(&args).unwrap();
+        let token = CancelToken::new();
+        opts.cancel = Some(token.clone());
+
+        // Like Ctrl-C, from another thread once the output is being written
+        let lock = sibling_filename(&output, ".lock");
+        let result = thread::scope(|scope| {
+            scope.spawn(|| {
+                while !lock.exists() {
+                    thread::sleep(Duration::from_millis(1));
+                }
+                token.cancel();
+            });
+            encode(&input, &output, &opts)
+        });
+        assert!(matches!(result.as_ref().map_err(HuffmanError::root), Err(HuffmanError::Cancelled)), "{:?}", result);
+        assert_eq!(exit_code(&result.unwrap_err()), EXIT_CANCELLED);
+
+        // No output, and no temporary file or lock next to it
+        let name = output.file_name().unwrap().to_string_lossy().into_owned();
+        let left: Vec<_> = fs::read_dir(output.parent().unwrap()).unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|file| file.contains(&name))
+            .collect();
+        assert!(left.is_empty(), "{:?}", left);
+        let _ = fs::remove_file(input);
+    }
+
+    // Per file overhead of encode, which small inputs are dominated by
+    #[test]
+    #[ignore]
+    fn bench_encode_small_files() {
+        let mut rng = Rng(0x5A11);
+        let inputs: Vec<PathBuf> = (0..2000)
+            .map(|i| {
+                let path = temp_path(&format!("small-{}.txt", i));
+                fs::write(&path, text_data(&mut rng, 4096)).unwrap();
+                path
+            })
+            .collect();
+        let output_path = temp_path("small.encoded");
+
+        for (name, limit) in [("encode 2000 x 4 KB files", "8388608"), ("  streamed from disk", "0")] {
+            let elapsed = bench(|| {
+                for input in &inputs {
+                    let args: Vec<OsString> = vec![
+                        "huffman".into(), "encode".into(), input.into(), "--memory-limit".into(), limit.into(),
+                    ];
+                    encode(input, &output_path, &parse_args(&args).unwrap()).unwrap();
+                }
+            });
+            println!("{:<32} {:>8.1} us per file", name, elapsed.as_secs_f64() * 1e6 / inputs.len() as f64);
+        }
+        for input in &inputs {
+            let _ = fs::remove_file(input);
+        }
+        let _ = fs::remove_file(&output_path);
+    }
+}
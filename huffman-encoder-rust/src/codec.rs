@@ -0,0 +1,2363 @@
+// Encoding and decoding whole streams
+
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::io::{BufReader, BufWriter, Cursor, Seek, SeekFrom};
+
+use crate::adaptive::{decode_adaptive, encode_adaptive};
+use crate::bwt::{burrows_wheeler_transform, inverse_burrows_wheeler_transform, move_to_front, undo_move_to_front};
+use crate::bitio::{BitReader, BitWriter};
+use crate::checksum::{Checksum, ChecksumKind};
+#[cfg(feature = "std")]
+use crate::checksum::Crc32;
+use crate::context::{choose_tables, count_contexts};
+use crate::error::{HuffmanError, HuffmanResult, IoContext};
+use crate::filter::{apply_filter, FilterDecoder};
+#[cfg(feature = "std")]
+use crate::header::{decode_header, input_length, VERSION};
+use crate::header::{read_header, FileInfo, Header, MAGIC};
+#[cfg(feature = "std")]
+use crate::method::{Decoding, Registry};
+use crate::frequency::{count_with_progress, FrequencyTable, Symbol};
+use crate::io::{BufRead, Read, ErrorKind, Write};
+use crate::options::{DecoderOptions, EncoderOptions, Method};
+use crate::progress::{Phase, Progress, PROGRESS_STEP};
+use crate::range::{decode_range, encode_range, scale_counts};
+use crate::report::{DecodeReport, EncodeReport, StreamSizes, Stopwatch};
+use crate::rle::{run_length_encode, RunLengthDecoder};
+use crate::table::{build_code_lookup, build_pair_lookup, CodeLookup, Decoder, EncodingTable, ESCAPE};
+use crate::tokens::{choose_dictionary, count_symbols, symbols, token_symbols, TokenSymbols, FIRST_TOKEN};
+use crate::tree::HuffmanTree;
+
+// Encoding
+////////////////////////////////////////////////////////////////////////////////
+
+// Size of the chunks the input is read in and the output is written in,
+// see --buffer-size
+pub const DEFAULT_BUFFER_SIZE: usize = 64 * 1024;
+
+// The input of encode, a file or the spooled data of a pipe
+#[cfg(feature = "cli")]
+pub(crate) trait ReadSeek: Read + Seek {}
+#[cfg(feature = "cli")]
+impl<T: Read + Seek> ReadSeek for T {}
+
+// Writes a complete stream for `input`, which holds `original_length` bytes:
+// the header, the encoded data and the fields patched afterwards. The file is
+// left positioned after the stream, ready for the next block. Returns the
+// padding bits of the stream.
+#[cfg(feature = "std")]
+pub fn encode_block(
+    input: impl Read,
+    file: &mut (impl Write + Seek),
+    original_length: u64,
+    encoding_table: &EncodingTable,
+    buffer_size: usize,
+) -> HuffmanResult<u8> {
+    encode_block_with_progress(input, file, original_length, encoding_table, buffer_size, ChecksumKind::Crc32, &mut Progress::none())
+        .map(|stream| stream.padding_bits)
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn encode_block_with_progress(
+    input: impl Read,
+    file: &mut (impl Write + Seek),
+    original_length: u64,
+    encoding_table: &EncodingTable,
+    buffer_size: usize,
+    checksum_kind: ChecksumKind,
+    progress: &mut Progress,
+) -> HuffmanResult<StreamSizes> {
+    let header_start = file.stream_position()?;
+    let mut header = Header::new(original_length, encoding_table.clone()).with_checksum(checksum_kind);
+    header.write_to(file)?;
+    let (padding_bits, checksum) = encode_file_with_progress(input, &mut *file, encoding_table, buffer_size, checksum_kind, progress)?;
+    let end = file.stream_position()?;
+
+    // The same header again with the fields that are known now, it keeps its
+    // size
+    let header_bytes = header.serialized_len();
+    let payload_bytes = end - header_start - header_bytes;
+    header.padding_bits = padding_bits;
+    header.checksum = Some(checksum);
+    header.payload_length = Some(payload_bytes);
+    file.seek(SeekFrom::Start(header_start))?;
+    header.write_to(file)?;
+    file.seek(SeekFrom::Start(end))?;
+    Ok(StreamSizes { header_bytes, payload_bytes, padding_bits })
+}
+
+// Returns the padding bits and the CRC-32 of the input
+pub fn encode_file(
+    input: impl Read,
+    output: impl Write,
+    encoding_table: &EncodingTable,
+    buffer_size: usize,
+) -> HuffmanResult<(u8, u32)> {
+    encode_file_with_progress(input, output, encoding_table, buffer_size, ChecksumKind::Crc32, &mut Progress::none())
+}
+
+pub(crate) fn encode_file_with_progress(
+    mut input: impl Read,
+    output: impl Write,
+    encoding_table: &EncodingTable,
+    buffer_size: usize,
+    checksum_kind: ChecksumKind,
+    progress: &mut Progress,
+) -> HuffmanResult<(u8, u32)> {
+    let mut bit_writer = BitWriter::new(output, buffer_size)?;
+    let mut buffer = vec![0u8; buffer_size];
+    let mut crc = checksum_kind.start();
+    let codes = build_code_lookup(encoding_table);
+    let mut offset = 0u64;
+
+    loop {
+        // Same as calculate_frequencies, only the filled part is encoded
+        let chunk = match input.read(&mut buffer) {
+            Ok(0) => break, // EOF,
+            Ok(read) => &buffer[..read],
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        crc.update(chunk);
+        for (i, &byte) in chunk.iter().enumerate() {
+            match codes[byte as usize] {
+                Some(code) => {
+                    bit_writer.write_code(&code)?;
+                },
+                None => return Err(HuffmanError::UnknownSymbol { byte, offset: offset + i as u64 }),
+            }
+        }
+        offset += chunk.len() as u64;
+        progress.update(offset)?;
+    }
+
+    let padding_bits = bit_writer.finish()?;
+    progress.end(offset)?;
+    Ok((padding_bits, crc.finish()))
+}
+
+// encode_file_with_progress for data in memory that is coded in pairs of
+// bytes. An odd byte at the end has no code, it goes in the header. The
+// checksum is of all of the data.
+fn encode_pairs(
+    data: &[u8],
+    output: impl Write,
+    encoding_table: &EncodingTable,
+    buffer_size: usize,
+    checksum_kind: ChecksumKind,
+    progress: &mut Progress,
+) -> HuffmanResult<(u8, u32)> {
+    let mut bit_writer = BitWriter::new(output, buffer_size)?;
+    let mut crc = checksum_kind.start();
+    let codes = build_pair_lookup(encoding_table);
+    let mut offset = 0u64;
+
+    // The steps are an even number of bytes, so pairs never straddle them
+    for chunk in data.chunks(PROGRESS_STEP as usize) {
+        crc.update(chunk);
+        for (i, pair) in chunk.chunks_exact(2).enumerate() {
+            match codes[u16::from_le_bytes([pair[0], pair[1]]) as usize] {
+                Some(code) => {
+                    bit_writer.write_code(&code)?;
+                },
+                None => return Err(HuffmanError::UnknownSymbol { byte: pair[0], offset: offset + 2 * i as u64 }),
+            }
+        }
+        offset += chunk.len() as u64;
+        progress.update(offset)?;
+    }
+
+    let padding_bits = bit_writer.finish()?;
+    progress.end(offset)?;
+    Ok((padding_bits, crc.finish()))
+}
+
+// encode_pairs for data coded in tokens, with the symbols of the tokens of
+// its dictionary
+fn encode_tokens(
+    data: &[u8],
+    output: impl Write,
+    encoding_table: &EncodingTable,
+    token_symbols: &TokenSymbols,
+    buffer_size: usize,
+    checksum_kind: ChecksumKind,
+    progress: &mut Progress,
+) -> HuffmanResult<(u8, u32)> {
+    let mut bit_writer = BitWriter::new(output, buffer_size)?;
+    let mut crc = checksum_kind.start();
+    crc.update(data);
+    let codes = build_pair_lookup(encoding_table);
+
+    for (symbol, end) in symbols(data, token_symbols) {
+        match codes[symbol as usize] {
+            Some(code) => {
+                bit_writer.write_code(&code)?;
+            },
+            None => return Err(HuffmanError::UnknownSymbol { byte: data[end as usize - 1], offset: end - 1 }),
+        }
+        progress.update(end)?;
+    }
+
+    let padding_bits = bit_writer.finish()?;
+    progress.end(data.len() as u64)?;
+    Ok((padding_bits, crc.finish()))
+}
+
+// encode_pairs for data coded with the table of the byte before every byte,
+// or the shared `encoding_table` when there is none for it
+fn encode_contexts(
+    data: &[u8],
+    output: impl Write,
+    encoding_table: &EncodingTable,
+    contexts: &[Option<EncodingTable>],
+    buffer_size: usize,
+    checksum_kind: ChecksumKind,
+    progress: &mut Progress,
+) -> HuffmanResult<(u8, u32)> {
+    let mut bit_writer = BitWriter::new(output, buffer_size)?;
+    let mut crc = checksum_kind.start();
+    crc.update(data);
+    let shared = build_code_lookup(encoding_table);
+    let lookups: Vec<Option<CodeLookup>> = contexts.iter().map(|table| table.as_ref().map(build_code_lookup)).collect();
+
+    let mut previous = 0;
+    for (i, &byte) in data.iter().enumerate() {
+        let codes = lookups[previous as usize].as_ref().unwrap_or(&shared);
+        match codes[byte as usize] {
+            Some(code) => {
+                bit_writer.write_code(&code)?;
+            },
+            None => return Err(HuffmanError::UnknownSymbol { byte, offset: i as u64 }),
+        }
+        previous = byte;
+        progress.update(i as u64 + 1)?;
+    }
+
+    let padding_bits = bit_writer.finish()?;
+    progress.end(data.len() as u64)?;
+    Ok((padding_bits, crc.finish()))
+}
+
+// Decoding
+////////////////////////////////////////////////////////////////////////////////
+
+// Decodes the characters of one stream a byte at a time and checks that it
+// ends the way its header says. Shared by decode_file and HuffmanReader.
+pub(crate) struct StreamDecoder {
+    decoder: Decoder,
+    pub(crate) decoded: u64,
+    // None when the checksum isn't verified
+    crc: Option<Box<dyn Checksum>>,
+    // Only for run-length encoded streams, `decoded` counts its output
+    rle: Option<RunLengthDecoder>,
+    // The second byte of a pair, next in line
+    pending: Option<u8>,
+    // The token of the dictionary being decoded and the position of its next
+    // byte
+    token: Option<(usize, usize)>,
+    // The decoder of every context with a table of its own, and the context
+    // of the next byte
+    contexts: Vec<Option<Decoder>>,
+    previous: u8,
+    // Only for filtered streams, undoes the filter last
+    filter: Option<FilterDecoder>,
+    // Whether the table has an escape code, which the byte follows
+    escape: bool,
+}
+
+impl StreamDecoder {
+    pub(crate) fn new(header: &Header, verify_checksum: bool) -> Self {
+        let crc = header.checksum_kind.verifier(verify_checksum);
+        let rle = header.rle.then(RunLengthDecoder::default);
+        let contexts = header.contexts.iter().flatten().map(|table| table.as_ref().map(Decoder::new)).collect();
+        StreamDecoder {
+            decoder: Decoder::new(&header.encoding_table),
+            decoded: 0,
+            crc,
+            rle,
+            pending: None,
+            token: None,
+            contexts,
+            previous: 0,
+            filter: header.filter.map(|(filter, stride)| FilterDecoder::new(filter, stride)),
+            escape: header.encoding_table.escape().is_some(),
+        }
+    }
+
+    // The next character, None after the last one
+    #[inline]
+    pub(crate) fn next(&mut self, header: &Header, bit_reader: &mut BitReader<impl BufRead>) -> HuffmanResult<Option<u8>> {
+        // Version 0 files don't know their length and decode until the bits run out
+        if header.original_length.is_some_and(|length| self.decoded >= length) {
+            return Ok(None);
+        }
+        let decoder = match self.contexts.get(self.previous as usize) {
+            Some(Some(decoder)) => decoder,
+            _ => &self.decoder,
+        };
+        let character = if let Some(byte) = self.pending.take() {
+            Some(byte)
+        } else if let (Some((index, position)), Some(dictionary)) = (self.token, &header.dictionary) {
+            let token = &dictionary[index];
+            self.token = (position + 1 < token.len()).then_some((index, position + 1));
+            Some(token[position])
+        } else if header.trailing_byte.is_some() && header.original_length == Some(self.decoded + 1) {
+            // The byte an odd length leaves over has no code
+            header.trailing_byte
+        } else {
+            match &mut self.rle {
+                Some(rle) => rle.next(|| Ok(decode_code(decoder, bit_reader)?.map(|symbol| symbol as u8)))?,
+                None => match decode_code(decoder, bit_reader)? {
+                    Some(symbol) if header.symbol_width == 2 => {
+                        let [first, second] = symbol.to_le_bytes();
+                        self.pending = Some(second);
+                        Some(first)
+                    }
+                    Some(ESCAPE) if self.escape => match bit_reader.read_bits(8)? {
+                        Some(byte) => Some(byte as u8),
+                        None => return Err(HuffmanError::TruncatedData(String::from("data ends in the middle of an escaped byte"))),
+                    },
+                    Some(symbol) if symbol as usize >= FIRST_TOKEN => {
+                        let index = symbol as usize - FIRST_TOKEN;
+                        let Some(token) = header.dictionary.as_ref().and_then(|dictionary| dictionary.get(index)) else {
+                            return Err(HuffmanError::CorruptData(format!("code of symbol {}, which isn't in the dictionary", symbol)));
+                        };
+                        self.token = (token.len() > 1).then_some((index, 1));
+                        token.first().copied()
+                    }
+                    symbol => symbol.map(|symbol| symbol as u8),
+                },
+            }
+        };
+        let Some(character) = character else {
+            return Ok(None);
+        };
+        // The contexts are of the filtered bytes, the checksum of the data
+        self.previous = character;
+        let character = match &mut self.filter {
+            Some(filter) => filter.undo(character),
+            None => character,
+        };
+        if let Some(crc) = &mut self.crc {
+            crc.update(&[character]);
+        }
+        self.decoded += 1;
+        Ok(Some(character))
+    }
+
+    // Called once next() returned None
+    pub(crate) fn finish(&self, header: &Header, bit_reader: &BitReader<impl BufRead>) -> HuffmanResult<()> {
+        if let Some(length) = header.original_length.filter(|&length| self.decoded < length) {
+            return Err(HuffmanError::TruncatedData(
+                format!("data is truncated, decoded {} of {} bytes", self.decoded, length),
+            ));
+        }
+        if self.rle.as_ref().is_some_and(|rle| !rle.is_done()) {
+            return Err(HuffmanError::CorruptData(String::from("run-length data goes past the original length")));
+        }
+        if self.token.is_some() {
+            return Err(HuffmanError::CorruptData(String::from("a token goes past the original length")));
+        }
+
+        // Version 0 streams end where the padding starts, later versions stop
+        // after the original length and the rest of the byte has to be the padding
+        if header.original_length.is_some() {
+            let (count, value) = bit_reader.unread_bits();
+            if count != header.padding_bits % 8 || value != 0 {
+                return Err(HuffmanError::CorruptData(
+                    format!("padding doesn't match the data ({} unused bits, {} expected)", count, header.padding_bits),
+                ));
+            }
+        }
+        if let Some(payload_length) = header.payload_length {
+            let used = bit_reader.bits_read.div_ceil(8);
+            if used != payload_length {
+                return Err(HuffmanError::CorruptData(
+                    format!("payload length doesn't match the data ({} bytes declared, {} used)", payload_length, used),
+                ));
+            }
+        }
+        if let (Some(expected), Some(crc)) = (header.checksum, &self.crc) {
+            let actual = crc.finish();
+            if actual != expected {
+                return Err(HuffmanError::ChecksumMismatch { expected, actual });
+            }
+        }
+        Ok(())
+    }
+}
+
+// The character of the code at the start of `bit_reader`, None at the end
+#[inline]
+fn decode_code(decoder: &Decoder, bit_reader: &mut BitReader<impl BufRead>) -> HuffmanResult<Option<Symbol>> {
+    // Every code fits in 32 bits. Near the end fewer are available, the
+    // rest read as zero.
+    let (window, available) = bit_reader.peek_bits(32)?;
+    if available == 0 {
+        return Ok(None);
+    }
+    match decoder.decode(window) {
+        Some((character, length)) if length <= available => {
+            bit_reader.consume(length);
+            Ok(Some(character))
+        }
+        // The data ends in the middle of a code, or before enough bits
+        // to tell that there is no code
+        _ if available < 32 => Err(HuffmanError::TruncatedData(
+            format!("data ends in the middle of a code ({} dangling bits)", available),
+        )),
+        _ => Err(HuffmanError::CorruptData(String::from("invalid code in encoded data"))),
+    }
+}
+
+// Decodes the payload of one stream, which ends where `reader` does. Returns
+// the number of bits of encoded data that were read and the number of bytes
+// decoded. `progress` gets the position in the payload.
+fn decode_payload(
+    reader: impl BufRead,
+    mut output: impl Write,
+    header: &Header,
+    options: &DecoderOptions,
+    progress: &mut Progress,
+) -> HuffmanResult<(u64, u64)> {
+    if header.adaptive {
+        return Err(HuffmanError::Usage(String::from("an adaptive stream has no table, decode it with decode_stream")));
+    }
+    if let Some(primary_index) = header.primary_index {
+        return decode_bwt_payload(reader, output, header, primary_index, options, progress);
+    }
+    if header.range_counts.is_some() {
+        return decode_range(reader, output, header, options, progress);
+    }
+    let mut stream = StreamDecoder::new(header, options.verify_checksum);
+    let mut bit_reader = BitReader::new(reader, header.padding_bits)?;
+    while let Some(character) = stream.next(header, &mut bit_reader)? {
+        if let Some(max) = options.max_output_size.filter(|&max| stream.decoded > max) {
+            return Err(HuffmanError::OutputTooLarge { length: None, max });
+        }
+        output.write_all(&[character])?;
+        progress.update(bit_reader.bits_read / 8)?;
+    }
+    stream.finish(header, &bit_reader)?;
+    Ok((bit_reader.bits_read, stream.decoded))
+}
+
+// The room to reserve for what a stream decodes to: its length, as far as the
+// limit allows and at a byte per bit of the payload, which a code doesn't go
+// past. Runs, tokens and range coding can, the Vec grows for those, but a
+// forged length doesn't get its memory before anything is decoded.
+fn reserved_length(header: &Header, options: &DecoderOptions) -> usize {
+    let length = header.original_length.unwrap_or(0).min(options.max_output_size.unwrap_or(u64::MAX));
+    length.min(header.payload_length.unwrap_or(0).saturating_mul(8)) as usize
+}
+
+// decode_payload for a Burrows-Wheeler transformed stream. The transform is
+// undone once the whole block is decoded, and the checksum is of the block
+// after that. It keeps the length, so the limits are the same.
+fn decode_bwt_payload(
+    reader: impl BufRead,
+    mut output: impl Write,
+    header: &Header,
+    primary_index: u32,
+    options: &DecoderOptions,
+    progress: &mut Progress,
+) -> HuffmanResult<(u64, u64)> {
+    let mut stream = StreamDecoder::new(header, false);
+    let mut bit_reader = BitReader::new(reader, header.padding_bits)?;
+    let mut transformed = Vec::with_capacity(reserved_length(header, options));
+    while let Some(byte) = stream.next(header, &mut bit_reader)? {
+        if let Some(max) = options.max_output_size.filter(|&max| stream.decoded > max) {
+            return Err(HuffmanError::OutputTooLarge { length: None, max });
+        }
+        transformed.push(byte);
+        progress.update(bit_reader.bits_read / 8)?;
+    }
+    stream.finish(header, &bit_reader)?;
+
+    undo_move_to_front(&mut transformed);
+    let data = inverse_burrows_wheeler_transform(&transformed, primary_index)?;
+    if let (Some(mut crc), Some(expected)) = (header.checksum_kind.verifier(options.verify_checksum), header.checksum) {
+        crc.update(&data);
+        let actual = crc.finish();
+        if actual != expected {
+            return Err(HuffmanError::ChecksumMismatch { expected, actual });
+        }
+    }
+    output.write_all(&data)?;
+    Ok((bit_reader.bits_read, stream.decoded))
+}
+
+// Returns the number of bits of encoded data that were read. Only one stream
+// is decoded, so the threads of `options` aren't used.
+#[cfg(feature = "std")]
+pub fn decode_file(
+    reader: impl BufRead,
+    output_file: impl Write,
+    header: &Header,
+    options: &DecoderOptions,
+) -> HuffmanResult<u64> {
+    decode_file_with_progress(reader, output_file, header, options, &mut Progress::none()).map(|(bits, _)| bits)
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn decode_file_with_progress(
+    reader: impl BufRead,
+    output_file: impl Write,
+    header: &Header,
+    options: &DecoderOptions,
+    progress: &mut Progress,
+) -> HuffmanResult<(u64, u64)> {
+    // A stream that knows its payload length ends there, so its padding is in
+    // the last byte of the payload and not in the last byte of the file
+    let reader = reader.take(header.payload_length.unwrap_or(u64::MAX));
+    // Decoded bytes are collected and written in chunks. When decoding fails
+    // the drop writes out what was decoded so far, which --ignore-errors keeps.
+    let mut output = BufWriter::with_capacity(options.buffer_size, output_file);
+    let decoded = decode_payload(reader, &mut output, header, options, progress)?;
+
+    // Errors from the last write only show up here
+    output.flush()?;
+    Ok(decoded)
+}
+
+// decode_file_with_progress for an adaptive stream, which only says how long it
+// is at the end. The fields of the trailer are filled in into `header`.
+// Returns the bits of the payload and which bytes occurred.
+#[cfg(feature = "std")]
+pub(crate) fn decode_adaptive_file(
+    reader: impl BufRead,
+    output_file: impl Write,
+    header: &mut Header,
+    options: &DecoderOptions,
+    progress: &mut Progress,
+) -> HuffmanResult<(u64, [bool; 256])> {
+    let mut output = BufWriter::with_capacity(options.buffer_size, output_file);
+    let decoded = decode_adaptive(reader, &mut output, header, options, progress)?;
+    output.flush()?;
+    Ok(decoded)
+}
+
+// Decodes the stream of `header` alone, from `reader` right after the header,
+// and returns the bits of its payload and the bytes it decoded to, like
+// CompressionMethod::decode. Repair goes through a damaged file one stream at
+// a time with it.
+#[cfg(feature = "std")]
+pub(crate) fn decode_single_stream(
+    reader: &mut impl BufRead,
+    output: &mut impl Write,
+    header: &mut Header,
+    options: &DecoderOptions,
+) -> HuffmanResult<(u64, u64)> {
+    let mut progress = Progress::new(options.progress(), options.cancel(), None);
+    let mut stream = Decoding::new(header, options, &mut progress);
+    Registry::shipped().get(stream.header.method())
+        .and_then(|method| method.decode(reader, output, &mut stream))
+}
+
+// Errors of later streams say which one failed, like with_context
+fn in_stream(start: u64) -> impl FnOnce(HuffmanError) -> HuffmanError {
+    move |e| match (start, e) {
+        (0, e) | (_, e @ HuffmanError::Cancelled) => e,
+        (_, e) => HuffmanError::Context { context: format!("stream at offset {}", start), source: Box::new(e) },
+    }
+}
+
+// Blocks up to this size are decoded in memory on several threads. Larger
+// ones are decoded one at a time, straight from the file, so memory stays
+// bounded by the number of threads times this.
+#[cfg(feature = "std")]
+const PARALLEL_BLOCK_LIMIT: u64 = 32 * 1024 * 1024;
+
+#[cfg(feature = "std")]
+fn is_parallel_block(header: &Header) -> bool {
+    match (header.payload_length, header.original_length) {
+        (Some(payload), Some(length)) => payload <= PARALLEL_BLOCK_LIMIT && length <= PARALLEL_BLOCK_LIMIT,
+        _ => false,
+    }
+}
+
+// The sizes of a stream that decoded into `bits` of its payload, for the
+// DecodeReport. Version 0 streams end at the last byte they used.
+fn decoded_sizes(header: &Header, bits: u64) -> StreamSizes {
+    let payload_bytes = header.payload_length.unwrap_or(bits.div_ceil(8));
+    let padding_bits = (payload_bytes * 8).saturating_sub(bits).min(7) as u8;
+    StreamSizes { header_bytes: header.serialized_len() + header.trailer_len(), payload_bytes, padding_bits }
+}
+
+// Decodes the stream that starts with `header`, followed by any further streams
+// concatenated after it (like `cat a.encoded b.encoded`, or the blocks of
+// --block-size). Anything else after the encoded data is reported as trailing
+// garbage. With several threads, runs of streams that declare their payload
+// length are decoded in parallel, a batch of one per thread at a time, and
+// written in order. Every stream is decoded by the method of its header, see
+// method.rs.
+#[cfg(feature = "std")]
+pub fn decode_stream(
+    reader: &mut (impl BufRead + Seek),
+    output_file: &mut impl Write,
+    header: Header,
+    options: &DecoderOptions,
+) -> HuffmanResult<DecodeReport> {
+    Registry::shipped().decode_stream(reader, output_file, header, options)
+}
+
+// decode_stream with the methods of `registry`
+#[cfg(feature = "std")]
+pub(crate) fn decode_streams(
+    registry: &Registry,
+    reader: &mut (impl BufRead + Seek),
+    output_file: &mut impl Write,
+    header: Header,
+    options: &DecoderOptions,
+) -> HuffmanResult<DecodeReport> {
+    let mut stopwatch = Stopwatch::start();
+    let threads = options.threads;
+    let file_size = input_length(reader)?;
+    let mut header = header;
+    let mut start = reader.stream_position()? - header.serialized_len();
+    let first = start;
+    let mut report = DecodeReport::new(options.verify_checksum);
+    let mut symbols = vec![false; 1 << 16];
+    let mut progress = Progress::new(options.progress(), options.cancel(), Some(file_size));
+    progress.start_phase(Phase::Decoding, start)?;
+
+    loop {
+        let end = if threads > 1 && is_parallel_block(&header) {
+            let mut batch = Vec::with_capacity(threads);
+            let (end, following) = loop {
+                let payload_length = header.payload_length.unwrap_or(0);
+                let mut payload = Vec::with_capacity(payload_length as usize);
+                reader.by_ref().take(payload_length).read_to_end(&mut payload)?;
+                let end = start + header.serialized_len() + payload_length;
+                batch.push((start, header, payload));
+
+                // A bad header after the batch is reported once the batch is
+                // written, like it would be one stream at a time
+                match next_header(reader, end, file_size) {
+                    Ok(Some(next)) if batch.len() < threads && is_parallel_block(&next) => {
+                        header = next;
+                        start = end;
+                    }
+                    next => break (end, next),
+                }
+            };
+
+            // Each block gets the part of the size limit left after the ones
+            // before it
+            let mut limits = Vec::with_capacity(batch.len());
+            let mut offset = report.output_bytes;
+            for (_, header, _) in &batch {
+                let max_output_size = options.max_output_size.map(|max: u64| max.saturating_sub(offset));
+                limits.push(DecoderOptions { max_output_size, ..options.clone() });
+                offset += header.original_length.unwrap_or(0);
+            }
+            let results: Vec<(Vec<u8>, HuffmanResult<u64>, [bool; 256])> = std::thread::scope(|scope| {
+                let handles: Vec<_> = batch.iter_mut().zip(&limits)
+                    .map(|((_, header, payload), limit)| scope.spawn(move || {
+                        let mut decoded = Vec::with_capacity(reserved_length(header, limit));
+                        let mut progress = Progress::none();
+                        let mut stream = Decoding::new(header, limit, &mut progress);
+                        let result = registry.get(stream.header.method())
+                            .and_then(|method| method.decode(&mut &payload[..], &mut decoded, &mut stream))
+                            .map(|(bits, _)| bits);
+                        let occurred = stream.occurred;
+                        (decoded, result, occurred)
+                    }))
+                    .collect();
+                handles.into_iter()
+                    .map(|handle| handle.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+                    .collect()
+            });
+
+            // Everything up to the first failing block is written, including
+            // what that block decoded, like decoding one after the other would.
+            // The threads don't report progress, the blocks do once written.
+            for ((start, header, payload), (decoded, result, occurred)) in batch.iter().zip(results) {
+                output_file.write_all(&decoded)?;
+                report.output_bytes += decoded.len() as u64;
+                let bits = result.map_err(in_stream(*start))?;
+                report.add_stream(header, &decoded_sizes(header, bits), &mut symbols);
+                report.add_symbols(&occurred, &mut symbols);
+                progress.moved_to(start + header.serialized_len() + payload.len() as u64)?;
+            }
+            match following? {
+                Some(next) => {
+                    header = next;
+                    start = end;
+                    continue;
+                }
+                None => break,
+            }
+        } else {
+            let max_output_size = options.max_output_size.map(|max| max - report.output_bytes);
+            let remaining = DecoderOptions { max_output_size, ..options.clone() };
+            progress.moved_to(start + header.serialized_len())?;
+            let mut stream = Decoding::new(&mut header, &remaining, &mut progress);
+            let (bits, decoded) = registry.get(stream.header.method())
+                .and_then(|method| method.decode(&mut *reader, &mut *output_file, &mut stream))
+                .map_err(in_stream(start))?;
+            report.add_symbols(&stream.occurred, &mut symbols);
+            report.output_bytes += decoded;
+            report.add_stream(&header, &decoded_sizes(&header, bits), &mut symbols);
+
+            // Version 0 streams have no length, they just read until the end
+            if header.original_length.is_none() {
+                break;
+            }
+            start + header.serialized_len() + header.payload_length.unwrap_or(bits.div_ceil(8)) + header.trailer_len()
+        };
+
+        match next_header(reader, end, file_size)? {
+            Some(next) => {
+                header = next;
+                start = end;
+            }
+            None => break,
+        }
+    }
+
+    // Only the end of the file ends the streams without an error
+    progress.end_at(file_size)?;
+    report.input_bytes = file_size - first;
+    stopwatch.lap(&mut report.decoding_time);
+    progress.finish(report.progress());
+    Ok(report)
+}
+
+// The header of the stream starting at `end`, None at the end of the file
+#[cfg(feature = "std")]
+pub(crate) fn next_header(reader: &mut (impl Read + Seek), end: u64, file_size: u64) -> HuffmanResult<Option<Header>> {
+    if end >= file_size {
+        return Ok(None);
+    }
+
+    reader.seek(SeekFrom::Start(end))?;
+    let mut magic = [0u8; 4];
+    let is_stream = match reader.read_exact(&mut magic) {
+        Ok(()) => magic == *MAGIC,
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => false,
+        Err(e) => return Err(e.into()),
+    };
+    if !is_stream {
+        return Err(HuffmanError::TrailingGarbage { offset: end, length: file_size - end });
+    }
+
+    reader.seek(SeekFrom::Start(end))?;
+    decode_header(reader)
+        .with_context(|| format!("failed to read header of stream at offset {}", end))
+        .map(Some)
+}
+
+// Merging
+////////////////////////////////////////////////////////////////////////////////
+
+// Joins the streams of `inputs` into one, without decoding them: the payloads
+// follow each other bit for bit, so there is no padding in the middle, under a
+// single header with the combined length and checksum. Every input has to be
+// one stream of version 3 or 4, which says how long it and its payload are,
+// and all of them the same table. Returns the header of the merged stream.
+#[cfg(feature = "std")]
+pub fn merge_streams<R: BufRead>(inputs: &mut [R], mut output: impl Write, buffer_size: usize) -> HuffmanResult<Header> {
+    let mut headers = Vec::with_capacity(inputs.len());
+    for (i, input) in inputs.iter_mut().enumerate() {
+        let header = Header::read_from(input).with_context(|| format!("failed to read header of input {}", i + 1))?;
+        // The codes of an adaptive stream depend on everything before them
+        if header.adaptive {
+            return Err(HuffmanError::Usage(format!("input {} is adaptive, which can't be merged", i + 1)));
+        }
+        if header.payload_length.is_none() {
+            return Err(HuffmanError::Usage(format!(
+                "input {} is a version {} stream, which doesn't say how long it is, encode it again to merge it",
+                i + 1, header.version,
+            )));
+        }
+        if headers.first().is_some_and(|first: &Header| first.encoding_table != header.encoding_table) {
+            return Err(HuffmanError::Usage(format!("input {} has a different table than input 1", i + 1)));
+        }
+        // The byte an odd length leaves over would end up in the middle
+        if header.symbol_width != 1 {
+            return Err(HuffmanError::Usage(format!("input {} is coded in pairs of bytes, which can't be merged", i + 1)));
+        }
+        // Each block is transformed on its own, the merged one would be a block
+        if header.primary_index.is_some() {
+            return Err(HuffmanError::Usage(format!("input {} is Burrows-Wheeler transformed, which can't be merged", i + 1)));
+        }
+        // The first byte of every input is coded after a 0, not after the last
+        // byte of the one before it
+        if header.contexts.is_some() {
+            return Err(HuffmanError::Usage(format!("input {} has tables per context, which can't be merged", i + 1)));
+        }
+        // The range coder flushes its state at the end of every input
+        if header.range_counts.is_some() {
+            return Err(HuffmanError::Usage(format!("input {} is range coded, which can't be merged", i + 1)));
+        }
+        // The first bytes of every input are filtered against zeros, not
+        // against the last ones of the input before it
+        if header.filter.is_some() {
+            return Err(HuffmanError::Usage(format!("input {} is filtered, which can't be merged", i + 1)));
+        }
+        // The checksums are combined, which only CRC-32 ones can be
+        if header.checksum_kind == ChecksumKind::Xxh3 {
+            return Err(HuffmanError::Usage(format!("input {} has an xxh3 checksum, which can't be merged", i + 1)));
+        }
+        if headers.first().is_some_and(|first: &Header| first.checksum_kind != header.checksum_kind) {
+            return Err(HuffmanError::Usage(format!("input {} and input 1 differ in --checksum", i + 1)));
+        }
+        if headers.first().is_some_and(|first: &Header| first.rle != header.rle) {
+            return Err(HuffmanError::Usage(format!("input {} and input 1 differ in --rle", i + 1)));
+        }
+        // The symbols of the table are of the tokens in the dictionary
+        if headers.first().is_some_and(|first: &Header| first.dictionary != header.dictionary) {
+            return Err(HuffmanError::Usage(format!("input {} has a different dictionary than input 1", i + 1)));
+        }
+        headers.push(header);
+    }
+    let Some(first) = headers.first() else {
+        return Err(HuffmanError::Usage(String::from("nothing to merge")));
+    };
+
+    // Everything the merged header holds is known from the headers
+    let bits_of = |header: &Header| (header.payload_length.unwrap_or(0) * 8).saturating_sub(header.padding_bits as u64);
+    let total_bits: u64 = headers.iter().map(bits_of).sum();
+    let mut merged = Header::new(0, first.encoding_table.clone()).with_checksum(first.checksum_kind).with_info(first.info.clone());
+    // Every input ends with a whole packet, so the packets follow each other too
+    if first.rle {
+        merged = merged.with_rle();
+    }
+    if let Some(dictionary) = &first.dictionary {
+        merged = merged.with_dictionary(dictionary.clone());
+    }
+    for header in &headers {
+        let length = header.original_length.unwrap_or(0);
+        merged.checksum = merged.checksum.zip(header.checksum).map(|(first, second)| Crc32::combine(first, second, length));
+        merged.original_length = Some(merged.original_length.unwrap_or(0) + length);
+    }
+    merged.payload_length = Some(total_bits.div_ceil(8));
+    merged.padding_bits = ((8 - total_bits % 8) % 8) as u8;
+
+    merged.write_to(&mut output)?;
+    let mut writer = BitWriter::new(&mut output, buffer_size)?;
+    for (i, (input, header)) in inputs.iter_mut().zip(&headers).enumerate() {
+        let payload = (&mut *input).take(header.payload_length.unwrap_or(0));
+        let mut reader = BitReader::new(payload, header.padding_bits)?;
+        let copied = writer.copy_bits(&mut reader, bits_of(header))?;
+        if copied < bits_of(header) {
+            return Err(HuffmanError::TruncatedData(format!(
+                "input {} ends after {} of the {} bits of its payload", i + 1, copied, bits_of(header),
+            )));
+        }
+        if !input.fill_buf()?.is_empty() {
+            return Err(HuffmanError::Usage(format!("input {} holds more than one stream", i + 1)));
+        }
+    }
+    writer.finish()?;
+    Ok(merged)
+}
+
+// Upgrading
+////////////////////////////////////////////////////////////////////////////////
+
+// Rewrites a stream of version 0, 1 or 2 in the current version without
+// coding it again. Those only ever had plain tables of bytes, so the payload
+// is copied bit for bit under a header with the lengths and the checksum the
+// old one lacks. Only decoding knows them: `decoded` is the report of decoding
+// the stream and `checksum` the CRC-32 of what it decoded to. The input has to
+// be that one stream. Returns the header of the upgraded stream.
+#[cfg(feature = "std")]
+pub fn upgrade_stream<R: BufRead>(
+    input: &mut R,
+    mut output: impl Write,
+    decoded: &DecodeReport,
+    checksum: u32,
+    buffer_size: usize,
+) -> HuffmanResult<Header> {
+    let header = Header::read_from(input).with_context(|| String::from("failed to read header"))?;
+    if header.version >= VERSION {
+        return Err(HuffmanError::Usage(format!("the stream is version {} already", header.version)));
+    }
+    // Later streams could be of any version, with any table
+    if decoded.streams != 1 {
+        return Err(HuffmanError::Usage(format!("the input holds {} streams, only a single one keeps its payload", decoded.streams)));
+    }
+
+    let bits = decoded.payload_bits;
+    let mut upgraded = Header::new(decoded.output_bytes, header.encoding_table);
+    upgraded.checksum = Some(checksum);
+    upgraded.payload_length = Some(bits.div_ceil(8));
+    upgraded.padding_bits = ((8 - bits % 8) % 8) as u8;
+
+    upgraded.write_to(&mut output)?;
+    let mut writer = BitWriter::new(&mut output, buffer_size)?;
+    let mut reader = BitReader::new(&mut *input, 0)?;
+    let copied = writer.copy_bits(&mut reader, bits)?;
+    if copied < bits {
+        return Err(HuffmanError::TruncatedData(format!("the stream ends after {} of the {} bits of its payload", copied, bits)));
+    }
+    writer.finish()?;
+    Ok(upgraded)
+}
+
+// In memory
+////////////////////////////////////////////////////////////////////////////////
+
+// Encodes `data` into exactly the bytes `huffman encode` writes for a file
+// with the same contents: a single version 3 stream with canonical codes.
+// Encoding the same data always gives the same bytes.
+pub fn encode_bytes(data: &[u8]) -> HuffmanResult<Vec<u8>> {
+    encode_bytes_with(data, &EncoderOptions::default())
+}
+
+pub fn encode_bytes_with(data: &[u8], options: &EncoderOptions) -> HuffmanResult<Vec<u8>> {
+    let block_size = match options.block_size {
+        Some(block_size) => usize::try_from(block_size).unwrap_or(usize::MAX),
+        None => data.len().max(1),
+    };
+    let mut encoded = Vec::new();
+    let mut progress = Progress::new(options.progress(), options.cancel(), Some(data.len() as u64));
+    let mut report = EncodeReport::default();
+    for (i, block) in data.chunks(block_size).enumerate() {
+        encode_block_into(block, &mut encoded, options, &mut progress, (i * block_size) as u64, &mut report, None)?;
+    }
+    // An empty input still gets an (empty) stream
+    if data.is_empty() {
+        encode_block_into(data, &mut encoded, options, &mut progress, 0, &mut report, None)?;
+    }
+    progress.finish(report.progress());
+    Ok(encoded)
+}
+
+// Appends the stream of `block` to `encoded`, with the table of `options` or
+// the best one for the block. Like encode_block the header is written twice,
+// the second time with the fields that are known once the data is encoded.
+// The block starts at `offset` in the input, for `progress`, and goes into
+// `report`. With `frequencies` the counts of the block are added to it, and
+// the block is counted for them even with the table of `options`.
+pub(crate) fn encode_block_into(
+    block: &[u8],
+    encoded: &mut Vec<u8>,
+    options: &EncoderOptions,
+    progress: &mut Progress,
+    offset: u64,
+    report: &mut EncodeReport,
+    frequencies: Option<&mut FrequencyTable>,
+) -> HuffmanResult<()> {
+    let mut stopwatch = Stopwatch::start();
+    if options.adaptive {
+        progress.start_phase(Phase::Encoding, offset)?;
+        let header = Header::adaptive().with_checksum(options.checksum).with_info(first_info(options, offset));
+        let (_, sizes) = encode_adaptive(block, &mut *encoded, options.buffer_size, &header, progress, frequencies)?;
+        stopwatch.lap(&mut report.encoding_time);
+        report.input_bytes += block.len() as u64;
+        report.add_stream(&sizes);
+        return Ok(());
+    }
+    // With --filter the codes are of the filtered block, see filter.rs, with
+    // --bwt of the transformed block, see bwt.rs, with --rle of the packets,
+    // see rle.rs, and with --tokens of the tokens of a dictionary, see
+    // tokens.rs
+    let original = block;
+    let filtered;
+    let block = match options.filter {
+        Some(filter) => {
+            filtered = apply_filter(block, filter, options.stride);
+            &filtered[..]
+        }
+        None => block,
+    };
+    let mut primary_index = None;
+    let transformed;
+    let block = match options.bwt {
+        true => {
+            let (mut data, index) = burrows_wheeler_transform(block);
+            move_to_front(&mut data);
+            (transformed, primary_index) = (data, Some(index));
+            &transformed[..]
+        }
+        false => block,
+    };
+    let packets;
+    let block = match options.rle {
+        true => {
+            packets = run_length_encode(block);
+            &packets[..]
+        }
+        false => block,
+    };
+    let dictionary = options.tokens.then(|| choose_dictionary(block));
+    let token_symbols = dictionary.as_deref().map(token_symbols);
+    // With --context every byte has the codes of the byte before it, see
+    // context.rs, and with --method range there are no codes but the counts,
+    // see range.rs
+    let mut contexts = None;
+    let mut range_counts = None;
+    let counted;
+    let encoding_table = match (&options.encoding_table, frequencies) {
+        (Some(encoding_table), None) => encoding_table,
+        (_, frequencies) if options.method == Method::Range => {
+            progress.start_phase(Phase::Counting, offset)?;
+            let counts = count_with_progress(block, 1, progress)?;
+            if let Some(frequencies) = frequencies {
+                frequencies.merge(&counts);
+            }
+            (counted, range_counts) = (EncodingTable::new(), Some(scale_counts(&counts)));
+            stopwatch.lap(&mut report.counting_time);
+            &counted
+        }
+        (_, frequencies) if options.context => {
+            progress.start_phase(Phase::Counting, offset)?;
+            let counts = count_contexts(block, progress)?;
+            if let Some(frequencies) = frequencies {
+                counts.iter().for_each(|context| frequencies.merge(context));
+            }
+            let (shared, tables) = choose_tables(&counts);
+            (counted, contexts) = (shared, Some(tables));
+            stopwatch.lap(&mut report.counting_time);
+            &counted
+        }
+        (fixed, frequencies) => {
+            progress.start_phase(Phase::Counting, offset)?;
+            let counts = match &token_symbols {
+                Some(token_symbols) => count_symbols(block, token_symbols, progress)?,
+                None => count_with_progress(block, options.symbol_width, progress)?,
+            };
+            // The counts of the report are of bytes, whatever the codes are of
+            match (frequencies, counts.symbol_width()) {
+                (Some(frequencies), 1) => frequencies.merge(&counts),
+                (Some(frequencies), _) => frequencies.add(block),
+                (None, _) => {}
+            }
+            counted = match (fixed, HuffmanTree::from_frequencies(&counts)) {
+                (Some(encoding_table), _) => encoding_table.clone(),
+                (None, Some(tree)) => tree.codes(),
+                (None, None) => EncodingTable::new(),
+            };
+            stopwatch.lap(&mut report.counting_time);
+            &counted
+        }
+    };
+
+    let start = encoded.len();
+    let mut header = Header::new(original.len() as u64, encoding_table.clone());
+    if options.rle {
+        header = header.with_rle();
+    }
+    if options.symbol_width == 2 {
+        header = header.with_pairs((block.len() % 2 == 1).then(|| block[block.len() - 1]));
+    }
+    if let Some(dictionary) = dictionary.clone() {
+        header = header.with_dictionary(dictionary);
+    }
+    if let Some(primary_index) = primary_index {
+        header = header.with_bwt(primary_index);
+    }
+    if let Some(contexts) = contexts.clone() {
+        header = header.with_contexts(contexts);
+    }
+    if let Some(counts) = range_counts.clone() {
+        header = header.with_range(counts);
+    }
+    if let Some(filter) = options.filter {
+        header = header.with_filter(filter, options.stride);
+    }
+    header = header.with_checksum(options.checksum).with_info(first_info(options, offset));
+    header.write_to(encoded)?;
+    progress.start_phase(Phase::Encoding, offset)?;
+    let (padding_bits, checksum) = match (&token_symbols, &contexts, options.symbol_width) {
+        _ if range_counts.is_some() => {
+            let (_, checksum) = encode_range(block, &mut *encoded, range_counts.as_ref().unwrap(), options.checksum, progress)?;
+            (0, checksum)
+        }
+        (Some(token_symbols), _, _) => {
+            encode_tokens(block, &mut *encoded, encoding_table, token_symbols, options.buffer_size, options.checksum, progress)?
+        }
+        (None, Some(contexts), _) => {
+            encode_contexts(block, &mut *encoded, encoding_table, contexts, options.buffer_size, options.checksum, progress)?
+        }
+        (None, None, 2) => encode_pairs(block, &mut *encoded, encoding_table, options.buffer_size, options.checksum, progress)?,
+        (None, None, _) => encode_file_with_progress(block, &mut *encoded, encoding_table, options.buffer_size, options.checksum, progress)?,
+    };
+    let header_bytes = header.serialized_len();
+    let payload_bytes = (encoded.len() - start) as u64 - header_bytes;
+    header.padding_bits = padding_bits;
+    header.checksum = Some(match options.rle || options.bwt || options.filter.is_some() {
+        true => {
+            let mut crc = options.checksum.start();
+            crc.update(original);
+            crc.finish()
+        }
+        false => checksum,
+    });
+    header.payload_length = Some(payload_bytes);
+    header.write_to(&mut &mut encoded[start..])?;
+    stopwatch.lap(&mut report.encoding_time);
+    report.input_bytes += original.len() as u64;
+    report.add_stream(&StreamSizes { header_bytes, payload_bytes, padding_bits });
+    Ok(())
+}
+
+// The name and the modification time of the input go in the stream at the
+// start of it only
+fn first_info(options: &EncoderOptions, offset: u64) -> FileInfo {
+    match offset {
+        0 => options.file_info.clone(),
+        _ => FileInfo::default(),
+    }
+}
+
+// Encodes everything `input` holds into `output`, one stream per block of
+// `options`. As the header goes in front of the data, each block is read into
+// memory first, all of the input without a block size. A reader doesn't say
+// how long it is, so there is no total for the progress. The time spent
+// reading the input isn't in any phase of the report. Adaptive streams need
+// nothing up front, they are written as the input is read and only a buffer of
+// it is in memory. The method of `options` does the encoding, see method.rs.
+#[cfg(feature = "std")]
+pub fn encode_stream(input: impl Read, output: &mut impl Write, options: &EncoderOptions) -> HuffmanResult<EncodeReport> {
+    Registry::shipped().encode_stream(input, output, options)
+}
+
+// encode_stream for the streams with a table or range coded ones
+#[cfg(feature = "std")]
+pub(crate) fn encode_blocks(mut input: impl Read, output: &mut impl Write, options: &EncoderOptions) -> HuffmanResult<EncodeReport> {
+    let block_size = options.block_size.unwrap_or(u64::MAX);
+    let mut block = Vec::new();
+    let mut encoded = Vec::new();
+    let mut progress = Progress::new(options.progress(), options.cancel(), None);
+    let mut report = EncodeReport::default();
+    let mut frequencies = FrequencyTable::new();
+    loop {
+        block.clear();
+        (&mut input).take(block_size).read_to_end(&mut block)?;
+        // An empty input still gets an (empty) stream
+        if block.is_empty() && report.streams > 0 {
+            break;
+        }
+
+        encoded.clear();
+        encode_block_into(&block, &mut encoded, options, &mut progress, report.input_bytes, &mut report, Some(&mut frequencies))?;
+        output.write_all(&encoded)?;
+        if (block.len() as u64) < block_size {
+            break;
+        }
+    }
+    report.set_frequencies(&frequencies);
+    progress.finish(report.progress());
+    Ok(report)
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn encode_stream_adaptive(input: impl Read, output: &mut impl Write, options: &EncoderOptions) -> HuffmanResult<EncodeReport> {
+    let block_size = options.block_size.unwrap_or(u64::MAX);
+    let mut input = BufReader::with_capacity(options.buffer_size, input);
+    let mut progress = Progress::new(options.progress(), options.cancel(), None);
+    let mut report = EncodeReport::default();
+    let mut frequencies = FrequencyTable::new();
+    progress.start_phase(Phase::Encoding, 0)?;
+    loop {
+        let mut stopwatch = Stopwatch::start();
+        let block = (&mut input).take(block_size);
+        let header = Header::adaptive().with_checksum(options.checksum).with_info(first_info(options, report.input_bytes));
+        let (length, sizes) = encode_adaptive(block, &mut *output, options.buffer_size, &header, &mut progress, Some(&mut frequencies))?;
+        stopwatch.lap(&mut report.encoding_time);
+        report.input_bytes += length;
+        report.add_stream(&sizes);
+        // A full block is only followed by another one if there is more input
+        if length < block_size || input.fill_buf()?.is_empty() {
+            break;
+        }
+        progress.moved_to(report.input_bytes)?;
+    }
+    report.set_frequencies(&frequencies);
+    progress.finish(report.progress());
+    Ok(report)
+}
+
+// Decodes anything `huffman decode` accepts, with the same checks and errors:
+// every version of the header, concatenated streams and the blocks of
+// --block-size. There is no limit on the output, every decoded byte takes at
+// least one bit of the payload, so it stays within 8 times `encoded`. With
+// --rle two bits can stand for a run of 130 bytes, 520 times `encoded`, with
+// --symbol-width 2 a bit for two bytes, 16 times, and with --tokens a bit for
+// a token of up to 32 bytes, 256 times.
+pub fn decode_bytes(encoded: &[u8]) -> HuffmanResult<Vec<u8>> {
+    decode_bytes_with(encoded, &DecoderOptions::default())
+}
+
+pub fn decode_bytes_with(encoded: &[u8], options: &DecoderOptions) -> HuffmanResult<Vec<u8>> {
+    let mut decoded = Vec::new();
+    // Only decode_stream has the threads
+    #[cfg(feature = "std")]
+    if options.threads > 1 {
+        let mut reader = Cursor::new(encoded);
+        let header = decode_header(&mut reader)?;
+        decode_stream(&mut reader, &mut decoded, header, options)?;
+        return Ok(decoded);
+    }
+    decode_slice(encoded, &mut decoded, options)?;
+    Ok(decoded)
+}
+
+// Decodes into `output` instead of a new Vec and returns the number of bytes
+// decoded. Data that doesn't fit fails with OutputTooLarge, with the part that
+// fit already in `output`.
+pub fn decode_bytes_into(encoded: &[u8], output: &mut [u8]) -> HuffmanResult<usize> {
+    let options = DecoderOptions { max_output_size: Some(output.len() as u64), ..DecoderOptions::default() };
+    let mut rest = &mut output[..];
+    decode_slice(encoded, &mut rest, &options)?;
+    let unused = rest.len();
+    Ok(output.len() - unused)
+}
+
+// decode_stream for data that is all in memory, one stream after the other:
+// the payload of each stream is a slice of `encoded`, so there is nothing to
+// seek in
+pub(crate) fn decode_slice(encoded: &[u8], output: &mut impl Write, options: &DecoderOptions) -> HuffmanResult<DecodeReport> {
+    let mut stopwatch = Stopwatch::start();
+    let end = encoded.len() as u64;
+    let mut header = read_header(&mut &encoded[..], 0, Some(end))?;
+    let mut start = 0;
+    let mut report = DecodeReport::new(options.verify_checksum);
+    let mut symbols = vec![false; 1 << 16];
+    let mut progress = Progress::new(options.progress(), options.cancel(), Some(end));
+    progress.start_phase(Phase::Decoding, 0)?;
+    loop {
+        let payload_start = start + header.serialized_len();
+        // The payload can't go past the end, see decode_file
+        let payload_end = match header.payload_length {
+            Some(length) => payload_start.saturating_add(length).min(end),
+            None => end,
+        };
+        let payload = &encoded[payload_start as usize..payload_end as usize];
+        let max_output_size = options.max_output_size.map(|max| max - report.output_bytes);
+        let remaining = DecoderOptions { max_output_size, ..options.clone() };
+        progress.moved_to(payload_start)?;
+        let (bits, decoded) = match header.adaptive {
+            true => decode_adaptive(payload, &mut *output, &mut header, &remaining, &mut progress).map(|(bits, occurred)| {
+                report.add_symbols(&occurred, &mut symbols);
+                (bits, header.original_length.unwrap_or(0))
+            }),
+            false => decode_payload(payload, &mut *output, &header, &remaining, &mut progress),
+        }.map_err(in_stream(start))?;
+        report.output_bytes += decoded;
+        report.add_stream(&header, &decoded_sizes(&header, bits), &mut symbols);
+
+        // Version 0 streams have no length, they just read until the end
+        if header.original_length.is_none() {
+            break;
+        }
+
+        // The same as next_header
+        let next = payload_start + header.payload_length.unwrap_or(bits.div_ceil(8)) + header.trailer_len();
+        if next >= end {
+            break;
+        }
+        let rest = &encoded[next as usize..];
+        if !rest.starts_with(MAGIC) {
+            return Err(HuffmanError::TrailingGarbage { offset: next, length: end - next });
+        }
+        header = read_header(&mut &rest[..], next, Some(end))
+            .with_context(|| format!("failed to read header of stream at offset {}", next))?;
+        start = next;
+    }
+    progress.end_at(end)?;
+    report.input_bytes = end;
+    stopwatch.lap(&mut report.decoding_time);
+    progress.finish(report.progress());
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frequency::calculate_frequencies;
+    use crate::checksum::Xxh3;
+    use crate::header::VERSION_MARKER;
+    use crate::options::Filter;
+    use crate::table::{build_encoding_table, escaped_codes, Code};
+    use crate::test_util::Rng;
+    #[cfg(feature = "std")]
+    use crate::test_util::{bench, random_data, report_throughput, temp_path, text_data, MIB};
+    use crate::tree::build_huffman_tree;
+    #[cfg(feature = "std")]
+    use std::fs::{self, File};
+    #[cfg(feature = "std")]
+    use std::io::{BufReader, Cursor, Result as IoResult};
+
+    #[test]
+    fn test_decode_roundtrip() {
+        let data = b"the decode pipeline used by the fuzz tests works";
+        let encoded = encode_bytes(data).unwrap();
+        assert_eq!(decode_bytes(&encoded).unwrap(), data);
+    }
+
+    #[test]
+    fn test_decode_bytes_reads_concatenated_streams() {
+        let encoded = [encode_bytes(b"first ").unwrap(), encode_bytes(b"second").unwrap()].concat();
+        assert_eq!(decode_bytes(&encoded).unwrap(), b"first second");
+
+        let garbage = [&encoded[..], b"junk"].concat();
+        let error = decode_bytes(&garbage).unwrap_err();
+        assert_eq!(error.to_string(), format!("4 bytes of trailing garbage at offset {}", encoded.len()));
+    }
+
+    #[test]
+    fn test_decode_bytes_into_a_slice() {
+        let encoded = [encode_bytes(b"first ").unwrap(), encode_bytes(b"second").unwrap()].concat();
+        let mut output = [0u8; 16];
+        assert_eq!(decode_bytes_into(&encoded, &mut output).unwrap(), 12);
+        assert_eq!(&output[..12], b"first second");
+
+        // What fits is decoded before the error
+        let mut small = [0u8; 8];
+        let error = decode_bytes_into(&encoded, &mut small).unwrap_err();
+        assert!(matches!(error.root(), HuffmanError::OutputTooLarge { length: None, .. }));
+        assert_eq!(&small, b"first se");
+    }
+
+    // Both ends work on buffers just as well as on files
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_encode_and_decode_in_memory() {
+        let data = include_bytes!("../tests/fixtures/english.txt");
+        let frequencies = calculate_frequencies(&data[..], DEFAULT_BUFFER_SIZE).unwrap();
+        let encoding_table = build_encoding_table(&build_huffman_tree(&frequencies).unwrap());
+
+        let mut encoded = Cursor::new(Vec::new());
+        encode_block(&data[..], &mut encoded, data.len() as u64, &encoding_table, DEFAULT_BUFFER_SIZE).unwrap();
+        assert!(encoded.into_inner() == encode_bytes(data).unwrap());
+
+        let mut payload = Vec::new();
+        let (padding_bits, checksum) = encode_file(&data[..], &mut payload, &encoding_table, DEFAULT_BUFFER_SIZE).unwrap();
+        let header = Header {
+            version: 3,
+            original_length: Some(data.len() as u64),
+            checksum: Some(checksum),
+            checksum_kind: ChecksumKind::Crc32,
+            payload_length: Some(payload.len() as u64),
+            num_entries: encoding_table.len() as u32,
+            padding_bits,
+            encoding_table,
+            adaptive: false,
+            rle: false,
+            symbol_width: 1,
+            trailing_byte: None,
+            dictionary: None,
+            primary_index: None,
+            contexts: None,
+            range_counts: None,
+            filter: None,
+            info: FileInfo::default(),
+        };
+        let mut decoded = Vec::new();
+        let bits = decode_file(Cursor::new(&payload), &mut decoded, &header, &DecoderOptions::default()).unwrap();
+        assert_eq!(bits.div_ceil(8), payload.len() as u64);
+        assert!(decoded == data);
+    }
+
+    // Each kind of damage is reported as its own variant
+    #[test]
+    fn test_decode_errors_have_their_variant() {
+        let encoded = encode_bytes(b"abracadabra").unwrap();
+        let mut version = encoded.clone();
+        version[8] = 9;
+        let mut checksum = encoded.clone();
+        checksum[17] ^= 1;
+        let mut padding = encoded.clone();
+        padding[33] = 9;
+
+        let error = |input: &[u8]| decode_bytes(input).unwrap_err();
+        assert!(matches!(error(b"HUFF is the C version"), HuffmanError::InvalidMagic));
+        assert!(matches!(error(&version), HuffmanError::UnsupportedVersion(9)));
+        assert!(matches!(error(&encoded[..20]), HuffmanError::TruncatedHeader { offset: 20, .. }));
+        assert!(matches!(error(&padding), HuffmanError::CorruptHeader(_)));
+        assert!(matches!(error(&encoded[..encoded.len() - 1]), HuffmanError::TruncatedData(_)));
+        assert!(matches!(error(&checksum), HuffmanError::ChecksumMismatch { .. }));
+        let garbage = [&encoded[..], b"!"].concat();
+        assert!(matches!(error(&garbage), HuffmanError::TrailingGarbage { length: 1, .. }));
+
+        // Errors of later streams keep the variant under the context
+        let second = [&encoded[..], &checksum[..]].concat();
+        let error = error(&second);
+        assert!(matches!(error.root(), HuffmanError::ChecksumMismatch { .. }));
+        assert!(error.to_string().starts_with(&format!("stream at offset {}: checksum mismatch", encoded.len())));
+        assert!(error.is_corrupt());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_encode_reports_the_unknown_symbol() {
+        let frequencies = calculate_frequencies(&b"ab"[..], DEFAULT_BUFFER_SIZE).unwrap();
+        let encoding_table = build_encoding_table(&build_huffman_tree(&frequencies).unwrap());
+        let mut encoded = Cursor::new(Vec::new());
+        let error = encode_block(&b"abba cab"[..], &mut encoded, 8, &encoding_table, DEFAULT_BUFFER_SIZE).unwrap_err();
+        assert!(matches!(error, HuffmanError::UnknownSymbol { byte: b' ', offset: 4 }));
+    }
+
+    #[test]
+    fn test_decoder_options() {
+        let encoded = encode_bytes(b"abracadabra").unwrap();
+        let mut checksum = encoded.clone();
+        checksum[17] ^= 1;
+        let unchecked = DecoderOptions::builder().verify_checksum(false).build().unwrap();
+        assert!(matches!(decode_bytes(&checksum).unwrap_err(), HuffmanError::ChecksumMismatch { .. }));
+        assert_eq!(decode_bytes_with(&checksum, &unchecked).unwrap(), b"abracadabra");
+        // Everything else is still checked
+        let mut padding = encoded.clone();
+        padding[33] = 3;
+        assert!(decode_bytes_with(&padding, &unchecked).unwrap_err().is_corrupt());
+
+        let limited = DecoderOptions::builder().max_output_size(Some(10)).build().unwrap();
+        let error = decode_bytes_with(&encoded, &limited).unwrap_err();
+        assert!(matches!(error, HuffmanError::OutputTooLarge { length: None, max: 10 }));
+    }
+
+    #[test]
+    fn test_encoder_options() {
+        let data = include_bytes!("../tests/fixtures/english.txt");
+        let options = EncoderOptions::builder().block_size(1000).build().unwrap();
+        let encoded = encode_bytes_with(data, &options).unwrap();
+        assert_eq!(Header::read_from(&mut &encoded[..]).unwrap().original_length, Some(1000));
+        assert!(decode_bytes(&encoded).unwrap() == data);
+
+        // Every block keeps the table it is given
+        let frequencies = calculate_frequencies(&b"abc"[..], DEFAULT_BUFFER_SIZE).unwrap();
+        let encoding_table = build_encoding_table(&build_huffman_tree(&frequencies).unwrap());
+        let options = EncoderOptions::builder().encoding_table(encoding_table.clone()).block_size(2).build().unwrap();
+        let encoded = encode_bytes_with(b"aaaaa", &options).unwrap();
+        let header = Header::read_from(&mut &encoded[..]).unwrap();
+        assert_eq!(header.encoding_table, encoding_table);
+        assert_eq!(decode_bytes(&encoded).unwrap(), b"aaaaa");
+        let error = encode_bytes_with(b"abcd", &options).unwrap_err();
+        assert!(matches!(error, HuffmanError::UnknownSymbol { byte: b'd', offset: 1 }));
+    }
+
+    #[test]
+    fn test_escape_codes_encode_bytes_the_table_has_not_seen() {
+        // Trained on text, encoding every byte there is
+        let training = include_bytes!("../tests/fixtures/english.txt");
+        let encoding_table = escaped_codes(&calculate_frequencies(&training[..], DEFAULT_BUFFER_SIZE).unwrap());
+        let mut rng = Rng(0xE5CA);
+        let noise: Vec<u8> = (0..2000).map(|_| rng.next() as u8).collect();
+        let every_byte: Vec<u8> = (0..=255).collect();
+        let data = [&training[..2000], &noise[..], &every_byte[..]].concat();
+        assert!(data.iter().any(|&byte| encoding_table.get(byte as Symbol).is_none()));
+        for block_size in [None, Some(1000)] {
+            let mut builder = EncoderOptions::builder().encoding_table(encoding_table.clone());
+            if let Some(block_size) = block_size {
+                builder = builder.block_size(block_size);
+            }
+            let encoded = encode_bytes_with(&data, &builder.build().unwrap()).unwrap();
+            let header = Header::read_from(&mut &encoded[..]).unwrap();
+            assert_eq!((header.version, header.encoding_table.escape()), (6, encoding_table.escape()));
+            assert!(decode_bytes(&encoded).unwrap() == data);
+            for threads in [1, 4] {
+                let options = DecoderOptions::builder().threads(threads).build().unwrap();
+                assert!(decode_bytes_with(&encoded, &options).unwrap() == data);
+            }
+        }
+
+        // The text it was trained on only costs the bits of the escape code
+        // in the tree
+        let escaped = encode_bytes_with(training, &EncoderOptions::builder().encoding_table(encoding_table).build().unwrap()).unwrap();
+        let own = encode_bytes(training).unwrap();
+        assert!(escaped.len() < own.len() + own.len() / 50, "{} and {} bytes", escaped.len(), own.len());
+
+        // Cut in the middle of an escaped byte
+        let only_escape = EncodingTable::new().with_escape(Code { bits: 0, length: 1 });
+        let encoded = encode_bytes_with(b"\xff\xff", &EncoderOptions::builder().encoding_table(only_escape).build().unwrap()).unwrap();
+        assert!(decode_bytes(&encoded).unwrap() == b"\xff\xff");
+        let mut header = Header::read_from(&mut &encoded[..]).unwrap();
+        let payload = &encoded[header.serialized_len() as usize..];
+        header.original_length = Some(3);
+        let mut truncated = Vec::new();
+        header.write_to(&mut truncated).unwrap();
+        truncated.extend_from_slice(payload);
+        let unchecked = DecoderOptions::builder().verify_checksum(false).build().unwrap();
+        assert!(matches!(decode_bytes_with(&truncated, &unchecked), Err(HuffmanError::TruncatedData(_))), "{:?}", decode_bytes_with(&truncated, &unchecked));
+    }
+
+    #[test]
+    fn test_checksum_kinds() {
+        let data = include_bytes!("../tests/fixtures/english.txt");
+        let unchecked = DecoderOptions::builder().verify_checksum(false).build().unwrap();
+        let builders: [fn() -> crate::options::EncoderOptionsBuilder; 5] = [
+            EncoderOptions::builder,
+            || EncoderOptions::builder().block_size(1000),
+            || EncoderOptions::builder().rle(true),
+            || EncoderOptions::builder().method(Method::Range),
+            || EncoderOptions::builder().adaptive(true),
+        ];
+        for builder in builders {
+            for kind in ChecksumKind::ALL {
+                let options = builder().checksum(kind).build().unwrap();
+                let encoded = encode_bytes_with(data, &options).unwrap();
+                assert!(decode_bytes(&encoded).unwrap() == data, "{:?}", kind);
+                let mut header = Header::read_from(&mut &encoded[..]).unwrap();
+                assert_eq!(header.checksum_kind, kind);
+
+                // The same stream with a checksum of something else, which
+                // an adaptive stream has in the last 32 bits before the padding
+                let mut tampered = Vec::new();
+                match header.checksum {
+                    Some(checksum) => {
+                        let length = header.serialized_len() as usize;
+                        header.checksum = Some(checksum ^ 1);
+                        header.write_to(&mut tampered).unwrap();
+                        tampered.extend_from_slice(&encoded[length..]);
+                    }
+                    None => {
+                        tampered = encoded.clone();
+                        tampered[encoded.len() - 2] ^= 1;
+                    }
+                }
+                match kind {
+                    ChecksumKind::None => assert!(decode_bytes(&tampered).unwrap() == data),
+                    _ => assert!(matches!(decode_bytes(&tampered).map(|_| ()).unwrap_err().root(), HuffmanError::ChecksumMismatch { .. }), "{:?}", kind),
+                }
+                assert!(decode_bytes_with(&tampered, &unchecked).unwrap() == data);
+            }
+        }
+
+        // CRC-32 streams are still version 3 or 4, the others are version 5
+        let encoded = encode_bytes(b"abracadabra").unwrap();
+        assert_eq!(encoded[8], 3);
+        let options = EncoderOptions::builder().checksum(ChecksumKind::Xxh3).build().unwrap();
+        let xxh3 = encode_bytes_with(b"abracadabra", &options).unwrap();
+        assert_eq!(xxh3[8], 5);
+        let header = Header::read_from(&mut &xxh3[..]).unwrap();
+        let mut hash = Xxh3::new();
+        hash.update(b"abracadabra");
+        assert_eq!(header.checksum, Some(hash.finish_64() as u32));
+        let mut unknown = xxh3.clone();
+        unknown[10] = 9;
+        let error = decode_bytes(&unknown).unwrap_err();
+        assert_eq!(error.to_string(), HuffmanError::CorruptHeader(String::from("Unknown checksum algorithm: 9")).to_string());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_adaptive_roundtrip() {
+        let mut rng = Rng(0xADA9);
+        let inputs = [
+            include_bytes!("../tests/fixtures/english.txt").to_vec(),
+            include_bytes!("../tests/fixtures/gradient.png").to_vec(),
+            text_data(&mut rng, 100_000),
+            random_data(&mut rng, 100_000),
+            Vec::new(),
+            b"x".to_vec(),
+            (0..=255).collect(),
+            vec![0; 100_000],
+            b"ab".repeat(50_000),
+        ];
+        let adaptive = EncoderOptions::builder().adaptive(true).build().unwrap();
+        let blocks = EncoderOptions::builder().adaptive(true).block_size(4096).build().unwrap();
+        for data in &inputs {
+            for options in [&adaptive, &blocks] {
+                let encoded = encode_bytes_with(data, options).unwrap();
+                assert!(Header::read_from(&mut &encoded[..]).unwrap().adaptive);
+                assert!(decode_bytes(&encoded).unwrap() == *data, "{} bytes", data.len());
+
+                // Written as it is read, the same bytes
+                let mut streamed = Vec::new();
+                let encode = encode_stream(&data[..], &mut streamed, options).unwrap();
+                assert!(streamed == encoded);
+                assert_eq!(encode.output_bytes, encoded.len() as u64);
+
+                for threads in [1, 4] {
+                    let mut reader = Cursor::new(&encoded);
+                    let header = decode_header(&mut reader).unwrap();
+                    let mut decoded = Vec::new();
+                    let options = DecoderOptions::builder().threads(threads).build().unwrap();
+                    let report = decode_stream(&mut reader, &mut decoded, header, &options).unwrap();
+                    assert!(decoded == *data);
+                    assert_eq!((report.streams, report.header_bytes), (encode.streams, encode.header_bytes));
+                    assert_eq!((report.payload_bits, report.distinct_symbols), (encode.payload_bits, encode.distinct_symbols));
+                }
+            }
+        }
+
+        // Only the counts of the whole data give the best codes, adapting gets
+        // close on text
+        let text = text_data(&mut rng, 1_000_000);
+        let fixed = encode_bytes(&text).unwrap().len() as f64;
+        let adapted = encode_bytes_with(&text, &adaptive).unwrap().len() as f64;
+        assert!((adapted / fixed - 1.0).abs() < 0.01, "{} against {}", adapted, fixed);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_adaptive_streams_mix_with_the_others() {
+        let adaptive = EncoderOptions::builder().adaptive(true).build().unwrap();
+        let mut encoded = encode_bytes_with(b"abracadabra", &adaptive).unwrap();
+        encoded.extend(encode_bytes(b" and more").unwrap());
+        encoded.extend(encode_bytes_with(b", adapted", &adaptive).unwrap());
+        assert_eq!(decode_bytes(&encoded).unwrap(), b"abracadabra and more, adapted");
+
+        // The same checks as the other streams
+        let limited = DecoderOptions::builder().max_output_size(Some(10)).build().unwrap();
+        assert!(matches!(decode_bytes_with(&encoded, &limited), Err(HuffmanError::OutputTooLarge { max: 10, .. })));
+        let mut garbage = encode_bytes_with(b"abracadabra", &adaptive).unwrap();
+        garbage.push(0);
+        assert!(matches!(decode_bytes(&garbage), Err(HuffmanError::TrailingGarbage { length: 1, .. })));
+        let single = encode_bytes_with(b"abracadabra", &adaptive).unwrap();
+        let error = merge_streams(&mut [&single[..], &single[..]], Vec::new(), DEFAULT_BUFFER_SIZE).unwrap_err();
+        assert!(matches!(error, HuffmanError::Usage(_)));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_every_corrupted_byte_of_streams_with_flags_is_detected() {
+        let data = [&include_bytes!("../tests/fixtures/english.txt")[..], &[b' '; 1001]].concat();
+        let adaptive = EncoderOptions::builder().adaptive(true).build().unwrap();
+        let rle = EncoderOptions::builder().rle(true).build().unwrap();
+        let pairs = EncoderOptions::builder().symbol_width(2).build().unwrap();
+        let tokens = EncoderOptions::builder().tokens(true).build().unwrap();
+        let bwt = EncoderOptions::builder().bwt(true).build().unwrap();
+        let bwt_rle = EncoderOptions::builder().bwt(true).rle(true).build().unwrap();
+        let context = EncoderOptions::builder().context(true).build().unwrap();
+        let range = EncoderOptions::builder().method(Method::Range).build().unwrap();
+        let filter = EncoderOptions::builder().filter(Filter::Delta).stride(2).build().unwrap();
+        for options in [adaptive, rle, pairs, tokens, bwt, bwt_rle, context, range, filter] {
+            let encoded = encode_bytes_with(&data, &options).unwrap();
+            let undetected: Vec<usize> = (0..encoded.len())
+                .filter(|&position| {
+                    let mut corrupted = encoded.clone();
+                    corrupted[position] ^= 1 << (position % 8);
+                    decode_bytes(&corrupted).is_ok()
+                })
+                .collect();
+            assert!(undetected.is_empty(), "undetected bit flips at offsets {:?}", undetected);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_pairs_roundtrip() {
+        let mut rng = Rng(0x5108);
+        let utf16 = include_bytes!("../tests/fixtures/utf16le.txt");
+        let english: Vec<u8> = include_str!("../tests/fixtures/english.txt").encode_utf16().flat_map(u16::to_le_bytes).collect();
+        let inputs = [
+            Vec::new(),
+            b"x".to_vec(),
+            b"xyz".to_vec(),
+            utf16.to_vec(),
+            english.clone(),
+            text_data(&mut rng, 100_001),
+            random_data(&mut rng, 300_000),
+        ];
+        let pairs = EncoderOptions::builder().symbol_width(2).build().unwrap();
+        // Blocks of an odd size each leave a byte over
+        let blocks = EncoderOptions::builder().symbol_width(2).block_size(1001).build().unwrap();
+        for data in &inputs {
+            for options in [&pairs, &blocks] {
+                let encoded = encode_bytes_with(data, options).unwrap();
+                let header = Header::read_from(&mut &encoded[..]).unwrap();
+                assert_eq!(header.symbol_width, 2);
+                assert_eq!(header.trailing_byte.is_some(), header.original_length.unwrap() % 2 == 1);
+                assert!(decode_bytes(&encoded).unwrap() == *data, "{} bytes", data.len());
+                for threads in [1, 4] {
+                    let options = DecoderOptions::builder().threads(threads).build().unwrap();
+                    assert!(decode_bytes_with(&encoded, &options).unwrap() == *data);
+                }
+            }
+        }
+
+        // Text in UTF-16 has a byte of every pair that says little on its own
+        for data in [&utf16[..], &english] {
+            let (bytes, pairs) = (encode_bytes(data).unwrap().len(), encode_bytes_with(data, &pairs).unwrap().len());
+            assert!(pairs * 5 < bytes * 4, "{} bytes in pairs, {} as bytes", pairs, bytes);
+        }
+
+        let single = encode_bytes_with(b"xyz", &pairs).unwrap();
+        let error = merge_streams(&mut [&single[..], &single[..]], Vec::new(), DEFAULT_BUFFER_SIZE).unwrap_err();
+        assert_eq!(error.to_string(), "input 1 is coded in pairs of bytes, which can't be merged");
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_tokens_roundtrip() {
+        let mut rng = Rng(0x70CF);
+        let english = include_bytes!("../tests/fixtures/english.txt");
+        let utf8 = include_bytes!("../tests/fixtures/utf8.txt");
+        let text = text_data(&mut rng, 100_000);
+        // Words past the length of a token go as their bytes
+        let long_words = "incomprehensibilities_of_the_antidisestablishmentarians ".repeat(100);
+        let inputs = [
+            Vec::new(),
+            b"a".to_vec(),
+            b"the the the the".to_vec(),
+            english.to_vec(),
+            utf8.to_vec(),
+            text.clone(),
+            long_words.into_bytes(),
+            random_data(&mut rng, 100_000),
+        ];
+        let tokens = EncoderOptions::builder().tokens(true).build().unwrap();
+        let blocks = EncoderOptions::builder().tokens(true).block_size(1000).build().unwrap();
+        for data in &inputs {
+            for options in [&tokens, &blocks] {
+                let encoded = encode_bytes_with(data, options).unwrap();
+                assert!(Header::read_from(&mut &encoded[..]).unwrap().dictionary.is_some());
+                assert!(decode_bytes(&encoded).unwrap() == *data, "{} bytes", data.len());
+                for threads in [1, 4] {
+                    let options = DecoderOptions::builder().threads(threads).build().unwrap();
+                    assert!(decode_bytes_with(&encoded, &options).unwrap() == *data);
+                }
+            }
+        }
+
+        // The words of text repeat, the bytes of the UTF-8 characters in them
+        // even more so. english.txt is too short for its tokens to pay, it
+        // gets no worse than a byte per entry.
+        let size = |data: &[u8], options: &EncoderOptions| encode_bytes_with(data, options).unwrap().len();
+        for data in [&text[..], utf8] {
+            let (bytes, tokens) = (size(data, &EncoderOptions::default()), size(data, &tokens));
+            assert!(tokens * 3 < bytes * 2, "{} bytes in tokens, {} as bytes", tokens, bytes);
+        }
+        let header = Header::read_from(&mut &encode_bytes(english).unwrap()[..]).unwrap();
+        assert!(size(english, &tokens) <= size(english, &EncoderOptions::default()) + 4 + header.num_entries as usize);
+
+        // Streams with the same dictionary and table merge like any others
+        let encoded = encode_bytes_with(&text, &tokens).unwrap();
+        let output = merged(&[&encoded, &encoded]).unwrap();
+        assert!(decode_bytes(&output).unwrap() == [&text[..], &text].concat());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_bwt_roundtrip() {
+        let mut rng = Rng(0xB39);
+        let english = include_bytes!("../tests/fixtures/english.txt");
+        let inputs = [
+            Vec::new(),
+            b"a".to_vec(),
+            (0..=255).collect(),
+            (0..=255).flat_map(|byte| [byte; 300]).collect(),
+            english.to_vec(),
+            text_data(&mut rng, 200_000),
+            random_data(&mut rng, 50_000),
+            vec![0; 100_000],
+        ];
+        for block_size in [None, Some(1), Some(7), Some(1000), Some(65_536)] {
+            for rle in [false, true] {
+                let mut builder = EncoderOptions::builder().bwt(true).rle(rle);
+                if let Some(block_size) = block_size {
+                    builder = builder.block_size(block_size);
+                }
+                let options = builder.build().unwrap();
+                for data in &inputs {
+                    let encoded = encode_bytes_with(data, &options).unwrap();
+                    assert!(Header::read_from(&mut &encoded[..]).unwrap().primary_index.is_some());
+                    assert!(decode_bytes(&encoded).unwrap() == *data, "{} bytes in blocks of {:?}", data.len(), block_size);
+                    let threads = DecoderOptions::builder().threads(4).build().unwrap();
+                    assert!(decode_bytes_with(&encoded, &threads).unwrap() == *data);
+                }
+            }
+        }
+
+        // Text turns into few distinct bytes, mostly zeros
+        let corpus = [&english[..], include_bytes!("../tests/fixtures/utf8.txt"), include_bytes!("../README.md")].concat();
+        let bwt = EncoderOptions::builder().bwt(true).build().unwrap();
+        let (bytes, transformed) = (encode_bytes(&corpus).unwrap().len(), encode_bytes_with(&corpus, &bwt).unwrap().len());
+        assert!(transformed * 3 < bytes * 2, "{} bytes transformed, {} as they are", transformed, bytes);
+
+        let encoded = encode_bytes_with(b"abracadabra", &bwt).unwrap();
+        let error = merge_streams(&mut [&encoded[..]], Vec::new(), DEFAULT_BUFFER_SIZE).unwrap_err();
+        assert_eq!(error.to_string(), "input 1 is Burrows-Wheeler transformed, which can't be merged");
+        let limited = DecoderOptions::builder().max_output_size(Some(10)).build().unwrap();
+        assert!(matches!(decode_bytes_with(&encoded, &limited), Err(HuffmanError::OutputTooLarge { max: 10, .. })));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_context_roundtrip() {
+        let mut rng = Rng(0xC0DE);
+        let english = include_bytes!("../tests/fixtures/english.txt");
+        let inputs = [
+            Vec::new(),
+            b"a".to_vec(),
+            b"quick quiet quota".to_vec(),
+            (0..=255).collect(),
+            (0..=255).flat_map(|byte| [byte, 255 - byte, byte]).collect(),
+            english.to_vec(),
+            text_data(&mut rng, 100_000),
+            random_data(&mut rng, 50_000),
+        ];
+        let context = EncoderOptions::builder().context(true).build().unwrap();
+        let blocks = EncoderOptions::builder().context(true).block_size(1000).build().unwrap();
+        for data in &inputs {
+            for options in [&context, &blocks] {
+                let encoded = encode_bytes_with(data, options).unwrap();
+                assert!(Header::read_from(&mut &encoded[..]).unwrap().contexts.is_some());
+                assert!(decode_bytes(&encoded).unwrap() == *data, "{} bytes", data.len());
+                let threads = DecoderOptions::builder().threads(4).build().unwrap();
+                assert!(decode_bytes_with(&encoded, &threads).unwrap() == *data);
+            }
+        }
+
+        // The bytes of text depend on the one before them
+        let corpus = [&english[..], include_bytes!("../tests/fixtures/utf8.txt"), include_bytes!("../README.md")].concat();
+        for data in [&english[..], &corpus] {
+            let (bytes, contexts) = (encode_bytes(data).unwrap().len(), encode_bytes_with(data, &context).unwrap().len());
+            assert!(contexts < bytes, "{} bytes with contexts, {} without", contexts, bytes);
+        }
+        let (bytes, contexts) = (encode_bytes(&corpus).unwrap().len(), encode_bytes_with(&corpus, &context).unwrap().len());
+        assert!(contexts * 5 < bytes * 4, "{} bytes with contexts, {} without", contexts, bytes);
+
+        let encoded = encode_bytes_with(b"quick quiet quota", &context).unwrap();
+        let error = merge_streams(&mut [&encoded[..]], Vec::new(), DEFAULT_BUFFER_SIZE).unwrap_err();
+        assert_eq!(error.to_string(), "input 1 has tables per context, which can't be merged");
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_range_roundtrip() {
+        let mut rng = Rng(0x4A2E);
+        let inputs = [
+            Vec::new(),
+            b"a".to_vec(),
+            b"abracadabra".to_vec(),
+            (0..=255).collect(),
+            include_bytes!("../tests/fixtures/english.txt").to_vec(),
+            text_data(&mut rng, 100_000),
+            random_data(&mut rng, 50_000),
+        ];
+        let range = EncoderOptions::builder().method(Method::Range).build().unwrap();
+        let blocks = EncoderOptions::builder().method(Method::Range).block_size(1000).build().unwrap();
+        for data in &inputs {
+            for options in [&range, &blocks] {
+                let encoded = encode_bytes_with(data, options).unwrap();
+                assert!(Header::read_from(&mut &encoded[..]).unwrap().range_counts.is_some());
+                assert!(decode_bytes(&encoded).unwrap() == *data, "{} bytes", data.len());
+                let threads = DecoderOptions::builder().threads(4).build().unwrap();
+                assert!(decode_bytes_with(&encoded, &threads).unwrap() == *data);
+            }
+        }
+
+        // A code takes at least a bit for every byte, the range coder less
+        // than that for one that is almost every byte
+        let sparse = include_bytes!("../tests/fixtures/sparse.bin");
+        let (bytes, range_coded) = (encode_bytes(sparse).unwrap().len(), encode_bytes_with(sparse, &range).unwrap().len());
+        assert!(range_coded * 3 < bytes, "{} bytes range coded, {} with codes", range_coded, bytes);
+
+        let encoded = encode_bytes_with(b"abracadabra", &range).unwrap();
+        let error = merge_streams(&mut [&encoded[..]], Vec::new(), DEFAULT_BUFFER_SIZE).unwrap_err();
+        assert_eq!(error.to_string(), "input 1 is range coded, which can't be merged");
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_filter_roundtrip() {
+        let mut rng = Rng(0xF11E);
+        let inputs = [
+            Vec::new(),
+            b"a".to_vec(),
+            b"abc".to_vec(),
+            (0..=255).collect(),
+            text_data(&mut rng, 10_001),
+            random_data(&mut rng, 20_000),
+        ];
+        let builder = |filter: Filter, stride: u8| EncoderOptions::builder().filter(filter).stride(stride);
+        for filter in [Filter::Delta, Filter::XorPrev] {
+            for stride in [1, 2, 4] {
+                let options = [
+                    builder(filter, stride),
+                    builder(filter, stride).block_size(1000),
+                    builder(filter, stride).rle(true),
+                    builder(filter, stride).symbol_width(2),
+                    builder(filter, stride).tokens(true),
+                    builder(filter, stride).context(true),
+                    builder(filter, stride).method(Method::Range),
+                ];
+                for options in options.map(|builder| builder.build().unwrap()) {
+                    for data in &inputs {
+                        let encoded = encode_bytes_with(data, &options).unwrap();
+                        assert_eq!(Header::read_from(&mut &encoded[..]).unwrap().filter, Some((filter, stride)));
+                        assert!(decode_bytes(&encoded).unwrap() == *data, "{:?} of {} bytes", options, data.len());
+                    }
+                }
+            }
+        }
+
+        // The low bytes of a sine wave in 16-bit samples are all over the
+        // place, the differences of the samples are few
+        let pcm: Vec<u8> = (0..100_000)
+            .map(|i| (3000.0 * (i as f64 * 100.0 * 2.0 * std::f64::consts::PI / 44100.0).sin()) as i16)
+            .flat_map(i16::to_le_bytes)
+            .collect();
+        let delta = EncoderOptions::builder().filter(Filter::Delta).stride(2).build().unwrap();
+        let (bytes, filtered) = (encode_bytes(&pcm).unwrap().len(), encode_bytes_with(&pcm, &delta).unwrap().len());
+        assert!(filtered * 3 < bytes * 2, "{} bytes filtered, {} without", filtered, bytes);
+
+        let encoded = encode_bytes_with(b"abc", &delta).unwrap();
+        let error = merge_streams(&mut [&encoded[..]], Vec::new(), DEFAULT_BUFFER_SIZE).unwrap_err();
+        assert_eq!(error.to_string(), "input 1 is filtered, which can't be merged");
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_rle_roundtrip() {
+        let mut rng = Rng(0x5107);
+        let mut sparse = vec![0u8; 200_000];
+        for _ in 0..500 {
+            sparse[rng.below(200_000)] = rng.next() as u8;
+        }
+        let inputs = [
+            Vec::new(),
+            b"x".to_vec(),
+            vec![0; 1_000_000],
+            sparse,
+            include_bytes!("../tests/fixtures/pattern.bin").to_vec(),
+            text_data(&mut rng, 100_000),
+            random_data(&mut rng, 100_000),
+        ];
+        let rle = EncoderOptions::builder().rle(true).build().unwrap();
+        let blocks = EncoderOptions::builder().rle(true).block_size(1000).build().unwrap();
+        for data in &inputs {
+            for options in [&rle, &blocks] {
+                let encoded = encode_bytes_with(data, options).unwrap();
+                let header = Header::read_from(&mut &encoded[..]).unwrap();
+                assert!(header.rle && header.original_length == Some(data.len().min(options.block_size.unwrap_or(u64::MAX) as usize) as u64));
+                assert!(decode_bytes(&encoded).unwrap() == *data, "{} bytes", data.len());
+                for threads in [1, 4] {
+                    let options = DecoderOptions::builder().threads(threads).build().unwrap();
+                    assert!(decode_bytes_with(&encoded, &options).unwrap() == *data);
+                }
+            }
+        }
+
+        // Random data grows by little more than a control byte per 128 bytes
+        let random = &inputs[6];
+        let (plain, with_rle) = (encode_bytes(random).unwrap().len(), encode_bytes_with(random, &rle).unwrap().len());
+        assert!(with_rle <= plain + random.len() / 100, "{} bytes with --rle, {} without", with_rle, plain);
+
+        // A bit per byte is the best the codes can do on their own
+        let zeros = &inputs[2];
+        let (plain, rle) = (encode_bytes(zeros).unwrap().len(), encode_bytes_with(zeros, &rle).unwrap().len());
+        assert!(plain >= zeros.len() / 8 && rle * 30 < plain, "{} bytes with --rle, {} without", rle, plain);
+    }
+
+    // Minimized inputs that used to panic or loop, kept as regressions
+    #[test]
+    fn test_decode_crashers() {
+        let v1_prefix = |num_entries: u32, padding_bits: u8| {
+            let mut bytes = b"HRST".to_vec();
+            bytes.extend_from_slice(&VERSION_MARKER.to_le_bytes());
+            bytes.push(1);
+            bytes.extend_from_slice(&8u64.to_le_bytes());
+            bytes.extend_from_slice(&num_entries.to_le_bytes());
+            bytes.push(padding_bits);
+            bytes
+        };
+
+        let counts: FrequencyTable = [(b'a', 1)].into_iter().collect();
+        let header = Header::new(1 << 32, EncodingTable::new()).with_range(counts);
+        let mut range_bomb = Vec::new();
+        Header { payload_length: Some(5), ..header }.write_to(&mut range_bomb).unwrap();
+        range_bomb.extend_from_slice(&[0; 5]);
+
+        let crashers: Vec<(&str, Vec<u8>)> = vec![
+            // 8 - padding_bits underflowed
+            ("padding", [v1_prefix(1, 200), vec![b'a', 1, 0, 0, 0, 0, 0]].concat()),
+            // Shift by 32 - length with a zero length code
+            ("zero-length", [v1_prefix(1, 0), vec![b'a', 0, 0, 0, 0, 0, 0]].concat()),
+            // Shift by 32 - length overflowed the other way
+            ("long-code", [v1_prefix(1, 0), vec![b'a', 200, 0, 0, 0, 0, 0]].concat()),
+            // Codes that never match overflowed the length counter
+            ("no-match", [v1_prefix(1, 0), vec![b'a', 2, 0, 0, 0, 0], vec![0xFF; 64]].concat()),
+            // Entry count used to size the table
+            ("entries", v1_prefix(u32::MAX - 1, 0)),
+            // Truncated in the middle of the version 1 prefix
+            ("prefix", v1_prefix(1, 0)[..11].to_vec()),
+            // Version 0 header without any data
+            ("v0", b"HRST\x01\x00\x00\x00\x00".to_vec()),
+            // A single range coded byte takes no bits, so 5 bytes of payload
+            // decoded to 4 GiB
+            ("range-bomb", range_bomb),
+        ];
+
+        for (name, crasher) in crashers {
+            assert!(decode_bytes(&crasher).is_err(), "{} decoded", name);
+        }
+    }
+
+    // A cheap stand-in for a real fuzzer: random mutations of valid files must
+    // be rejected or decoded, but never panic. Every kind of stream gets them.
+    #[test]
+    fn test_decode_random_mutations() {
+        let builder = EncoderOptions::builder;
+        let encodings = [
+            EncoderOptions::default(),
+            builder().adaptive(true).build().unwrap(),
+            builder().rle(true).build().unwrap(),
+            builder().symbol_width(2).build().unwrap(),
+            builder().tokens(true).build().unwrap(),
+            builder().bwt(true).build().unwrap(),
+            builder().context(true).build().unwrap(),
+            builder().method(Method::Range).build().unwrap(),
+            builder().filter(Filter::Delta).stride(2).build().unwrap(),
+            builder().filter(Filter::XorPrev).build().unwrap(),
+            builder().block_size(8).build().unwrap(),
+        ];
+        let samples: [&[u8]; 4] = [
+            b"",
+            b"a",
+            b"abracadabra, abracadabra",
+            &(0..=255).collect::<Vec<u8>>(),
+        ];
+        let mut state = 0x9E37_79B9_7F4A_7C15u64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        for (sample, options) in samples.iter().flat_map(|sample| encodings.iter().map(move |options| (sample, options))) {
+            let encoded = encode_bytes_with(sample, options).unwrap();
+            for _ in 0..250 {
+                let mut mutated = encoded.clone();
+                match next() % 4 {
+                    0 => {
+                        let bit = next() as usize % (mutated.len() * 8);
+                        mutated[bit / 8] ^= 1 << (bit % 8);
+                    }
+                    1 => mutated.truncate(next() as usize % mutated.len()),
+                    2 => {
+                        let at = next() as usize % (mutated.len() + 1);
+                        mutated.insert(at, next() as u8);
+                    }
+                    _ => {
+                        let at = next() as usize % mutated.len();
+                        mutated[at] = next() as u8;
+                    }
+                }
+                let _ = decode_bytes(&mutated);
+            }
+        }
+    }
+
+    // Fields where a flipped bit is allowed to go unnoticed, as byte ranges of
+    // the encoded file. Nothing in the format is ignorable at the moment, a new
+    // field either gets covered by a check or has to be listed here.
+    const UNCHECKED_FIELDS: [(&str, std::ops::Range<usize>); 0] = [];
+
+    // Flips one bit at every position of an encoded fixture. Each mutation has
+    // to be rejected, either by the checksum or by a format check.
+    #[test]
+    fn test_every_corrupted_byte_is_detected() {
+        let data = include_bytes!("../tests/fixtures/english.txt");
+        let encoded = encode_bytes(data).unwrap();
+        let mut undetected = Vec::new();
+
+        for position in 0..encoded.len() {
+            let mut corrupted = encoded.clone();
+            corrupted[position] ^= 1 << (position % 8);
+            let ignorable = UNCHECKED_FIELDS.iter().any(|(_, range)| range.contains(&position));
+            if !ignorable && decode_bytes(&corrupted).is_ok() {
+                undetected.push(position);
+            }
+        }
+        assert!(undetected.is_empty(), "undetected bit flips at offsets {:?}", undetected);
+    }
+
+    fn roundtrip_property(data: &[u8]) -> bool {
+        let encoded = encode_bytes(data).unwrap();
+        matches!(decode_bytes(&encoded), Ok(decoded) if decoded == data)
+    }
+
+    // Greedily removes chunks and simplifies bytes while the property keeps
+    // failing, so a counterexample ends up as small as possible
+    fn shrink(mut data: Vec<u8>, property: impl Fn(&[u8]) -> bool) -> Vec<u8> {
+        let mut chunk = data.len().max(1);
+        while chunk > 0 {
+            let mut start = 0;
+            while start < data.len() {
+                let mut candidate = data.clone();
+                candidate.drain(start..(start + chunk).min(data.len()));
+                if !property(&candidate) {
+                    data = candidate;
+                } else {
+                    start += chunk;
+                }
+            }
+            chunk /= 2;
+        }
+        for i in 0..data.len() {
+            if data[i] != 0 {
+                let mut candidate = data.clone();
+                candidate[i] = 0;
+                if !property(&candidate) {
+                    data = candidate;
+                }
+            }
+        }
+        data
+    }
+
+    fn check_property(name: &str, cases: usize, generate: impl Fn(&mut Rng) -> Vec<u8>) {
+        for case in 0..cases {
+            let seed = 0x5EED_0000 + case as u64;
+            let data = generate(&mut Rng(seed));
+            if !roundtrip_property(&data) {
+                let minimal = shrink(data, roundtrip_property);
+                panic!("{}: roundtrip failed for seed {:#x}, minimal input: {:?}", name, seed, minimal);
+            }
+        }
+    }
+
+    #[test]
+    fn test_property_roundtrip_arbitrary_bytes() {
+        check_property("arbitrary", 100, |rng| {
+            let len = rng.below(300);
+            (0..len).map(|_| rng.next() as u8).collect()
+        });
+    }
+
+    #[test]
+    fn test_property_roundtrip_small_alphabets() {
+        check_property("small alphabet", 100, |rng| {
+            let alphabet: Vec<u8> = (0..1 + rng.below(4)).map(|_| rng.next() as u8).collect();
+            let len = rng.below(300);
+            (0..len).map(|_| alphabet[rng.below(alphabet.len())]).collect()
+        });
+    }
+
+    #[test]
+    fn test_property_roundtrip_long_runs() {
+        check_property("long runs", 50, |rng| {
+            let mut data = Vec::new();
+            for _ in 0..rng.below(5) {
+                let byte = rng.next() as u8;
+                data.extend(std::iter::repeat_n(byte, rng.below(2000)));
+            }
+            data
+        });
+    }
+
+    // Two symbols get 1 bit codes, so these lengths end just before, on and just
+    // after a byte boundary of the output
+    #[test]
+    fn test_property_roundtrip_around_byte_boundaries() {
+        check_property("byte boundaries", 60, |rng| {
+            let len = (8 * (1 + rng.below(20))) as isize + rng.below(3) as isize - 1;
+            (0..len).map(|_| if rng.next() % 2 == 0 { b'x' } else { b'y' }).collect()
+        });
+    }
+
+    #[test]
+    fn test_shrink_finds_minimal_counterexample() {
+        // A fake property that fails whenever the input contains 7 and 9
+        let property = |data: &[u8]| !(data.contains(&7) && data.contains(&9));
+        let data = vec![1, 2, 7, 3, 4, 5, 9, 6];
+        assert_eq!(shrink(data, property), [7, 9]);
+    }
+
+    #[cfg(feature = "std")]
+    fn merged(inputs: &[&[u8]]) -> HuffmanResult<Vec<u8>> {
+        let mut output = Vec::new();
+        merge_streams(&mut inputs.to_vec(), &mut output, 16)?;
+        Ok(output)
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_merge_streams_with_one_table() {
+        let parts = [&b"abracadabra"[..], b"", b"a bad cab", b"abba"];
+        let whole = parts.concat();
+        let frequencies = calculate_frequencies(&whole[..], DEFAULT_BUFFER_SIZE).unwrap();
+        let options = EncoderOptions::builder().encoding_table(build_encoding_table(&build_huffman_tree(&frequencies).unwrap())).build().unwrap();
+        let encoded: Vec<_> = parts.iter().map(|part| encode_bytes_with(part, &options).unwrap()).collect();
+        let inputs: Vec<&[u8]> = encoded.iter().map(|e| &e[..]).collect();
+
+        // The same bytes as encoding all of it, checksum and padding included
+        let output = merged(&inputs).unwrap();
+        assert_eq!(decode_bytes(&output).unwrap(), whole);
+        assert_eq!(output, encode_bytes_with(&whole, &options).unwrap());
+        assert_eq!(merged(&inputs[..1]).unwrap(), encoded[0]);
+        assert_eq!(merged(&[inputs[2], inputs[0]]).unwrap(), encode_bytes_with(b"a bad cababracadabra", &options).unwrap());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_merge_streams_rejects() {
+        let first = encode_bytes(b"abracadabra").unwrap();
+        let other = encode_bytes(b"something else").unwrap();
+        let blocks = encode_bytes_with(b"abracadabra", &EncoderOptions::builder().block_size(4).build().unwrap()).unwrap();
+        let v0 = include_bytes!("../tests/fixtures/versions/abracadabra.v0.encoded");
+        let message = |inputs: &[&[u8]]| match merged(inputs) {
+            Err(HuffmanError::Usage(message)) => message,
+            other => panic!("{:?}", other.map(|_| ())),
+        };
+        assert_eq!(message(&[&first, &other]), "input 2 has a different table than input 1");
+        assert_eq!(message(&[&blocks]), "input 1 holds more than one stream");
+        assert!(message(&[&first, v0]).starts_with("input 2 is a version 0 stream"));
+        assert_eq!(message(&[]), "nothing to merge");
+        let xxh3 = encode_bytes_with(b"abracadabra", &EncoderOptions::builder().checksum(ChecksumKind::Xxh3).build().unwrap()).unwrap();
+        assert_eq!(message(&[&first, &xxh3]), "input 2 has an xxh3 checksum, which can't be merged");
+        let none = encode_bytes_with(b"abracadabra", &EncoderOptions::builder().checksum(ChecksumKind::None).build().unwrap()).unwrap();
+        assert_eq!(message(&[&first, &none]), "input 2 and input 1 differ in --checksum");
+        assert!(matches!(merged(&[&first[..first.len() - 1]]), Err(HuffmanError::TruncatedData(_))));
+    }
+
+    // What upgrade_stream makes of `encoded`, after decoding it for the report
+    // and the checksum
+    #[cfg(feature = "std")]
+    fn upgraded(encoded: &[u8]) -> HuffmanResult<Vec<u8>> {
+        let mut decoded = Vec::new();
+        let report = decode_slice(encoded, &mut decoded, &DecoderOptions::default())?;
+        let mut crc = Crc32::new();
+        crc.update(&decoded);
+        let mut output = Vec::new();
+        upgrade_stream(&mut &encoded[..], &mut output, &report, crc.finish(), 16)?;
+        Ok(output)
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_upgrade_stream_keeps_the_payload() {
+        let current = encode_bytes(b"abracadabra").unwrap();
+        let old = [
+            &include_bytes!("../tests/fixtures/versions/abracadabra.v0.encoded")[..],
+            include_bytes!("../tests/fixtures/versions/abracadabra.v1.encoded"),
+            include_bytes!("../tests/fixtures/versions/abracadabra.v2.encoded"),
+        ];
+        for (version, encoded) in old.into_iter().enumerate() {
+            let output = upgraded(encoded).unwrap();
+            let (before, after) = (Header::read_from(&mut &encoded[..]).unwrap(), Header::read_from(&mut &output[..]).unwrap());
+            assert_eq!((before.version, after.version), (version as u8, VERSION));
+            assert_eq!(after.encoding_table, before.encoding_table);
+            assert_eq!(output[after.serialized_len() as usize..], encoded[before.serialized_len() as usize..], "version {}", version);
+            assert_eq!(decode_bytes(&output).unwrap(), b"abracadabra");
+            // The same as encoding it today, where the codes were the same
+            assert_eq!(output, current, "version {}", version);
+        }
+
+        // Codes from before they were canonical stay what they were
+        let legacy = include_bytes!("../tests/fixtures/legacy/english.txt.encoded");
+        let output = upgraded(legacy).unwrap();
+        let header = Header::read_from(&mut &output[..]).unwrap();
+        assert_eq!(header.encoding_table, Header::read_from(&mut &legacy[..]).unwrap().encoding_table);
+        assert!(decode_bytes(&output).unwrap() == include_bytes!("../tests/fixtures/english.txt"));
+
+        let message = |encoded: &[u8]| match upgraded(encoded) {
+            Err(HuffmanError::Usage(message)) => message,
+            other => panic!("{:?}", other.map(|_| ())),
+        };
+        assert_eq!(message(&current), "the stream is version 3 already");
+        assert_eq!(message(&[old[1], old[1]].concat()), "the input holds 2 streams, only a single one keeps its payload");
+    }
+
+    // The encoding pass as it was before reading in chunks
+    #[cfg(feature = "std")]
+    fn encode_file_by_byte(input: impl Read, output_file: &mut File, encoding_table: &EncodingTable) -> IoResult<u8> {
+        let mut reader = BufReader::new(input);
+        let mut bit_writer = BitWriter::new(output_file, DEFAULT_BUFFER_SIZE)?;
+        let codes = build_code_lookup(encoding_table);
+        let mut buffer = [0u8; 1];
+        while reader.read(&mut buffer)? > 0 {
+            let code = codes[buffer[0] as usize].unwrap();
+            bit_writer.write_code(&code)?;
+        }
+        bit_writer.finish()
+    }
+
+    #[test]
+    #[ignore]
+    #[cfg(feature = "std")]
+    fn bench_encode_file_reading() {
+        let mut rng = Rng(0xC4C4);
+        let data = text_data(&mut rng, 100_000_000);
+        let input_path = temp_path("reading.txt");
+        let output_path = temp_path("reading.encoded");
+        fs::write(&input_path, &data).unwrap();
+        let encoding_table = build_encoding_table(&build_huffman_tree(&calculate_frequencies(&data[..], DEFAULT_BUFFER_SIZE).unwrap()).unwrap());
+
+        let elapsed = bench(|| {
+            let mut output_file = File::create(&output_path).unwrap();
+            encode_file(File::open(&input_path).unwrap(), &mut output_file, &encoding_table, DEFAULT_BUFFER_SIZE).unwrap();
+        });
+        report_throughput("encode_file 100 MB file", data.len(), elapsed);
+        let elapsed = bench(|| {
+            let mut output_file = File::create(&output_path).unwrap();
+            encode_file_by_byte(File::open(&input_path).unwrap(), &mut output_file, &encoding_table).unwrap();
+        });
+        report_throughput("  byte at a time", data.len(), elapsed);
+
+        let _ = fs::remove_file(&input_path);
+        let _ = fs::remove_file(&output_path);
+    }
+
+    #[test]
+    #[ignore]
+    #[cfg(feature = "std")]
+    fn bench_decode_blocks_parallel() {
+        let mut rng = Rng(0xB10C);
+        let data = text_data(&mut rng, 64 * MIB);
+        let encoded_path = temp_path("blocks.encoded");
+        let decoded_path = temp_path("blocks.decoded");
+        let mut file = File::create(&encoded_path).unwrap();
+        for block in data.chunks(MIB) {
+            let frequencies = calculate_frequencies(block, DEFAULT_BUFFER_SIZE).unwrap();
+            let encoding_table = build_encoding_table(&build_huffman_tree(&frequencies).unwrap());
+            encode_block(block, &mut file, block.len() as u64, &encoding_table, DEFAULT_BUFFER_SIZE).unwrap();
+        }
+        drop(file);
+
+        let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+        println!("{} CPUs", cpus);
+        for threads in [1, 2, 4, 8] {
+            let elapsed = bench(|| {
+                let mut reader = BufReader::new(File::open(&encoded_path).unwrap());
+                let header = decode_header(&mut reader).unwrap();
+                let mut output_file = File::create(&decoded_path).unwrap();
+                let options = DecoderOptions::builder().threads(threads).build().unwrap();
+                decode_stream(&mut reader, &mut output_file, header, &options).unwrap();
+            });
+            report_throughput(&format!("decode 64 x 1 MiB blocks, {} threads", threads), data.len(), elapsed);
+        }
+        assert!(fs::read(&decoded_path).unwrap() == data);
+        fs::remove_file(&encoded_path).unwrap();
+        fs::remove_file(&decoded_path).unwrap();
+    }
+
+    // Encodes and decodes through temp files, as the codec only works on files
+    #[cfg(feature = "std")]
+    fn bench_codec(name: &str, data: &[u8]) {
+        let encoded_path = temp_path(&format!("{}.encoded", name));
+        let decoded_path = temp_path(&format!("{}.decoded", name));
+
+        let encode_elapsed = bench(|| {
+            let mut file = File::create(&encoded_path).unwrap();
+            let frequencies = calculate_frequencies(data, DEFAULT_BUFFER_SIZE).unwrap();
+            let encoding_table = build_encoding_table(&build_huffman_tree(&frequencies).unwrap());
+            encode_block(data, &mut file, data.len() as u64, &encoding_table, DEFAULT_BUFFER_SIZE).unwrap();
+        });
+        let decode_elapsed = bench(|| {
+            let mut reader = BufReader::new(File::open(&encoded_path).unwrap());
+            let header = decode_header(&mut reader).unwrap();
+            let mut output_file = File::create(&decoded_path).unwrap();
+            decode_stream(&mut reader, &mut output_file, header, &DecoderOptions::default()).unwrap();
+        });
+        assert_eq!(fs::read(&decoded_path).unwrap(), data);
+        let _ = fs::remove_file(&encoded_path);
+        let _ = fs::remove_file(&decoded_path);
+
+        report_throughput(&format!("encode {}", name), data.len(), encode_elapsed);
+        report_throughput(&format!("decode {}", name), data.len(), decode_elapsed);
+    }
+
+    #[test]
+    #[ignore]
+    #[cfg(feature = "std")]
+    fn bench_encode_decode() {
+        let mut rng = Rng(0xC0DEC);
+        bench_codec("text 8 MiB", &text_data(&mut rng, 8 * MIB));
+        bench_codec("random 8 MiB", &random_data(&mut rng, 8 * MIB));
+    }
+}
@@ -0,0 +1,240 @@
+// Defaults for the flags from config files, so flags given every time don't
+// have to be typed every time. The keys are the long flags without their
+// dashes, true or false for the ones without a value:
+//
+//     # ~/.config/huff/config.toml
+//     stats = true
+//     force = true
+//     output-template = "{dir}/{name}.huf"
+//     threads = 4
+//
+// Only that much of TOML is read: a key, =, and a string, an integer or a
+// boolean, with comments after #. The user's file is HUFF_CONFIG when it is
+// set, or huff/config.toml under XDG_CONFIG_HOME or ~/.config; .huff.toml in
+// the current directory overrides it key by key.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::error::{HuffmanError, HuffmanResult, IoContext};
+
+// Points at another file than the user's config
+pub(crate) const CONFIG_VAR: &str = "HUFF_CONFIG";
+
+// The file of a project, in the directory the command runs in
+const PROJECT_CONFIG: &str = ".huff.toml";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Value {
+    Bool(bool),
+    Integer(i64),
+    String(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Setting {
+    pub(crate) key: String,
+    pub(crate) value: Value,
+    // Where it was set, as file:line, for the messages about it
+    pub(crate) origin: String,
+}
+
+// The settings of a file, in order. Fails with Usage and the line on anything
+// that isn't a key, =, and a value, or a key set twice.
+pub(crate) fn parse_config(text: &str, path: &Path) -> HuffmanResult<Vec<Setting>> {
+    let mut settings: Vec<Setting> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let origin = format!("{}:{}", path.display(), number + 1);
+        let malformed = |message: &str| HuffmanError::Usage(format!("{}: {}", origin, message));
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with('[') {
+            return Err(malformed("tables aren't supported, only key = value"));
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(malformed("expected key = value"));
+        };
+        let key = key.trim();
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(malformed(&format!("invalid key '{}'", key)));
+        }
+        let value = parse_value(value.trim()).map_err(|message| malformed(&message))?;
+        if settings.iter().any(|setting| setting.key == key) {
+            return Err(malformed(&format!("{} is set twice", key)));
+        }
+        settings.push(Setting { key: key.to_string(), value, origin });
+    }
+    Ok(settings)
+}
+
+// The line up to a # that isn't in a string
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            }
+            (Some(q), c) if c == q && !escaped => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '#') => return &line[..i],
+            _ => {}
+        }
+        escaped = false;
+    }
+    line
+}
+
+fn parse_value(raw: &str) -> Result<Value, String> {
+    match raw {
+        "" => return Err(String::from("missing value")),
+        "true" => return Ok(Value::Bool(true)),
+        "false" => return Ok(Value::Bool(false)),
+        _ => {}
+    }
+    if let Some(literal) = raw.strip_prefix('\'') {
+        return match literal.strip_suffix('\'') {
+            Some(text) if !text.contains('\'') => Ok(Value::String(text.to_string())),
+            _ => Err(format!("unterminated string {}", raw)),
+        };
+    }
+    if let Some(basic) = raw.strip_prefix('"') {
+        return parse_basic_string(basic).ok_or_else(|| format!("invalid string {}", raw)).map(Value::String);
+    }
+    let digits = raw.replace('_', "");
+    match digits.parse::<i64>() {
+        Ok(n) if !raw.starts_with('_') && !raw.ends_with('_') => Ok(Value::Integer(n)),
+        _ => Err(format!("invalid value {}, expected a string, an integer, true or false", raw)),
+    }
+}
+
+// A "string" after its opening quote, with the escapes of TOML but \u
+fn parse_basic_string(rest: &str) -> Option<String> {
+    let mut text = String::new();
+    let mut chars = rest.chars();
+    loop {
+        match chars.next()? {
+            '"' => return chars.as_str().is_empty().then_some(text),
+            '\\' => text.push(match chars.next()? {
+                '"' => '"',
+                '\\' => '\\',
+                'n' => '\n',
+                't' => '\t',
+                'r' => '\r',
+                _ => return None,
+            }),
+            c => text.push(c),
+        }
+    }
+}
+
+// The files to read, the user's and then the project's, and whether a
+// missing one is an error: only when HUFF_CONFIG names it
+fn config_files() -> Vec<(PathBuf, bool)> {
+    let mut files = Vec::new();
+    match std::env::var_os(CONFIG_VAR).filter(|path| !path.is_empty()) {
+        Some(path) => files.push((PathBuf::from(path), true)),
+        None => {
+            let config_home = std::env::var_os("XDG_CONFIG_HOME")
+                .filter(|dir| !dir.is_empty())
+                .map(PathBuf::from)
+                .or_else(|| std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE")).map(|home| Path::new(&home).join(".config")));
+            if let Some(dir) = config_home {
+                files.push((dir.join("huff").join("config.toml"), false));
+            }
+        }
+    }
+    files.push((PathBuf::from(PROJECT_CONFIG), false));
+    files
+}
+
+// The settings of all the config files, a key of a later file replacing the
+// same key of an earlier one
+pub(crate) fn load_settings() -> HuffmanResult<Vec<Setting>> {
+    let mut layers = Vec::new();
+    for (path, required) in config_files() {
+        let text = match fs::read_to_string(&path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !required => continue,
+            read => read.with_context(|| format!("failed to read config '{}'", path.display()))?,
+        };
+        layers.push(parse_config(&text, &path)?);
+    }
+    Ok(merge_settings(layers))
+}
+
+fn merge_settings(layers: Vec<Vec<Setting>>) -> Vec<Setting> {
+    let mut merged: Vec<Setting> = Vec::new();
+    for setting in layers.into_iter().flatten() {
+        match merged.iter_mut().find(|earlier| earlier.key == setting.key) {
+            Some(earlier) => *earlier = setting,
+            None => merged.push(setting),
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> HuffmanResult<Vec<Setting>> {
+        parse_config(text, Path::new("config.toml"))
+    }
+
+    fn setting(key: &str, value: Value, origin: &str) -> Setting {
+        Setting { key: key.to_string(), value, origin: origin.to_string() }
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let text = "# what I always want\nstats = true\n\nforce=false  # not yet\noutput-template = \"{dir}/#{name}.huf\"\nthreads = 1_000\nfilter = 'delta'\nmethod = \"a \\\"b\\\"\\n\"\n";
+        assert_eq!(
+            parse(text).unwrap(),
+            [
+                setting("stats", Value::Bool(true), "config.toml:2"),
+                setting("force", Value::Bool(false), "config.toml:4"),
+                setting("output-template", Value::String(String::from("{dir}/#{name}.huf")), "config.toml:5"),
+                setting("threads", Value::Integer(1000), "config.toml:6"),
+                setting("filter", Value::String(String::from("delta")), "config.toml:7"),
+                setting("method", Value::String(String::from("a \"b\"\n")), "config.toml:8"),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_malformed_configs() {
+        for (text, message) in [
+            ("stats = true\nthreads\n", "config.toml:2: expected key = value"),
+            ("threads = \n", "config.toml:1: missing value"),
+            ("[encode]\nstats = true\n", "config.toml:1: tables aren't supported, only key = value"),
+            ("threads = four\n", "config.toml:1: invalid value four, expected a string, an integer, true or false"),
+            ("method = \"range\n", "config.toml:1: invalid string \"range"),
+            ("method = 'range\n", "config.toml:1: unterminated string 'range"),
+            ("my key = 1\n", "config.toml:1: invalid key 'my key'"),
+            ("stats = true\nstats = false\n", "config.toml:2: stats is set twice"),
+        ] {
+            let error = parse(text).unwrap_err();
+            assert!(matches!(error, HuffmanError::Usage(_)), "{:?}", text);
+            assert_eq!(error.to_string(), message, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn test_later_files_win() {
+        let user = parse("stats = true\nformat = \"text\"\n").unwrap();
+        let project = parse_config("format = \"binary\"\nforce = true\n", Path::new(".huff.toml")).unwrap();
+        assert_eq!(
+            merge_settings(vec![user, project]),
+            [
+                setting("stats", Value::Bool(true), "config.toml:1"),
+                setting("format", Value::String(String::from("binary")), ".huff.toml:1"),
+                setting("force", Value::Bool(true), ".huff.toml:2"),
+            ],
+        );
+    }
+}
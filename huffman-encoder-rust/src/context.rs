@@ -0,0 +1,94 @@
+// Order-1 context modeling, for `encode --context`: every byte is coded with
+// a table for the byte before it, its context, so after a 'q' the 'u' gets a
+// code of a bit or two. The first byte of a block comes after a 0.
+//
+// A table costs its place in the header, and most contexts of a short input
+// are seen too few times to pay for it. Those share one table, picked for
+// the bytes that follow all of them.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::error::HuffmanResult;
+use crate::frequency::FrequencyTable;
+use crate::progress::Progress;
+use crate::table::EncodingTable;
+use crate::tree::HuffmanTree;
+
+// A context is the previous byte
+pub(crate) const NUM_CONTEXTS: usize = 256;
+
+// The bits a table of its own takes in the header: the number of its codes,
+// and every byte with the length of its code, see header.rs
+fn table_bits(table: &EncodingTable) -> u64 {
+    8 * (1 + 2 * table.len() as u64)
+}
+
+// The counts of the bytes after every byte
+pub(crate) fn count_contexts(data: &[u8], progress: &mut Progress) -> HuffmanResult<Vec<FrequencyTable>> {
+    let mut counts = vec![FrequencyTable::new(); NUM_CONTEXTS];
+    let mut previous = 0;
+    for (i, &byte) in data.iter().enumerate() {
+        counts[previous as usize].counts[byte as usize] += 1;
+        previous = byte;
+        progress.update(i as u64 + 1)?;
+    }
+    progress.end(data.len() as u64)?;
+    Ok(counts)
+}
+
+// The shared table and the table of every context that has one of its own.
+// A context gets one when its codes are shorter than those of a table of all
+// bytes by more than the table costs, and the shared table is then picked for
+// the contexts that are left.
+pub(crate) fn choose_tables(counts: &[FrequencyTable]) -> (EncodingTable, Vec<Option<EncodingTable>>) {
+    let codes = |counts: &FrequencyTable| HuffmanTree::from_frequencies(counts).map(|tree| tree.codes());
+    let mut all = FrequencyTable::new();
+    for context in counts {
+        all.merge(context);
+    }
+    let Some(order_0) = codes(&all) else {
+        return (EncodingTable::new(), vec![None; NUM_CONTEXTS]);
+    };
+
+    let mut shared = FrequencyTable::new();
+    let tables = counts.iter()
+        .map(|context| {
+            let own = codes(context).filter(|own| {
+                table_bits(own) + own.expected_payload_bits(context) < order_0.expected_payload_bits(context)
+            });
+            if own.is_none() {
+                shared.merge(context);
+            }
+            own
+        })
+        .collect();
+    (codes(&shared).unwrap_or_default(), tables)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frequent_contexts_get_a_table() {
+        // 'q' is always followed by 'u', which is worth a table of one code.
+        // The bytes after the rest of the letters are too few for one.
+        let data = [&b"quick quiet quota "[..]; 50].concat();
+        let counts = count_contexts(&data, &mut Progress::none()).unwrap();
+        assert_eq!(counts[b'q' as usize].get(b'u' as u16), 150);
+        assert_eq!(counts[0].total(), 1);
+
+        let (shared, tables) = choose_tables(&counts);
+        let q = tables[b'q' as usize].as_ref().unwrap();
+        assert_eq!(q.len(), 1);
+        assert_eq!(q.get(b'u' as u16).unwrap().length, 1);
+        assert!(tables[0].is_none());
+        // The shared table has the bytes of the contexts without a table
+        assert!(shared.get(b'u' as u16).is_none());
+        assert!(shared.get(b'q' as u16).is_some());
+
+        let (shared, tables) = choose_tables(&count_contexts(b"", &mut Progress::none()).unwrap());
+        assert!(shared.is_empty() && tables.iter().all(Option::is_none));
+    }
+}
@@ -0,0 +1,213 @@
+// The errors of the library, and error messages that say what failed
+
+use alloc::boxed::Box;
+use alloc::string::String;
+#[cfg(feature = "std")]
+use alloc::string::ToString;
+use core::fmt;
+
+use crate::io::{Error, ErrorKind};
+
+// Everything that can go wrong encoding or decoding. Reading the input and
+// writing the output fail with Io, the rest says what is wrong with the data
+// or how the library was used. Functions that only do IO, like the bit IO and
+// counting the bytes, return io::Error instead.
+#[derive(Debug)]
+pub enum HuffmanError {
+    Io(Error),
+    // The input doesn't start with "HRST"
+    InvalidMagic,
+    UnsupportedVersion(u8),
+    // A stream of a method that isn't in the Registry decoding it, see
+    // method.rs
+    UnsupportedMethod(u8),
+    // The input ends at `offset`, in the middle of the header
+    TruncatedHeader { offset: u64, field: String },
+    // A field of the header that is out of range or doesn't add up
+    CorruptHeader(String),
+    // The encoded data ends before all of it is decoded
+    TruncatedData(String),
+    // The encoded data doesn't match its header
+    CorruptData(String),
+    ChecksumMismatch { expected: u32, actual: u32 },
+    // Encoding a byte that has no code in the table, `offset` is where it is
+    // in the input
+    UnknownSymbol { byte: u8, offset: u64 },
+    // An encoding table given to the library that can't be decoded, see
+    // EncodingTable::validate
+    InvalidTable(String),
+    // The data was encoded with another table file than the one given, see
+    // --table-file
+    TableMismatch(String),
+    // Something that isn't a stream after the last one
+    TrailingGarbage { offset: u64, length: u64 },
+    // The output, or with `length` the size the input declares, is over `max`
+    OutputTooLarge { length: Option<u64>, max: u64 },
+    // Options that don't work together, like the same file as input and output
+    Usage(String),
+    // The CancelToken of the options was cancelled
+    Cancelled,
+    // What we were doing when `source` happened, like which file or stream
+    Context { context: String, source: Box<HuffmanError> },
+}
+
+pub type HuffmanResult<T> = Result<T, HuffmanError>;
+
+impl HuffmanError {
+    // The error without the context around it
+    pub fn root(&self) -> &HuffmanError {
+        match self {
+            HuffmanError::Context { source, .. } => source.root(),
+            error => error,
+        }
+    }
+
+    // Whether the input is damaged or not in the format at all
+    pub fn is_corrupt(&self) -> bool {
+        matches!(
+            self.root(),
+            HuffmanError::InvalidMagic
+                | HuffmanError::UnsupportedVersion(_)
+                | HuffmanError::UnsupportedMethod(_)
+                | HuffmanError::TruncatedHeader { .. }
+                | HuffmanError::CorruptHeader(_)
+                | HuffmanError::TruncatedData(_)
+                | HuffmanError::CorruptData(_)
+                | HuffmanError::ChecksumMismatch { .. }
+                | HuffmanError::TrailingGarbage { .. }
+        )
+    }
+
+    // The closest io::ErrorKind, for errors that go through Read and Write
+    pub fn kind(&self) -> ErrorKind {
+        match self.root() {
+            HuffmanError::Io(e) => e.kind(),
+            HuffmanError::TruncatedHeader { .. } | HuffmanError::TruncatedData(_) => ErrorKind::UnexpectedEof,
+            HuffmanError::OutputTooLarge { .. } => ErrorKind::FileTooLarge,
+            HuffmanError::InvalidTable(_) | HuffmanError::TableMismatch(_) | HuffmanError::Usage(_) => ErrorKind::InvalidInput,
+            // Not Interrupted, which IO loops retry
+            HuffmanError::Cancelled => ErrorKind::Other,
+            _ => ErrorKind::InvalidData,
+        }
+    }
+}
+
+impl fmt::Display for HuffmanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HuffmanError::Io(e) => write!(f, "{}", e),
+            HuffmanError::InvalidMagic => write!(f, "Invalid file format"),
+            HuffmanError::UnsupportedVersion(version) => write!(f, "Unsupported version: {}", version),
+            HuffmanError::UnsupportedMethod(method) => write!(f, "encoded with an unsupported method ({})", method),
+            HuffmanError::TruncatedHeader { offset, field } => {
+                write!(f, "truncated header, the file ends at offset {} in the {}", offset, field)
+            }
+            HuffmanError::CorruptHeader(message)
+            | HuffmanError::TruncatedData(message)
+            | HuffmanError::CorruptData(message)
+            | HuffmanError::Usage(message) => write!(f, "{}", message),
+            HuffmanError::InvalidTable(message) => write!(f, "invalid encoding table: {}", message),
+            HuffmanError::TableMismatch(message) => write!(f, "table mismatch: {}", message),
+            HuffmanError::ChecksumMismatch { expected, actual } => {
+                write!(f, "checksum mismatch, the data is corrupt (expected {:08x}, got {:08x})", expected, actual)
+            }
+            HuffmanError::UnknownSymbol { byte, offset } => {
+                write!(f, "character not in encoding table: {} (at offset {})", byte, offset)
+            }
+            HuffmanError::TrailingGarbage { offset, length } => {
+                write!(f, "{} bytes of trailing garbage at offset {}", length, offset)
+            }
+            HuffmanError::OutputTooLarge { length: None, max } => {
+                write!(f, "output exceeds the maximum size of {} bytes (see --max-output-size)", max)
+            }
+            HuffmanError::OutputTooLarge { length: Some(length), max } => write!(
+                f,
+                "the data decodes to {} bytes, more than the maximum of {} bytes (see --max-output-size)",
+                length, max,
+            ),
+            HuffmanError::Cancelled => write!(f, "cancelled"),
+            HuffmanError::Context { context, source } => write!(f, "{}: {}", context, source),
+        }
+    }
+}
+
+impl core::error::Error for HuffmanError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            HuffmanError::Io(e) => Some(e),
+            HuffmanError::Context { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<Error> for HuffmanError {
+    fn from(e: Error) -> Self {
+        HuffmanError::Io(e)
+    }
+}
+
+// For Read and Write, which can only return an io::Error. A plain IO error is
+// passed on as it is, anything else is attached and can be taken back out with
+// get_ref() and downcast_ref::<HuffmanError>().
+#[cfg(feature = "std")]
+impl From<HuffmanError> for Error {
+    fn from(e: HuffmanError) -> Self {
+        match e {
+            HuffmanError::Io(e) => e,
+            e => Error::new(e.kind(), e),
+        }
+    }
+}
+
+// An IO error can't be cloned, its copy keeps the kind and the message
+impl Clone for HuffmanError {
+    fn clone(&self) -> Self {
+        match self {
+            #[cfg(feature = "std")]
+            HuffmanError::Io(e) => HuffmanError::Io(Error::new(e.kind(), e.to_string())),
+            #[cfg(not(feature = "std"))]
+            HuffmanError::Io(e) => HuffmanError::Io(e.clone()),
+            HuffmanError::InvalidMagic => HuffmanError::InvalidMagic,
+            HuffmanError::UnsupportedVersion(version) => HuffmanError::UnsupportedVersion(*version),
+            HuffmanError::UnsupportedMethod(method) => HuffmanError::UnsupportedMethod(*method),
+            HuffmanError::TruncatedHeader { offset, field } => {
+                HuffmanError::TruncatedHeader { offset: *offset, field: field.clone() }
+            }
+            HuffmanError::CorruptHeader(message) => HuffmanError::CorruptHeader(message.clone()),
+            HuffmanError::TruncatedData(message) => HuffmanError::TruncatedData(message.clone()),
+            HuffmanError::CorruptData(message) => HuffmanError::CorruptData(message.clone()),
+            HuffmanError::ChecksumMismatch { expected, actual } => {
+                HuffmanError::ChecksumMismatch { expected: *expected, actual: *actual }
+            }
+            HuffmanError::UnknownSymbol { byte, offset } => HuffmanError::UnknownSymbol { byte: *byte, offset: *offset },
+            HuffmanError::InvalidTable(message) => HuffmanError::InvalidTable(message.clone()),
+            HuffmanError::TableMismatch(message) => HuffmanError::TableMismatch(message.clone()),
+            HuffmanError::TrailingGarbage { offset, length } => {
+                HuffmanError::TrailingGarbage { offset: *offset, length: *length }
+            }
+            HuffmanError::OutputTooLarge { length, max } => HuffmanError::OutputTooLarge { length: *length, max: *max },
+            HuffmanError::Usage(message) => HuffmanError::Usage(message.clone()),
+            HuffmanError::Cancelled => HuffmanError::Cancelled,
+            HuffmanError::Context { context, source } => {
+                HuffmanError::Context { context: context.clone(), source: source.clone() }
+            }
+        }
+    }
+}
+
+// Prefixes errors with what we were doing and to which file, so a bare "No
+// such file or directory" says whether it was the input or the output.
+// Cancelled isn't about any file, it stays as it is.
+pub(crate) trait IoContext<T> {
+    fn with_context<F: FnOnce() -> String>(self, context: F) -> HuffmanResult<T>;
+}
+
+impl<T, E: Into<HuffmanError>> IoContext<T> for Result<T, E> {
+    fn with_context<F: FnOnce() -> String>(self, context: F) -> HuffmanResult<T> {
+        self.map_err(|e| match e.into() {
+            HuffmanError::Cancelled => HuffmanError::Cancelled,
+            e => HuffmanError::Context { context: context(), source: Box::new(e) },
+        })
+    }
+}
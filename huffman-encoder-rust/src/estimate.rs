@@ -0,0 +1,123 @@
+// How well data compresses, worked out from its byte counts without encoding
+// it
+
+use crate::frequency::FrequencyTable;
+use crate::header::{header_len, VERSION};
+#[cfg(feature = "std")]
+use crate::header::{FLAGS_VERSION, RANGE_ENTRY_SIZE};
+#[cfg(feature = "std")]
+use crate::range::{scale_counts, CODE_BYTES};
+use crate::table::EncodingTable;
+
+// The Shannon entropy of the counts, the fewest bits per byte any code that
+// assigns each byte a fixed code can average. Huffman codes stay within one
+// bit of it. 0 for empty data and for data of a single repeated byte. Needs
+// std for the logarithm.
+#[cfg(feature = "std")]
+pub fn entropy_bits_per_byte(frequencies: &FrequencyTable) -> f64 {
+    let total = frequencies.total() as f64;
+    frequencies.iter()
+        .map(|(_, count)| count as f64 / total)
+        .map(|p| -p * p.log2())
+        // Not sum(), which gives -0.0 for empty data
+        .fold(0.0, |sum, bits| sum + bits)
+}
+
+// The size in bytes encode writes for data with these counts, encoded in one
+// stream with `encoding_table`: the header with its entries and the payload.
+// Exactly the size of the output when the table has a code for every byte
+// that occurs.
+pub fn estimate_encoded_size(frequencies: &FrequencyTable, encoding_table: &EncodingTable) -> u64 {
+    let header = header_len(VERSION, encoding_table.len() as u32, 1);
+    header + encoding_table.expected_payload_bits(frequencies).div_ceil(8)
+}
+
+// About the size in bytes `encode --method range` writes for data with these
+// counts in one stream: the header with the scaled counts, the payload at the
+// entropy of those and the bytes the coder flushes at the end. Within a few
+// bytes of the output, which rounds a little at every byte.
+#[cfg(feature = "std")]
+pub fn estimate_range_size(frequencies: &FrequencyTable) -> u64 {
+    let scaled = scale_counts(frequencies);
+    let header = header_len(FLAGS_VERSION, 0, 1) + scaled.len() as u64 * RANGE_ENTRY_SIZE;
+    let total = scaled.total() as f64;
+    let bits = frequencies.iter()
+        .map(|(byte, count)| count as f64 * (total / scaled.get(byte) as f64).log2())
+        .fold(0.0, |sum, bits| sum + bits);
+    let flush = if frequencies.total() > 0 { CODE_BYTES as u64 } else { 0 };
+    header + (bits / 8.0).ceil() as u64 + flush
+}
+
+// Encoded size over original size, below 1 when encoding makes the data
+// smaller. Empty data counts as 1 byte, like in bench.
+pub fn estimate_ratio(frequencies: &FrequencyTable, encoding_table: &EncodingTable) -> f64 {
+    estimate_encoded_size(frequencies, encoding_table) as f64 / frequencies.total().max(1) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{encode_bytes, DEFAULT_BUFFER_SIZE};
+    #[cfg(feature = "std")]
+    use crate::codec::encode_bytes_with;
+    #[cfg(feature = "std")]
+    use crate::options::{EncoderOptions, Method};
+    use crate::frequency::calculate_frequencies;
+    use crate::tree::HuffmanTree;
+
+    const FIXTURES: [&[u8]; 5] = [
+        include_bytes!("../tests/fixtures/english.txt"),
+        include_bytes!("../tests/fixtures/data.json"),
+        include_bytes!("../tests/fixtures/utf8.txt"),
+        include_bytes!("../tests/fixtures/gradient.png"),
+        include_bytes!("../tests/fixtures/pattern.bin"),
+    ];
+
+    fn codes_for(data: &[u8]) -> (FrequencyTable, EncodingTable) {
+        let frequencies = calculate_frequencies(data, DEFAULT_BUFFER_SIZE).unwrap();
+        let encoding_table = HuffmanTree::from_frequencies(&frequencies).map(|tree| tree.codes()).unwrap_or_default();
+        (frequencies, encoding_table)
+    }
+
+    #[test]
+    fn test_estimates_match_the_encoded_fixtures() {
+        let samples = FIXTURES.iter().copied().chain([&b""[..], b"a", b"aaaa", b"abracadabra"]);
+        for data in samples {
+            let (frequencies, encoding_table) = codes_for(data);
+            let encoded = encode_bytes(data).unwrap();
+            assert_eq!(estimate_encoded_size(&frequencies, &encoding_table), encoded.len() as u64, "{} bytes", data.len());
+            let ratio = encoded.len() as f64 / data.len().max(1) as f64;
+            assert_eq!(estimate_ratio(&frequencies, &encoding_table), ratio);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_entropy_bits_per_byte() {
+        assert_eq!(entropy_bits_per_byte(&FrequencyTable::new()), 0.0);
+        assert_eq!(entropy_bits_per_byte(&FrequencyTable::from_iter([(b'a', 100)])), 0.0);
+        assert_eq!(entropy_bits_per_byte(&FrequencyTable::from_iter([(b'a', 5), (b'b', 5)])), 1.0);
+        let uniform: FrequencyTable = (0..=255).map(|byte| (byte, 3)).collect();
+        assert!((entropy_bits_per_byte(&uniform) - 8.0).abs() < 1e-9);
+
+        // The payload of every fixture is between the entropy and a bit more
+        for data in FIXTURES {
+            let (frequencies, encoding_table) = codes_for(data);
+            let entropy = entropy_bits_per_byte(&frequencies);
+            let average = encoding_table.expected_payload_bits(&frequencies) as f64 / data.len() as f64;
+            assert!(entropy <= average + 1e-9 && average < entropy + 1.0, "{} against {}", average, entropy);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_range_estimates_are_close_to_the_encoded_fixtures() {
+        let range = EncoderOptions::builder().method(Method::Range).build().unwrap();
+        let sparse = include_bytes!("../tests/fixtures/sparse.bin");
+        for data in FIXTURES.iter().copied().chain([&sparse[..], b"", b"a", b"abracadabra"]) {
+            let (frequencies, _) = codes_for(data);
+            let encoded = encode_bytes_with(data, &range).unwrap().len() as u64;
+            assert!(estimate_range_size(&frequencies).abs_diff(encoded) <= 8, "{} bytes, {} estimated", encoded, estimate_range_size(&frequencies));
+        }
+    }
+}
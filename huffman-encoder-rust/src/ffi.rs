@@ -0,0 +1,239 @@
+// The C interface, declared in include/hrst.h. Buffers in, buffers out: the
+// output is allocated here and goes back through hrst_free. Nothing unwinds
+// into C, a panic is reported as HRST_ERROR_PANIC.
+
+// The safety requirements of every function are in the header, with the rest
+// of the C documentation
+#![allow(clippy::missing_safety_doc)]
+
+use std::io::{self, ErrorKind, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::{ptr, slice};
+
+use crate::codec::{decode_slice, encode_block_into};
+use crate::error::HuffmanError;
+use crate::estimate::estimate_encoded_size;
+use crate::frequency::FrequencyTable;
+use crate::options::{DecoderOptions, EncoderOptions};
+use crate::progress::Progress;
+use crate::report::EncodeReport;
+use crate::tree::HuffmanTree;
+
+// Same values as hrst_status in include/hrst.h, only ever added to at the end
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HrstStatus {
+    Ok = 0,
+    NullPointer = 1,
+    Allocation = 2,
+    Panic = 3,
+    Io = 4,
+    InvalidMagic = 5,
+    UnsupportedVersion = 6,
+    TruncatedHeader = 7,
+    CorruptHeader = 8,
+    TruncatedData = 9,
+    CorruptData = 10,
+    ChecksumMismatch = 11,
+    UnknownSymbol = 12,
+    InvalidTable = 13,
+    TrailingGarbage = 14,
+    OutputTooLarge = 15,
+    Usage = 16,
+    Cancelled = 17,
+    UnsupportedMethod = 18,
+    TableMismatch = 19,
+}
+
+impl From<&HuffmanError> for HrstStatus {
+    // Context has no code of its own, the error under it says what failed
+    fn from(error: &HuffmanError) -> Self {
+        match error.root() {
+            HuffmanError::Io(e) if e.kind() == ErrorKind::OutOfMemory => HrstStatus::Allocation,
+            HuffmanError::Io(_) => HrstStatus::Io,
+            HuffmanError::InvalidMagic => HrstStatus::InvalidMagic,
+            HuffmanError::UnsupportedVersion(_) => HrstStatus::UnsupportedVersion,
+            HuffmanError::TruncatedHeader { .. } => HrstStatus::TruncatedHeader,
+            HuffmanError::CorruptHeader(_) => HrstStatus::CorruptHeader,
+            HuffmanError::TruncatedData(_) => HrstStatus::TruncatedData,
+            HuffmanError::CorruptData(_) => HrstStatus::CorruptData,
+            HuffmanError::ChecksumMismatch { .. } => HrstStatus::ChecksumMismatch,
+            HuffmanError::UnknownSymbol { .. } => HrstStatus::UnknownSymbol,
+            HuffmanError::InvalidTable(_) => HrstStatus::InvalidTable,
+            HuffmanError::TrailingGarbage { .. } => HrstStatus::TrailingGarbage,
+            HuffmanError::OutputTooLarge { .. } => HrstStatus::OutputTooLarge,
+            HuffmanError::Usage(_) => HrstStatus::Usage,
+            HuffmanError::Cancelled => HrstStatus::Cancelled,
+            HuffmanError::UnsupportedMethod(_) => HrstStatus::UnsupportedMethod,
+            HuffmanError::TableMismatch(_) => HrstStatus::TableMismatch,
+            HuffmanError::Context { .. } => unreachable!("root() is never a context"),
+        }
+    }
+}
+
+// A Vec that reports running out of memory as an error instead of aborting
+struct FallibleVec(Vec<u8>);
+
+impl Write for FallibleVec {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        self.0.try_reserve(buffer.len()).map_err(|_| io::Error::from(ErrorKind::OutOfMemory))?;
+        self.0.extend_from_slice(buffer);
+        Ok(buffer.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// The checks and the output handling shared by encode and decode: `codec` gets
+// the input and returns the output, or the status to report
+pub(crate) unsafe fn run(
+    input: *const u8,
+    input_length: usize,
+    output: *mut *mut u8,
+    output_length: *mut usize,
+    codec: impl FnOnce(&[u8]) -> Result<Vec<u8>, HrstStatus>,
+) -> HrstStatus {
+    if output.is_null() || output_length.is_null() {
+        return HrstStatus::NullPointer;
+    }
+    *output = ptr::null_mut();
+    *output_length = 0;
+    // A null input is fine as long as it is empty
+    let input = match (input.is_null(), input_length) {
+        (true, 0) => &[][..],
+        (true, _) => return HrstStatus::NullPointer,
+        (false, _) => slice::from_raw_parts(input, input_length),
+    };
+
+    let result = match panic::catch_unwind(AssertUnwindSafe(|| codec(input))) {
+        Ok(result) => result,
+        Err(_) => return HrstStatus::Panic,
+    };
+    match result {
+        // Empty output is handed out as null, so there is never a dangling
+        // pointer for C to hold on to
+        Ok(data) if data.is_empty() => HrstStatus::Ok,
+        Ok(data) => {
+            let data = Box::into_raw(data.into_boxed_slice());
+            *output = data as *mut u8;
+            *output_length = data.len();
+            HrstStatus::Ok
+        }
+        Err(status) => status,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hrst_encode(
+    input: *const u8,
+    input_length: usize,
+    output: *mut *mut u8,
+    output_length: *mut usize,
+) -> HrstStatus {
+    run(input, input_length, output, output_length, |data| {
+        // The same bytes as encode_bytes, with the whole output reserved up
+        // front so it can't grow while encoding
+        let mut frequencies = FrequencyTable::new();
+        frequencies.add(data);
+        let encoding_table = HuffmanTree::from_frequencies(&frequencies).map(|tree| tree.codes()).unwrap_or_default();
+        let size = usize::try_from(estimate_encoded_size(&frequencies, &encoding_table)).map_err(|_| HrstStatus::Allocation)?;
+        let mut encoded = Vec::new();
+        encoded.try_reserve_exact(size).map_err(|_| HrstStatus::Allocation)?;
+        let options = EncoderOptions { encoding_table: Some(encoding_table), ..EncoderOptions::default() };
+        let mut report = EncodeReport::default();
+        encode_block_into(data, &mut encoded, &options, &mut Progress::none(), 0, &mut report, None).map_err(|e| HrstStatus::from(&e))?;
+        Ok(encoded)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hrst_decode(
+    input: *const u8,
+    input_length: usize,
+    output: *mut *mut u8,
+    output_length: *mut usize,
+) -> HrstStatus {
+    run(input, input_length, output, output_length, |encoded| {
+        let mut decoded = FallibleVec(Vec::new());
+        decode_slice(encoded, &mut decoded, &DecoderOptions::default()).map_err(|e| HrstStatus::from(&e))?;
+        Ok(decoded.0)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hrst_free(data: *mut u8, length: usize) {
+    if !data.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(data, length)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{decode_bytes, encode_bytes};
+
+    // Calls `function` like C would and copies the output back into a Vec
+    fn call(
+        function: unsafe extern "C" fn(*const u8, usize, *mut *mut u8, *mut usize) -> HrstStatus,
+        input: &[u8],
+    ) -> Result<Vec<u8>, HrstStatus> {
+        let mut output = ptr::null_mut();
+        let mut output_length = 0;
+        let status = unsafe { function(input.as_ptr(), input.len(), &mut output, &mut output_length) };
+        if status != HrstStatus::Ok {
+            assert!(output.is_null() && output_length == 0);
+            return Err(status);
+        }
+        let data = match output.is_null() {
+            true => Vec::new(),
+            false => unsafe { slice::from_raw_parts(output, output_length) }.to_vec(),
+        };
+        unsafe { hrst_free(output, output_length) };
+        Ok(data)
+    }
+
+    #[test]
+    fn test_ffi_matches_encode_and_decode_bytes() {
+        let samples: [&[u8]; 4] = [b"", b"a", b"abracadabra", include_bytes!("../tests/fixtures/english.txt")];
+        for data in samples {
+            let encoded = call(hrst_encode, data).unwrap();
+            assert!(encoded == encode_bytes(data).unwrap(), "{} bytes", data.len());
+            assert!(call(hrst_decode, &encoded).unwrap() == data);
+        }
+        let concatenated = [encode_bytes(b"first ").unwrap(), encode_bytes(b"second").unwrap()].concat();
+        assert_eq!(call(hrst_decode, &concatenated).unwrap(), decode_bytes(&concatenated).unwrap());
+    }
+
+    #[test]
+    fn test_ffi_reports_the_error_under_the_context() {
+        let encoded = encode_bytes(b"abracadabra").unwrap();
+        let mut checksum = encoded.clone();
+        checksum[17] ^= 1;
+        assert_eq!(call(hrst_decode, b"HUFF is the C version"), Err(HrstStatus::InvalidMagic));
+        assert_eq!(call(hrst_decode, &checksum), Err(HrstStatus::ChecksumMismatch));
+        assert_eq!(call(hrst_decode, &[&encoded[..], &checksum[..]].concat()), Err(HrstStatus::ChecksumMismatch));
+        assert_eq!(call(hrst_decode, &[&encoded[..], b"!"].concat()), Err(HrstStatus::TrailingGarbage));
+        assert_eq!(call(hrst_decode, &encoded[..encoded.len() - 1]), Err(HrstStatus::TruncatedData));
+    }
+
+    #[test]
+    fn test_ffi_handles_null_pointers() {
+        let mut output = ptr::null_mut();
+        let mut output_length = 0;
+        unsafe {
+            // A null input is empty data, unless it claims a length
+            assert_eq!(hrst_encode(ptr::null(), 0, &mut output, &mut output_length), HrstStatus::Ok);
+            assert!(decode_bytes(slice::from_raw_parts(output, output_length)).unwrap().is_empty());
+            hrst_free(output, output_length);
+            assert_eq!(hrst_encode(ptr::null(), 5, &mut output, &mut output_length), HrstStatus::NullPointer);
+            assert!(output.is_null() && output_length == 0);
+            assert_eq!(hrst_decode(ptr::null(), 0, &mut output, &mut output_length), HrstStatus::TruncatedHeader);
+
+            assert_eq!(hrst_encode(b"a".as_ptr(), 1, ptr::null_mut(), &mut output_length), HrstStatus::NullPointer);
+            assert_eq!(hrst_decode(b"a".as_ptr(), 1, &mut output, ptr::null_mut()), HrstStatus::NullPointer);
+            hrst_free(ptr::null_mut(), 0);
+        }
+    }
+}
@@ -0,0 +1,86 @@
+// Filters before the codes, for `encode --filter`: every byte is replaced by
+// its difference from the byte `stride` bytes before it (delta) or by the two
+// XORed (xor-prev), the bytes before the first ones taken as 0. Data that
+// varies smoothly, like audio, images or series of numbers, turns into small
+// differences that are mostly the same few bytes, even when its own bytes are
+// all over the place. The stride is the size of a sample, so 16-bit audio
+// takes the difference of every byte with the same byte of the sample before.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::options::Filter;
+
+// The filtered bytes of `data`
+pub(crate) fn apply_filter(data: &[u8], filter: Filter, stride: u8) -> Vec<u8> {
+    let stride = stride as usize;
+    let mut filtered = Vec::with_capacity(data.len());
+    for (i, &byte) in data.iter().enumerate() {
+        let before = if i >= stride { data[i - stride] } else { 0 };
+        filtered.push(match filter {
+            Filter::Delta => byte.wrapping_sub(before),
+            Filter::XorPrev => byte ^ before,
+        });
+    }
+    filtered
+}
+
+// Undoes apply_filter a byte at a time, keeping the last `stride` bytes
+pub(crate) struct FilterDecoder {
+    filter: Filter,
+    history: Vec<u8>,
+    position: usize,
+}
+
+impl FilterDecoder {
+    pub(crate) fn new(filter: Filter, stride: u8) -> Self {
+        FilterDecoder { filter, history: vec![0; stride as usize], position: 0 }
+    }
+
+    // The byte of the data the filtered byte stands for
+    #[inline]
+    pub(crate) fn undo(&mut self, filtered: u8) -> u8 {
+        let before = self.history[self.position];
+        let byte = match self.filter {
+            Filter::Delta => filtered.wrapping_add(before),
+            Filter::XorPrev => filtered ^ before,
+        };
+        self.history[self.position] = byte;
+        self.position = (self.position + 1) % self.history.len();
+        byte
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{random_data, Rng};
+
+    #[test]
+    fn test_filters_roundtrip() {
+        let mut rng = Rng(0xF117);
+        let inputs = [Vec::new(), vec![7], random_data(&mut rng, 1001), (0..=255).cycle().take(3000).collect()];
+        for data in &inputs {
+            for filter in [Filter::Delta, Filter::XorPrev] {
+                for stride in [1, 2, 4] {
+                    let filtered = apply_filter(data, filter, stride);
+                    assert_eq!(filtered.len(), data.len());
+                    let mut decoder = FilterDecoder::new(filter, stride);
+                    let restored: Vec<u8> = filtered.iter().map(|&byte| decoder.undo(byte)).collect();
+                    assert!(restored == *data, "{:?} of {} bytes with a stride of {}", filter, data.len(), stride);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_steps_become_the_same_byte() {
+        // 16-bit samples that go up by 3 at every step
+        let samples: Vec<u8> = (0..100u16).flat_map(|i| (1000 + 3 * i).to_le_bytes()).collect();
+        let filtered = apply_filter(&samples, Filter::Delta, 2);
+        assert_eq!(&filtered[..4], [0xE8, 0x03, 3, 0]);
+        // The high byte only changes when the low one wraps around
+        assert!(filtered[4..].chunks(2).all(|sample| sample == [3, 0] || sample == [3, 1]));
+        assert_eq!(&apply_filter(b"aab", Filter::XorPrev, 1), b"a\0\x03");
+    }
+}
@@ -0,0 +1,117 @@
+// The byte counts of an input, kept next to it for --freq-cache
+
+use std::fs::{self, File};
+use std::io::{Result as IoResult, Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use crate::checksum::Crc32;
+use crate::frequency::{read_at, FrequencyTable};
+use crate::output::sibling_filename;
+
+// With --freq-cache the counts of an input are kept in a hidden file next to
+// it, so encoding it again doesn't have to count it again:
+//   "HFRQ" | version: u8 | size: u64 | modified seconds: u64 | nanoseconds: u32
+//          | head checksum: u32 | tail checksum: u32 | counts: 256 x u64
+const FREQ_CACHE_MAGIC: &[u8; 4] = b"HFRQ";
+const FREQ_CACHE_VERSION: u8 = 1;
+const FREQ_CACHE_SIZE: usize = 33 + 256 * 8;
+// Bytes at either end of the input that are checksummed into the key, which
+// catches edits that kept the size and the modification time
+const FREQ_CACHE_EDGE_SIZE: u64 = 64 * 1024;
+// Inputs modified this recently aren't cached, they could change again without
+// changing their modification time
+const FREQ_CACHE_SETTLE_TIME: std::time::Duration = std::time::Duration::from_secs(2);
+
+// What has to match for the cached counts to be used
+#[derive(PartialEq)]
+pub(crate) struct CacheKey {
+    pub(crate) size: u64,
+    modified: std::time::Duration,
+    head_checksum: u32,
+    tail_checksum: u32,
+}
+
+pub(crate) fn freq_cache_filename(input_filename: &Path) -> PathBuf {
+    sibling_filename(input_filename, ".freq")
+}
+
+// Errors when any part of the key can't be determined, there is no caching then
+pub(crate) fn freq_cache_key(file: &File) -> IoResult<CacheKey> {
+    let metadata = file.metadata()?;
+    let modified = metadata.modified()?
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(|_| Error::other("modified before 1970"))?;
+    let size = metadata.len();
+    let edge = FREQ_CACHE_EDGE_SIZE.min(size);
+    let checksum_at = |offset: u64| -> IoResult<u32> {
+        let mut buffer = vec![0u8; edge as usize];
+        let mut filled = 0;
+        while filled < buffer.len() {
+            match read_at(file, &mut buffer[filled..], offset + filled as u64) {
+                Ok(0) => return Err(Error::from(ErrorKind::UnexpectedEof)),
+                Ok(read) => filled += read,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        let mut crc = Crc32::new();
+        crc.update(&buffer);
+        Ok(crc.finish())
+    };
+    Ok(CacheKey { size, modified, head_checksum: checksum_at(0)?, tail_checksum: checksum_at(size - edge)? })
+}
+
+// None for a missing, damaged or stale cache, which just means counting
+pub(crate) fn load_freq_cache(filename: &Path, key: &CacheKey) -> Option<FrequencyTable> {
+    let data = fs::read(filename).ok()?;
+    if data.len() != FREQ_CACHE_SIZE || data[0..4] != *FREQ_CACHE_MAGIC || data[4] != FREQ_CACHE_VERSION {
+        return None;
+    }
+    let u64_at = |offset: usize| u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap());
+    let u32_at = |offset: usize| u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+    let cached_key = CacheKey {
+        size: u64_at(5),
+        modified: std::time::Duration::new(u64_at(13), u32_at(21)),
+        head_checksum: u32_at(25),
+        tail_checksum: u32_at(29),
+    };
+    if cached_key != *key {
+        return None;
+    }
+
+    let mut frequencies = FrequencyTable::new();
+    for (i, count) in frequencies.counts.iter_mut().enumerate() {
+        *count = u64_at(33 + i * 8);
+    }
+    // Counts that don't add up to the input can't be its counts
+    (frequencies.total() == key.size).then_some(frequencies)
+}
+
+// Written next to the cache and renamed over it, so a reader never sees half
+// of it. Returns false when the input changed too recently to be trusted.
+pub(crate) fn store_freq_cache(filename: &Path, key: &CacheKey, frequencies: &FrequencyTable) -> IoResult<bool> {
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+    if key.modified + FREQ_CACHE_SETTLE_TIME > now {
+        return Ok(false);
+    }
+
+    let mut data = Vec::with_capacity(FREQ_CACHE_SIZE);
+    data.extend_from_slice(FREQ_CACHE_MAGIC);
+    data.push(FREQ_CACHE_VERSION);
+    data.extend_from_slice(&key.size.to_le_bytes());
+    data.extend_from_slice(&key.modified.as_secs().to_le_bytes());
+    data.extend_from_slice(&key.modified.subsec_nanos().to_le_bytes());
+    data.extend_from_slice(&key.head_checksum.to_le_bytes());
+    data.extend_from_slice(&key.tail_checksum.to_le_bytes());
+    for count in &frequencies.counts {
+        data.extend_from_slice(&count.to_le_bytes());
+    }
+
+    let mut temp_filename = filename.as_os_str().to_owned();
+    temp_filename.push(format!(".{}.tmp", std::process::id()));
+    let temp_filename = PathBuf::from(temp_filename);
+    fs::write(&temp_filename, &data)
+        .and_then(|()| fs::rename(&temp_filename, filename))
+        .inspect_err(|_| { let _ = fs::remove_file(&temp_filename); })?;
+    Ok(true)
+}
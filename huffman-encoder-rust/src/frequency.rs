@@ -0,0 +1,483 @@
+// Counting how often every byte occurs in the input
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(feature = "cli")]
+use std::fs::File;
+#[cfg(feature = "cli")]
+use std::ops::Range;
+
+#[cfg(feature = "serde")]
+use alloc::collections::BTreeMap;
+#[cfg(feature = "serde")]
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::error::{HuffmanError, HuffmanResult};
+use crate::io::{Read, Result as IoResult, ErrorKind};
+use crate::progress::{Progress, PROGRESS_STEP};
+
+// What the codes are for: a byte, or with --symbol-width 2 a pair of bytes,
+// the first one in the low bits
+pub type Symbol = u16;
+
+// Count per symbol. There are exactly 256 bytes, or 65536 pairs, so a Vec
+// indexed by the symbol does the job of a map without hashing every input
+// byte.
+#[derive(Clone, PartialEq)]
+pub struct FrequencyTable {
+    pub(crate) counts: Vec<u64>,
+}
+
+// Chunks from this size on are counted in lanes, summing the lanes costs more
+// than it saves on less
+const LANE_THRESHOLD: usize = 4096;
+
+impl FrequencyTable {
+    pub fn new() -> Self {
+        FrequencyTable { counts: vec![0; 256] }
+    }
+
+    // Counts of the symbols of `symbol_width` bytes, 1 or 2
+    pub fn with_symbol_width(symbol_width: u8) -> Self {
+        assert!(matches!(symbol_width, 1 | 2), "symbols are 1 or 2 bytes, not {}", symbol_width);
+        FrequencyTable { counts: vec![0; 1 << (8 * symbol_width as u32)] }
+    }
+
+    pub fn symbol_width(&self) -> u8 {
+        if self.counts.len() > 256 { 2 } else { 1 }
+    }
+
+    // With 2 byte symbols the data is counted in pairs, an odd byte at the end
+    // has no symbol and isn't counted
+    pub fn add(&mut self, data: &[u8]) {
+        if self.symbol_width() == 2 {
+            for pair in data.chunks_exact(2) {
+                self.counts[u16::from_le_bytes([pair[0], pair[1]]) as usize] += 1;
+            }
+            return;
+        }
+        if data.len() < LANE_THRESHOLD {
+            self.add_serial(data);
+            return;
+        }
+
+        // With a single table, runs of the same byte wait for the previous
+        // increment of the same counter. Four tables, one per position modulo
+        // 4, let those increments happen side by side.
+        let mut lanes = [[0u64; 256]; 4];
+        let mut chunks = data.chunks_exact(4);
+        for chunk in &mut chunks {
+            lanes[0][chunk[0] as usize] += 1;
+            lanes[1][chunk[1] as usize] += 1;
+            lanes[2][chunk[2] as usize] += 1;
+            lanes[3][chunk[3] as usize] += 1;
+        }
+        self.add_serial(chunks.remainder());
+        for lane in &lanes {
+            for (count, lane_count) in self.counts.iter_mut().zip(lane) {
+                *count += lane_count;
+            }
+        }
+    }
+
+    fn add_serial(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.counts[byte as usize] += 1;
+        }
+    }
+
+    pub fn get(&self, symbol: Symbol) -> u64 {
+        self.counts.get(symbol as usize).copied().unwrap_or(0)
+    }
+
+    // Both tables have the same symbol width
+    pub fn merge(&mut self, other: &FrequencyTable) {
+        debug_assert_eq!(self.symbol_width(), other.symbol_width());
+        for (count, other_count) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other_count;
+        }
+    }
+
+    // The symbols that occur, in increasing order, with their counts
+    pub fn iter(&self) -> impl Iterator<Item = (Symbol, u64)> + '_ {
+        self.counts.iter().enumerate()
+            .filter(|&(_, &count)| count > 0)
+            .map(|(symbol, &count)| (symbol as Symbol, count))
+    }
+
+    // Number of distinct symbols
+    pub fn len(&self) -> usize {
+        self.counts.iter().filter(|&&count| count > 0).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Number of symbols counted, the size of the input with 1 byte symbols
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+}
+
+impl Default for FrequencyTable {
+    fn default() -> Self {
+        FrequencyTable::new()
+    }
+}
+
+impl FromIterator<(u8, u64)> for FrequencyTable {
+    fn from_iter<I: IntoIterator<Item = (u8, u64)>>(iter: I) -> Self {
+        let mut frequencies = FrequencyTable::new();
+        for (byte, count) in iter {
+            frequencies.counts[byte as usize] += count;
+        }
+        frequencies
+    }
+}
+
+impl fmt::Debug for FrequencyTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+// Serde
+////////////////////////////////////////////////////////////////////////////////
+
+// The bytes that occur with their counts, like iter(): {"97": 5, "98": 2}
+#[cfg(feature = "serde")]
+impl Serialize for FrequencyTable {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.iter())
+    }
+}
+
+// Zero counts are allowed and left out. The counts have to add up to a u64,
+// like the counts of any input do, as total() relies on it.
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for FrequencyTable {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let counts = BTreeMap::<u8, u64>::deserialize(deserializer)?;
+        if counts.values().try_fold(0u64, |total, &count| total.checked_add(count)).is_none() {
+            return Err(de::Error::custom("the counts add up to more than 2^64 - 1 bytes"));
+        }
+        Ok(counts.into_iter().collect())
+    }
+}
+
+// Returns the count of every byte (uint64, a u32 overflows past 4 GiB)
+pub fn calculate_frequencies(input: impl Read, buffer_size: usize) -> IoResult<FrequencyTable> {
+    calculate_frequencies_with_progress(input, buffer_size, &mut Progress::none()).map_err(|e| match e {
+        HuffmanError::Io(e) => e,
+        _ => unreachable!("without a token counting only fails reading"),
+    })
+}
+
+// The same with progress, which can cancel it
+pub(crate) fn calculate_frequencies_with_progress(
+    mut input: impl Read,
+    buffer_size: usize,
+    progress: &mut Progress,
+) -> HuffmanResult<FrequencyTable> {
+    let mut frequencies = FrequencyTable::new();
+    let mut buffer = vec![0; buffer_size];
+    let mut counted = 0u64;
+
+    loop {
+        // A read may fill only part of the buffer, only that part is counted
+        match input.read(&mut buffer) {
+            Ok(0) => break, // EOF
+            Ok(read) => {
+                frequencies.add(&buffer[..read]);
+                counted += read as u64;
+                progress.update(counted)?;
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    progress.end(counted)?;
+    Ok(frequencies)
+}
+
+// The same for data in memory, counted a step at a time. The steps are an even
+// number of bytes, so pairs never straddle them.
+pub(crate) fn count_with_progress(data: &[u8], symbol_width: u8, progress: &mut Progress) -> HuffmanResult<FrequencyTable> {
+    let mut frequencies = FrequencyTable::with_symbol_width(symbol_width);
+    let mut counted = 0u64;
+    for chunk in data.chunks(PROGRESS_STEP as usize) {
+        frequencies.add(chunk);
+        counted += chunk.len() as u64;
+        progress.update(counted)?;
+    }
+    progress.end(counted)?;
+    Ok(frequencies)
+}
+
+#[cfg(feature = "cli")]
+// Every thread counts at least this much, smaller inputs aren't worth the
+// threads and are counted serially
+pub(crate) const PARALLEL_RANGE_SIZE: u64 = 8 * 1024 * 1024;
+
+#[cfg(feature = "cli")]
+// The bytes of `file` in `range` split into `threads` ranges, each counted on
+// its own thread. Reads go to an offset, so the threads don't share a file
+// position. The sum is the same as calculate_frequencies.
+pub(crate) fn calculate_frequencies_parallel(
+    file: &File,
+    range: Range<u64>,
+    threads: usize,
+    buffer_size: usize,
+) -> IoResult<FrequencyTable> {
+    let threads = threads.max(1) as u64;
+    let size = range.end - range.start;
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..threads)
+            .map(|i| {
+                let start = range.start + size * i / threads;
+                let end = range.start + size * (i + 1) / threads;
+                scope.spawn(move || count_range(file, start, end, buffer_size))
+            })
+            .collect();
+
+        let mut frequencies = FrequencyTable::new();
+        for handle in handles {
+            let counts = handle.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic))?;
+            frequencies.merge(&counts);
+        }
+        Ok(frequencies)
+    })
+}
+
+#[cfg(feature = "cli")]
+fn count_range(file: &File, start: u64, end: u64, buffer_size: usize) -> IoResult<FrequencyTable> {
+    let mut frequencies = FrequencyTable::new();
+    let mut buffer = vec![0; buffer_size];
+    let mut offset = start;
+    while offset < end {
+        let len = (end - offset).min(buffer_size as u64) as usize;
+        match read_at(file, &mut buffer[..len], offset) {
+            Ok(0) => break, // The file got shorter
+            Ok(read) => {
+                frequencies.add(&buffer[..read]);
+                offset += read as u64;
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(frequencies)
+}
+
+#[cfg(all(feature = "cli", unix))]
+pub(crate) fn read_at(file: &File, buffer: &mut [u8], offset: u64) -> IoResult<usize> {
+    use std::os::unix::fs::FileExt;
+    file.read_at(buffer, offset)
+}
+
+// Moves the file position too, but nothing else uses it while counting
+#[cfg(all(feature = "cli", windows))]
+pub(crate) fn read_at(file: &File, buffer: &mut [u8], offset: u64) -> IoResult<usize> {
+    use std::os::windows::fs::FileExt;
+    file.seek_read(buffer, offset)
+}
+
+#[cfg(all(feature = "cli", not(any(unix, windows))))]
+pub(crate) fn read_at(_file: &File, _buffer: &mut [u8], _offset: u64) -> IoResult<usize> {
+    Err(Error::new(ErrorKind::Unsupported, "reading at an offset is not supported on this platform"))
+}
+
+#[cfg(all(test, feature = "cli"))]
+mod tests {
+    use super::*;
+    use crate::codec::DEFAULT_BUFFER_SIZE;
+    use crate::test_util::{bench, random_data, report_throughput, temp_path, text_data, Rng, MIB};
+    use std::collections::HashMap;
+    use std::fs;
+    use std::io::{BufReader, Error};
+
+    // The original byte at a time loop, kept to check the chunked version
+    fn calculate_frequencies_by_byte(input: impl Read) -> IoResult<FrequencyTable> {
+        let mut frequencies: HashMap<u8, u64> = HashMap::new();
+        let mut reader = BufReader::new(input);
+        let mut buffer = [0; 1];
+        loop {
+            match reader.read(&mut buffer)? {
+                0 => return Ok(frequencies.into_iter().collect()),
+                _ => *frequencies.entry(buffer[0]).or_insert(0) += 1,
+            }
+        }
+    }
+
+    // Hands out the data in reads of random sizes, like a pipe would
+    struct ShortReads<'a> {
+        data: &'a [u8],
+        rng: Rng,
+    }
+
+    impl Read for ShortReads<'_> {
+        fn read(&mut self, buffer: &mut [u8]) -> IoResult<usize> {
+            if self.rng.below(4) == 0 {
+                return Err(Error::from(ErrorKind::Interrupted));
+            }
+            let len = self.data.len().min(buffer.len()).min(1 + self.rng.below(1000));
+            buffer[..len].copy_from_slice(&self.data[..len]);
+            self.data = &self.data[len..];
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn test_chunked_frequencies_match_byte_at_a_time() {
+        let mut rng = Rng(0xF2E9);
+        for len in [0, 1, DEFAULT_BUFFER_SIZE - 1, DEFAULT_BUFFER_SIZE, DEFAULT_BUFFER_SIZE + 1, 3 * DEFAULT_BUFFER_SIZE + 17] {
+            let alphabet = 1 + rng.below(256);
+            let data: Vec<u8> = (0..len).map(|_| rng.below(alphabet) as u8).collect();
+            let expected = calculate_frequencies_by_byte(&data[..]).unwrap();
+
+            assert_eq!(calculate_frequencies(&data[..], DEFAULT_BUFFER_SIZE).unwrap(), expected, "{} bytes", len);
+            let short_reads = ShortReads { data: &data, rng: Rng(len as u64 + 1) };
+            assert_eq!(calculate_frequencies(short_reads, DEFAULT_BUFFER_SIZE).unwrap(), expected, "{} bytes in short reads", len);
+        }
+    }
+
+    #[test]
+    fn test_lanes_count_the_same_as_one_table() {
+        let mut rng = Rng(0x1A4E);
+        for _ in 0..200 {
+            // Around the threshold and well past it, with long runs of the same
+            // byte as well as spread out data
+            let len = [rng.below(16), LANE_THRESHOLD - 2 + rng.below(4), rng.below(200_000)][rng.below(3)];
+            let symbols = 1 + rng.below(256);
+            let run = 1 + rng.below(100);
+            let mut data = Vec::with_capacity(len);
+            while data.len() < len {
+                let byte = rng.below(symbols) as u8;
+                data.extend(std::iter::repeat_n(byte, run.min(len - data.len())));
+            }
+
+            let mut lanes = FrequencyTable::new();
+            lanes.add(&data);
+            let mut serial = FrequencyTable::new();
+            serial.add_serial(&data);
+            assert_eq!(lanes, serial, "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn test_pairs_are_counted_as_one_symbol() {
+        let mut frequencies = FrequencyTable::with_symbol_width(2);
+        frequencies.add(b"a\0b\0a\0\xff\xffz");
+        let counts: Vec<(Symbol, u64)> = frequencies.iter().collect();
+        assert_eq!(counts, [(0x0061, 2), (0x0062, 1), (0xffff, 1)]);
+        assert_eq!((frequencies.len(), frequencies.total(), frequencies.symbol_width()), (3, 4, 2));
+
+        let data = random_data(&mut Rng(0x9A1B), 3 * PROGRESS_STEP as usize + 3);
+        let counted = count_with_progress(&data, 2, &mut Progress::none()).unwrap();
+        frequencies = FrequencyTable::with_symbol_width(2);
+        frequencies.add(&data);
+        assert!(counted == frequencies);
+    }
+
+    #[test]
+    fn test_parallel_frequencies_match_serial() {
+        let mut rng = Rng(0x7EAD);
+        for len in [0, 5, DEFAULT_BUFFER_SIZE + 1, 5 * DEFAULT_BUFFER_SIZE + 3] {
+            let data = random_data(&mut rng, len);
+            let path = temp_path("parallel");
+            fs::write(&path, &data).unwrap();
+            let file = File::open(&path).unwrap();
+            let expected = calculate_frequencies(&data[..], DEFAULT_BUFFER_SIZE).unwrap();
+
+            // More threads than bytes leaves some ranges empty
+            for threads in [1, 2, 3, 8, 64] {
+                let frequencies = calculate_frequencies_parallel(&file, 0..len as u64, threads, DEFAULT_BUFFER_SIZE).unwrap();
+                assert_eq!(frequencies, expected, "{} bytes on {} threads", len, threads);
+            }
+            // A file that shrank after its size was taken just counts less
+            let frequencies = calculate_frequencies_parallel(&file, 0..len as u64 + 100, 4, DEFAULT_BUFFER_SIZE).unwrap();
+            assert_eq!(frequencies, expected, "{} bytes, 100 missing", len);
+            fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_calculate_frequencies() {
+        let mut rng = Rng(0xBE7C);
+        for size in [MIB, 8 * MIB, 64 * MIB] {
+            let data = random_data(&mut rng, size);
+            let elapsed = bench(|| {
+                std::hint::black_box(calculate_frequencies(&data[..], DEFAULT_BUFFER_SIZE).unwrap());
+            });
+            report_throughput(&format!("calculate_frequencies {} MiB", size / MIB), size, elapsed);
+        }
+
+        let data = random_data(&mut rng, 100_000_000);
+        let elapsed = bench(|| {
+            std::hint::black_box(calculate_frequencies_by_byte(&data[..]).unwrap());
+        });
+        report_throughput("calculate_frequencies_by_byte", data.len(), elapsed);
+
+        // Lanes against a single table, on inputs with more and fewer repeats
+        let text = text_data(&mut rng, 100_000_000);
+        let zeros = vec![0u8; 100_000_000];
+        for (name, input) in [("random", &data), ("text", &text), ("zeros", &zeros)] {
+            let elapsed = bench(|| {
+                std::hint::black_box(calculate_frequencies(&input[..], DEFAULT_BUFFER_SIZE).unwrap());
+            });
+            report_throughput(&format!("calculate_frequencies 100 MB {}", name), input.len(), elapsed);
+            let elapsed = bench(|| {
+                let mut frequencies = FrequencyTable::new();
+                for chunk in input.chunks(DEFAULT_BUFFER_SIZE) {
+                    frequencies.add_serial(chunk);
+                }
+                std::hint::black_box(frequencies);
+            });
+            report_throughput("  one table", input.len(), elapsed);
+        }
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_calculate_frequencies_parallel() {
+        let mut rng = Rng(0x9A9A);
+        let data = random_data(&mut rng, 256 * MIB);
+        let path = temp_path("parallel.bin");
+        fs::write(&path, &data).unwrap();
+        let file = File::open(&path).unwrap();
+        let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+        println!("{} CPUs", cpus);
+        for threads in [1, 2, 4, 8] {
+            let elapsed = bench(|| {
+                std::hint::black_box(calculate_frequencies_parallel(&file, 0..data.len() as u64, threads, DEFAULT_BUFFER_SIZE).unwrap());
+            });
+            report_throughput(&format!("calculate_frequencies 256 MiB, {} threads", threads), data.len(), elapsed);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_json_round_trips() {
+        let mut frequencies = FrequencyTable::new();
+        frequencies.add(b"abracadabra");
+        let json = serde_json::to_string(&frequencies).unwrap();
+        assert_eq!(json, r#"{"97":5,"98":2,"99":1,"100":1,"114":2}"#);
+        assert_eq!(serde_json::from_str::<FrequencyTable>(&json).unwrap(), frequencies);
+        assert_eq!(serde_json::from_str::<FrequencyTable>("{}").unwrap(), FrequencyTable::new());
+        assert_eq!(serde_json::from_str::<FrequencyTable>(r#"{"97":5,"98":0}"#).unwrap().len(), 1);
+
+        let mut rng = Rng(0x5345_5244);
+        let data = random_data(&mut rng, 100_000);
+        let frequencies = calculate_frequencies(&data[..], DEFAULT_BUFFER_SIZE).unwrap();
+        let json = serde_json::to_string(&frequencies).unwrap();
+        assert_eq!(serde_json::from_str::<FrequencyTable>(&json).unwrap(), frequencies);
+
+        let error = |json: &str| serde_json::from_str::<FrequencyTable>(json).unwrap_err().to_string();
+        assert!(error(&format!(r#"{{"97":{},"98":1}}"#, u64::MAX)).starts_with("the counts add up to more than"));
+        assert!(error(r#"{"97":-1}"#).starts_with("invalid value: integer `-1`"));
+    }
+}
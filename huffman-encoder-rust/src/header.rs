@@ -0,0 +1,1278 @@
+// The header in front of every stream: its lengths, checksum and table
+
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::io::{Seek, SeekFrom};
+
+use crate::checksum::ChecksumKind;
+use crate::error::{HuffmanError, HuffmanResult};
+use crate::io::{BufRead, Read, Result as IoResult, ErrorKind, Write};
+use crate::context::NUM_CONTEXTS;
+use crate::frequency::{FrequencyTable, Symbol};
+use crate::options::Filter;
+use crate::range::{max_range_length, TOTAL as RANGE_TOTAL};
+use crate::table::{canonical_codes, entry_size, Code, EncodingTable};
+use crate::tokens::{FIRST_TOKEN, MAX_TOKENS, MAX_TOKEN_LENGTH};
+
+// Layout of the header, all numbers little endian:
+//
+// Version 0 (the original format, still decoded):
+//   "HRST" | num_entries: u32 | padding_bits: u8 | entries
+//
+// Version 1 (no checksum, still decoded):
+//   "HRST" | 0xFFFFFFFF | version: u8 | original_length: u64
+//          | num_entries: u32 | padding_bits: u8 | entries
+//
+// Version 2 (no payload length, still decoded):
+//   "HRST" | 0xFFFFFFFF | version: u8 | original_length: u64 | checksum: u32
+//          | num_entries: u32 | padding_bits: u8 | entries
+//
+// Version 3:
+//   "HRST" | 0xFFFFFFFF | version: u8 | original_length: u64 | checksum: u32
+//          | payload_length: u64 | num_entries: u32 | padding_bits: u8 | entries
+//
+// Version 4 (only streams with a flag, the others are still version 3):
+//   "HRST" | 0xFFFFFFFF | version: u8 | flags: u8 | original_length: u64
+//          | checksum: u32 | payload_length: u64 | num_entries: u32
+//          | padding_bits: u8 | entries
+//
+// A version 0 file never has more than 256 entries, so the marker in place of
+// num_entries tells the versions apart. The checksum is the CRC-32 of the
+// original data. The payload length is the number of bytes of encoded data
+// after the entries, so the end of a stream is known without decoding it.
+//
+// Each entry is character: u8 | length: u8 | bits: u32
+//
+// A version 4 header has one of the flags, or FLAG_BWT on its own or with
+// FLAG_RLE. FLAG_RLE says the codes are of the data after run-length encoding
+// (see rle.rs), the lengths and the checksum are still of the original data. With FLAG_PAIRS the codes are of
+// pairs of bytes, little endian, and every entry has a character: u16. The
+// flags are followed by the byte an odd length leaves over, which has no
+// code, or 0:
+//   "HRST" | 0xFFFFFFFF | version: u8 | flags: u8 | trailing_byte: u8
+//          | original_length: u64 | ...
+// With FLAG_TOKENS the codes are of bytes and of the tokens of a dictionary
+// (see tokens.rs), which follows the flags, and the entries have a
+// character: u16 too:
+//   "HRST" | 0xFFFFFFFF | version: u8 | flags: u8 | num_tokens: u32
+//          | tokens | original_length: u64 | ...
+// Each token is length: u8 | bytes, its symbol is 256 plus its index.
+// FLAG_BWT says the codes are of the data after the Burrows-Wheeler transform
+// and move-to-front (see bwt.rs), before any run-length encoding. The flags
+// are followed by the primary index of the transform:
+//   "HRST" | 0xFFFFFFFF | version: u8 | flags: u8 | primary_index: u32
+//          | original_length: u64 | ...
+// With FLAG_CONTEXT every byte is coded with the table of the byte before it
+// (see context.rs), or with the entries when that has none. A bit per context,
+// the lowest bit of the first byte for 0, says which have a table, and each
+// of those follows as its number of codes less one and the length of the code
+// of every byte that has one, in the order of the bytes. The codes are the
+// canonical ones of those lengths:
+//   "HRST" | 0xFFFFFFFF | version: u8 | flags: u8 | has_table: [u8; 32]
+//          | tables | original_length: u64 | ...
+// Each table is num_codes: u8 | (byte: u8 | length: u8) * (num_codes + 1).
+// FLAG_RANGE says the payload is range coded (see range.rs) instead of coded
+// with the entries, which are the counts of the bytes, scaled to add up to at
+// most 65536, in the order of the bytes:
+//   "HRST" | 0xFFFFFFFF | version: u8 | flags: u8 | original_length: u64
+//          | checksum: u32 | payload_length: u64 | num_entries: u32
+//          | padding_bits: u8 | (byte: u8 | count: u16) * num_entries
+// FLAG_FILTER goes with any of those, or on its own, and says every byte went
+// through a filter (see filter.rs) before anything else. The filter and its
+// stride follow the fields of the other flags:
+//   "HRST" | 0xFFFFFFFF | version: u8 | flags: u8 | ... | filter: u8
+//          | stride: u8 | original_length: u64 | ...
+// The filter is 1 for delta and 2 for xor-prev.
+// With FLAG_ADAPTIVE the codes
+// change as the data goes (see adaptive.rs), so there is no table, and the
+// payload ends with a code of its own. The length and the checksum are only
+// known then, they follow the payload, and the header ends at the flags:
+//   "HRST" | 0xFFFFFFFF | version: u8 | flags: u8
+//   payload | original_length: u64 | checksum: u32
+//
+// Version 5 (only streams whose checksum isn't CRC-32, the others are still
+// version 3 or 4):
+//   "HRST" | 0xFFFFFFFF | version: u8 | flags: u8 | checksum_kind: u8 | ...
+// and the rest as in version 4, where the flags may also be 0. The checksum is
+// then the lower 32 bits of XXH3-64 for 1, or 0 and not verified for 2 (see
+// checksum.rs).
+//
+// Version 6 (only streams with a name, a modification time or a table id,
+// the first one of an encode with --stdin-name, --stdin-mtime or a
+// --table-file that has an id, and streams with an escape code):
+//   "HRST" | 0xFFFFFFFF | version: u8 | flags: u8 | checksum_kind: u8
+//          | info: u8 | (name_length: u16 | name)? | mtime: i64?
+//          | table_id: u64? | (escape_length: u8 | escape_bits: u32)? | ...
+// and the rest as in version 5. INFO_NAME in info says the name of the input
+// follows, as bytes, INFO_MTIME its modification time, in seconds since the
+// Unix epoch, and INFO_TABLE_ID the id of the table file its codes are from.
+// They come before the other fields, also in an adaptive stream.
+// INFO_ESCAPE says the table has an escape code, like an entry without its
+// character. A byte without a code is written as that code and its 8 bits
+// (see EncodingTable::with_escape); only plain tables of bytes have one.
+pub(crate) const MAGIC: &[u8; 4] = b"HRST"; // Huffman Rust
+pub(crate) const VERSION_MARKER: u32 = 0xFFFF_FFFF;
+pub(crate) const VERSION: u8 = 3;
+pub(crate) const FLAGS_VERSION: u8 = 4;
+pub(crate) const CHECKSUM_VERSION: u8 = 5;
+pub(crate) const INFO_VERSION: u8 = 6;
+const FLAG_ADAPTIVE: u8 = 1;
+const FLAG_RLE: u8 = 2;
+const FLAG_PAIRS: u8 = 4;
+const FLAG_TOKENS: u8 = 8;
+const FLAG_BWT: u8 = 16;
+const FLAG_CONTEXT: u8 = 32;
+const FLAG_RANGE: u8 = 64;
+const FLAG_FILTER: u8 = 128;
+const INFO_NAME: u8 = 1;
+const INFO_MTIME: u8 = 2;
+const INFO_TABLE_ID: u8 = 4;
+const INFO_ESCAPE: u8 = 8;
+const FILTER_DELTA: u8 = 1;
+const FILTER_XOR_PREV: u8 = 2;
+// The methods that code the payload, the ids of their CompressionMethod (see
+// method.rs). A method is the flag that picks its coder, none for the codes
+// of the table.
+pub const METHOD_TABLE: u8 = 0;
+pub const METHOD_ADAPTIVE: u8 = FLAG_ADAPTIVE;
+pub const METHOD_RANGE: u8 = FLAG_RANGE;
+// A byte and its count in a range coded stream
+pub(crate) const RANGE_ENTRY_SIZE: u64 = 3;
+// After the payload of an adaptive stream
+pub(crate) const TRAILER_SIZE: u64 = 12;
+
+// Everything before the entries
+const V0_PREFIX_SIZE: u64 = 9;
+const V1_PREFIX_SIZE: u64 = 22;
+const V2_PREFIX_SIZE: u64 = 26;
+const V3_PREFIX_SIZE: u64 = 34;
+const V4_PREFIX_SIZE: u64 = 35;
+const PAIRS_PREFIX_SIZE: u64 = 36;
+const ADAPTIVE_HEADER_SIZE: u64 = 10;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Header {
+    pub version: u8,
+    // Not stored in version 0 files
+    pub original_length: Option<u64>,
+    // Only stored from version 2 on
+    pub checksum: Option<u32>,
+    // The algorithm of the checksum, other than CRC-32 only in version 5
+    pub checksum_kind: ChecksumKind,
+    // Only stored from version 3 on
+    pub payload_length: Option<u64>,
+    // The entries in the file, more than encoding_table has when a character
+    // is in there twice
+    pub num_entries: u32,
+    pub padding_bits: u8,
+    pub encoding_table: EncodingTable,
+    // Coded with an adaptive tree, always in version 4. Until the stream is
+    // decoded the lengths and the checksum are None, see TRAILER_SIZE.
+    pub adaptive: bool,
+    // Run-length encoded before the codes, only in version 4
+    pub rle: bool,
+    // Bytes per symbol, 2 only in version 4. Pairs leave the last byte of an
+    // odd length over, it is stored in the header instead.
+    pub symbol_width: u8,
+    pub trailing_byte: Option<u8>,
+    // The tokens of a stream coded in words, only in version 4
+    pub dictionary: Option<Vec<Vec<u8>>>,
+    // The primary index of a Burrows-Wheeler transformed stream, only in
+    // version 4
+    pub primary_index: Option<u32>,
+    // The table of every context of a stream coded with tables per context,
+    // None for those that use the shared table, only in version 4
+    pub contexts: Option<Vec<Option<EncodingTable>>>,
+    // The scaled counts of a range coded stream, which has them as its
+    // entries instead of codes, only in version 4
+    pub range_counts: Option<Box<FrequencyTable>>,
+    // The filter of a filtered stream and its stride, only in version 4
+    pub filter: Option<(Filter, u8)>,
+    // What the input was, only in version 6
+    pub info: FileInfo,
+}
+
+// The name and the modification time of the input of a stream, which decode
+// can give its output, and the table file it was encoded with, which decode
+// can check. Only the first stream of a file has them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FileInfo {
+    // As given, decode makes a file name of it
+    pub name: Option<Vec<u8>>,
+    // Seconds since the Unix epoch
+    pub mtime: Option<i64>,
+    // The random id a table file was saved with, see --table-file
+    pub table_id: Option<u64>,
+}
+
+impl FileInfo {
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none() && self.mtime.is_none() && self.table_id.is_none()
+    }
+
+    // The info byte and the fields, see INFO_VERSION
+    fn len(&self) -> u64 {
+        1 + self.name.as_ref().map_or(0, |name| 2 + name.len() as u64)
+            + if self.mtime.is_some() { 8 } else { 0 }
+            + if self.table_id.is_some() { 8 } else { 0 }
+    }
+}
+
+impl Header {
+    // The header of a stream of `original_length` bytes about to be encoded,
+    // in the current version, which is 6 for a table with an escape code. The
+    // checksum, payload length and padding bits are zero until the data is
+    // encoded.
+    pub fn new(original_length: u64, encoding_table: EncodingTable) -> Self {
+        Header {
+            version: if encoding_table.escape().is_some() { INFO_VERSION } else { VERSION },
+            original_length: Some(original_length),
+            checksum: Some(0),
+            checksum_kind: ChecksumKind::Crc32,
+            payload_length: Some(0),
+            num_entries: encoding_table.len() as u32,
+            padding_bits: 0,
+            encoding_table,
+            adaptive: false,
+            rle: false,
+            symbol_width: 1,
+            trailing_byte: None,
+            dictionary: None,
+            primary_index: None,
+            contexts: None,
+            range_counts: None,
+            filter: None,
+            info: FileInfo::default(),
+        }
+    }
+
+    // The same for data that is run-length encoded first
+    pub fn with_rle(self) -> Self {
+        Header { version: self.version.max(FLAGS_VERSION), rle: true, ..self }
+    }
+
+    // The same for data encoded in pairs of bytes, with the byte left over
+    // when the length is odd
+    pub fn with_pairs(self, trailing_byte: Option<u8>) -> Self {
+        Header { version: self.version.max(FLAGS_VERSION), symbol_width: 2, trailing_byte, ..self }
+    }
+
+    // The same for data coded in the tokens of `dictionary`
+    pub fn with_dictionary(self, dictionary: Vec<Vec<u8>>) -> Self {
+        Header { version: self.version.max(FLAGS_VERSION), dictionary: Some(dictionary), ..self }
+    }
+
+    // The same for data that went through the Burrows-Wheeler transform, with
+    // its primary index
+    pub fn with_bwt(self, primary_index: u32) -> Self {
+        Header { version: self.version.max(FLAGS_VERSION), primary_index: Some(primary_index), ..self }
+    }
+
+    // The same for data coded with the tables of `contexts`, the encoding
+    // table is the shared one
+    pub fn with_contexts(self, contexts: Vec<Option<EncodingTable>>) -> Self {
+        Header { version: self.version.max(FLAGS_VERSION), contexts: Some(contexts), ..self }
+    }
+
+    // The same for data range coded with `counts`, which has no codes
+    pub fn with_range(self, counts: FrequencyTable) -> Self {
+        Header {
+            version: self.version.max(FLAGS_VERSION),
+            num_entries: counts.len() as u32,
+            encoding_table: EncodingTable::new(),
+            range_counts: Some(Box::new(counts)),
+            ..self
+        }
+    }
+
+    // The same for data that went through `filter` first
+    pub fn with_filter(self, filter: Filter, stride: u8) -> Self {
+        Header { version: self.version.max(FLAGS_VERSION), filter: Some((filter, stride)), ..self }
+    }
+
+    // The same with a checksum of `kind`, which takes version 5 unless it is
+    // CRC-32
+    pub fn with_checksum(self, kind: ChecksumKind) -> Self {
+        match kind {
+            ChecksumKind::Crc32 => self,
+            kind => Header { version: self.version.max(CHECKSUM_VERSION), checksum_kind: kind, ..self },
+        }
+    }
+
+    // The same with the name and the modification time of the input, which
+    // take version 6 unless there are none. Names are at most 65535 bytes.
+    pub fn with_info(self, info: FileInfo) -> Self {
+        match info.is_empty() {
+            true => self,
+            false => Header { version: INFO_VERSION, info, ..self },
+        }
+    }
+
+    // The header of an adaptive stream, the same for any data
+    pub fn adaptive() -> Self {
+        Header {
+            version: FLAGS_VERSION,
+            original_length: None,
+            checksum: None,
+            checksum_kind: ChecksumKind::Crc32,
+            payload_length: None,
+            num_entries: 0,
+            padding_bits: 0,
+            encoding_table: EncodingTable::new(),
+            adaptive: true,
+            rle: false,
+            symbol_width: 1,
+            trailing_byte: None,
+            dictionary: None,
+            primary_index: None,
+            contexts: None,
+            range_counts: None,
+            filter: None,
+            info: FileInfo::default(),
+        }
+    }
+
+    // Writes the header in the layout of its version, the inverse of
+    // read_from. Fields the version stores but that are None are written as
+    // 0, and the number of entries is the number of codes in the table.
+    pub fn write_to(&self, writer: &mut impl Write) -> IoResult<()> {
+        writer.write_all(MAGIC)?;
+        if self.version > 0 {
+            writer.write_all(&VERSION_MARKER.to_le_bytes())?;
+            writer.write_all(&[self.version])?;
+        }
+        if self.version >= FLAGS_VERSION {
+            let flags = if self.adaptive { FLAG_ADAPTIVE } else { 0 }
+                | if self.rle { FLAG_RLE } else { 0 }
+                | if self.symbol_width == 2 { FLAG_PAIRS } else { 0 }
+                | if self.dictionary.is_some() { FLAG_TOKENS } else { 0 }
+                | if self.primary_index.is_some() { FLAG_BWT } else { 0 }
+                | if self.contexts.is_some() { FLAG_CONTEXT } else { 0 }
+                | if self.range_counts.is_some() { FLAG_RANGE } else { 0 }
+                | if self.filter.is_some() { FLAG_FILTER } else { 0 };
+            writer.write_all(&[flags])?;
+            if self.version >= CHECKSUM_VERSION {
+                writer.write_all(&[self.checksum_kind.id()])?;
+            }
+            if self.version >= INFO_VERSION {
+                write_info(writer, &self.info, &self.encoding_table)?;
+            }
+            if self.adaptive {
+                return Ok(());
+            }
+            if self.symbol_width == 2 {
+                writer.write_all(&[self.trailing_byte.unwrap_or(0)])?;
+            }
+            if let Some(dictionary) = &self.dictionary {
+                writer.write_all(&(dictionary.len() as u32).to_le_bytes())?;
+                for token in dictionary {
+                    writer.write_all(&[token.len() as u8])?;
+                    writer.write_all(token)?;
+                }
+            }
+            if let Some(primary_index) = self.primary_index {
+                writer.write_all(&primary_index.to_le_bytes())?;
+            }
+            if let Some(contexts) = &self.contexts {
+                write_contexts(writer, contexts)?;
+            }
+            if let Some((filter, stride)) = self.filter {
+                let filter = match filter {
+                    Filter::Delta => FILTER_DELTA,
+                    Filter::XorPrev => FILTER_XOR_PREV,
+                };
+                writer.write_all(&[filter, stride])?;
+            }
+        }
+        if self.version > 0 {
+            writer.write_all(&self.original_length.unwrap_or(0).to_le_bytes())?;
+        }
+        if self.version >= 2 {
+            writer.write_all(&self.checksum.unwrap_or(0).to_le_bytes())?;
+        }
+        if self.version >= 3 {
+            writer.write_all(&self.payload_length.unwrap_or(0).to_le_bytes())?;
+        }
+        if let Some(counts) = &self.range_counts {
+            writer.write_all(&(counts.len() as u32).to_le_bytes())?;
+            writer.write_all(&[self.padding_bits])?;
+            for (byte, count) in counts.iter() {
+                writer.write_all(&[byte as u8])?;
+                writer.write_all(&(count as u16).to_le_bytes())?;
+            }
+            return Ok(());
+        }
+        writer.write_all(&(self.encoding_table.len() as u32).to_le_bytes())?;
+        writer.write_all(&[self.padding_bits])?;
+        self.encoding_table.write_entries(writer, self.entry_width())
+    }
+
+    // Reads a header written by write_to. The reader has nothing to check the
+    // declared sizes against, decode_header does that for files.
+    pub fn read_from(reader: &mut impl BufRead) -> HuffmanResult<Header> {
+        read_header(reader, 0, None)
+    }
+
+    // Number of bytes the header takes up in the file
+    pub fn serialized_len(&self) -> u64 {
+        match self.adaptive {
+            true => ADAPTIVE_HEADER_SIZE + checksum_kind_len(self.version) + self.info_len(),
+            false => {
+                let dictionary = self.dictionary.as_deref().map_or(0, dictionary_len);
+                let primary_index = if self.primary_index.is_some() { 4 } else { 0 };
+                let contexts = self.contexts.as_deref().map_or(0, contexts_len);
+                let filter = if self.filter.is_some() { 2 } else { 0 };
+                prefix_len(self.version, self.symbol_width) + self.info_len() + dictionary + primary_index + contexts + filter
+                    + self.num_entries as u64 * self.entry_len()
+            }
+        }
+    }
+
+    fn info_len(&self) -> u64 {
+        let escape = if self.encoding_table.escape().is_some() { ESCAPE_LEN } else { 0 };
+        if self.version >= INFO_VERSION { self.info.len() + escape } else { 0 }
+    }
+
+    // Bytes of the character of an entry, a symbol of a token doesn't fit in one
+    fn entry_width(&self) -> u8 {
+        if self.dictionary.is_some() { 2 } else { self.symbol_width }
+    }
+
+    fn entry_len(&self) -> u64 {
+        if self.range_counts.is_some() { RANGE_ENTRY_SIZE } else { entry_size(self.entry_width()) }
+    }
+
+    // The method the payload is coded with, one of the METHOD_ constants
+    pub fn method(&self) -> u8 {
+        if self.adaptive {
+            METHOD_ADAPTIVE
+        } else if self.range_counts.is_some() {
+            METHOD_RANGE
+        } else {
+            METHOD_TABLE
+        }
+    }
+
+    // Number of bytes after the payload
+    pub fn trailer_len(&self) -> u64 {
+        if self.adaptive { TRAILER_SIZE } else { 0 }
+    }
+}
+
+// Size of a header of `version` with `num_entries` entries of symbols of
+// `symbol_width` bytes
+pub(crate) fn header_len(version: u8, num_entries: u32, symbol_width: u8) -> u64 {
+    prefix_len(version, symbol_width) + num_entries as u64 * entry_size(symbol_width)
+}
+
+// The number of tokens and the tokens
+fn dictionary_len(dictionary: &[Vec<u8>]) -> u64 {
+    4 + dictionary.iter().map(|token| 1 + token.len() as u64).sum::<u64>()
+}
+
+// The bit of every context and the tables of those that have one
+fn contexts_len(contexts: &[Option<EncodingTable>]) -> u64 {
+    NUM_CONTEXTS as u64 / 8 + contexts.iter().flatten().map(|table| 1 + 2 * table.len() as u64).sum::<u64>()
+}
+
+// Everything before the entries, and before the dictionary of tokens
+fn prefix_len(version: u8, symbol_width: u8) -> u64 {
+    match (version, symbol_width) {
+        (0, _) => V0_PREFIX_SIZE,
+        (1, _) => V1_PREFIX_SIZE,
+        (2, _) => V2_PREFIX_SIZE,
+        (3, _) => V3_PREFIX_SIZE,
+        (_, 1) => V4_PREFIX_SIZE + checksum_kind_len(version),
+        _ => PAIRS_PREFIX_SIZE + checksum_kind_len(version),
+    }
+}
+
+// The byte of the algorithm of the checksum, only in version 5
+fn checksum_kind_len(version: u8) -> u64 {
+    if version >= CHECKSUM_VERSION { 1 } else { 0 }
+}
+
+// The length and the bits of an escape code
+const ESCAPE_LEN: u64 = 5;
+
+// The info and the escape code of the table, see INFO_VERSION
+fn write_info(writer: &mut impl Write, info: &FileInfo, encoding_table: &EncodingTable) -> IoResult<()> {
+    let bits = if info.name.is_some() { INFO_NAME } else { 0 }
+        | if info.mtime.is_some() { INFO_MTIME } else { 0 }
+        | if info.table_id.is_some() { INFO_TABLE_ID } else { 0 }
+        | if encoding_table.escape().is_some() { INFO_ESCAPE } else { 0 };
+    writer.write_all(&[bits])?;
+    if let Some(name) = &info.name {
+        writer.write_all(&(name.len() as u16).to_le_bytes())?;
+        writer.write_all(name)?;
+    }
+    if let Some(mtime) = info.mtime {
+        writer.write_all(&mtime.to_le_bytes())?;
+    }
+    if let Some(table_id) = info.table_id {
+        writer.write_all(&table_id.to_le_bytes())?;
+    }
+    encoding_table.write_escape(writer)
+}
+
+fn read_info(reader: &mut impl Read, offset: &mut u64) -> HuffmanResult<(FileInfo, Option<Code>)> {
+    let bits = read_u8(reader, offset, "info")?;
+    if bits & !(INFO_NAME | INFO_MTIME | INFO_TABLE_ID | INFO_ESCAPE) != 0 {
+        return Err(HuffmanError::CorruptHeader(format!("Invalid info: {:#04x}", bits)));
+    }
+    let mut info = FileInfo::default();
+    if bits & INFO_NAME != 0 {
+        let length = u16::from_le_bytes(read_field(reader, offset, "name length")?);
+        let mut name = alloc::vec![0; length as usize];
+        for byte in &mut name {
+            *byte = read_u8(reader, offset, "name")?;
+        }
+        info.name = Some(name);
+    }
+    if bits & INFO_MTIME != 0 {
+        info.mtime = Some(i64::from_le_bytes(read_field(reader, offset, "modification time")?));
+    }
+    if bits & INFO_TABLE_ID != 0 {
+        info.table_id = Some(read_u64(reader, offset, "table id")?);
+    }
+    let escape = match bits & INFO_ESCAPE != 0 {
+        true => Some(EncodingTable::read_escape(reader, offset)?),
+        false => None,
+    };
+    Ok((info, escape))
+}
+
+// Reads one field of the header, `offset` is where it starts in the input.
+// Running out of data means the header was cut short, which is reported with
+// the point where the input ends instead of a bare "failed to fill whole
+// buffer".
+fn read_field<const N: usize>(reader: &mut impl Read, offset: &mut u64, field: &str) -> HuffmanResult<[u8; N]> {
+    let mut buffer = [0u8; N];
+    let mut filled = 0;
+    while filled < N {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => return Err(truncated_header(*offset + filled as u64, field)),
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    *offset += N as u64;
+    Ok(buffer)
+}
+
+fn truncated_header(end: u64, field: &str) -> HuffmanError {
+    HuffmanError::TruncatedHeader { offset: end, field: field.to_string() }
+}
+
+pub(crate) fn read_u8(reader: &mut impl Read, offset: &mut u64, field: &str) -> HuffmanResult<u8> {
+    Ok(read_field::<1>(reader, offset, field)?[0])
+}
+
+pub(crate) fn read_u32(reader: &mut impl Read, offset: &mut u64, field: &str) -> HuffmanResult<u32> {
+    Ok(u32::from_le_bytes(read_field(reader, offset, field)?))
+}
+
+fn read_u64(reader: &mut impl Read, offset: &mut u64, field: &str) -> HuffmanResult<u64> {
+    Ok(u64::from_le_bytes(read_field(reader, offset, field)?))
+}
+
+// Length of the whole input, leaving the position where it was
+#[cfg(feature = "std")]
+pub(crate) fn input_length(reader: &mut impl Seek) -> IoResult<u64> {
+    let position = reader.stream_position()?;
+    let length = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(position))?;
+    Ok(length)
+}
+
+// Works on a file as well as on a buffer in a Cursor
+#[cfg(feature = "std")]
+pub fn decode_header(reader: &mut (impl Read + Seek)) -> HuffmanResult<Header> {
+    let start = reader.stream_position()?;
+    let end = input_length(reader)?;
+    read_header(reader, start, Some(end))
+}
+
+// Reads the header that starts at offset `start` of the input. Without the
+// `end` of the input, like on a pipe, the declared sizes can't be checked
+// against what is there; the entries are bounded either way.
+pub(crate) fn read_header(reader: &mut impl Read, start: u64, end: Option<u64>) -> HuffmanResult<Header> {
+    let mut offset = start;
+    let huff_bytes: [u8; 4] = read_field(reader, &mut offset, "magic")?;
+    if huff_bytes != *MAGIC {
+        return Err(HuffmanError::InvalidMagic)
+    }
+
+    let mut num_entries = read_u32(reader, &mut offset, "number of entries")?;
+    let mut version = 0;
+    let mut original_length = None;
+    let mut checksum = None;
+    let mut checksum_kind = ChecksumKind::Crc32;
+    let mut payload_length = None;
+    let mut rle = false;
+    let mut symbol_width = 1;
+    let mut trailing_byte = 0;
+    let mut dictionary = None;
+    let mut primary_index = None;
+    let mut contexts = None;
+    let mut range = false;
+    let mut filter = None;
+    let mut info = FileInfo::default();
+    let mut escape = None;
+
+    if num_entries == VERSION_MARKER {
+        version = read_u8(reader, &mut offset, "version")?;
+        if !(1..=INFO_VERSION).contains(&version) {
+            return Err(HuffmanError::UnsupportedVersion(version))
+        }
+        if version >= FLAGS_VERSION {
+            let flags = read_u8(reader, &mut offset, "flags")?;
+            if version >= CHECKSUM_VERSION {
+                let id = read_u8(reader, &mut offset, "checksum algorithm")?;
+                checksum_kind = ChecksumKind::from_id(id)
+                    .ok_or_else(|| HuffmanError::CorruptHeader(format!("Unknown checksum algorithm: {}", id)))?;
+            }
+            if version >= INFO_VERSION {
+                (info, escape) = read_info(reader, &mut offset)?;
+            }
+            let bwt = flags & FLAG_BWT != 0;
+            let filtered = flags & FLAG_FILTER != 0;
+            // Only plain tables of bytes can have an escape code
+            if escape.is_some() && flags != 0 {
+                return Err(HuffmanError::CorruptHeader(format!("Invalid flags: {:#04x} with an escape code", flags)));
+            }
+            match (flags & !FLAG_BWT & !FLAG_FILTER, bwt) {
+                (FLAG_ADAPTIVE, false) if !filtered => return Ok(Header { version, checksum_kind, info, ..Header::adaptive() }),
+                (FLAG_RLE, _) if !(bwt && filtered) => rle = true,
+                (0, true) if !filtered => {}
+                (0, false) if filtered || version >= CHECKSUM_VERSION => {}
+                (FLAG_PAIRS, false) => {
+                    symbol_width = 2;
+                    trailing_byte = read_u8(reader, &mut offset, "trailing byte")?;
+                }
+                (FLAG_TOKENS, false) => dictionary = Some(read_dictionary(reader, &mut offset, end)?),
+                (FLAG_CONTEXT, false) => contexts = Some(read_contexts(reader, &mut offset)?),
+                (FLAG_RANGE, false) => range = true,
+                _ => return Err(HuffmanError::CorruptHeader(format!("Invalid flags: {:#04x}", flags))),
+            }
+            if bwt {
+                primary_index = Some(read_u32(reader, &mut offset, "primary index")?);
+            }
+            if filtered {
+                let [kind, stride] = read_field(reader, &mut offset, "filter")?;
+                let kind = match kind {
+                    FILTER_DELTA => Filter::Delta,
+                    FILTER_XOR_PREV => Filter::XorPrev,
+                    _ => return Err(HuffmanError::CorruptHeader(format!("Invalid filter: {}", kind))),
+                };
+                if stride == 0 {
+                    return Err(HuffmanError::CorruptHeader("Invalid stride: 0 bytes".to_string()));
+                }
+                filter = Some((kind, stride));
+            }
+        }
+        original_length = Some(read_u64(reader, &mut offset, "original length")?);
+        if version >= 2 {
+            checksum = Some(read_u32(reader, &mut offset, "checksum")?);
+        }
+        if version >= 3 {
+            payload_length = Some(read_u64(reader, &mut offset, "payload length")?);
+        }
+        num_entries = read_u32(reader, &mut offset, "number of entries")?;
+    }
+    let info_len = if version >= INFO_VERSION { info.len() + if escape.is_some() { ESCAPE_LEN } else { 0 } } else { 0 };
+    let prefix_size = prefix_len(version, symbol_width) + info_len + dictionary.as_deref().map_or(0, dictionary_len)
+        + if primary_index.is_some() { 4 } else { 0 } + contexts.as_deref().map_or(0, contexts_len)
+        + if filter.is_some() { 2 } else { 0 };
+    let entry_width = if dictionary.is_some() { 2 } else { symbol_width };
+    let entry_len = if range { RANGE_ENTRY_SIZE } else { entry_size(entry_width) };
+
+    let padding_bits = read_u8(reader, &mut offset, "padding")?;
+    if padding_bits > 8 {
+        return Err(HuffmanError::CorruptHeader(format!("Invalid padding: {} bits", padding_bits)))
+    }
+
+    if num_entries > 1 << (8 * entry_width) {
+        return Err(HuffmanError::CorruptHeader(format!("Invalid number of entries: {}", num_entries)))
+    }
+
+    // Validate the declared sizes before trusting them for any allocation. Each
+    // entry takes 6 bytes (7 for pairs), so the file has to be at least that
+    // large, and every character takes at least one bit of the payload.
+    let payload_size = match end {
+        Some(end) => {
+            let file_size = end.saturating_sub(start);
+            let max_entries = file_size.saturating_sub(prefix_size) / entry_len;
+            if num_entries as u64 > max_entries {
+                return Err(truncated_header(end, &format!("entries ({} declared)", num_entries)))
+            }
+            // Streams that declare their payload can't use the data of the next one
+            let payload_size = file_size.saturating_sub(prefix_size + num_entries as u64 * entry_len);
+            Some(payload_length.map_or(payload_size, |length: u64| length.min(payload_size)))
+        }
+        None => payload_length,
+    };
+    // A run stands for up to 130 bytes and takes at least 2 bits, a pair
+    // takes at least 1 bit and the trailing byte none, and so does a token.
+    // A range coded byte can take next to nothing, the counts tell, below.
+    let max_bytes_per_byte = match (rle, &dictionary, range) {
+        (_, _, true) => u64::MAX,
+        (true, _, _) => 8 * 65,
+        (_, Some(_), _) => 8 * MAX_TOKEN_LENGTH as u64,
+        _ => 8 * symbol_width as u64,
+    };
+    if let (Some(length), Some(payload_size)) = (original_length, payload_size) {
+        if length > payload_size.saturating_mul(max_bytes_per_byte).saturating_add(symbol_width as u64 - 1) {
+            return Err(HuffmanError::CorruptHeader(
+                format!("Invalid original length: {} bytes from {} bytes of data", length, payload_size),
+            ))
+        }
+    }
+    // Set only when the length is odd, so a flipped bit in it is noticed
+    let odd = original_length.is_some_and(|length| length % 2 == 1);
+    let trailing_byte = match (symbol_width, odd) {
+        (2, true) => Some(trailing_byte),
+        _ if trailing_byte == 0 => None,
+        _ => return Err(HuffmanError::CorruptHeader(format!("Invalid trailing byte: {:#04x} of an even length", trailing_byte))),
+    };
+
+    // The row of the end of the block, see burrows_wheeler_transform
+    if let (Some(index), Some(length)) = (primary_index, original_length) {
+        if (index == 0) != (length == 0) || index as u64 > length {
+            return Err(HuffmanError::CorruptHeader(format!("Invalid primary index: {} of {} bytes", index, length)))
+        }
+    }
+
+    if range {
+        if padding_bits != 0 {
+            return Err(HuffmanError::CorruptHeader(format!("Invalid padding: {} bits of range coded data", padding_bits)))
+        }
+        let range_counts = read_range_counts(reader, &mut offset, num_entries)?;
+        if range_counts.is_empty() && original_length.is_some_and(|length| length > 0) {
+            return Err(HuffmanError::CorruptHeader("Invalid counts: none for a stream with data".to_string()))
+        }
+        if let (Some(length), Some(payload_size)) = (original_length, payload_size) {
+            if length > max_range_length(&range_counts, payload_size) {
+                return Err(HuffmanError::CorruptHeader(
+                    format!("Invalid original length: {} bytes from {} bytes of range coded data", length, payload_size),
+                ))
+            }
+        }
+        return Ok(Header {
+            version, original_length, checksum, checksum_kind, payload_length, num_entries, padding_bits, encoding_table: EncodingTable::new(),
+            adaptive: false, rle, symbol_width, trailing_byte, dictionary, primary_index, contexts, range_counts: Some(Box::new(range_counts)), filter, info,
+        });
+    }
+    let mut encoding_table = EncodingTable::read_entries(reader, &mut offset, num_entries, entry_width)?;
+    if let Some(escape) = escape {
+        encoding_table = encoding_table.with_escape(escape);
+    }
+    // Only bytes and the tokens there are have a symbol
+    if let Some(dictionary) = &dictionary {
+        if let Some((symbol, _)) = encoding_table.iter().last().filter(|&(symbol, _)| symbol as usize >= FIRST_TOKEN + dictionary.len()) {
+            return Err(HuffmanError::CorruptHeader(
+                format!("Invalid entry: symbol {} of a dictionary of {} tokens", symbol, dictionary.len()),
+            ))
+        }
+    }
+    Ok(Header {
+        version, original_length, checksum, checksum_kind, payload_length, num_entries, padding_bits, encoding_table,
+        adaptive: false, rle, symbol_width, trailing_byte, dictionary, primary_index, contexts, range_counts: None, filter, info,
+    })
+}
+
+// The counts of a range coded stream, every one at least 1, in the order of
+// the bytes, and together no more than the range coder can split its range in
+fn read_range_counts(reader: &mut impl Read, offset: &mut u64, num_entries: u32) -> HuffmanResult<FrequencyTable> {
+    let mut counts = FrequencyTable::new();
+    let mut previous = None;
+    for _ in 0..num_entries {
+        let [byte, low, high] = read_field(reader, offset, "entries")?;
+        let count = u16::from_le_bytes([low, high]);
+        if previous.is_some_and(|previous| byte <= previous) || count == 0 {
+            return Err(HuffmanError::CorruptHeader(format!("Invalid count: {} of byte {:#04x}", count, byte)))
+        }
+        counts.counts[byte as usize] = count as u64;
+        previous = Some(byte);
+    }
+    if counts.total() > RANGE_TOTAL {
+        return Err(HuffmanError::CorruptHeader(format!("Invalid counts: {} in total", counts.total())))
+    }
+    Ok(counts)
+}
+
+fn write_contexts(writer: &mut impl Write, contexts: &[Option<EncodingTable>]) -> IoResult<()> {
+    let mut has_table = [0u8; NUM_CONTEXTS / 8];
+    for (context, table) in contexts.iter().enumerate() {
+        if table.is_some() {
+            has_table[context / 8] |= 1 << (context % 8);
+        }
+    }
+    writer.write_all(&has_table)?;
+    for table in contexts.iter().flatten() {
+        writer.write_all(&[(table.len() - 1) as u8])?;
+        // An EncodingTable iterates in canonical order, the bytes go in theirs
+        let mut lengths: Vec<(Symbol, u8)> = table.iter().map(|(byte, code)| (byte, code.length)).collect();
+        lengths.sort_unstable();
+        for (byte, length) in lengths {
+            writer.write_all(&[byte as u8, length])?;
+        }
+    }
+    Ok(())
+}
+
+// The tables of the contexts of a stream. The bytes of a table have to come in
+// order and their lengths have to fit in a prefix code, or the canonical codes
+// wouldn't be those of the encoder.
+fn read_contexts(reader: &mut impl Read, offset: &mut u64) -> HuffmanResult<Vec<Option<EncodingTable>>> {
+    let has_table: [u8; NUM_CONTEXTS / 8] = read_field(reader, offset, "contexts")?;
+    let mut contexts = Vec::with_capacity(NUM_CONTEXTS);
+    for context in 0..NUM_CONTEXTS {
+        if has_table[context / 8] & (1 << (context % 8)) == 0 {
+            contexts.push(None);
+            continue;
+        }
+        let num_codes = read_u8(reader, offset, "tables of the contexts")? as usize + 1;
+        let mut lengths = EncodingTable::new();
+        let mut previous = None;
+        for _ in 0..num_codes {
+            let [byte, length] = read_field(reader, offset, "tables of the contexts")?;
+            if previous.is_some_and(|previous| byte <= previous) || !(1..=32).contains(&length) {
+                return Err(HuffmanError::CorruptHeader(
+                    format!("Invalid code of context {}: byte {:#04x} of {} bits", context, byte, length),
+                ));
+            }
+            lengths.insert(byte as Symbol, Code { bits: 0, length });
+            previous = Some(byte);
+        }
+        let Some(table) = canonical_codes(&lengths) else {
+            return Err(HuffmanError::CorruptHeader(format!("Invalid code lengths of context {}", context)));
+        };
+        contexts.push(Some(table));
+    }
+    Ok(contexts)
+}
+
+// The dictionary of a stream coded in tokens. Every token takes at least 3
+// bytes of the input, which bounds their number like the entries.
+fn read_dictionary(reader: &mut impl Read, offset: &mut u64, end: Option<u64>) -> HuffmanResult<Vec<Vec<u8>>> {
+    let num_tokens = read_u32(reader, offset, "number of tokens")?;
+    if num_tokens as usize > MAX_TOKENS {
+        return Err(HuffmanError::CorruptHeader(format!("Invalid number of tokens: {}", num_tokens)))
+    }
+    if let Some(end) = end {
+        if num_tokens as u64 > end.saturating_sub(*offset) / 3 {
+            return Err(truncated_header(end, &format!("tokens ({} declared)", num_tokens)))
+        }
+    }
+    let mut dictionary = Vec::with_capacity(num_tokens as usize);
+    for i in 0..num_tokens {
+        let length = read_u8(reader, offset, "tokens")? as usize;
+        if !(2..=MAX_TOKEN_LENGTH).contains(&length) {
+            return Err(HuffmanError::CorruptHeader(format!("Invalid length of token {}: {} bytes", i + 1, length)))
+        }
+        let mut token = Vec::with_capacity(length);
+        for _ in 0..length {
+            token.push(read_u8(reader, offset, "tokens")?);
+        }
+        dictionary.push(token);
+    }
+    Ok(dictionary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frequency::FrequencyTable;
+    use crate::test_util::Rng;
+    use crate::tree::HuffmanTree;
+
+    fn random_header(rng: &mut Rng) -> Header {
+        let version = rng.below(FLAGS_VERSION as usize + 1) as u8;
+        if version == FLAGS_VERSION && rng.below(3) == 0 {
+            return Header::adaptive();
+        }
+        // Version 4 streams are run-length encoded, in pairs, in tokens, with
+        // tables per context or range coded
+        let flags = [FLAG_RLE, FLAG_PAIRS, FLAG_TOKENS, FLAG_CONTEXT, FLAG_RANGE];
+        let flag = if version == FLAGS_VERSION { flags[rng.below(flags.len())] } else { 0 };
+        let pairs = flag == FLAG_PAIRS;
+        let symbol_width = if pairs { 2 } else { 1 };
+        let dictionary = (flag == FLAG_TOKENS).then(|| {
+            let tokens = (0..rng.below(20)).map(|_| (0..2 + rng.below(MAX_TOKEN_LENGTH - 1)).map(|_| rng.next() as u8).collect());
+            tokens.collect::<Vec<Vec<u8>>>()
+        });
+        let symbols = match &dictionary {
+            Some(dictionary) => FIRST_TOKEN + dictionary.len(),
+            None => 1 << (8 * symbol_width),
+        };
+        let mut encoding_table = EncodingTable::new();
+        for _ in 0..rng.below(257) {
+            let length = 1 + rng.below(32) as u8;
+            let bits = (rng.next() as u32) & (u32::MAX << (32 - length));
+            encoding_table.insert(rng.below(symbols) as Symbol, Code { bits, length });
+        }
+        // Reading checks that every symbol takes at least a bit of the payload
+        let payload_length = rng.next() >> 5;
+        let original_length = rng.next() % (payload_length * 8 * symbol_width as u64 + 1);
+        // The flag of run-length encoding stands for the transform too, which
+        // goes with or without it
+        let bwt = flag == FLAG_RLE && rng.below(2) == 0;
+        let rle = flag == FLAG_RLE && (!bwt || rng.below(2) == 0);
+        let primary_index = bwt.then(|| match original_length {
+            0 => 0,
+            length => 1 + (rng.next() % length.min(u32::MAX as u64)) as u32,
+        });
+        // The codes of a context are canonical, from the lengths of a tree
+        let contexts = (flag == FLAG_CONTEXT).then(|| {
+            let table = |rng: &mut Rng| {
+                let counts: FrequencyTable = (0..1 + rng.below(256)).map(|_| (rng.next() as u8, 1 + rng.next() % 1000)).collect();
+                HuffmanTree::from_frequencies(&counts).unwrap().codes()
+            };
+            (0..NUM_CONTEXTS).map(|_| (rng.below(8) == 0).then(|| table(rng))).collect()
+        });
+        // Any of them can be filtered, but not transformed
+        let filter = (version == FLAGS_VERSION && !bwt && rng.below(3) == 0)
+            .then(|| ([Filter::Delta, Filter::XorPrev][rng.below(2)], 1 + rng.below(255) as u8));
+        // Range coded streams have counts instead of codes
+        if flag == FLAG_RANGE {
+            let counts = (0..1 + rng.below(256)).map(|_| (rng.next() as u8, 1 + rng.next() % 255)).collect();
+            let header = Header::new(original_length, EncodingTable::new()).with_range(counts);
+            return Header { checksum: Some(rng.next() as u32), payload_length: Some(payload_length), filter, ..header };
+        }
+        Header {
+            version,
+            original_length: (version >= 1).then_some(original_length),
+            checksum: (version >= 2).then(|| rng.next() as u32),
+            checksum_kind: ChecksumKind::Crc32,
+            payload_length: (version >= 3).then_some(payload_length),
+            num_entries: encoding_table.len() as u32,
+            padding_bits: rng.below(9) as u8,
+            encoding_table,
+            adaptive: false,
+            rle,
+            symbol_width,
+            trailing_byte: (pairs && original_length % 2 == 1).then(|| rng.next() as u8),
+            dictionary,
+            primary_index,
+            contexts,
+            range_counts: None,
+            filter,
+            info: FileInfo::default(),
+        }
+    }
+
+    #[test]
+    fn test_write_to_and_read_from_are_inverses() {
+        let mut rng = Rng(0x4845_4144);
+        for _ in 0..500 {
+            let header = random_header(&mut rng);
+            // Any of them can have another checksum, which takes version 5,
+            // and a name or a time, which take version 6
+            let header = match rng.below(4) {
+                0 if header.version >= 3 => header.with_checksum(ChecksumKind::ALL[1 + rng.below(2)]),
+                _ => header,
+            };
+            let header = match rng.below(4) {
+                0 if header.version >= 3 => header.with_info(FileInfo {
+                    name: (rng.below(2) == 0).then(|| (0..rng.below(300)).map(|_| rng.next() as u8).collect()),
+                    mtime: (rng.below(2) == 0).then(|| rng.next() as i64),
+                    table_id: (rng.below(2) == 0).then(|| rng.next()),
+                }),
+                _ => header,
+            };
+            let mut bytes = Vec::new();
+            header.write_to(&mut bytes).unwrap();
+            assert_eq!(bytes.len() as u64, header.serialized_len(), "{:?}", header);
+            assert_eq!(Header::read_from(&mut &bytes[..]).unwrap(), header);
+        }
+    }
+
+    #[test]
+    fn test_every_version_matches_its_fixture() {
+        let fixtures: [&[u8]; 4] = [
+            include_bytes!("../tests/fixtures/versions/abracadabra.v0.encoded"),
+            include_bytes!("../tests/fixtures/versions/abracadabra.v1.encoded"),
+            include_bytes!("../tests/fixtures/versions/abracadabra.v2.encoded"),
+            include_bytes!("../tests/fixtures/versions/abracadabra.v3.encoded"),
+        ];
+        for (version, fixture) in fixtures.into_iter().enumerate() {
+            let header = Header::read_from(&mut &fixture[..]).unwrap();
+            assert_eq!(header.version, version as u8);
+            assert_eq!(header.original_length, (version >= 1).then_some(11));
+            assert_eq!(header.checksum.is_some(), version >= 2);
+            assert_eq!(header.payload_length.is_some(), version >= 3);
+            assert_eq!(header.num_entries, 5);
+
+            let mut bytes = Vec::new();
+            header.write_to(&mut bytes).unwrap();
+            assert_eq!(bytes, &fixture[..header.serialized_len() as usize], "version {}", version);
+            // The whole payload is the rest of the fixture
+            if let Some(payload_length) = header.payload_length {
+                assert_eq!(header.serialized_len() + payload_length, fixture.len() as u64);
+            }
+        }
+    }
+
+    // The header written before encoding has the same size as the final one,
+    // so it can be written again over it
+    #[test]
+    fn test_new_header_keeps_its_size() {
+        let fixture = include_bytes!("../tests/fixtures/versions/abracadabra.v3.encoded");
+        let header = Header::read_from(&mut &fixture[..]).unwrap();
+        let provisional = Header::new(11, header.encoding_table.clone());
+        assert_eq!(provisional.serialized_len(), header.serialized_len());
+
+        let mut bytes = Vec::new();
+        provisional.write_to(&mut bytes).unwrap();
+        let patched = Header { checksum: header.checksum, payload_length: header.payload_length, padding_bits: header.padding_bits, ..provisional };
+        assert_eq!(patched, header);
+        assert_eq!(bytes.len() as u64, header.serialized_len());
+    }
+
+    #[test]
+    fn test_checksum_headers() {
+        // The algorithm follows the flags, which may be none
+        let encoding_table: EncodingTable = [(b'a', Code { bits: 0, length: 1 })].into_iter().collect();
+        let header = Header { payload_length: Some(1), ..Header::new(5, encoding_table).with_checksum(ChecksumKind::Xxh3) };
+        let mut bytes = Vec::new();
+        header.write_to(&mut bytes).unwrap();
+        assert_eq!(&bytes[..11], b"HRST\xFF\xFF\xFF\xFF\x05\x00\x01");
+        assert_eq!(bytes.len() as u64, header.serialized_len());
+        assert_eq!(header.serialized_len(), header_len(VERSION, 1, 1) + 2);
+        assert_eq!(Header::read_from(&mut &bytes[..]).unwrap(), header);
+        // With flags, and for an adaptive stream
+        let header = Header { payload_length: Some(1), ..header.with_rle() };
+        let mut bytes = Vec::new();
+        header.write_to(&mut bytes).unwrap();
+        assert_eq!(&bytes[..11], b"HRST\xFF\xFF\xFF\xFF\x05\x02\x01");
+        assert_eq!(Header::read_from(&mut &bytes[..]).unwrap(), header);
+        let adaptive = Header::adaptive().with_checksum(ChecksumKind::None);
+        let mut bytes = Vec::new();
+        adaptive.write_to(&mut bytes).unwrap();
+        assert_eq!(bytes, b"HRST\xFF\xFF\xFF\xFF\x05\x01\x02");
+        assert_eq!(adaptive.serialized_len(), bytes.len() as u64);
+        assert_eq!(Header::read_from(&mut &bytes[..]).unwrap(), adaptive);
+        // CRC-32 keeps the version, so older readers still decode it
+        assert_eq!(Header::adaptive().with_checksum(ChecksumKind::Crc32), Header::adaptive());
+
+        bytes[10] = 7;
+        match Header::read_from(&mut &bytes[..]) {
+            Err(HuffmanError::CorruptHeader(message)) => assert_eq!(message, "Unknown checksum algorithm: 7"),
+            other => panic!("expected a corrupt header, got {:?}", other),
+        }
+        assert!(matches!(Header::read_from(&mut &bytes[..10]), Err(HuffmanError::TruncatedHeader { offset: 10, .. })));
+        assert!(matches!(Header::read_from(&mut &b"HRST\xFF\xFF\xFF\xFF\x07"[..]), Err(HuffmanError::UnsupportedVersion(7))));
+    }
+
+    #[test]
+    fn test_info_headers() {
+        // The info follows the algorithm of the checksum, the name first
+        let encoding_table: EncodingTable = [(b'a', Code { bits: 0, length: 1 })].into_iter().collect();
+        let info = FileInfo { name: Some(b"notes.txt".to_vec()), mtime: Some(1_700_000_000), table_id: None };
+        let header = Header { payload_length: Some(1), ..Header::new(5, encoding_table).with_info(info.clone()) };
+        let mut bytes = Vec::new();
+        header.write_to(&mut bytes).unwrap();
+        assert_eq!(&bytes[..31], b"HRST\xFF\xFF\xFF\xFF\x06\x00\x00\x03\x09\x00notes.txt\x00\xF1\x53\x65\x00\x00\x00\x00");
+        assert_eq!(bytes.len() as u64, header.serialized_len());
+        assert_eq!(Header::read_from(&mut &bytes[..]).unwrap(), header);
+        // Another checksum keeps the version
+        assert_eq!(header.clone().with_checksum(ChecksumKind::Xxh3).version, INFO_VERSION);
+        // Only the time, and in an adaptive stream
+        let adaptive = Header::adaptive().with_info(FileInfo { mtime: Some(-1), ..FileInfo::default() });
+        let mut bytes = Vec::new();
+        adaptive.write_to(&mut bytes).unwrap();
+        assert_eq!(bytes, b"HRST\xFF\xFF\xFF\xFF\x06\x01\x00\x02\xFF\xFF\xFF\xFF\xFF\xFF\xFF\xFF");
+        assert_eq!(adaptive.serialized_len(), bytes.len() as u64);
+        assert_eq!(Header::read_from(&mut &bytes[..]).unwrap(), adaptive);
+        // Without any the version stays
+        assert_eq!(Header::adaptive().with_info(FileInfo::default()), Header::adaptive());
+
+        assert!(matches!(Header::read_from(&mut &bytes[..15]), Err(HuffmanError::TruncatedHeader { offset: 15, .. })));
+
+        // The table id comes last
+        let with_table = Header::adaptive().with_info(FileInfo { mtime: Some(-1), table_id: Some(0x0102_0304_0506_0708), ..FileInfo::default() });
+        let mut table_bytes = Vec::new();
+        with_table.write_to(&mut table_bytes).unwrap();
+        assert_eq!(&table_bytes[..12], b"HRST\xFF\xFF\xFF\xFF\x06\x01\x00\x06");
+        assert_eq!(&table_bytes[20..], b"\x08\x07\x06\x05\x04\x03\x02\x01");
+        assert_eq!(Header::read_from(&mut &table_bytes[..]).unwrap(), with_table);
+
+        // The escape code comes after all of those, in any stream with a table
+        let encoding_table: EncodingTable = [(b'a', Code { bits: 0, length: 1 })].into_iter().collect();
+        let escaped = Header { payload_length: Some(1), ..Header::new(5, encoding_table.with_escape(Code { bits: 1 << 31, length: 1 })) };
+        assert_eq!(escaped.version, INFO_VERSION);
+        let mut escaped_bytes = Vec::new();
+        escaped.write_to(&mut escaped_bytes).unwrap();
+        assert_eq!(&escaped_bytes[..17], b"HRST\xFF\xFF\xFF\xFF\x06\x00\x00\x08\x01\x00\x00\x00\x80");
+        assert_eq!(escaped_bytes.len() as u64, escaped.serialized_len());
+        assert_eq!(Header::read_from(&mut &escaped_bytes[..]).unwrap(), escaped);
+        // Only plain tables of bytes have one
+        escaped_bytes[9] = FLAG_RLE;
+        match Header::read_from(&mut &escaped_bytes[..]) {
+            Err(HuffmanError::CorruptHeader(message)) => assert_eq!(message, "Invalid flags: 0x02 with an escape code"),
+            other => panic!("expected a corrupt header, got {:?}", other),
+        }
+        escaped_bytes[9] = 0;
+        escaped_bytes[12] = 25;
+        assert!(matches!(Header::read_from(&mut &escaped_bytes[..]), Err(HuffmanError::CorruptHeader(_))));
+
+        bytes[11] = 16;
+        match Header::read_from(&mut &bytes[..]) {
+            Err(HuffmanError::CorruptHeader(message)) => assert_eq!(message, "Invalid info: 0x10"),
+            other => panic!("expected a corrupt header, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_flags_headers() {
+        let mut bytes = Vec::new();
+        Header::adaptive().write_to(&mut bytes).unwrap();
+        assert_eq!(bytes, b"HRST\xFF\xFF\xFF\xFF\x04\x01");
+        assert_eq!(Header::adaptive().serialized_len(), bytes.len() as u64);
+        assert_eq!(Header::read_from(&mut &bytes[..]).unwrap(), Header::adaptive());
+
+        // No other flags yet, and no adaptive run-length encoding
+        bytes[9] = 3;
+        assert!(matches!(Header::read_from(&mut &bytes[..]), Err(HuffmanError::CorruptHeader(_))));
+        assert!(matches!(Header::read_from(&mut &bytes[..9]), Err(HuffmanError::TruncatedHeader { offset: 9, .. })));
+
+        // The fields of version 3 after the flags
+        let encoding_table: EncodingTable = [(b'a', Code { bits: 0, length: 1 })].into_iter().collect();
+        let header = Header::new(500, encoding_table).with_rle();
+        let mut bytes = Vec::new();
+        header.write_to(&mut bytes).unwrap();
+        assert_eq!(&bytes[..10], b"HRST\xFF\xFF\xFF\xFF\x04\x02");
+        assert_eq!(bytes.len() as u64, header.serialized_len());
+        assert_eq!(header.serialized_len(), header_len(VERSION, 1, 1) + 1);
+        // Runs decode to more than 8 bytes per byte of payload
+        bytes[22..30].copy_from_slice(&1u64.to_le_bytes());
+        bytes.push(0);
+        let read = read_header(&mut &bytes[..], 0, Some(bytes.len() as u64)).unwrap();
+        assert_eq!(read, Header { payload_length: Some(1), ..header });
+
+        // Pairs have the byte an odd length leaves over after the flags, and
+        // entries of 7 bytes
+        let encoding_table: EncodingTable = [(u16::from_le_bytes(*b"a\0"), Code { bits: 0, length: 1 })].into_iter().collect();
+        let header = Header { payload_length: Some(1), ..Header::new(5, encoding_table).with_pairs(Some(b'!')) };
+        let mut bytes = Vec::new();
+        header.write_to(&mut bytes).unwrap();
+        assert_eq!(&bytes[..11], b"HRST\xFF\xFF\xFF\xFF\x04\x04!");
+        assert_eq!(&bytes[bytes.len() - 7..bytes.len() - 5], b"a\0");
+        assert_eq!(bytes.len() as u64, header.serialized_len());
+        assert_eq!(header.serialized_len(), header_len(VERSION, 1, 1) + 3);
+        assert_eq!(Header::read_from(&mut &bytes[..]).unwrap(), header);
+        // An even length leaves nothing over, the byte has to be 0
+        bytes[11..19].copy_from_slice(&4u64.to_le_bytes());
+        assert!(matches!(Header::read_from(&mut &bytes[..]), Err(HuffmanError::CorruptHeader(_))));
+        bytes[10] = 0;
+        assert_eq!(Header::read_from(&mut &bytes[..]).unwrap().trailing_byte, None);
+
+        // Tokens have their dictionary after the flags, and entries of 7 bytes
+        let encoding_table: EncodingTable = [(257u16, Code { bits: 0, length: 1 })].into_iter().collect();
+        let dictionary = vec![b"the".to_vec(), b"and".to_vec()];
+        let header = Header { payload_length: Some(1), ..Header::new(3, encoding_table).with_dictionary(dictionary) };
+        let mut bytes = Vec::new();
+        header.write_to(&mut bytes).unwrap();
+        assert_eq!(&bytes[..22], b"HRST\xFF\xFF\xFF\xFF\x04\x08\x02\0\0\0\x03the\x03and");
+        assert_eq!(bytes.len() as u64, header.serialized_len());
+        assert_eq!(header.serialized_len(), header_len(VERSION, 1, 1) + 1 + 12 + 1);
+        assert_eq!(Header::read_from(&mut &bytes[..]).unwrap(), header);
+        // The entry is for the second token, there has to be one
+        let error = |bytes: &[u8]| match Header::read_from(&mut &bytes[..]) {
+            Err(HuffmanError::CorruptHeader(message)) => message,
+            other => panic!("expected a corrupt header, got {:?}", other),
+        };
+        let mut one_token = bytes.clone();
+        one_token[10] = 1;
+        one_token.drain(18..22);
+        assert_eq!(error(&one_token), "Invalid entry: symbol 257 of a dictionary of 1 tokens");
+        let mut short = bytes.clone();
+        short[14] = 1;
+        assert_eq!(error(&short), "Invalid length of token 1: 1 bytes");
+
+        // The transform has its primary index after the flags, on its own or
+        // with run-length encoding
+        let encoding_table: EncodingTable = [(b'a', Code { bits: 0, length: 1 })].into_iter().collect();
+        for (header, flags) in [(Header::new(5, encoding_table.clone()), 16), (Header::new(5, encoding_table).with_rle(), 18)] {
+            let header = Header { payload_length: Some(1), ..header.with_bwt(3) };
+            let mut bytes = Vec::new();
+            header.write_to(&mut bytes).unwrap();
+            assert_eq!(&bytes[..14], [&b"HRST\xFF\xFF\xFF\xFF\x04"[..], &[flags, 3, 0, 0, 0]].concat());
+            assert_eq!(bytes.len() as u64, header.serialized_len());
+            assert_eq!(header.serialized_len(), header_len(VERSION, 1, 1) + 5);
+            assert_eq!(Header::read_from(&mut &bytes[..]).unwrap(), header);
+            bytes[10] = 6;
+            assert_eq!(error(&bytes), "Invalid primary index: 6 of 5 bytes");
+        }
+        // It doesn't go with the other flags
+        assert_eq!(error(b"HRST\xFF\xFF\xFF\xFF\x04\x14"), "Invalid flags: 0x14");
+
+        // Tables per context have a bit for every context after the flags,
+        // and the lengths of the codes of those with a bit set
+        let encoding_table: EncodingTable = [(b'a', Code { bits: 0, length: 1 })].into_iter().collect();
+        let mut contexts = vec![None; NUM_CONTEXTS];
+        contexts[b'q' as usize] = Some([(b'a', Code { bits: 0, length: 1 }), (b'u', Code { bits: 1 << 31, length: 1 })].into_iter().collect());
+        let header = Header { payload_length: Some(1), ..Header::new(3, encoding_table).with_contexts(contexts) };
+        let mut bytes = Vec::new();
+        header.write_to(&mut bytes).unwrap();
+        assert_eq!(&bytes[9..10], b"\x20");
+        assert_eq!(bytes[10..42].iter().position(|&bits| bits != 0), Some(b'q' as usize / 8));
+        assert_eq!(bytes[10 + b'q' as usize / 8], 1 << (b'q' % 8));
+        assert_eq!(&bytes[42..47], b"\x01a\x01u\x01");
+        assert_eq!(bytes.len() as u64, header.serialized_len());
+        assert_eq!(header.serialized_len(), header_len(VERSION, 1, 1) + 1 + 32 + 5);
+        assert_eq!(Header::read_from(&mut &bytes[..]).unwrap(), header);
+        // The bytes come in order, and their codes have to fit
+        let mut unordered = bytes.clone();
+        unordered[45] = b'0';
+        assert_eq!(error(&unordered), "Invalid code of context 113: byte 0x30 of 1 bits");
+        let mut too_many = bytes.clone();
+        too_many[42] = 2;
+        too_many.splice(47..47, *b"z\x01");
+        assert_eq!(error(&too_many), "Invalid code lengths of context 113");
+
+        // Range coded streams have a count of 2 bytes in every entry
+        let counts: FrequencyTable = [(b'a', 3), (b'z', 500)].into_iter().collect();
+        let header = Header { payload_length: Some(5), ..Header::new(503, EncodingTable::new()).with_range(counts) };
+        let mut bytes = Vec::new();
+        header.write_to(&mut bytes).unwrap();
+        assert_eq!(&bytes[9..10], b"\x40");
+        assert_eq!(&bytes[bytes.len() - 6..], b"a\x03\0z\xF4\x01");
+        assert_eq!(bytes.len() as u64, header.serialized_len());
+        assert_eq!(Header::read_from(&mut &bytes[..]).unwrap(), header);
+        // Every byte that occurs has a count, in the order of the bytes
+        let mut zero = bytes.clone();
+        zero[bytes.len() - 5] = 0;
+        assert_eq!(error(&zero), "Invalid count: 0 of byte 0x61");
+        let mut unordered = bytes.clone();
+        unordered[bytes.len() - 3] = b'a';
+        assert_eq!(error(&unordered), "Invalid count: 500 of byte 0x61");
+        let mut too_many = bytes.clone();
+        too_many[bytes.len() - 2..].copy_from_slice(&[0xFF, 0xFF]);
+        assert_eq!(error(&too_many), "Invalid counts: 65538 in total");
+
+        // The filter and its stride follow the fields of the other flags
+        let encoding_table: EncodingTable = [(b'a', Code { bits: 0, length: 1 })].into_iter().collect();
+        let header = Header { payload_length: Some(1), ..Header::new(5, encoding_table).with_rle().with_filter(Filter::Delta, 2) };
+        let mut bytes = Vec::new();
+        header.write_to(&mut bytes).unwrap();
+        assert_eq!(&bytes[8..12], b"\x04\x82\x01\x02");
+        assert_eq!(bytes.len() as u64, header.serialized_len());
+        assert_eq!(header.serialized_len(), header_len(VERSION, 1, 1) + 1 + 2);
+        assert_eq!(Header::read_from(&mut &bytes[..]).unwrap(), header);
+        let mut unknown = bytes.clone();
+        unknown[10] = 3;
+        assert_eq!(error(&unknown), "Invalid filter: 3");
+        let mut no_stride = bytes.clone();
+        no_stride[11] = 0;
+        assert_eq!(error(&no_stride), "Invalid stride: 0 bytes");
+        // Adaptive and transformed streams aren't filtered
+        assert_eq!(error(b"HRST\xFF\xFF\xFF\xFF\x04\x81"), "Invalid flags: 0x81");
+        assert_eq!(error(b"HRST\xFF\xFF\xFF\xFF\x04\x92"), "Invalid flags: 0x92");
+    }
+}
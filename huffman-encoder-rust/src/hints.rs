@@ -0,0 +1,103 @@
+// Telling the OS how inputs are read
+
+use std::fs::{File, OpenOptions};
+use std::io::Result as IoResult;
+use std::path::Path;
+
+// What the OS is told about how an input is read. Both passes read strictly
+// from start to end, and an input that's done with can leave the cache to
+// make room for data that's more useful there.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Advice {
+    Sequential,
+    DontNeed,
+}
+
+// posix_fadvise is only called where its signature is known to match, None
+// elsewhere
+#[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+fn fadvise_code(advice: Advice) -> Option<i32> {
+    match advice {
+        Advice::Sequential => Some(2), // POSIX_FADV_SEQUENTIAL
+        Advice::DontNeed => Some(4), // POSIX_FADV_DONTNEED
+    }
+}
+
+#[cfg(not(all(target_os = "linux", target_pointer_width = "64")))]
+fn fadvise_code(_advice: Advice) -> Option<i32> {
+    None
+}
+
+// Windows takes the hint when the file is opened instead
+#[cfg(any(windows, test))]
+fn sequential_open_flags() -> u32 {
+    if cfg!(windows) {
+        0x0800_0000 // FILE_FLAG_SEQUENTIAL_SCAN
+    } else {
+        0
+    }
+}
+
+pub(crate) fn open_sequential(path: &Path) -> IoResult<File> {
+    let mut options = OpenOptions::new();
+    options.read(true);
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::OpenOptionsExt;
+        options.custom_flags(sequential_open_flags());
+    }
+    let file = options.open(path)?;
+    advise(&file, Advice::Sequential);
+    Ok(file)
+}
+
+// Only a hint, so failures are ignored
+pub(crate) fn advise(file: &File, advice: Advice) {
+    #[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+    if let Some(code) = fadvise_code(advice) {
+        use std::os::unix::io::AsRawFd;
+        extern "C" {
+            fn posix_fadvise(fd: i32, offset: i64, len: i64, advice: i32) -> i32;
+        }
+        // Offset and length 0 cover the whole file. The descriptor stays open
+        // for the duration of the call, as the file is borrowed.
+        unsafe {
+            posix_fadvise(file.as_raw_fd(), 0, 0, code);
+        }
+    }
+    #[cfg(not(all(target_os = "linux", target_pointer_width = "64")))]
+    let _ = (file, fadvise_code(advice));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_path;
+    use std::fs;
+    use std::io::Read;
+
+    #[test]
+    fn test_access_hints_per_platform() {
+        if cfg!(all(target_os = "linux", target_pointer_width = "64")) {
+            assert_eq!(fadvise_code(Advice::Sequential), Some(2));
+            assert_eq!(fadvise_code(Advice::DontNeed), Some(4));
+        } else {
+            assert_eq!(fadvise_code(Advice::Sequential), None);
+            assert_eq!(fadvise_code(Advice::DontNeed), None);
+        }
+        let expected = if cfg!(windows) { 0x0800_0000 } else { 0 };
+        assert_eq!(sequential_open_flags(), expected);
+    }
+
+    #[test]
+    fn test_advised_files_read_the_same() {
+        let path = temp_path("advised");
+        fs::write(&path, b"read from start to end").unwrap();
+        let mut file = open_sequential(&path).unwrap();
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents).unwrap();
+        advise(&file, Advice::DontNeed);
+        assert_eq!(contents, b"read from start to end");
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,156 @@
+// The parts of std::io the codec is written against. With the std feature they
+// are the real ones. Without it they are a minimal copy that reads from slices
+// and writes to vectors and slices, which is all the in-memory codec needs on
+// a target that only has alloc.
+
+#[cfg(feature = "std")]
+pub use std::io::{BufRead, Error, ErrorKind, Read, Result, Write};
+
+#[cfg(not(feature = "std"))]
+pub use self::bare::{BufRead, Error, ErrorKind, Read, Result, Write};
+
+#[cfg(not(feature = "std"))]
+mod bare {
+    use alloc::vec::Vec;
+    use core::fmt;
+
+    // The kinds the codec reports or retries on, same names as in std
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum ErrorKind {
+        Interrupted,
+        UnexpectedEof,
+        WriteZero,
+        InvalidData,
+        InvalidInput,
+        FileTooLarge,
+        Other,
+    }
+
+    // Only the kind, there is no OS error or payload to carry
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct Error {
+        kind: ErrorKind,
+    }
+
+    impl Error {
+        pub fn kind(&self) -> ErrorKind {
+            self.kind
+        }
+    }
+
+    impl From<ErrorKind> for Error {
+        fn from(kind: ErrorKind) -> Self {
+            Error { kind }
+        }
+    }
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            let message = match self.kind {
+                ErrorKind::Interrupted => "operation interrupted",
+                ErrorKind::UnexpectedEof => "unexpected end of file",
+                ErrorKind::WriteZero => "failed to write whole buffer",
+                ErrorKind::InvalidData => "invalid data",
+                ErrorKind::InvalidInput => "invalid input parameter",
+                ErrorKind::FileTooLarge => "file too large",
+                ErrorKind::Other => "other error",
+            };
+            write!(f, "{}", message)
+        }
+    }
+
+    impl core::error::Error for Error {}
+
+    pub type Result<T> = core::result::Result<T, Error>;
+
+    pub trait Read {
+        fn read(&mut self, buffer: &mut [u8]) -> Result<usize>;
+    }
+
+    pub trait BufRead: Read {
+        fn fill_buf(&mut self) -> Result<&[u8]>;
+        fn consume(&mut self, amount: usize);
+    }
+
+    pub trait Write {
+        fn write(&mut self, buffer: &[u8]) -> Result<usize>;
+
+        fn write_all(&mut self, mut buffer: &[u8]) -> Result<()> {
+            while !buffer.is_empty() {
+                match self.write(buffer)? {
+                    0 => return Err(ErrorKind::WriteZero.into()),
+                    written => buffer = &buffer[written..],
+                }
+            }
+            Ok(())
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Read for &[u8] {
+        fn read(&mut self, buffer: &mut [u8]) -> Result<usize> {
+            let read = buffer.len().min(self.len());
+            buffer[..read].copy_from_slice(&self[..read]);
+            *self = &self[read..];
+            Ok(read)
+        }
+    }
+
+    impl BufRead for &[u8] {
+        fn fill_buf(&mut self) -> Result<&[u8]> {
+            Ok(self)
+        }
+
+        fn consume(&mut self, amount: usize) {
+            *self = &self[amount..];
+        }
+    }
+
+    impl<R: Read + ?Sized> Read for &mut R {
+        fn read(&mut self, buffer: &mut [u8]) -> Result<usize> {
+            (**self).read(buffer)
+        }
+    }
+
+    impl<B: BufRead + ?Sized> BufRead for &mut B {
+        fn fill_buf(&mut self) -> Result<&[u8]> {
+            (**self).fill_buf()
+        }
+
+        fn consume(&mut self, amount: usize) {
+            (**self).consume(amount)
+        }
+    }
+
+    impl Write for Vec<u8> {
+        fn write(&mut self, buffer: &[u8]) -> Result<usize> {
+            self.extend_from_slice(buffer);
+            Ok(buffer.len())
+        }
+    }
+
+    // Fills the slice from the front and moves past what was written, once it
+    // is full write_all fails with WriteZero
+    impl Write for &mut [u8] {
+        fn write(&mut self, buffer: &[u8]) -> Result<usize> {
+            let written = buffer.len().min(self.len());
+            let (head, tail) = core::mem::take(self).split_at_mut(written);
+            head.copy_from_slice(&buffer[..written]);
+            *self = tail;
+            Ok(written)
+        }
+    }
+
+    impl<W: Write + ?Sized> Write for &mut W {
+        fn write(&mut self, buffer: &[u8]) -> Result<usize> {
+            (**self).write(buffer)
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            (**self).flush()
+        }
+    }
+}
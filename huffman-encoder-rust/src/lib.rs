@@ -0,0 +1,95 @@
+// Huffman coding of files: counting the bytes, building the tree and the
+// table, and writing and reading the header and the encoded data. The binary
+// in main.rs only adds the command line on top.
+//
+// Without the std feature only the core is built, on alloc alone: everything
+// that works on slices and vectors. Streams and threads need std, the command
+// line and its file handling the cli feature. Tests always have std, but only
+// see what the features build.
+
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "async")]
+pub mod async_io;
+pub mod bitio;
+pub mod cancel;
+#[cfg(feature = "cli")]
+pub mod cli;
+pub mod codec;
+pub mod estimate;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod header;
+pub mod io;
+#[cfg(feature = "std")]
+pub mod method;
+pub mod options;
+pub mod progress;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod report;
+#[cfg(feature = "std")]
+pub mod stream;
+pub mod table;
+pub mod tree;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+mod adaptive;
+mod bwt;
+mod checksum;
+#[cfg(feature = "cli")]
+mod config;
+mod context;
+mod error;
+mod filter;
+#[cfg(feature = "cli")]
+mod freq_cache;
+mod frequency;
+#[cfg(feature = "cli")]
+mod hints;
+#[cfg(feature = "cli")]
+mod output;
+#[cfg(feature = "cli")]
+mod pipeline;
+#[cfg(feature = "cli")]
+mod prompt;
+mod range;
+mod rle;
+#[cfg(feature = "cli")]
+mod style;
+#[cfg(feature = "cli")]
+mod template;
+#[cfg(feature = "cli")]
+mod throttle;
+#[cfg(test)]
+mod test_util;
+mod tokens;
+
+#[cfg(feature = "async")]
+pub use async_io::{AsyncHuffmanReader, AsyncHuffmanWriter};
+pub use bitio::{BitReader, BitWriter};
+pub use cancel::CancelToken;
+pub use checksum::{Checksum, ChecksumKind, Crc32, Xxh3};
+pub use error::{HuffmanError, HuffmanResult};
+pub use codec::{decode_bytes, decode_bytes_into, decode_bytes_with, encode_bytes, encode_bytes_with, encode_file, DEFAULT_BUFFER_SIZE};
+#[cfg(feature = "std")]
+pub use codec::{decode_file, decode_stream, encode_block, encode_stream, merge_streams, upgrade_stream};
+pub use estimate::{estimate_encoded_size, estimate_ratio};
+#[cfg(feature = "std")]
+pub use estimate::{entropy_bits_per_byte, estimate_range_size};
+pub use frequency::{calculate_frequencies, FrequencyTable};
+pub use header::{FileInfo, Header};
+#[cfg(feature = "std")]
+pub use header::decode_header;
+#[cfg(feature = "std")]
+pub use method::{CompressionMethod, Decoding, Registry};
+pub use options::{DecoderOptions, EncoderOptions, Filter, Method};
+pub use progress::{NoProgress, Phase, ProgressSink, Report};
+pub use report::{ChecksumStatus, DecodeReport, EncodeReport};
+#[cfg(feature = "std")]
+pub use stream::{HuffmanReader, HuffmanWriter};
+pub use table::{build_encoding_table, escaped_codes, Code, EncodingTable};
+pub use tree::{build_huffman_tree, HuffmanTree};
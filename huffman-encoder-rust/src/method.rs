@@ -0,0 +1,331 @@
+// The coders of the payload of a stream, behind a trait: codes from the table
+// in the header, adaptive codes and range coding. encode_stream and
+// decode_stream look the method up in a Registry, by the options for encoding
+// and by Header::method() for every stream they decode, so another coder only
+// has to register itself:
+//
+//     let mut registry = Registry::default();
+//     registry.register(Arc::new(MyMethod));
+//     registry.decode_stream(&mut reader, &mut output, header, &options)?;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use std::io::Seek;
+use std::sync::OnceLock;
+
+use crate::codec::{decode_adaptive_file, decode_file_with_progress, decode_streams, encode_blocks, encode_stream_adaptive};
+use crate::error::{HuffmanError, HuffmanResult};
+use crate::header::{Header, METHOD_ADAPTIVE, METHOD_RANGE, METHOD_TABLE};
+use crate::io::{BufRead, Read, Write};
+use crate::options::{DecoderOptions, EncoderOptions, Method};
+use crate::progress::Progress;
+use crate::report::{DecodeReport, EncodeReport};
+
+// A coder of payloads. Its id is the method of the headers of the streams it
+// writes, so decoding knows which method to hand a stream to.
+pub trait CompressionMethod: Send + Sync {
+    fn id(&self) -> u8;
+
+    // For error messages and the like, "huffman" or "range"
+    fn name(&self) -> &'static str;
+
+    // Encodes everything `input` holds into `output`, one stream per block of
+    // `options`, like encode_stream
+    fn encode(&self, input: &mut dyn Read, output: &mut dyn Write, options: &EncoderOptions) -> HuffmanResult<EncodeReport>;
+
+    // Decodes the payload of the stream of `stream.header()`, which `input` is
+    // at, and whatever follows it up to the end of the stream. Returns the
+    // number of bits of payload that were read and the number of bytes
+    // decoded.
+    fn decode(&self, input: &mut dyn BufRead, output: &mut dyn Write, stream: &mut Decoding) -> HuffmanResult<(u64, u64)>;
+}
+
+// What CompressionMethod::decode gets besides the data: the header of the
+// stream, the options with what is left of the size limit for it, and where
+// its progress goes
+pub struct Decoding<'a, 'p> {
+    pub(crate) header: &'a mut Header,
+    pub(crate) options: &'a DecoderOptions,
+    pub(crate) progress: &'a mut Progress<'p>,
+    // The bytes that occurred, for streams without a table
+    pub(crate) occurred: [bool; 256],
+}
+
+impl<'a, 'p> Decoding<'a, 'p> {
+    pub(crate) fn new(header: &'a mut Header, options: &'a DecoderOptions, progress: &'a mut Progress<'p>) -> Self {
+        Decoding { header, options, progress, occurred: [false; 256] }
+    }
+
+    pub fn header(&self) -> &Header {
+        self.header
+    }
+
+    // For the fields a stream only has after its payload, like the length
+    // and the checksum of an adaptive one
+    pub fn header_mut(&mut self) -> &mut Header {
+        self.header
+    }
+
+    pub fn options(&self) -> &DecoderOptions {
+        self.options
+    }
+
+    // The payload was read up to `processed` bytes in. Fails with Cancelled
+    // once the CancelToken of the options is cancelled.
+    pub fn update(&mut self, processed: u64) -> HuffmanResult<()> {
+        self.progress.update(processed)
+    }
+
+    // Bytes that occurred in a stream that has no table to say so, for
+    // DecodeReport::distinct_symbols
+    pub fn add_symbols(&mut self, occurred: &[bool; 256]) {
+        for (seen, &byte) in self.occurred.iter_mut().zip(occurred) {
+            *seen |= byte;
+        }
+    }
+}
+
+// The methods encode_stream and decode_stream can use, by id
+#[derive(Clone)]
+pub struct Registry {
+    methods: Vec<Arc<dyn CompressionMethod>>,
+}
+
+impl Registry {
+    // A registry without any method, which decodes nothing
+    pub fn empty() -> Self {
+        Registry { methods: Vec::new() }
+    }
+
+    // The default registry, shared by the functions that don't take one
+    pub(crate) fn shipped() -> &'static Registry {
+        static SHIPPED: OnceLock<Registry> = OnceLock::new();
+        SHIPPED.get_or_init(Registry::default)
+    }
+
+    // Adds `method`, in place of the one with the same id if there is one
+    pub fn register(&mut self, method: Arc<dyn CompressionMethod>) {
+        self.methods.retain(|registered| registered.id() != method.id());
+        self.methods.push(method);
+    }
+
+    // Takes out the method with `id`, so its streams fail to decode
+    pub fn remove(&mut self, id: u8) -> Option<Arc<dyn CompressionMethod>> {
+        let index = self.methods.iter().position(|method| method.id() == id)?;
+        Some(self.methods.remove(index))
+    }
+
+    pub fn get(&self, id: u8) -> HuffmanResult<&dyn CompressionMethod> {
+        self.methods.iter()
+            .find(|method| method.id() == id)
+            .map(|method| &**method)
+            .ok_or(HuffmanError::UnsupportedMethod(id))
+    }
+
+    // encode_stream with the method of `options`
+    pub fn encode_stream(&self, mut input: impl Read, output: &mut impl Write, options: &EncoderOptions) -> HuffmanResult<EncodeReport> {
+        self.get(method_id(options))?.encode(&mut input, output, options)
+    }
+
+    // decode_stream with the methods of the registry
+    pub fn decode_stream(
+        &self,
+        reader: &mut (impl BufRead + Seek),
+        output: &mut impl Write,
+        header: Header,
+        options: &DecoderOptions,
+    ) -> HuffmanResult<DecodeReport> {
+        decode_streams(self, reader, output, header, options)
+    }
+}
+
+// Codes from a table, range coding and adaptive codes
+impl Default for Registry {
+    fn default() -> Self {
+        let mut registry = Registry::empty();
+        registry.register(Arc::new(TableMethod));
+        registry.register(Arc::new(AdaptiveMethod));
+        registry.register(Arc::new(RangeMethod));
+        registry
+    }
+}
+
+// The method the streams of `options` get
+fn method_id(options: &EncoderOptions) -> u8 {
+    match (options.adaptive, options.method) {
+        (true, _) => METHOD_ADAPTIVE,
+        (false, Method::Huffman) => METHOD_TABLE,
+        (false, Method::Range) => METHOD_RANGE,
+    }
+}
+
+// Codes from the table in the header, with any of the transforms before them
+struct TableMethod;
+
+impl CompressionMethod for TableMethod {
+    fn id(&self) -> u8 {
+        METHOD_TABLE
+    }
+
+    fn name(&self) -> &'static str {
+        "huffman"
+    }
+
+    fn encode(&self, input: &mut dyn Read, output: &mut dyn Write, options: &EncoderOptions) -> HuffmanResult<EncodeReport> {
+        encode_blocks(input, &mut &mut *output, options)
+    }
+
+    fn decode(&self, input: &mut dyn BufRead, output: &mut dyn Write, stream: &mut Decoding) -> HuffmanResult<(u64, u64)> {
+        decode_file_with_progress(input, output, stream.header, stream.options, stream.progress)
+    }
+}
+
+// Codes that change as the data goes, see adaptive.rs
+struct AdaptiveMethod;
+
+impl CompressionMethod for AdaptiveMethod {
+    fn id(&self) -> u8 {
+        METHOD_ADAPTIVE
+    }
+
+    fn name(&self) -> &'static str {
+        "adaptive"
+    }
+
+    fn encode(&self, input: &mut dyn Read, output: &mut dyn Write, options: &EncoderOptions) -> HuffmanResult<EncodeReport> {
+        encode_stream_adaptive(input, &mut &mut *output, options)
+    }
+
+    fn decode(&self, input: &mut dyn BufRead, output: &mut dyn Write, stream: &mut Decoding) -> HuffmanResult<(u64, u64)> {
+        let (bits, occurred) = decode_adaptive_file(input, output, stream.header, stream.options, stream.progress)?;
+        stream.add_symbols(&occurred);
+        Ok((bits, stream.header.original_length.unwrap_or(0)))
+    }
+}
+
+// Range coding with the counts in the header, see range.rs. Its streams go
+// through the same blocks and transforms as those with a table.
+struct RangeMethod;
+
+impl CompressionMethod for RangeMethod {
+    fn id(&self) -> u8 {
+        METHOD_RANGE
+    }
+
+    fn name(&self) -> &'static str {
+        "range"
+    }
+
+    fn encode(&self, input: &mut dyn Read, output: &mut dyn Write, options: &EncoderOptions) -> HuffmanResult<EncodeReport> {
+        encode_blocks(input, &mut &mut *output, options)
+    }
+
+    fn decode(&self, input: &mut dyn BufRead, output: &mut dyn Write, stream: &mut Decoding) -> HuffmanResult<(u64, u64)> {
+        decode_file_with_progress(input, output, stream.header, stream.options, stream.progress)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::decode_header;
+    use crate::test_util::{text_data, Rng};
+    use std::io::Cursor;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn encoded_with(registry: &Registry, data: &[u8], options: &EncoderOptions) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        registry.encode_stream(data, &mut encoded, options).unwrap();
+        encoded
+    }
+
+    fn decoded_with(registry: &Registry, encoded: &[u8]) -> HuffmanResult<Vec<u8>> {
+        let mut reader = Cursor::new(encoded);
+        let header = decode_header(&mut reader)?;
+        let mut decoded = Vec::new();
+        registry.decode_stream(&mut reader, &mut decoded, header, &DecoderOptions::default())?;
+        Ok(decoded)
+    }
+
+    // Hands everything to the table method, counting the calls
+    #[derive(Default)]
+    struct Counting {
+        encoded: AtomicU32,
+        decoded: AtomicU32,
+    }
+
+    impl CompressionMethod for Counting {
+        fn id(&self) -> u8 {
+            METHOD_TABLE
+        }
+
+        fn name(&self) -> &'static str {
+            "counting"
+        }
+
+        fn encode(&self, input: &mut dyn Read, output: &mut dyn Write, options: &EncoderOptions) -> HuffmanResult<EncodeReport> {
+            self.encoded.fetch_add(1, Ordering::Relaxed);
+            TableMethod.encode(input, output, options)
+        }
+
+        fn decode(&self, input: &mut dyn BufRead, output: &mut dyn Write, stream: &mut Decoding) -> HuffmanResult<(u64, u64)> {
+            self.decoded.fetch_add(1, Ordering::Relaxed);
+            TableMethod.decode(input, output, stream)
+        }
+    }
+
+    #[test]
+    fn test_every_method_roundtrips() {
+        let registry = Registry::default();
+        let data = text_data(&mut Rng(0x3E7), 5000);
+        let options = [
+            EncoderOptions::default(),
+            EncoderOptions::builder().adaptive(true).build().unwrap(),
+            EncoderOptions::builder().method(Method::Range).build().unwrap(),
+        ];
+        for (options, id) in options.iter().zip([METHOD_TABLE, METHOD_ADAPTIVE, METHOD_RANGE]) {
+            let encoded = encoded_with(&registry, &data, options);
+            let header = decode_header(&mut Cursor::new(&encoded)).unwrap();
+            assert_eq!(header.method(), id, "{}", registry.get(id).unwrap().name());
+            assert!(decoded_with(&registry, &encoded).unwrap() == data, "{}", registry.get(id).unwrap().name());
+        }
+    }
+
+    #[test]
+    fn test_streams_go_to_the_registered_method() {
+        let counting = Arc::new(Counting::default());
+        let mut registry = Registry::default();
+        registry.register(counting.clone());
+        assert_eq!(registry.get(METHOD_TABLE).unwrap().name(), "counting");
+
+        // Three blocks, each a stream of its own
+        let data = text_data(&mut Rng(0x3E8), 3000);
+        let options = EncoderOptions::builder().block_size(1000).build().unwrap();
+        let encoded = encoded_with(&registry, &data, &options);
+        assert_eq!(counting.encoded.load(Ordering::Relaxed), 1);
+        assert!(decoded_with(&registry, &encoded).unwrap() == data);
+        assert_eq!(counting.decoded.load(Ordering::Relaxed), 3);
+
+        // The other methods are still the shipped ones
+        let adaptive = EncoderOptions::builder().adaptive(true).build().unwrap();
+        assert!(decoded_with(&registry, &encoded_with(&registry, &data, &adaptive)).unwrap() == data);
+        assert_eq!(counting.decoded.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn test_unsupported_method() {
+        let data = text_data(&mut Rng(0x3E9), 1000);
+        let range = EncoderOptions::builder().method(Method::Range).build().unwrap();
+        let encoded = encoded_with(&Registry::default(), &data, &range);
+
+        let mut registry = Registry::default();
+        assert_eq!(registry.remove(METHOD_RANGE).unwrap().name(), "range");
+        let error = decoded_with(&registry, &encoded).unwrap_err();
+        assert!(matches!(error, HuffmanError::UnsupportedMethod(METHOD_RANGE)));
+        assert_eq!(error.to_string(), "encoded with an unsupported method (64)");
+        let mut encoded_too = Vec::new();
+        let error = registry.encode_stream(&data[..], &mut encoded_too, &range).unwrap_err();
+        assert!(matches!(error, HuffmanError::UnsupportedMethod(METHOD_RANGE)));
+        assert!(matches!(decoded_with(&Registry::empty(), &encoded).unwrap_err(), HuffmanError::UnsupportedMethod(_)));
+    }
+}
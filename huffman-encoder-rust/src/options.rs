@@ -0,0 +1,614 @@
+// Options of the library entry points, built with a builder that checks them:
+//
+//     let options = EncoderOptions::builder().block_size(1 << 20).build()?;
+//     let encoded = encode_bytes_with(data, &options)?;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+
+use crate::bwt::MAX_BLOCK_SIZE as MAX_BWT_BLOCK_SIZE;
+use crate::codec::DEFAULT_BUFFER_SIZE;
+use crate::cancel::CancelToken;
+use crate::checksum::ChecksumKind;
+use crate::error::{HuffmanError, HuffmanResult};
+use crate::header::FileInfo;
+use crate::progress::{ProgressSink, SharedSink};
+use crate::table::EncodingTable;
+
+// Range of the size of the chunks input is read and output written in
+pub(crate) const MIN_BUFFER_SIZE: usize = 4 * 1024;
+pub(crate) const MAX_BUFFER_SIZE: usize = 64 * 1024 * 1024;
+
+fn check_buffer_size(buffer_size: usize) -> HuffmanResult<()> {
+    if !(MIN_BUFFER_SIZE..=MAX_BUFFER_SIZE).contains(&buffer_size) {
+        return Err(HuffmanError::Usage(
+            format!("buffer size of {} bytes, expected {} to {}", buffer_size, MIN_BUFFER_SIZE, MAX_BUFFER_SIZE),
+        ));
+    }
+    Ok(())
+}
+
+// Encoder
+////////////////////////////////////////////////////////////////////////////////
+
+// The block size of --bwt without --block-size, the largest block of bzip2
+pub(crate) const DEFAULT_BWT_BLOCK_SIZE: u64 = 900 * 1000;
+
+// How the bytes of a block are coded, like --method
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Method {
+    // A code of whole bits for every byte, from a table in the header
+    #[default]
+    Huffman,
+    // Range coding with the counts of the bytes in the header, see range.rs
+    Range,
+}
+
+// What every byte is replaced with before the codes, like --filter, see
+// filter.rs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Filter {
+    // Its difference from the byte a stride before
+    Delta,
+    // The two XORed
+    XorPrev,
+}
+
+// The defaults give exactly the output of `huffman encode`
+#[derive(Clone, Debug, PartialEq)]
+pub struct EncoderOptions {
+    // None encodes everything in one stream
+    pub(crate) block_size: Option<u64>,
+    // None picks the best table for every block
+    pub(crate) encoding_table: Option<EncodingTable>,
+    // Adaptive streams instead of ones with a table, see adaptive.rs
+    pub(crate) adaptive: bool,
+    // Run-length encode every block before its codes, see rle.rs
+    pub(crate) rle: bool,
+    // Bytes per symbol the codes are of, 1 or 2
+    pub(crate) symbol_width: u8,
+    // Codes of words and other tokens, see tokens.rs
+    pub(crate) tokens: bool,
+    // The Burrows-Wheeler transform and move-to-front first, see bwt.rs
+    pub(crate) bwt: bool,
+    // A table for every previous byte, see context.rs
+    pub(crate) context: bool,
+    pub(crate) method: Method,
+    // A filter before everything else and the bytes per sample it works on
+    pub(crate) filter: Option<Filter>,
+    pub(crate) stride: u8,
+    pub(crate) checksum: ChecksumKind,
+    // The name and modification time of the input, in the first stream
+    pub(crate) file_info: FileInfo,
+    pub(crate) buffer_size: usize,
+    pub(crate) progress: SharedSink,
+    pub(crate) cancel: Option<CancelToken>,
+}
+
+impl EncoderOptions {
+    pub fn builder() -> EncoderOptionsBuilder {
+        EncoderOptionsBuilder { options: EncoderOptions::default() }
+    }
+
+    pub fn block_size(&self) -> Option<u64> {
+        self.block_size
+    }
+
+    pub fn encoding_table(&self) -> Option<&EncodingTable> {
+        self.encoding_table.as_ref()
+    }
+
+    pub fn adaptive(&self) -> bool {
+        self.adaptive
+    }
+
+    pub fn rle(&self) -> bool {
+        self.rle
+    }
+
+    pub fn symbol_width(&self) -> u8 {
+        self.symbol_width
+    }
+
+    pub fn tokens(&self) -> bool {
+        self.tokens
+    }
+
+    pub fn bwt(&self) -> bool {
+        self.bwt
+    }
+
+    pub fn context(&self) -> bool {
+        self.context
+    }
+
+    pub fn method(&self) -> Method {
+        self.method
+    }
+
+    pub fn filter(&self) -> Option<Filter> {
+        self.filter
+    }
+
+    pub fn stride(&self) -> u8 {
+        self.stride
+    }
+
+    pub fn checksum(&self) -> ChecksumKind {
+        self.checksum
+    }
+
+    pub fn file_info(&self) -> &FileInfo {
+        &self.file_info
+    }
+
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    pub fn progress(&self) -> &dyn ProgressSink {
+        self.progress.get()
+    }
+
+    pub fn cancel(&self) -> Option<&CancelToken> {
+        self.cancel.as_ref()
+    }
+}
+
+impl Default for EncoderOptions {
+    fn default() -> Self {
+        EncoderOptions {
+            block_size: None,
+            encoding_table: None,
+            adaptive: false,
+            rle: false,
+            symbol_width: 1,
+            tokens: false,
+            bwt: false,
+            context: false,
+            method: Method::Huffman,
+            filter: None,
+            stride: 1,
+            checksum: ChecksumKind::Crc32,
+            file_info: FileInfo::default(),
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            progress: SharedSink::default(),
+            cancel: None,
+        }
+    }
+}
+
+pub struct EncoderOptionsBuilder {
+    options: EncoderOptions,
+}
+
+impl EncoderOptionsBuilder {
+    // Encodes the input in independent blocks of this many bytes, like
+    // --block-size
+    pub fn block_size(mut self, block_size: u64) -> Self {
+        self.options.block_size = Some(block_size);
+        self
+    }
+
+    // Encodes every block with this table instead of counting the input.
+    // Encoding fails on a byte that has no code in it.
+    pub fn encoding_table(mut self, encoding_table: EncodingTable) -> Self {
+        self.options.encoding_table = Some(encoding_table);
+        self
+    }
+
+    // Encodes in one pass with codes that adapt to the data seen so far, like
+    // --adaptive. Nothing is counted and there is no table in the header.
+    pub fn adaptive(mut self, adaptive: bool) -> Self {
+        self.options.adaptive = adaptive;
+        self
+    }
+
+    // Run-length encodes every block first and the codes are of the result,
+    // like --rle, for data with long runs of a byte. The table is picked for
+    // it, so none can be given.
+    pub fn rle(mut self, rle: bool) -> Self {
+        self.options.rle = rle;
+        self
+    }
+
+    // With 2 the codes are of pairs of bytes, like --symbol-width 2, for data
+    // that comes in 2 byte units like UTF-16 text or 16-bit audio. The table is
+    // picked for the pairs, so none can be given.
+    pub fn symbol_width(mut self, symbol_width: u8) -> Self {
+        self.options.symbol_width = symbol_width;
+        self
+    }
+
+    // Codes whole words, runs of whitespace and the bytes in between like
+    // --tokens, for text. Each block gets a dictionary of its own and the table
+    // is picked for its tokens, so none can be given.
+    pub fn tokens(mut self, tokens: bool) -> Self {
+        self.options.tokens = tokens;
+        self
+    }
+
+    // Applies the Burrows-Wheeler transform and move-to-front to every block
+    // before the codes, like --bwt, and with rle(true) run-length encodes the
+    // result. The transform sorts the whole block, so without a block size
+    // the blocks are DEFAULT_BWT_BLOCK_SIZE bytes.
+    pub fn bwt(mut self, bwt: bool) -> Self {
+        self.options.bwt = bwt;
+        self
+    }
+
+    // Codes every byte with a table for the byte before it, like --context,
+    // for text. The tables are picked for each block, so none can be given.
+    pub fn context(mut self, context: bool) -> Self {
+        self.options.context = context;
+        self
+    }
+
+    // With Method::Range every block is range coded instead, like --method
+    // range, which takes fractions of a bit for the most frequent bytes. The
+    // counts are of each block, so no table can be given.
+    pub fn method(mut self, method: Method) -> Self {
+        self.options.method = method;
+        self
+    }
+
+    // Replaces every byte of a block with its difference from the byte
+    // `stride` bytes before, or with the two XORed, before anything else, like
+    // --filter, for audio, images and other data of samples that vary
+    // smoothly. The stride is 1 unless given.
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.options.filter = Some(filter);
+        self
+    }
+
+    // The bytes per sample of the filter, like --stride, 2 for 16-bit audio
+    pub fn stride(mut self, stride: u8) -> Self {
+        self.options.stride = stride;
+        self
+    }
+
+    // The checksum of every stream, like --checksum: CRC-32 unless given.
+    // Streams with another one are version 5, which decoders before it can't
+    // read, and with ChecksumKind::None decoding can't tell if they are intact.
+    pub fn checksum(mut self, checksum: ChecksumKind) -> Self {
+        self.options.checksum = checksum;
+        self
+    }
+
+    // The name and the modification time the first stream keeps of the input,
+    // like --stdin-name and --stdin-mtime, and the id of the table file it is
+    // encoded with. That stream is version 6.
+    pub fn file_info(mut self, file_info: FileInfo) -> Self {
+        self.options.file_info = file_info;
+        self
+    }
+
+    pub fn buffer_size(mut self, buffer_size: usize) -> Self {
+        self.options.buffer_size = buffer_size;
+        self
+    }
+
+    // Tells `progress` how far counting and encoding got, see ProgressSink
+    pub fn progress(mut self, progress: Arc<dyn ProgressSink>) -> Self {
+        self.options.progress = SharedSink(Some(progress));
+        self
+    }
+
+    // Encoding fails with Cancelled soon after `cancel` is cancelled
+    pub fn cancel(mut self, cancel: CancelToken) -> Self {
+        self.options.cancel = Some(cancel);
+        self
+    }
+
+    pub fn build(self) -> HuffmanResult<EncoderOptions> {
+        if self.options.block_size == Some(0) {
+            return Err(HuffmanError::Usage(String::from("block size of 0 bytes, expected at least 1")));
+        }
+        if self.options.adaptive && self.options.rle {
+            return Err(HuffmanError::Usage(String::from("adaptive streams can't be run-length encoded")));
+        }
+        match self.options.symbol_width {
+            1 => {}
+            2 if self.options.adaptive || self.options.rle => {
+                return Err(HuffmanError::Usage(String::from("pairs of bytes can't be coded adaptively or run-length encoded")));
+            }
+            2 => {}
+            width => return Err(HuffmanError::Usage(format!("symbol width of {} bytes, expected 1 or 2", width))),
+        }
+        if self.options.bwt && (self.options.adaptive || self.options.symbol_width != 1 || self.options.tokens) {
+            return Err(HuffmanError::Usage(String::from(
+                "Burrows-Wheeler transformed blocks can't be coded adaptively, in pairs of bytes or in tokens",
+            )));
+        }
+        if self.options.tokens && (self.options.adaptive || self.options.rle || self.options.symbol_width != 1) {
+            return Err(HuffmanError::Usage(String::from(
+                "tokens can't be coded adaptively, run-length encoded or in pairs of bytes",
+            )));
+        }
+        let own_symbols = self.options.rle || self.options.symbol_width != 1 || self.options.tokens || self.options.bwt;
+        if self.options.context && (self.options.adaptive || own_symbols) {
+            return Err(HuffmanError::Usage(String::from(
+                "tables per context can't go with adaptive codes, run-length encoding, pairs of bytes, tokens or the Burrows-Wheeler transform",
+            )));
+        }
+        if self.options.method == Method::Range && (self.options.adaptive || own_symbols || self.options.context) {
+            return Err(HuffmanError::Usage(String::from(
+                "range coding can't go with adaptive codes, run-length encoding, pairs of bytes, tokens, the Burrows-Wheeler transform or tables per context",
+            )));
+        }
+        match self.options.filter {
+            _ if self.options.stride == 0 => {
+                return Err(HuffmanError::Usage(String::from("stride of 0 bytes, expected at least 1")));
+            }
+            None if self.options.stride != 1 => {
+                return Err(HuffmanError::Usage(String::from("a stride needs a filter")));
+            }
+            Some(_) if self.options.adaptive || self.options.bwt => {
+                return Err(HuffmanError::Usage(String::from(
+                    "filters can't go with adaptive codes or the Burrows-Wheeler transform",
+                )));
+            }
+            _ => {}
+        }
+        if let Some(encoding_table) = &self.options.encoding_table {
+            if self.options.adaptive {
+                return Err(HuffmanError::Usage(String::from("adaptive streams have no table, it can't be given")));
+            }
+            if self.options.rle {
+                return Err(HuffmanError::Usage(String::from("run-length encoded streams need a table of their own")));
+            }
+            if self.options.symbol_width != 1 {
+                return Err(HuffmanError::Usage(String::from("streams of pairs of bytes need a table of their own")));
+            }
+            if self.options.tokens {
+                return Err(HuffmanError::Usage(String::from("streams of tokens need a table of their own")));
+            }
+            if self.options.bwt {
+                return Err(HuffmanError::Usage(String::from("Burrows-Wheeler transformed streams need a table of their own")));
+            }
+            if self.options.context {
+                return Err(HuffmanError::Usage(String::from("streams with tables per context need tables of their own")));
+            }
+            if self.options.method == Method::Range {
+                return Err(HuffmanError::Usage(String::from("range coded streams have no table, it can't be given")));
+            }
+            if self.options.filter.is_some() {
+                return Err(HuffmanError::Usage(String::from("filtered streams need a table of their own")));
+            }
+            encoding_table.validate()?;
+        }
+        if self.options.file_info.name.as_ref().is_some_and(|name| name.len() > u16::MAX as usize) {
+            return Err(HuffmanError::Usage(format!("a name of at most {} bytes", u16::MAX)));
+        }
+        check_buffer_size(self.options.buffer_size)?;
+        let mut options = self.options;
+        if options.bwt {
+            let block_size = *options.block_size.get_or_insert(DEFAULT_BWT_BLOCK_SIZE);
+            if block_size > MAX_BWT_BLOCK_SIZE {
+                return Err(HuffmanError::Usage(
+                    format!("Burrows-Wheeler transformed blocks of {} bytes, expected at most {}", block_size, MAX_BWT_BLOCK_SIZE),
+                ));
+            }
+        }
+        Ok(options)
+    }
+}
+
+// Decoder
+////////////////////////////////////////////////////////////////////////////////
+
+// The defaults decode like `huffman decode --threads 1 --max-output-size none`
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecoderOptions {
+    pub(crate) max_output_size: Option<u64>,
+    pub(crate) verify_checksum: bool,
+    pub(crate) threads: usize,
+    pub(crate) buffer_size: usize,
+    pub(crate) progress: SharedSink,
+    pub(crate) cancel: Option<CancelToken>,
+}
+
+impl DecoderOptions {
+    pub fn builder() -> DecoderOptionsBuilder {
+        DecoderOptionsBuilder { options: DecoderOptions::default() }
+    }
+
+    pub fn max_output_size(&self) -> Option<u64> {
+        self.max_output_size
+    }
+
+    pub fn verify_checksum(&self) -> bool {
+        self.verify_checksum
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    pub fn progress(&self) -> &dyn ProgressSink {
+        self.progress.get()
+    }
+
+    pub fn cancel(&self) -> Option<&CancelToken> {
+        self.cancel.as_ref()
+    }
+}
+
+impl Default for DecoderOptions {
+    fn default() -> Self {
+        DecoderOptions {
+            max_output_size: None,
+            verify_checksum: true,
+            threads: 1,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            progress: SharedSink::default(),
+            cancel: None,
+        }
+    }
+}
+
+pub struct DecoderOptionsBuilder {
+    options: DecoderOptions,
+}
+
+impl DecoderOptionsBuilder {
+    // Fails with OutputTooLarge once the output grows past this, None for no
+    // limit
+    pub fn max_output_size(mut self, max_output_size: Option<u64>) -> Self {
+        self.options.max_output_size = max_output_size;
+        self
+    }
+
+    // Without the check a stream with a wrong checksum decodes anyway, and the
+    // CRC isn't computed at all. Everything else is still checked.
+    pub fn verify_checksum(mut self, verify_checksum: bool) -> Self {
+        self.options.verify_checksum = verify_checksum;
+        self
+    }
+
+    // Blocks of up to 32 MiB are decoded on this many threads
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.options.threads = threads;
+        self
+    }
+
+    pub fn buffer_size(mut self, buffer_size: usize) -> Self {
+        self.options.buffer_size = buffer_size;
+        self
+    }
+
+    // Tells `progress` how far decoding got, see ProgressSink
+    pub fn progress(mut self, progress: Arc<dyn ProgressSink>) -> Self {
+        self.options.progress = SharedSink(Some(progress));
+        self
+    }
+
+    // Decoding fails with Cancelled soon after `cancel` is cancelled
+    pub fn cancel(mut self, cancel: CancelToken) -> Self {
+        self.options.cancel = Some(cancel);
+        self
+    }
+
+    pub fn build(self) -> HuffmanResult<DecoderOptions> {
+        if self.options.threads == 0 {
+            return Err(HuffmanError::Usage(String::from("0 threads, expected at least 1")));
+        }
+        check_buffer_size(self.options.buffer_size)?;
+        Ok(self.options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::table::Code;
+
+    #[test]
+    fn test_default_options() {
+        let encoder = EncoderOptions::default();
+        assert_eq!(encoder.block_size(), None);
+        assert!(encoder.encoding_table().is_none());
+        assert!(!encoder.adaptive());
+        assert!(!encoder.rle());
+        assert_eq!(encoder.buffer_size(), DEFAULT_BUFFER_SIZE);
+        assert_eq!(EncoderOptions::builder().build().unwrap(), encoder);
+
+        let decoder = DecoderOptions::default();
+        assert_eq!(decoder.max_output_size(), None);
+        assert!(decoder.verify_checksum());
+        assert_eq!(decoder.threads(), 1);
+        assert_eq!(decoder.buffer_size(), DEFAULT_BUFFER_SIZE);
+        assert_eq!(DecoderOptions::builder().build().unwrap(), decoder);
+    }
+
+    #[test]
+    fn test_builders_chain() {
+        let encoding_table: EncodingTable = [(b'a', Code { bits: 0, length: 1 })].into_iter().collect();
+        let encoder = EncoderOptions::builder()
+            .block_size(1000)
+            .encoding_table(encoding_table.clone())
+            .buffer_size(8192)
+            // The last call wins
+            .block_size(4096)
+            .build()
+            .unwrap();
+        assert_eq!(encoder.block_size(), Some(4096));
+        assert_eq!(encoder.encoding_table(), Some(&encoding_table));
+        assert_eq!(encoder.buffer_size(), 8192);
+        // Transformed blocks are as large as bzip2's unless given
+        assert_eq!(EncoderOptions::builder().bwt(true).build().unwrap().block_size(), Some(DEFAULT_BWT_BLOCK_SIZE));
+
+        let decoder = DecoderOptions::builder()
+            .max_output_size(Some(100))
+            .verify_checksum(false)
+            .threads(4)
+            .buffer_size(MAX_BUFFER_SIZE)
+            .build()
+            .unwrap();
+        assert_eq!(decoder.max_output_size(), Some(100));
+        assert!(!decoder.verify_checksum());
+        assert_eq!(decoder.threads(), 4);
+        assert_eq!(decoder.buffer_size(), MAX_BUFFER_SIZE);
+    }
+
+    #[test]
+    fn test_builders_reject_invalid_options() {
+        let usage = |result: HuffmanResult<()>| match result {
+            Err(HuffmanError::Usage(message)) => message,
+            _ => panic!("expected a usage error"),
+        };
+        let encoder = |builder: EncoderOptionsBuilder| builder.build().map(|_| ());
+        let decoder = |builder: DecoderOptionsBuilder| builder.build().map(|_| ());
+
+        assert_eq!(usage(encoder(EncoderOptions::builder().block_size(0))), "block size of 0 bytes, expected at least 1");
+        assert_eq!(
+            usage(encoder(EncoderOptions::builder().buffer_size(100))),
+            "buffer size of 100 bytes, expected 4096 to 67108864",
+        );
+        assert!(usage(encoder(EncoderOptions::builder().buffer_size(MAX_BUFFER_SIZE + 1))).starts_with("buffer size"));
+        assert_eq!(usage(decoder(DecoderOptions::builder().threads(0))), "0 threads, expected at least 1");
+        assert!(usage(decoder(DecoderOptions::builder().buffer_size(0))).starts_with("buffer size"));
+        let table: EncodingTable = [(b'a', Code { bits: 0, length: 1 })].into_iter().collect();
+        let adaptive = EncoderOptions::builder().adaptive(true).encoding_table(table.clone());
+        assert_eq!(usage(encoder(adaptive)), "adaptive streams have no table, it can't be given");
+        let range = EncoderOptions::builder().method(Method::Range).encoding_table(table.clone());
+        assert_eq!(usage(encoder(range)), "range coded streams have no table, it can't be given");
+        let rle = EncoderOptions::builder().rle(true).encoding_table(table);
+        assert_eq!(usage(encoder(rle)), "run-length encoded streams need a table of their own");
+        let both = EncoderOptions::builder().rle(true).adaptive(true);
+        assert_eq!(usage(encoder(both)), "adaptive streams can't be run-length encoded");
+        let pairs = EncoderOptions::builder().tokens(true).symbol_width(2);
+        assert_eq!(usage(encoder(pairs)), "tokens can't be coded adaptively, run-length encoded or in pairs of bytes");
+        let bwt = EncoderOptions::builder().bwt(true).adaptive(true);
+        assert_eq!(
+            usage(encoder(bwt)),
+            "Burrows-Wheeler transformed blocks can't be coded adaptively, in pairs of bytes or in tokens",
+        );
+        let too_large = EncoderOptions::builder().bwt(true).block_size(1 << 32);
+        assert_eq!(usage(encoder(too_large)), "Burrows-Wheeler transformed blocks of 4294967296 bytes, expected at most 4294967295");
+        let context = EncoderOptions::builder().context(true).rle(true);
+        assert_eq!(
+            usage(encoder(context)),
+            "tables per context can't go with adaptive codes, run-length encoding, pairs of bytes, tokens or the Burrows-Wheeler transform",
+        );
+        let range = EncoderOptions::builder().method(Method::Range).tokens(true);
+        assert!(usage(encoder(range)).starts_with("range coding can't go with adaptive codes"));
+        assert_eq!(usage(encoder(EncoderOptions::builder().stride(2))), "a stride needs a filter");
+        assert_eq!(usage(encoder(EncoderOptions::builder().filter(Filter::Delta).stride(0))), "stride of 0 bytes, expected at least 1");
+        let filter = EncoderOptions::builder().filter(Filter::XorPrev).bwt(true);
+        assert_eq!(usage(encoder(filter)), "filters can't go with adaptive codes or the Burrows-Wheeler transform");
+
+        // A table that can't be decoded fails before anything is encoded
+        let overlapping: EncodingTable = [
+            (b'a', Code { bits: 0, length: 1 }),
+            (b'b', Code { bits: 0, length: 2 }),
+        ].into_iter().collect();
+        let error = EncoderOptions::builder().encoding_table(overlapping).build().unwrap_err();
+        assert!(matches!(error, HuffmanError::InvalidTable(_)));
+    }
+}
@@ -0,0 +1,275 @@
+// Writing the output next to its final place, and the checks before it
+
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Result as IoResult, Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::error::{HuffmanError, HuffmanResult};
+
+// Whether both paths point at the same file on disk, following symlinks. A
+// missing output can't be the input, so that is never considered the same.
+fn is_same_file(a: &Path, b: &Path) -> IoResult<bool> {
+    let (a_meta, b_meta) = match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a_meta), Ok(b_meta)) => (a_meta, b_meta),
+        _ => return Ok(false),
+    };
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        Ok(a_meta.dev() == b_meta.dev() && a_meta.ino() == b_meta.ino())
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = (a_meta, b_meta);
+        Ok(fs::canonicalize(a)? == fs::canonicalize(b)?)
+    }
+}
+
+// Creating the output truncates it, so writing over the input would destroy the
+// data before it was ever read.
+pub(crate) fn ensure_distinct_output(input: &Path, output: &Path) -> HuffmanResult<()> {
+    if is_same_file(input, output)? {
+        return Err(HuffmanError::Usage(format!(
+            "input and output are the same file: '{}' and '{}'",
+            input.display(), output.display()
+        )));
+    }
+    Ok(())
+}
+
+// The output is written to a temporary file next to it and only renamed into
+// place by commit(), so a failure halfway never leaves a broken output behind
+// or clobbers an existing one. Dropping it without commit removes the file.
+pub(crate) struct OutputFile {
+    pub(crate) file: File,
+    temp_filename: PathBuf,
+    filename: PathBuf,
+    committed: bool,
+    #[cfg(not(unix))]
+    readonly: bool,
+    // Declared last so it is released after the temporary file is cleaned up
+    _lock: OutputLock,
+}
+
+// Advisory lock on `.<name>.lock` next to the output, held while the output is
+// being written, so two processes writing the same output can't interleave.
+struct OutputLock {
+    file: File,
+    filename: PathBuf,
+}
+
+impl OutputLock {
+    fn acquire(filename: PathBuf) -> IoResult<Self> {
+        loop {
+            let file = OpenOptions::new().write(true).create(true).truncate(false).open(&filename)?;
+            match file.try_lock() {
+                Ok(()) => {},
+                Err(fs::TryLockError::WouldBlock) => {
+                    return Err(Error::new(
+                        ErrorKind::WouldBlock,
+                        "output is being written by another process",
+                    ));
+                }
+                Err(fs::TryLockError::Error(e)) => return Err(e),
+            }
+
+            // The previous holder removes the lock file before unlocking it.
+            // If that happened after we opened it, we locked a file nobody
+            // else will see, so try again with a fresh one.
+            if Self::is_current(&file, &filename) {
+                return Ok(OutputLock { file, filename });
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    fn is_current(file: &File, filename: &Path) -> bool {
+        use std::os::unix::fs::MetadataExt;
+        match (file.metadata(), fs::metadata(filename)) {
+            (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+            _ => false,
+        }
+    }
+
+    // Open files can't be removed on Windows, so the lock file never changes
+    #[cfg(not(unix))]
+    fn is_current(_file: &File, _filename: &Path) -> bool {
+        true
+    }
+}
+
+impl Drop for OutputLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.filename);
+        let _ = self.file.unlock();
+    }
+}
+
+// Hidden file next to `filename`, like `.notes.txt.<suffix>`
+pub(crate) fn sibling_filename(filename: &Path, suffix: &str) -> PathBuf {
+    let name = filename.file_name().unwrap_or(filename.as_os_str());
+    let mut sibling_name = OsString::from(".");
+    sibling_name.push(name);
+    sibling_name.push(suffix);
+    filename.with_file_name(sibling_name)
+}
+
+impl OutputFile {
+    // Creates the file with the permissions of the input, so encoding a
+    // private file doesn't produce a world readable copy. On Unix the mode is
+    // set before any data is written.
+    pub(crate) fn create(filename: &Path, permissions: &fs::Permissions) -> IoResult<Self> {
+        let lock = OutputLock::acquire(sibling_filename(filename, ".lock"))?;
+
+        // Unique per process and per output, in case a lock file was removed
+        // by hand while we're writing
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let temp_filename = sibling_filename(filename, &format!(".{}-{}.tmp", std::process::id(), id));
+
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+
+        #[cfg(unix)]
+        let file = {
+            use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+            // Never more permissive than the input, even before the chmod below
+            // undoes the umask. Setuid and friends are not carried over.
+            let mode = permissions.mode() & 0o777;
+            let file = options.mode(mode).open(&temp_filename)?;
+            file.set_permissions(fs::Permissions::from_mode(mode))?;
+            file
+        };
+
+        #[cfg(not(unix))]
+        let file = options.open(&temp_filename)?;
+
+        Ok(OutputFile {
+            file,
+            temp_filename,
+            filename: filename.to_path_buf(),
+            committed: false,
+            // Applied after the rename, a read-only file can't be written to
+            #[cfg(not(unix))]
+            readonly: permissions.readonly(),
+            _lock: lock,
+        })
+    }
+
+    // Where the output is until commit, to read it back before it replaces
+    // anything
+    pub(crate) fn temp_filename(&self) -> &Path {
+        &self.temp_filename
+    }
+
+    // With fsync the data is synced before the rename, and the directory after
+    // it, so once this returns the renamed file and its contents are on disk.
+    pub(crate) fn commit(mut self, fsync: bool) -> IoResult<()> {
+        self.file.flush()?;
+        if fsync {
+            self.file.sync_all()?;
+        }
+        fs::rename(&self.temp_filename, &self.filename)?;
+        self.committed = true;
+
+        #[cfg(not(unix))]
+        if self.readonly {
+            let mut permissions = fs::metadata(&self.filename)?.permissions();
+            permissions.set_readonly(true);
+            fs::set_permissions(&self.filename, permissions)?;
+        }
+
+        if fsync {
+            sync_parent(&self.filename)?;
+        }
+        Ok(())
+    }
+}
+
+// Makes sure the entry of `filename` in its directory is on disk, like after
+// renaming or removing it. Windows can't open a directory for that.
+pub(crate) fn sync_parent(filename: &Path) -> IoResult<()> {
+    #[cfg(unix)]
+    {
+        let dir = match filename.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        File::open(dir)?.sync_all()?;
+    }
+    #[cfg(not(unix))]
+    let _ = filename;
+    Ok(())
+}
+
+impl Drop for OutputFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.temp_filename);
+        }
+    }
+}
+
+// Reserves `length` bytes on disk for `file`, so running out of space fails
+// right away instead of partway through decoding. Where that isn't possible
+// the file is only resized, which still keeps it in one piece on most file
+// systems.
+pub(crate) fn preallocate(file: &File, length: u64) -> IoResult<()> {
+    #[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+    {
+        use std::os::unix::io::AsRawFd;
+        extern "C" {
+            fn posix_fallocate(fd: i32, offset: i64, len: i64) -> i32;
+        }
+        let len = i64::try_from(length).map_err(|_| Error::from(ErrorKind::FileTooLarge))?;
+        // Returns the error instead of setting errno. The descriptor stays open
+        // for the duration of the call, as the file is borrowed.
+        match unsafe { posix_fallocate(file.as_raw_fd(), 0, len) } {
+            0 => return Ok(()),
+            // Not supported by the file system, fall back to resizing
+            22 | 95 => {} // EINVAL, EOPNOTSUPP
+            code => return Err(Error::from_raw_os_error(code)),
+        }
+    }
+    file.set_len(length)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{temp_path, MIB};
+    use std::io::{Seek, Write};
+
+    #[cfg(unix)]
+    #[test]
+    fn test_output_file_has_input_permissions_before_writing() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = temp_path("permissions");
+        let output = OutputFile::create(&path, &fs::Permissions::from_mode(0o600)).unwrap();
+        let mode = output.file.metadata().unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(output.file.metadata().unwrap().len(), 0);
+
+        output.commit(false).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_preallocate_sizes_the_file() {
+        let path = temp_path("preallocated");
+        let mut file = File::create(&path).unwrap();
+        preallocate(&file, 3 * MIB as u64).unwrap();
+        assert_eq!(file.metadata().unwrap().len(), 3 * MIB as u64);
+        // Writing starts at the beginning, like decoding does
+        file.write_all(b"decoded").unwrap();
+        assert_eq!(file.stream_position().unwrap(), 7);
+        assert_eq!(file.metadata().unwrap().len(), 3 * MIB as u64);
+        fs::remove_file(&path).unwrap();
+    }
+}
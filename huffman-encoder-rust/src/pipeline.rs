@@ -0,0 +1,200 @@
+// Reading the input ahead on a thread of its own
+
+use std::io::{Read, Result as IoResult, ErrorKind};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+
+// Blocks from this size on are read on a thread of their own while they are
+// encoded, so the disk doesn't sit idle while codes are written
+pub(crate) const PIPELINE_THRESHOLD: u64 = 16 * 1024 * 1024;
+pub(crate) const PIPELINE_BUFFER_SIZE: usize = 1024 * 1024;
+// Buffers going back and forth between the threads
+const PIPELINE_BUFFERS: usize = 4;
+
+// Reads its input ahead on a scoped thread, into buffers that are handed
+// back to be filled again once they're read. Dropping it stops the thread, a
+// read error on the thread is returned by the next read.
+pub(crate) struct PipelinedReader {
+    filled: Receiver<IoResult<Vec<u8>>>,
+    empty: SyncSender<Vec<u8>>,
+    current: Option<Vec<u8>>,
+    position: usize,
+    done: bool,
+}
+
+impl PipelinedReader {
+    pub(crate) fn spawn<'scope>(
+        scope: &'scope std::thread::Scope<'scope, '_>,
+        mut input: impl Read + Send + 'scope,
+        buffer_size: usize,
+    ) -> Self {
+        let (filled_sender, filled) = sync_channel(PIPELINE_BUFFERS);
+        let (empty, empty_receiver) = sync_channel::<Vec<u8>>(PIPELINE_BUFFERS);
+        for _ in 0..PIPELINE_BUFFERS {
+            empty.send(vec![0u8; buffer_size]).expect("the receiver is still here");
+        }
+
+        // Every buffer is in one of the channels or being used, so neither
+        // side ever blocks on a full channel. Either side hanging up ends it.
+        scope.spawn(move || {
+            while let Ok(mut buffer) = empty_receiver.recv() {
+                buffer.resize(buffer_size, 0);
+                let mut length = 0;
+                while length < buffer_size {
+                    match input.read(&mut buffer[length..]) {
+                        Ok(0) => break,
+                        Ok(read) => length += read,
+                        Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                        Err(e) => {
+                            let _ = filled_sender.send(Err(e));
+                            return;
+                        }
+                    }
+                }
+                if length == 0 {
+                    return; // EOF
+                }
+                buffer.truncate(length);
+                if filled_sender.send(Ok(buffer)).is_err() {
+                    return;
+                }
+            }
+        });
+
+        PipelinedReader { filled, empty, current: None, position: 0, done: false }
+    }
+}
+
+impl Read for PipelinedReader {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        loop {
+            if let Some(current) = &self.current {
+                if self.position < current.len() {
+                    let length = buf.len().min(current.len() - self.position);
+                    buf[..length].copy_from_slice(&current[self.position..self.position + length]);
+                    self.position += length;
+                    return Ok(length);
+                }
+            }
+            if self.done {
+                return Ok(0);
+            }
+
+            match self.filled.recv() {
+                Ok(Ok(buffer)) => {
+                    if let Some(used) = self.current.replace(buffer) {
+                        let _ = self.empty.send(used);
+                    }
+                    self.position = 0;
+                }
+                Ok(Err(e)) => {
+                    self.done = true;
+                    return Err(e);
+                }
+                // The thread is done, there's nothing more to read
+                Err(_) => self.done = true,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{encode_block, DEFAULT_BUFFER_SIZE};
+    use crate::frequency::calculate_frequencies;
+    use crate::table::build_encoding_table;
+    use crate::test_util::{bench, report_throughput, temp_path, text_data, Rng, MIB};
+    use crate::tree::build_huffman_tree;
+    use std::fs::{self, File};
+    use std::io::Error;
+
+    // Returns `data`, then fails
+    struct FailingReader<'a> {
+        data: &'a [u8],
+    }
+
+    impl Read for FailingReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+            if self.data.is_empty() {
+                return Err(Error::other("disk on fire"));
+            }
+            self.data.read(buf)
+        }
+    }
+
+    #[test]
+    fn test_pipelined_reader_reads_everything() {
+        let mut rng = Rng(0x919E);
+        let data: Vec<u8> = (0..100_003).map(|_| rng.next() as u8).collect();
+        for buffer_size in [1, 4096, 1 << 20] {
+            let read = std::thread::scope(|scope| {
+                let mut reader = PipelinedReader::spawn(scope, &data[..], buffer_size);
+                let mut read = Vec::new();
+                // Reads of odd sizes, across the buffer boundaries
+                let mut chunk = vec![0u8; 1 + rng.below(10_000)];
+                loop {
+                    match reader.read(&mut chunk).unwrap() {
+                        0 => return read,
+                        n => read.extend_from_slice(&chunk[..n]),
+                    }
+                }
+            });
+            assert!(read == data, "buffer size {}", buffer_size);
+        }
+    }
+
+    #[test]
+    fn test_pipelined_reader_returns_read_errors_once() {
+        let data = vec![7u8; 10_000];
+        std::thread::scope(|scope| {
+            let mut reader = PipelinedReader::spawn(scope, FailingReader { data: &data }, 4096);
+            let mut read = Vec::new();
+            let error = reader.read_to_end(&mut read).unwrap_err();
+            assert_eq!(error.to_string(), "disk on fire");
+            // Everything before the error still came through
+            assert_eq!(read.len(), 8192);
+            assert_eq!(reader.read(&mut [0u8; 16]).unwrap(), 0);
+        });
+    }
+
+    #[test]
+    fn test_pipelined_reader_stops_when_dropped() {
+        // An endless input, the scope only ends if the thread stops
+        std::thread::scope(|scope| {
+            let mut reader = PipelinedReader::spawn(scope, std::io::repeat(1), 4096);
+            let mut buffer = [0u8; 100];
+            reader.read_exact(&mut buffer).unwrap();
+            assert_eq!(buffer, [1u8; 100]);
+        });
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_encode_pipelined() {
+        let mut rng = Rng(0x919E);
+        let data = text_data(&mut rng, 64 * MIB);
+        let input_path = temp_path("pipelined.txt");
+        let output_path = temp_path("pipelined.encoded");
+        fs::write(&input_path, &data).unwrap();
+        let encoding_table = build_encoding_table(&build_huffman_tree(&calculate_frequencies(&data[..], DEFAULT_BUFFER_SIZE).unwrap()).unwrap());
+        let length = data.len() as u64;
+
+        let elapsed = bench(|| {
+            let input = File::open(&input_path).unwrap();
+            let mut output_file = File::create(&output_path).unwrap();
+            std::thread::scope(|scope| {
+                let block = PipelinedReader::spawn(scope, &input, PIPELINE_BUFFER_SIZE);
+                encode_block(block, &mut output_file, length, &encoding_table, DEFAULT_BUFFER_SIZE).unwrap();
+            });
+        });
+        report_throughput("encode 64 MiB file, pipelined", data.len(), elapsed);
+        let elapsed = bench(|| {
+            let mut output_file = File::create(&output_path).unwrap();
+            encode_block(File::open(&input_path).unwrap(), &mut output_file, length, &encoding_table, DEFAULT_BUFFER_SIZE).unwrap();
+        });
+        report_throughput("  one thread", data.len(), elapsed);
+
+        let _ = fs::remove_file(&input_path);
+        let _ = fs::remove_file(&output_path);
+    }
+}
@@ -0,0 +1,301 @@
+// Reporting how far encoding and decoding got, for a progress bar or the
+// status page of a service. The sink goes in the options:
+//
+//     let options = EncoderOptions::builder().progress(Arc::new(bar)).build()?;
+
+use alloc::sync::Arc;
+use core::fmt;
+
+use crate::cancel::CancelToken;
+use crate::error::HuffmanResult;
+
+// What an operation is busy with. Encoding counts the bytes of a block before
+// encoding it, so with blocks the two take turns, one block at a time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    Counting,
+    Encoding,
+    Decoding,
+}
+
+// What an operation did, once it succeeded
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Report {
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+}
+
+// Told how far an operation got, from the thread running it. `processed` is
+// the position in the input, out of `total` bytes when the input says how
+// long it is, and never goes down within a phase. on_bytes() comes at the
+// start of a phase, after every PROGRESS_STEP bytes and at its end, so it
+// doesn't have to be cheap. Nothing is reported after a failure.
+pub trait ProgressSink: Send + Sync {
+    fn on_phase_start(&self, _phase: Phase) {}
+
+    fn on_bytes(&self, _processed: u64, _total: Option<u64>) {}
+
+    fn on_finish(&self, _report: &Report) {}
+}
+
+// The default sink, which ignores everything
+pub struct NoProgress;
+
+impl ProgressSink for NoProgress {}
+
+// Input between two calls to on_bytes()
+pub const PROGRESS_STEP: u64 = 1024 * 1024;
+
+// The sink of the options, None for NoProgress. Options are equal when they
+// report to the same sink.
+#[derive(Clone, Default)]
+pub(crate) struct SharedSink(pub(crate) Option<Arc<dyn ProgressSink>>);
+
+impl SharedSink {
+    pub(crate) fn get(&self) -> &dyn ProgressSink {
+        match &self.0 {
+            Some(sink) => &**sink,
+            None => &NoProgress,
+        }
+    }
+}
+
+impl PartialEq for SharedSink {
+    fn eq(&self, other: &Self) -> bool {
+        match (&self.0, &other.0) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (a, b) => a.is_none() && b.is_none(),
+        }
+    }
+}
+
+impl fmt::Debug for SharedSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(if self.0.is_some() { "ProgressSink" } else { "NoProgress" })
+    }
+}
+
+// The progress of one operation, which keeps the calls to the sink down to
+// one per PROGRESS_STEP. The loops report the position in the part of the
+// input they work on, which starts at `start`. Every report checks `cancel`
+// too, so the same loops stop with Cancelled.
+pub(crate) struct Progress<'a> {
+    sink: &'a dyn ProgressSink,
+    cancel: Option<&'a CancelToken>,
+    total: Option<u64>,
+    start: u64,
+    next: u64,
+    reported: Option<u64>,
+}
+
+impl<'a> Progress<'a> {
+    pub(crate) fn new(sink: &'a dyn ProgressSink, cancel: Option<&'a CancelToken>, total: Option<u64>) -> Self {
+        Progress { sink, cancel, total, start: 0, next: 0, reported: None }
+    }
+
+    // For the loops that are also used on their own, without a sink
+    pub(crate) fn none() -> Progress<'static> {
+        Progress::new(&NoProgress, None, None)
+    }
+
+    pub(crate) fn start_phase(&mut self, phase: Phase, start: u64) -> HuffmanResult<()> {
+        self.sink.on_phase_start(phase);
+        self.next = start;
+        self.moved_to(start)
+    }
+
+    // Another part of the input, in the same phase
+    pub(crate) fn moved_to(&mut self, start: u64) -> HuffmanResult<()> {
+        self.start = start;
+        self.update(0)
+    }
+
+    #[inline]
+    pub(crate) fn update(&mut self, processed: u64) -> HuffmanResult<()> {
+        if self.start + processed >= self.next {
+            return self.report(self.start + processed);
+        }
+        Ok(())
+    }
+
+    // The end of the part, reported even when it is less than a step on
+    pub(crate) fn end(&mut self, processed: u64) -> HuffmanResult<()> {
+        self.end_at(self.start + processed)
+    }
+
+    pub(crate) fn end_at(&mut self, position: u64) -> HuffmanResult<()> {
+        if self.reported != Some(position) {
+            return self.report(position);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn finish(&self, report: Report) {
+        self.sink.on_finish(&report);
+    }
+
+    // Cancelling is checked after the sink, which may be what cancelled
+    fn report(&mut self, position: u64) -> HuffmanResult<()> {
+        self.sink.on_bytes(position, self.total);
+        self.reported = Some(position);
+        self.next = position + PROGRESS_STEP;
+        self.cancel.map_or(Ok(()), CancelToken::check)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{decode_bytes_with, encode_bytes_with};
+    use crate::options::{DecoderOptions, EncoderOptions};
+    use crate::test_util::{text_data, Rng, MIB};
+    use std::sync::Mutex;
+
+    #[derive(Clone, Debug, PartialEq)]
+    enum Event {
+        Phase(Phase),
+        Bytes(u64, Option<u64>),
+        Finish(Report),
+    }
+
+    // Records every call, to check them afterwards
+    #[derive(Default)]
+    struct RecordingSink(Mutex<Vec<Event>>);
+
+    impl RecordingSink {
+        fn events(&self) -> Vec<Event> {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    impl ProgressSink for RecordingSink {
+        fn on_phase_start(&self, phase: Phase) {
+            self.0.lock().unwrap().push(Event::Phase(phase));
+        }
+
+        fn on_bytes(&self, processed: u64, total: Option<u64>) {
+            self.0.lock().unwrap().push(Event::Bytes(processed, total));
+        }
+
+        fn on_finish(&self, report: &Report) {
+            self.0.lock().unwrap().push(Event::Finish(*report));
+        }
+    }
+
+    // The phases with the positions reported in each, after checking that
+    // they only go up, that every phase reports where it starts and ends, and
+    // that the report comes last
+    fn checked_phases(events: &[Event], total: Option<u64>) -> (Vec<(Phase, Vec<u64>)>, Report) {
+        let mut phases: Vec<(Phase, Vec<u64>)> = Vec::new();
+        let mut report = None;
+        for event in events {
+            assert!(report.is_none(), "{:?} after the report", event);
+            match event {
+                Event::Phase(phase) => phases.push((*phase, Vec::new())),
+                Event::Bytes(processed, event_total) => {
+                    assert_eq!(*event_total, total);
+                    let positions = &mut phases.last_mut().expect("bytes before a phase").1;
+                    assert!(positions.last().is_none_or(|last| last < processed), "{} after {:?}", processed, positions);
+                    positions.push(*processed);
+                }
+                Event::Finish(finished) => report = Some(*finished),
+            }
+        }
+        (phases, report.expect("no report"))
+    }
+
+    // Between two positions there is a step, except before the end
+    fn assert_bounded(positions: &[u64]) {
+        for pair in positions[..positions.len() - 1].windows(2) {
+            assert!(pair[1] - pair[0] >= PROGRESS_STEP, "{:?}", pair);
+        }
+    }
+
+    #[test]
+    fn test_progress_of_encode_and_decode_bytes() {
+        let data = text_data(&mut Rng(0x9E06), 5 * MIB + 123);
+        let length = data.len() as u64;
+        let sink = Arc::new(RecordingSink::default());
+        let options = EncoderOptions::builder().progress(sink.clone()).build().unwrap();
+        let encoded = encode_bytes_with(&data, &options).unwrap();
+
+        let (phases, report) = checked_phases(&sink.events(), Some(length));
+        assert_eq!(phases.iter().map(|(phase, _)| *phase).collect::<Vec<_>>(), [Phase::Counting, Phase::Encoding]);
+        for (_, positions) in &phases {
+            assert_eq!((positions[0], *positions.last().unwrap()), (0, length));
+            assert_bounded(positions);
+            assert!(positions.len() >= 6, "{:?}", positions);
+        }
+        assert_eq!(report, Report { input_bytes: length, output_bytes: encoded.len() as u64 });
+
+        let sink = Arc::new(RecordingSink::default());
+        let options = DecoderOptions::builder().progress(sink.clone()).build().unwrap();
+        decode_bytes_with(&encoded, &options).unwrap();
+        let (phases, report) = checked_phases(&sink.events(), Some(encoded.len() as u64));
+        let [(Phase::Decoding, positions)] = &phases[..] else { panic!("{:?}", phases) };
+        assert_eq!((positions[0], *positions.last().unwrap()), (0, encoded.len() as u64));
+        assert_bounded(positions);
+        assert_eq!(report, Report { input_bytes: encoded.len() as u64, output_bytes: length });
+    }
+
+    #[test]
+    fn test_progress_of_blocks() {
+        let data = text_data(&mut Rng(0xB10C), 3 * MIB);
+        let length = data.len() as u64;
+        let block_size = 2 * MIB as u64;
+        let sink = Arc::new(RecordingSink::default());
+        let options = EncoderOptions::builder().block_size(block_size).progress(sink.clone()).build().unwrap();
+        let encoded = encode_bytes_with(&data, &options).unwrap();
+
+        // Counting and encoding take turns, each from where the block starts
+        let (phases, _) = checked_phases(&sink.events(), Some(length));
+        let ranges: Vec<_> = phases.iter().map(|(phase, positions)| (*phase, positions[0], *positions.last().unwrap())).collect();
+        assert_eq!(ranges, [
+            (Phase::Counting, 0, block_size),
+            (Phase::Encoding, 0, block_size),
+            (Phase::Counting, block_size, length),
+            (Phase::Encoding, block_size, length),
+        ]);
+
+        // All streams in one phase, on any number of threads
+        for threads in [1, 4] {
+            let sink = Arc::new(RecordingSink::default());
+            let options = DecoderOptions::builder().threads(threads).progress(sink.clone()).build().unwrap();
+            assert!(decode_bytes_with(&encoded, &options).unwrap() == data);
+            let (phases, report) = checked_phases(&sink.events(), Some(encoded.len() as u64));
+            assert_eq!(phases.len(), 1, "{} threads", threads);
+            assert_eq!(*phases[0].1.last().unwrap(), encoded.len() as u64);
+            assert_eq!(report, Report { input_bytes: encoded.len() as u64, output_bytes: length });
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_progress_without_a_total() {
+        use crate::codec::encode_stream;
+
+        let data = text_data(&mut Rng(0x5EAD), 3 * MIB);
+        let sink = Arc::new(RecordingSink::default());
+        let options = EncoderOptions::builder().block_size(MIB as u64).progress(sink.clone()).build().unwrap();
+        let mut encoded = Vec::new();
+        encode_stream(&data[..], &mut encoded, &options).unwrap();
+        let (phases, report) = checked_phases(&sink.events(), None);
+        assert_eq!(phases.len(), 6);
+        assert_eq!(*phases[5].1.last().unwrap(), data.len() as u64);
+        assert_eq!(report, Report { input_bytes: data.len() as u64, output_bytes: encoded.len() as u64 });
+    }
+
+    #[test]
+    fn test_no_report_after_a_failure() {
+        let encoded = encode_bytes_with(b"abracadabra", &EncoderOptions::default()).unwrap();
+        let sink = Arc::new(RecordingSink::default());
+        let options = DecoderOptions::builder().progress(sink.clone()).build().unwrap();
+        assert!(decode_bytes_with(&encoded[..encoded.len() - 1], &options).is_err());
+        assert!(sink.events().iter().all(|event| !matches!(event, Event::Finish(_))), "{:?}", sink.events());
+
+        // The options still compare equal with the same sink
+        let same = DecoderOptions::builder().progress(sink.clone()).build().unwrap();
+        assert_eq!(same, options);
+        assert_ne!(DecoderOptions::default(), options);
+    }
+}
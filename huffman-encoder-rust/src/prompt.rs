@@ -0,0 +1,180 @@
+// Asking before an existing output is overwritten, like `cp -i`. The binary
+// only asks on a terminal without --force or --quiet; otherwise an existing
+// output is refused unless --force replaces it.
+
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+use crate::error::{HuffmanError, HuffmanResult};
+
+// Where the questions go and the answers come from, a person at the terminal
+// or a script in the tests
+pub trait Prompt: Send {
+    // The line answered to `question`, None when there is no answer, like at
+    // the end of the input
+    fn ask(&mut self, question: &str) -> io::Result<Option<String>>;
+}
+
+// The question on stderr, the answer a line of stdin
+pub struct TerminalPrompt;
+
+impl Prompt for TerminalPrompt {
+    fn ask(&mut self, question: &str) -> io::Result<Option<String>> {
+        let mut stderr = io::stderr().lock();
+        stderr.write_all(question.as_bytes())?;
+        stderr.flush()?;
+        let mut line = String::new();
+        match io::stdin().lock().read_line(&mut line)? {
+            0 => Ok(None),
+            _ => Ok(Some(line)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Answer {
+    Yes,
+    No,
+    // Yes to this one and all the ones after it
+    All,
+    // No to this one and all the ones after it
+    Quit,
+}
+
+// Anything that isn't one of the answers is no, like the N of the question says
+fn parse_answer(line: &str) -> Answer {
+    match line.trim().to_ascii_lowercase().as_str() {
+        "y" | "yes" => Answer::Yes,
+        "a" | "all" => Answer::All,
+        "q" | "quit" => Answer::Quit,
+        _ => Answer::No,
+    }
+}
+
+// The outputs of a run go by one of these, which remembers an answer of all
+// or quit for the ones after it
+pub struct Overwrite {
+    prompt: Box<dyn Prompt>,
+    all: bool,
+    quit: bool,
+}
+
+impl Overwrite {
+    pub fn new(prompt: Box<dyn Prompt>) -> Self {
+        Overwrite { prompt, all: false, quit: false }
+    }
+
+    // Whether `path` may be written: when there is nothing there yet, and
+    // otherwise when the answer is yes, or was all before. Fails with
+    // Cancelled once the answer was quit, so the run stops.
+    pub fn allow(&mut self, path: &Path) -> HuffmanResult<bool> {
+        if self.quit {
+            return Err(HuffmanError::Cancelled);
+        }
+        if self.all || path.symlink_metadata().is_err() {
+            return Ok(true);
+        }
+        let question = format!("overwrite '{}'? [y/N/a(ll)/q(uit)] ", path.display());
+        let answer = self.prompt.ask(&question)?.map_or(Answer::No, |line| parse_answer(&line));
+        match answer {
+            Answer::Yes => Ok(true),
+            Answer::No => Ok(false),
+            Answer::All => {
+                self.all = true;
+                Ok(true)
+            }
+            Answer::Quit => {
+                self.quit = true;
+                Err(HuffmanError::Cancelled)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_path;
+    use std::collections::VecDeque;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+
+    // Gives the answers in order and keeps the questions
+    struct Script {
+        answers: VecDeque<&'static str>,
+        questions: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Prompt for Script {
+        fn ask(&mut self, question: &str) -> io::Result<Option<String>> {
+            self.questions.lock().unwrap().push(question.to_string());
+            Ok(self.answers.pop_front().map(String::from))
+        }
+    }
+
+    fn scripted(answers: &[&'static str]) -> (Overwrite, Arc<Mutex<Vec<String>>>) {
+        let questions = Arc::new(Mutex::new(Vec::new()));
+        let script = Script { answers: answers.iter().copied().collect(), questions: questions.clone() };
+        (Overwrite::new(Box::new(script)), questions)
+    }
+
+    // A batch of outputs that all exist already
+    fn existing(name: &str, count: usize) -> Vec<PathBuf> {
+        (0..count)
+            .map(|i| {
+                let path = temp_path(&format!("{}-{}", name, i));
+                fs::write(&path, b"there already").unwrap();
+                path
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_answer() {
+        for (line, answer) in [("y\n", Answer::Yes), ("YES", Answer::Yes), (" a ", Answer::All), ("all\n", Answer::All), ("q", Answer::Quit),
+            ("Quit\n", Answer::Quit), ("n", Answer::No), ("\n", Answer::No), ("maybe", Answer::No)]
+        {
+            assert_eq!(parse_answer(line), answer, "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn test_asks_only_for_existing_outputs() {
+        let (mut overwrite, questions) = scripted(&["y", "n"]);
+        let outputs = existing("prompt-existing", 2);
+        let missing = temp_path("prompt-missing");
+        assert!(overwrite.allow(&missing).unwrap());
+        assert!(overwrite.allow(&outputs[0]).unwrap());
+        assert!(!overwrite.allow(&outputs[1]).unwrap());
+        // Out of answers, like stdin at its end
+        assert!(!overwrite.allow(&outputs[1]).unwrap());
+        let questions = questions.lock().unwrap();
+        assert_eq!(questions.len(), 3);
+        assert_eq!(questions[0], format!("overwrite '{}'? [y/N/a(ll)/q(uit)] ", outputs[0].display()));
+        outputs.iter().for_each(|path| fs::remove_file(path).unwrap());
+    }
+
+    #[test]
+    fn test_all_is_remembered_for_the_batch() {
+        let (mut overwrite, questions) = scripted(&["n", "a"]);
+        let outputs = existing("prompt-all", 4);
+        let allowed: Vec<bool> = outputs.iter().map(|path| overwrite.allow(path).unwrap()).collect();
+        assert_eq!(allowed, [false, true, true, true]);
+        assert_eq!(questions.lock().unwrap().len(), 2);
+        outputs.iter().for_each(|path| fs::remove_file(path).unwrap());
+    }
+
+    #[test]
+    fn test_quit_stops_the_batch() {
+        let (mut overwrite, questions) = scripted(&["y", "q", "y"]);
+        let outputs = existing("prompt-quit", 3);
+        assert!(overwrite.allow(&outputs[0]).unwrap());
+        assert!(matches!(overwrite.allow(&outputs[1]), Err(HuffmanError::Cancelled)));
+        // Not even asked again, and not even for outputs that don't exist
+        assert!(matches!(overwrite.allow(&outputs[2]), Err(HuffmanError::Cancelled)));
+        assert!(matches!(overwrite.allow(&temp_path("prompt-quit-missing")), Err(HuffmanError::Cancelled)));
+        assert_eq!(questions.lock().unwrap().len(), 2);
+        outputs.iter().for_each(|path| fs::remove_file(path).unwrap());
+    }
+}
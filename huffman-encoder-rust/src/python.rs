@@ -0,0 +1,87 @@
+// The Python module `hrst`, an abi3 extension built with maturin (see
+// pyproject.toml and python/test.sh):
+//
+//     import hrst
+//     encoded = hrst.encode(b"abracadabra")
+//     assert hrst.decode(encoded) == b"abracadabra"
+//
+// The data is encoded and decoded without the GIL, so other Python threads
+// keep running while a large buffer is worked on. The buffers are bytes, which
+// nobody can change in the meantime.
+
+use std::collections::BTreeMap;
+
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use crate::codec::{decode_bytes, encode_bytes};
+use crate::error::HuffmanError as Error;
+use crate::frequency::{FrequencyTable, Symbol};
+use crate::tree::HuffmanTree;
+
+create_exception!(hrst, HuffmanError, PyException);
+
+// An exception for every variant of HuffmanError, named after it and raised
+// with its message, so Python can catch hrst.ChecksumMismatch or all of them
+// as hrst.HuffmanError
+macro_rules! variant_exceptions {
+    ($($variant:ident),*) => {
+        $(create_exception!(hrst, $variant, HuffmanError);)*
+
+        fn to_python(error: Error) -> PyErr {
+            let message = error.to_string();
+            match error.root() {
+                $(Error::$variant { .. } => $variant::new_err(message),)*
+                // root() is never a Context
+                Error::Context { .. } => HuffmanError::new_err(message),
+            }
+        }
+
+        fn add_exceptions(module: &Bound<'_, PyModule>) -> PyResult<()> {
+            module.add("HuffmanError", module.py().get_type::<HuffmanError>())?;
+            $(module.add(stringify!($variant), module.py().get_type::<$variant>())?;)*
+            Ok(())
+        }
+    };
+}
+
+variant_exceptions!(
+    Io, InvalidMagic, UnsupportedVersion, TruncatedHeader, CorruptHeader, TruncatedData, CorruptData,
+    ChecksumMismatch, UnknownSymbol, InvalidTable, TrailingGarbage, OutputTooLarge, Usage, Cancelled,
+    UnsupportedMethod, TableMismatch
+);
+
+// The bytes huffman encode writes for a file with `data` in it
+#[pyfunction]
+fn encode<'py>(py: Python<'py>, data: &[u8]) -> PyResult<Bound<'py, PyBytes>> {
+    let encoded = py.detach(|| encode_bytes(data)).map_err(to_python)?;
+    Ok(PyBytes::new(py, &encoded))
+}
+
+#[pyfunction]
+fn decode<'py>(py: Python<'py>, encoded: &[u8]) -> PyResult<Bound<'py, PyBytes>> {
+    let decoded = py.detach(|| decode_bytes(encoded)).map_err(to_python)?;
+    Ok(PyBytes::new(py, &decoded))
+}
+
+// The table encode would pick for `data`, as a dict from each byte that
+// occurs to its code as a string of bits and the length of the code
+#[pyfunction]
+fn table(py: Python<'_>, data: &[u8]) -> BTreeMap<Symbol, (String, u8)> {
+    py.detach(|| {
+        let mut frequencies = FrequencyTable::new();
+        frequencies.add(data);
+        let encoding_table = HuffmanTree::from_frequencies(&frequencies).map(|tree| tree.codes()).unwrap_or_default();
+        encoding_table.iter().map(|(byte, code)| (byte, (code.to_string(), code.length))).collect()
+    })
+}
+
+#[pymodule]
+fn hrst(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_function(wrap_pyfunction!(encode, module)?)?;
+    module.add_function(wrap_pyfunction!(decode, module)?)?;
+    module.add_function(wrap_pyfunction!(table, module)?)?;
+    add_exceptions(module)
+}
@@ -0,0 +1,366 @@
+// Range coding, for `encode --method range`: instead of a code per byte, every
+// byte narrows a range by its share of the counts of the block, so it takes
+// the bits of its probability, fractions of a bit included. Codes take whole
+// bits, which costs up to a bit per byte on skewed data, where one byte takes
+// most of the counts.
+//
+// The counts go in the header scaled to at most TOTAL, with every byte that
+// occurs at 1 or more. The range is 32 bits, and kept above TOP by shifting
+// out its top byte. The low end has a 33rd bit for the carry into the bytes
+// written already, which go out a byte late, with the 0xFF bytes a carry would
+// go through held back until it is known (LZMA's coder). The first byte is
+// always 0, and the end flushes all of the low end, so the decoder reads as
+// many bytes as were written and ends with nothing left of its code. Both are
+// checked, every byte of the payload counts.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+
+use crate::checksum::ChecksumKind;
+use crate::error::{HuffmanError, HuffmanResult};
+use crate::filter::FilterDecoder;
+use crate::frequency::FrequencyTable;
+use crate::header::Header;
+use crate::io::{BufRead, ErrorKind, Write};
+use crate::options::DecoderOptions;
+use crate::progress::Progress;
+
+// The most the scaled counts add up to
+pub(crate) const TOTAL: u64 = 1 << 16;
+const TOP: u32 = 1 << 24;
+// The bytes the decoder starts with, and the encoder flushes at the end
+pub(crate) const CODE_BYTES: usize = 5;
+
+// `counts` scaled so they add up to at most TOTAL, every byte that occurs to 1
+// or more. Counts that fit already stay as they are. A single byte gets a
+// neighbour with a count of 1, so that it takes a little of a bit each time
+// and the length stays bounded by the payload, see max_range_length.
+pub(crate) fn scale_counts(counts: &FrequencyTable) -> FrequencyTable {
+    let total = counts.total();
+    if let (1, Some((byte, count))) = (counts.len(), counts.iter().next()) {
+        return [(byte as u8, count.min(TOTAL - 1)), (byte as u8 ^ 1, 1)].into_iter().collect();
+    }
+    if total <= TOTAL {
+        return counts.clone();
+    }
+    // Room for the bytes raised to 1
+    let target = TOTAL - 256;
+    counts.iter().map(|(byte, count)| (byte as u8, (count as u128 * target as u128 / total as u128).max(1) as u64)).collect()
+}
+
+// The most bytes `payload_size` bytes range coded with `counts` can decode to.
+// A byte narrows the range to at most max/total of it and only the bytes read
+// widen it again, 8 bits each on top of the 32 it starts with, so a byte takes
+// at least log2(total/max) bits, which is at least (total - max) / max or 1.
+// A single byte takes none, scale_counts doesn't write those any more, and
+// one is allowed as many bytes as a run of --rle.
+pub(crate) fn max_range_length(counts: &FrequencyTable, payload_size: u64) -> u64 {
+    let total = counts.total();
+    let max = counts.iter().map(|(_, count)| count).max().unwrap_or(0);
+    if total == 0 {
+        return 0;
+    }
+    if max == total {
+        return payload_size.saturating_mul(8 * 65);
+    }
+    let bits = payload_size.saturating_mul(8).saturating_add(32);
+    bits.saturating_mul(max.div_ceil(total - max))
+}
+
+// Where the range of every byte starts among the counts, and their total
+fn starts(counts: &FrequencyTable) -> ([u32; 256], u32) {
+    let mut starts = [0u32; 256];
+    let mut total = 0;
+    for (byte, count) in counts.counts.iter().enumerate() {
+        starts[byte] = total;
+        total += *count as u32;
+    }
+    (starts, total)
+}
+
+struct RangeEncoder<W: Write> {
+    low: u64,
+    range: u32,
+    // The byte held back, and the number of bytes it stands for with the 0xFF
+    // bytes after it
+    cache: u8,
+    pending: u64,
+    output: W,
+    written: u64,
+}
+
+impl<W: Write> RangeEncoder<W> {
+    fn new(output: W) -> Self {
+        RangeEncoder { low: 0, range: u32::MAX, cache: 0, pending: 1, output, written: 0 }
+    }
+
+    fn encode(&mut self, start: u32, size: u32, total: u32) -> HuffmanResult<()> {
+        let r = self.range / total;
+        self.low += r as u64 * start as u64;
+        self.range = r * size;
+        while self.range < TOP {
+            self.range <<= 8;
+            self.shift_low()?;
+        }
+        Ok(())
+    }
+
+    fn shift_low(&mut self) -> HuffmanResult<()> {
+        if self.low < 0xFF00_0000 || self.low >= 1 << 32 {
+            let carry = (self.low >> 32) as u8;
+            let mut byte = self.cache;
+            for _ in 0..self.pending {
+                self.output.write_all(&[byte.wrapping_add(carry)])?;
+                byte = 0xFF;
+            }
+            self.written += self.pending;
+            self.pending = 0;
+            self.cache = (self.low >> 24) as u8;
+        }
+        self.pending += 1;
+        self.low = (self.low & 0x00FF_FFFF) << 8;
+        Ok(())
+    }
+
+    // Returns the number of bytes written
+    fn finish(mut self) -> HuffmanResult<u64> {
+        for _ in 0..CODE_BYTES {
+            self.shift_low()?;
+        }
+        Ok(self.written)
+    }
+}
+
+struct RangeDecoder<R: BufRead> {
+    code: u32,
+    range: u32,
+    input: R,
+    read: u64,
+}
+
+impl<R: BufRead> RangeDecoder<R> {
+    fn new(input: R) -> HuffmanResult<Self> {
+        let mut decoder = RangeDecoder { code: 0, range: u32::MAX, input, read: 0 };
+        // The byte held back before the first one, which a carry never reaches
+        if decoder.next_byte()? != 0 {
+            return Err(HuffmanError::CorruptData(String::from("range coded data doesn't start with a 0")));
+        }
+        for _ in 1..CODE_BYTES {
+            decoder.code = (decoder.code << 8) | decoder.next_byte()? as u32;
+        }
+        Ok(decoder)
+    }
+
+    fn next_byte(&mut self) -> HuffmanResult<u8> {
+        let byte = loop {
+            match self.input.fill_buf() {
+                Ok(buffer) => break buffer.first().copied(),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        };
+        let Some(byte) = byte else {
+            return Err(HuffmanError::TruncatedData(format!("range coded data ends after {} bytes", self.read)));
+        };
+        self.input.consume(1);
+        self.read += 1;
+        Ok(byte)
+    }
+
+    // The encoder flushes all of its low end, which the code is the distance
+    // from, so once the last byte is decoded nothing is left of it
+    fn finish(&self) -> HuffmanResult<()> {
+        if self.code != 0 {
+            return Err(HuffmanError::CorruptData(String::from("range coded data doesn't end with the last byte")));
+        }
+        Ok(())
+    }
+
+    // Where the code falls among the counts, which have to be narrowed to
+    // the byte there with `consume`
+    fn target(&self, total: u32) -> HuffmanResult<u32> {
+        match self.code / (self.range / total) {
+            target if target < total => Ok(target),
+            _ => Err(HuffmanError::CorruptData(String::from("range coded data past the counts"))),
+        }
+    }
+
+    fn consume(&mut self, start: u32, size: u32, total: u32) -> HuffmanResult<()> {
+        let r = self.range / total;
+        self.code -= r * start;
+        self.range = r * size;
+        while self.range < TOP {
+            self.range <<= 8;
+            self.code = (self.code << 8) | self.next_byte()? as u32;
+        }
+        Ok(())
+    }
+}
+
+// Range codes `data` with `counts`, which every byte of it has to be in.
+// Returns the bytes written and the checksum of `data`, of `checksum_kind`.
+pub(crate) fn encode_range(
+    data: &[u8],
+    output: impl Write,
+    counts: &FrequencyTable,
+    checksum_kind: ChecksumKind,
+    progress: &mut Progress,
+) -> HuffmanResult<(u64, u32)> {
+    let (starts, total) = starts(counts);
+    let mut encoder = RangeEncoder::new(output);
+    let mut crc = checksum_kind.start();
+    crc.update(data);
+    for (i, &byte) in data.iter().enumerate() {
+        let size = counts.counts[byte as usize] as u32;
+        if size == 0 {
+            return Err(HuffmanError::UnknownSymbol { byte, offset: i as u64 });
+        }
+        encoder.encode(starts[byte as usize], size, total)?;
+        progress.update(i as u64 + 1)?;
+    }
+    let written = encoder.finish()?;
+    progress.end(data.len() as u64)?;
+    Ok((written, crc.finish()))
+}
+
+// Decodes the payload of a range coded stream, which ends where `reader` does,
+// like decode_payload. The data has to use all of it.
+pub(crate) fn decode_range(
+    reader: impl BufRead,
+    mut output: impl Write,
+    header: &Header,
+    options: &DecoderOptions,
+    progress: &mut Progress,
+) -> HuffmanResult<(u64, u64)> {
+    let Some(counts) = &header.range_counts else {
+        return Err(HuffmanError::Usage(String::from("the stream isn't range coded")));
+    };
+    let (starts, total) = starts(counts);
+    // The byte of every place among the counts
+    let mut bytes = vec![0u8; total as usize];
+    for (byte, count) in counts.iter() {
+        let start = starts[byte as usize] as usize;
+        bytes[start..start + count as usize].fill(byte as u8);
+    }
+
+    let length = header.original_length.unwrap_or(0);
+    let mut decoder = RangeDecoder::new(reader)?;
+    let mut crc = header.checksum_kind.verifier(options.verify_checksum);
+    let mut filter = header.filter.map(|(filter, stride)| FilterDecoder::new(filter, stride));
+    for decoded in 1..=length {
+        if let Some(max) = options.max_output_size.filter(|&max| decoded > max) {
+            return Err(HuffmanError::OutputTooLarge { length: None, max });
+        }
+        let byte = bytes[decoder.target(total)? as usize];
+        decoder.consume(starts[byte as usize], counts.counts[byte as usize] as u32, total)?;
+        let byte = match &mut filter {
+            Some(filter) => filter.undo(byte),
+            None => byte,
+        };
+        output.write_all(&[byte])?;
+        if let Some(crc) = &mut crc {
+            crc.update(&[byte]);
+        }
+        progress.update(decoder.read)?;
+    }
+    decoder.finish()?;
+
+    if let Some(payload_length) = header.payload_length.filter(|&payload_length| payload_length != decoder.read) {
+        return Err(HuffmanError::CorruptData(
+            format!("payload length doesn't match the data ({} bytes declared, {} used)", payload_length, decoder.read),
+        ));
+    }
+    if let (Some(expected), Some(crc)) = (header.checksum, crc) {
+        let actual = crc.finish();
+        if actual != expected {
+            return Err(HuffmanError::ChecksumMismatch { expected, actual });
+        }
+    }
+    Ok((decoder.read * 8, length))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{random_data, text_data, Rng};
+
+    fn roundtrip(data: &[u8]) -> Vec<u8> {
+        let mut counts = FrequencyTable::new();
+        counts.add(data);
+        let counts = scale_counts(&counts);
+        let mut encoded = Vec::new();
+        let (written, checksum) = encode_range(data, &mut encoded, &counts, ChecksumKind::Crc32, &mut Progress::none()).unwrap();
+        assert_eq!(written, encoded.len() as u64);
+        assert_eq!(encoded[0], 0);
+        assert!(data.len() as u64 <= max_range_length(&counts, written), "{} bytes from {}", data.len(), written);
+
+        let header = Header::new(data.len() as u64, Default::default()).with_range(counts);
+        let header = Header { checksum: Some(checksum), payload_length: Some(written), ..header };
+        let mut decoded = Vec::new();
+        let (bits, length) = decode_range(&encoded[..], &mut decoded, &header, &DecoderOptions::default(), &mut Progress::none()).unwrap();
+        assert_eq!((bits, length), (written * 8, data.len() as u64));
+        assert!(decoded == data);
+        encoded
+    }
+
+    #[test]
+    fn test_scaled_counts_keep_every_byte() {
+        let counts: FrequencyTable = [(b'a', 1_000_000_000), (b'b', 1), (b'c', 70_000)].into_iter().collect();
+        let scaled = scale_counts(&counts);
+        assert!(scaled.total() <= TOTAL);
+        assert_eq!(scaled.get(b'b' as u16), 1);
+        assert!(scaled.get(b'a' as u16) > scaled.get(b'c' as u16));
+        let small: FrequencyTable = [(b'a', 10), (b'b', 1)].into_iter().collect();
+        assert!(scale_counts(&small) == small);
+    }
+
+    #[test]
+    fn test_range_coding_roundtrips() {
+        let mut rng = Rng(0x5A2E);
+        for length in [0, 1, 2, 3, 100, 10_000] {
+            roundtrip(&random_data(&mut rng, length));
+        }
+        roundtrip(&text_data(&mut rng, 100_000));
+        roundtrip(&(0..=255).collect::<Vec<u8>>());
+        // Carries through long runs of 0xFF bytes, from codes at the top of
+        // the range
+        roundtrip(&[[0xFFu8; 1000].as_slice(), &[0]].concat());
+        // Any number of bytes, from uniform to heavily skewed
+        for _ in 0..50 {
+            let symbols = 1 + rng.below(256);
+            let data: Vec<u8> = (0..rng.below(5000)).map(|_| (rng.below(symbols) * rng.below(symbols) / symbols) as u8).collect();
+            roundtrip(&data);
+        }
+    }
+
+    #[test]
+    fn test_skewed_data_takes_less_than_a_bit_per_byte() {
+        // A byte that is almost always the same one takes a fraction of a bit,
+        // a code at least a whole one
+        let mut rng = Rng(0x5A2F);
+        let data: Vec<u8> = (0..100_000).map(|_| if rng.below(50) == 0 { b'x' } else { b'.' }).collect();
+        let encoded = roundtrip(&data);
+        assert!(encoded.len() * 4 < data.len() / 8, "{} bytes", encoded.len());
+        // A single byte takes next to nothing past the flush, but something,
+        // so that a few bytes can't claim any length
+        assert_eq!(roundtrip(&[b'a'; 10_000]).len(), CODE_BYTES);
+        let zeros = roundtrip(&vec![0; 10_000_000]);
+        assert!(zeros.len() > CODE_BYTES && zeros.len() < 1000, "{} bytes", zeros.len());
+    }
+
+    #[test]
+    fn test_damaged_data_is_an_error() {
+        let data = text_data(&mut Rng(0x5A30), 2000);
+        let mut counts = FrequencyTable::new();
+        counts.add(&data);
+        let mut encoded = Vec::new();
+        let (_, checksum) = encode_range(&data, &mut encoded, &counts, ChecksumKind::Crc32, &mut Progress::none()).unwrap();
+        let header = Header { checksum: Some(checksum), ..Header::new(data.len() as u64, Default::default()).with_range(counts) };
+        let decode = |encoded: &[u8]| decode_range(encoded, Vec::new(), &header, &DecoderOptions::default(), &mut Progress::none());
+        assert!(matches!(decode(&encoded[..encoded.len() - 1]), Err(HuffmanError::TruncatedData(_))));
+        let mut flipped = encoded.clone();
+        flipped[encoded.len() / 2] ^= 0x10;
+        assert!(decode(&flipped).is_err());
+    }
+}
@@ -0,0 +1,261 @@
+// What encode_stream and decode_stream did, in numbers, for a program that
+// wants to show or record them. `huffman --stats` prints these.
+
+use core::time::Duration;
+
+use crate::checksum::ChecksumKind;
+#[cfg(feature = "std")]
+use crate::estimate::entropy_bits_per_byte;
+#[cfg(feature = "std")]
+use crate::frequency::FrequencyTable;
+use crate::frequency::Symbol;
+use crate::header::Header;
+use crate::progress::Report;
+
+// The sizes are summed over all streams. Every stream pads its payload to a
+// whole byte, so the payload of a stream is payload_bits plus padding_bits.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EncodeReport {
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+    pub streams: u64,
+    // Headers with their tables, and the trailers of adaptive streams
+    pub header_bytes: u64,
+    pub payload_bits: u64,
+    pub padding_bits: u64,
+    // Bytes that occur in the input, and its entropy in bits per byte, see
+    // entropy_bits_per_byte
+    pub distinct_symbols: usize,
+    pub entropy: f64,
+    pub counting_time: Duration,
+    pub encoding_time: Duration,
+}
+
+impl EncodeReport {
+    pub(crate) fn add_stream(&mut self, stream: &StreamSizes) {
+        self.streams += 1;
+        self.header_bytes += stream.header_bytes;
+        self.payload_bits += stream.payload_bits();
+        self.padding_bits += stream.padding_bits as u64;
+        self.output_bytes += stream.header_bytes + stream.payload_bytes;
+    }
+
+    // The counts of the whole input, once it is encoded
+    #[cfg(feature = "std")]
+    pub(crate) fn set_frequencies(&mut self, frequencies: &FrequencyTable) {
+        self.distinct_symbols = frequencies.len();
+        self.entropy = entropy_bits_per_byte(frequencies);
+    }
+
+    pub(crate) fn progress(&self) -> Report {
+        Report { input_bytes: self.input_bytes, output_bytes: self.output_bytes }
+    }
+}
+
+// Whether decoding checked the data against the checksums of the headers
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChecksumStatus {
+    // Every stream had one, and they all matched
+    #[default]
+    Verified,
+    // Turned off with DecoderOptions::verify_checksum
+    NotVerified,
+    // Some streams are from before version 2 or were encoded with
+    // --checksum none, which have none
+    Missing,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DecodeReport {
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+    pub streams: u64,
+    pub header_bytes: u64,
+    pub payload_bits: u64,
+    pub padding_bits: u64,
+    // Symbols with a code in any of the tables, bytes or pairs of bytes
+    pub distinct_symbols: usize,
+    pub checksum: ChecksumStatus,
+    pub decoding_time: Duration,
+}
+
+impl DecodeReport {
+    pub(crate) fn new(verify_checksum: bool) -> Self {
+        let checksum = if verify_checksum { ChecksumStatus::Verified } else { ChecksumStatus::NotVerified };
+        DecodeReport { checksum, ..DecodeReport::default() }
+    }
+
+    // `symbols` has room for every pair, the symbols seen so far are set
+    pub(crate) fn add_stream(&mut self, header: &Header, stream: &StreamSizes, symbols: &mut [bool]) {
+        self.streams += 1;
+        self.header_bytes += stream.header_bytes;
+        self.payload_bits += stream.payload_bits();
+        self.padding_bits += stream.padding_bits as u64;
+        let missing = header.checksum.is_none() || header.checksum_kind == ChecksumKind::None;
+        if missing && self.checksum == ChecksumStatus::Verified {
+            self.checksum = ChecksumStatus::Missing;
+        }
+        for (character, _) in header.encoding_table.iter() {
+            self.add_symbol(character, symbols);
+        }
+        // Range coded streams have the counts of their bytes instead
+        for (byte, _) in header.range_counts.iter().flat_map(|counts| counts.iter()) {
+            self.add_symbol(byte, symbols);
+        }
+    }
+
+    // Adaptive streams have no table, decoding them says which bytes occurred
+    pub(crate) fn add_symbols(&mut self, occurred: &[bool; 256], symbols: &mut [bool]) {
+        for character in 0..=255 {
+            if occurred[character as usize] {
+                self.add_symbol(character, symbols);
+            }
+        }
+    }
+
+    fn add_symbol(&mut self, character: Symbol, symbols: &mut [bool]) {
+        if !symbols[character as usize] {
+            symbols[character as usize] = true;
+            self.distinct_symbols += 1;
+        }
+    }
+
+    pub(crate) fn progress(&self) -> Report {
+        Report { input_bytes: self.input_bytes, output_bytes: self.output_bytes }
+    }
+}
+
+// The parts of one stream
+pub(crate) struct StreamSizes {
+    pub(crate) header_bytes: u64,
+    pub(crate) payload_bytes: u64,
+    pub(crate) padding_bits: u8,
+}
+
+impl StreamSizes {
+    fn payload_bits(&self) -> u64 {
+        (self.payload_bytes * 8).saturating_sub(self.padding_bits as u64)
+    }
+}
+
+// Adds the time since the last lap to one of the phases. Without std there is
+// no clock, and the phases take no time.
+pub(crate) struct Stopwatch(#[cfg(feature = "std")] std::time::Instant);
+
+impl Stopwatch {
+    pub(crate) fn start() -> Self {
+        Stopwatch(#[cfg(feature = "std")] std::time::Instant::now())
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn lap(&mut self, phase: &mut Duration) {
+        let now = std::time::Instant::now();
+        *phase += now - self.0;
+        self.0 = now;
+    }
+
+    #[cfg(not(feature = "std"))]
+    pub(crate) fn lap(&mut self, _phase: &mut Duration) {}
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::codec::{decode_stream, encode_stream};
+    use crate::header::decode_header;
+    use crate::options::{DecoderOptions, EncoderOptions};
+    use std::io::Cursor;
+
+    fn encoded_with_report(data: &[u8], options: &EncoderOptions) -> (Vec<u8>, EncodeReport) {
+        let mut encoded = Vec::new();
+        let report = encode_stream(data, &mut encoded, options).unwrap();
+        (encoded, report)
+    }
+
+    fn decode_report(encoded: &[u8], options: &DecoderOptions) -> DecodeReport {
+        let mut reader = Cursor::new(encoded);
+        let header = decode_header(&mut reader).unwrap();
+        decode_stream(&mut reader, &mut Vec::new(), header, options).unwrap()
+    }
+
+    // The same report without the times, which differ every run
+    fn untimed<T: Clone>(report: &T, clear: impl FnOnce(&mut T)) -> T {
+        let mut report = report.clone();
+        clear(&mut report);
+        report
+    }
+
+    #[test]
+    fn test_encode_report() {
+        // 23 bits of codes: a is 0, the other four 3 bits long
+        let (encoded, report) = encoded_with_report(b"abracadabra", &EncoderOptions::default());
+        let frequencies: FrequencyTable = [(b'a', 5), (b'b', 2), (b'r', 2), (b'c', 1), (b'd', 1)].into_iter().collect();
+        let expected = EncodeReport {
+            input_bytes: 11,
+            output_bytes: 67,
+            streams: 1,
+            header_bytes: 64,
+            payload_bits: 23,
+            padding_bits: 1,
+            distinct_symbols: 5,
+            entropy: entropy_bits_per_byte(&frequencies),
+            ..EncodeReport::default()
+        };
+        assert_eq!(untimed(&report, |r| (r.counting_time, r.encoding_time) = Default::default()), expected);
+        assert_eq!(encoded.len() as u64, report.output_bytes);
+
+        // Two 1 bit codes fill whole bytes
+        let (_, report) = encoded_with_report(b"abababab", &EncoderOptions::default());
+        assert_eq!((report.payload_bits, report.padding_bits, report.entropy), (8, 0, 1.0));
+
+        // Nothing still makes an empty stream
+        let (encoded, report) = encoded_with_report(b"", &EncoderOptions::default());
+        assert_eq!((report.streams, report.header_bytes, report.payload_bits), (1, encoded.len() as u64, 0));
+        assert_eq!((report.distinct_symbols, report.entropy), (0, 0.0));
+    }
+
+    #[test]
+    fn test_decode_report_matches_the_encode_report() {
+        let data = include_bytes!("../tests/fixtures/english.txt");
+        let options = EncoderOptions::builder().block_size(4096).build().unwrap();
+        let (encoded, encode) = encoded_with_report(data, &options);
+        assert_eq!(encode.streams, data.len().div_ceil(4096) as u64);
+        assert_eq!(encode.header_bytes + (encode.payload_bits + encode.padding_bits) / 8, encoded.len() as u64);
+        assert!(encode.padding_bits < 8 * encode.streams);
+
+        for threads in [1, 4] {
+            let report = decode_report(&encoded, &DecoderOptions::builder().threads(threads).build().unwrap());
+            let expected = DecodeReport {
+                input_bytes: encode.output_bytes,
+                output_bytes: encode.input_bytes,
+                streams: encode.streams,
+                header_bytes: encode.header_bytes,
+                payload_bits: encode.payload_bits,
+                padding_bits: encode.padding_bits,
+                distinct_symbols: encode.distinct_symbols,
+                checksum: ChecksumStatus::Verified,
+                ..DecodeReport::default()
+            };
+            assert_eq!(untimed(&report, |r| r.decoding_time = Duration::ZERO), expected, "{} threads", threads);
+        }
+    }
+
+    #[test]
+    fn test_decode_report_of_old_versions() {
+        let v0 = include_bytes!("../tests/fixtures/versions/abracadabra.v0.encoded");
+        let v3 = include_bytes!("../tests/fixtures/versions/abracadabra.v3.encoded");
+        for encoded in [&v0[..], v3] {
+            let report = decode_report(encoded, &DecoderOptions::default());
+            let header = decode_header(&mut Cursor::new(encoded)).unwrap();
+            assert_eq!((report.input_bytes, report.output_bytes), (encoded.len() as u64, 11));
+            assert_eq!((report.header_bytes, report.payload_bits), (header.serialized_len(), 23));
+            assert_eq!(report.header_bytes + (report.payload_bits + report.padding_bits) / 8, encoded.len() as u64);
+        }
+
+        // Only version 2 and later have a checksum
+        assert_eq!(decode_report(v0, &DecoderOptions::default()).checksum, ChecksumStatus::Missing);
+        assert_eq!(decode_report(v3, &DecoderOptions::default()).checksum, ChecksumStatus::Verified);
+        let unchecked = DecoderOptions::builder().verify_checksum(false).build().unwrap();
+        assert_eq!(decode_report(v3, &unchecked).checksum, ChecksumStatus::NotVerified);
+    }
+}
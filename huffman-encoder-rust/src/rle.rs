@@ -0,0 +1,145 @@
+// Run-length encoding before the Huffman codes, for `encode --rle`: runs of a
+// byte become a count and the byte, which the codes alone can't do better
+// than a bit per byte. The data is a series of packets, each with a control
+// byte c in front:
+//
+//   c < 128:  c + 1 literal bytes follow (1 to 128)
+//   c >= 128: the next byte repeats c - 125 times (3 to 130)
+//
+// Shorter runs stay literals, so a run always takes fewer bytes than it
+// stands for, and nothing grows by more than a byte per 128.
+
+use alloc::vec::Vec;
+
+use crate::error::HuffmanResult;
+
+const MAX_LITERALS: usize = 128;
+const MIN_RUN: usize = 3;
+const MAX_RUN: usize = 130;
+const RUN: u8 = 128;
+
+// The largest run_length_encode can make of `length` bytes
+pub(crate) fn max_encoded_len(length: usize) -> usize {
+    length + length.div_ceil(MAX_LITERALS)
+}
+
+pub(crate) fn run_length_encode(data: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(max_encoded_len(data.len()));
+    let run_at = |i: usize| data[i..].iter().take(MAX_RUN).take_while(|&&byte| byte == data[i]).count();
+    let mut i = 0;
+    while i < data.len() {
+        let run = run_at(i);
+        if run >= MIN_RUN {
+            encoded.push(RUN + (run - MIN_RUN) as u8);
+            encoded.push(data[i]);
+            i += run;
+            continue;
+        }
+        // Literals up to the next run worth a packet of its own
+        let start = i;
+        while i < data.len() && i - start < MAX_LITERALS && !data[i..].starts_with(&[data[i]; MIN_RUN]) {
+            i += 1;
+        }
+        encoded.push((i - start - 1) as u8);
+        encoded.extend_from_slice(&data[start..i]);
+    }
+    encoded
+}
+
+// Turns the bytes the Huffman codes decode to back into the data, one byte at
+// a time
+#[derive(Default)]
+pub(crate) struct RunLengthDecoder {
+    // Bytes left of the current packet, and the byte of a run
+    left: u8,
+    repeat: Option<u8>,
+}
+
+impl RunLengthDecoder {
+    // The next byte of the data, taking the bytes of the packets from `next`.
+    // None once `next` runs out.
+    #[inline]
+    pub(crate) fn next(&mut self, mut next: impl FnMut() -> HuffmanResult<Option<u8>>) -> HuffmanResult<Option<u8>> {
+        if self.left == 0 {
+            let Some(control) = next()? else {
+                return Ok(None);
+            };
+            if control < RUN {
+                (self.left, self.repeat) = (control + 1, None);
+            } else {
+                let Some(byte) = next()? else {
+                    return Ok(None);
+                };
+                (self.left, self.repeat) = (control - RUN + MIN_RUN as u8, Some(byte));
+            }
+        }
+        let byte = match self.repeat {
+            Some(byte) => Some(byte),
+            None => next()?,
+        };
+        if byte.is_some() {
+            self.left -= 1;
+        }
+        Ok(byte)
+    }
+
+    // Whether the last packet is complete
+    pub(crate) fn is_done(&self) -> bool {
+        self.left == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{random_data, text_data, Rng};
+
+    fn decode(encoded: &[u8]) -> (Vec<u8>, bool) {
+        let mut decoder = RunLengthDecoder::default();
+        let mut bytes = encoded.iter().copied();
+        let mut decoded = Vec::new();
+        while let Some(byte) = decoder.next(|| Ok(bytes.next())).unwrap() {
+            decoded.push(byte);
+        }
+        (decoded, decoder.is_done())
+    }
+
+    #[test]
+    fn test_run_length_roundtrip() {
+        let mut rng = Rng(0x5105);
+        let inputs = [
+            Vec::new(),
+            b"a".to_vec(),
+            b"aa".to_vec(),
+            b"aaa".to_vec(),
+            b"abbcccdddd".to_vec(),
+            vec![0; 100_000],
+            (0..=255).collect(),
+            // Runs that only just don't fit in a packet
+            [vec![7; MAX_RUN + 1], vec![8; MAX_RUN + 2], vec![9; 2]].concat(),
+            text_data(&mut rng, 10_000),
+            random_data(&mut rng, 10_000),
+        ];
+        for data in inputs {
+            let encoded = run_length_encode(&data);
+            assert!(encoded.len() <= max_encoded_len(data.len()));
+            assert_eq!(decode(&encoded), (data, true));
+        }
+    }
+
+    #[test]
+    fn test_run_length_packets() {
+        assert_eq!(run_length_encode(b"abbcccdddd"), b"\x02abb\x80c\x81d");
+        assert_eq!(run_length_encode(&[0; 200]), [255, 0, 195, 0]);
+        // Random bytes hardly ever repeat, they only get the control bytes
+        let data = random_data(&mut Rng(0x5106), 128 * 100);
+        assert_eq!(run_length_encode(&data).len(), max_encoded_len(data.len()));
+    }
+
+    #[test]
+    fn test_run_length_decoder_stops_in_a_packet() {
+        // Two of three literals, and a run without its byte
+        assert_eq!(decode(b"\x02ab"), (b"ab".to_vec(), false));
+        assert_eq!(decode(b"\x01ab\x80"), (b"ab".to_vec(), true));
+    }
+}
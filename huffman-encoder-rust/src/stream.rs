@@ -0,0 +1,765 @@
+// Encoding and decoding through the Write and Read traits, for io::copy and
+// friends.
+//
+// The format is handled by WriterState and ReaderState, which do no IO of
+// their own: data goes in and the encoded bytes come out, or the other way
+// around. HuffmanWriter and HuffmanReader only move those bytes through Write
+// and BufRead, and the adapters in async_io through their tokio counterparts,
+// so both kinds give the same bytes and the same errors.
+
+use std::io::{BufRead, ErrorKind, Read, Result as IoResult, Take, Write};
+use std::mem;
+
+use crate::bitio::{BitReader, BitWriter};
+use crate::checksum::Crc32;
+use crate::codec::{encode_bytes, StreamDecoder, DEFAULT_BUFFER_SIZE};
+use crate::error::{HuffmanError, HuffmanResult, IoContext};
+use crate::header::{read_header, Header, MAGIC};
+use crate::table::{build_code_lookup, CodeLookup, EncodingTable};
+
+// Writing
+////////////////////////////////////////////////////////////////////////////////
+
+// Encodes everything written to it into `output`, in the format of encode.
+//
+// The header comes before the data and holds its lengths and checksum, and a
+// plain writer can't go back to fill them in, so the current stream is kept in
+// memory until it ends: encoded with a table given up front (new), or as
+// plain data that gets a table of its own when it ends (buffered).
+//
+// - flush() ends the current stream and writes it out, so everything written
+//   so far can be decoded. Later writes go into a new stream, concatenated
+//   after it, which decode reads as one file. A flush with nothing written
+//   since the last one only flushes `output`.
+// - finish() ends the last stream and hands back `output`. Finishing without
+//   writing anything still writes an (empty) stream, so the output is always
+//   a valid file.
+// - Dropping the writer without finish() finishes it anyway, like BitWriter,
+//   but errors are lost then.
+//
+// A buffered writer that is only finished gives the same bytes as encode for
+// a file holding everything written.
+pub struct HuffmanWriter<W: Write> {
+    state: WriterState,
+    // Only None once finish() has taken it
+    output: Option<W>,
+}
+
+// Everything of HuffmanWriter but the output: the current stream, and the
+// bytes of the whole stream once it ends
+pub(crate) struct WriterState {
+    mode: Mode,
+    // Whether a stream was ended already
+    written: bool,
+}
+
+enum Mode {
+    Table {
+        // Its fields are filled in when the stream ends
+        header: Box<Header>,
+        codes: Box<CodeLookup>,
+        payload: BitWriter<Vec<u8>>,
+        length: u64,
+        crc: Crc32,
+    },
+    Buffered {
+        data: Vec<u8>,
+    },
+}
+
+impl WriterState {
+    // Writing a byte that has no code in `encoding_table` fails, and so does a
+    // table that isn't a prefix code
+    pub(crate) fn new(encoding_table: EncodingTable) -> HuffmanResult<Self> {
+        encoding_table.validate()?;
+        let codes = Box::new(build_code_lookup(&encoding_table));
+        Ok(WriterState {
+            mode: Mode::Table {
+                header: Box::new(Header::new(0, encoding_table)),
+                codes,
+                payload: BitWriter::new(Vec::new(), DEFAULT_BUFFER_SIZE)?,
+                length: 0,
+                crc: Crc32::new(),
+            },
+            written: false,
+        })
+    }
+
+    pub(crate) fn buffered() -> Self {
+        WriterState { mode: Mode::Buffered { data: Vec::new() }, written: false }
+    }
+
+    // Whether the current stream has any data
+    pub(crate) fn is_pending(&self) -> bool {
+        match &self.mode {
+            Mode::Table { length, .. } => *length > 0,
+            Mode::Buffered { data } => !data.is_empty(),
+        }
+    }
+
+    // Whether finishing ends a stream: the current one, or an empty one when
+    // there was none at all
+    pub(crate) fn needs_last_stream(&self) -> bool {
+        self.is_pending() || !self.written
+    }
+
+    // Takes the bytes of `buf` up to the first one without a code
+    pub(crate) fn write(&mut self, buf: &[u8]) -> HuffmanResult<usize> {
+        match &mut self.mode {
+            Mode::Table { codes, payload, length, crc, .. } => {
+                let accepted = buf.iter().position(|&byte| codes[byte as usize].is_none()).unwrap_or(buf.len());
+                if accepted == 0 && !buf.is_empty() {
+                    return Err(HuffmanError::UnknownSymbol { byte: buf[0], offset: *length });
+                }
+                for &byte in &buf[..accepted] {
+                    let code = codes[byte as usize].expect("checked above");
+                    payload.write_code(&code)?;
+                }
+                crc.update(&buf[..accepted]);
+                *length += accepted as u64;
+                Ok(accepted)
+            }
+            Mode::Buffered { data } => {
+                data.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+        }
+    }
+
+    // Ends the current stream and starts a new one. Returns the bytes of the
+    // stream, header and payload.
+    pub(crate) fn end_stream(&mut self) -> HuffmanResult<Vec<u8>> {
+        let stream = match &mut self.mode {
+            Mode::Table { header, payload, length, crc, .. } => {
+                let finished = mem::replace(payload, BitWriter::new(Vec::new(), DEFAULT_BUFFER_SIZE)?);
+                let (payload, padding_bits) = finished.into_inner()?;
+                header.original_length = Some(*length);
+                header.checksum = Some(crc.finish());
+                header.payload_length = Some(payload.len() as u64);
+                header.padding_bits = padding_bits;
+                let mut stream = Vec::with_capacity(header.serialized_len() as usize + payload.len());
+                header.write_to(&mut stream)?;
+                stream.extend_from_slice(&payload);
+                *length = 0;
+                *crc = Crc32::new();
+                stream
+            }
+            Mode::Buffered { data } => {
+                let stream = encode_bytes(data)?;
+                data.clear();
+                stream
+            }
+        };
+        self.written = true;
+        Ok(stream)
+    }
+}
+
+impl<W: Write> HuffmanWriter<W> {
+    // Writing a byte that has no code in `encoding_table` fails, and so does a
+    // table that isn't a prefix code
+    pub fn new(output: W, encoding_table: EncodingTable) -> IoResult<Self> {
+        Ok(HuffmanWriter { state: WriterState::new(encoding_table)?, output: Some(output) })
+    }
+
+    pub fn buffered(output: W) -> Self {
+        HuffmanWriter { state: WriterState::buffered(), output: Some(output) }
+    }
+
+    // Writes out the current stream and starts a new one
+    fn end_stream(&mut self) -> HuffmanResult<()> {
+        let Some(output) = self.output.as_mut() else {
+            return Ok(());
+        };
+        output.write_all(&self.state.end_stream()?)?;
+        Ok(())
+    }
+
+    // Ends the last stream and returns the writer it went to
+    pub fn finish(mut self) -> HuffmanResult<W> {
+        let result = if self.state.needs_last_stream() { self.end_stream() } else { Ok(()) };
+        // Taken either way, so the drop doesn't try again
+        let output = self.output.take().expect("the writer is only taken here");
+        result.map(|()| output)
+    }
+}
+
+impl<W: Write> Write for HuffmanWriter<W> {
+    // Stops at the first byte without a code, the bytes before it are taken
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        Ok(self.state.write(buf)?)
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        if self.state.is_pending() {
+            self.end_stream()?;
+        }
+        match self.output.as_mut() {
+            Some(output) => output.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<W: Write> Drop for HuffmanWriter<W> {
+    fn drop(&mut self) {
+        if self.output.is_some() && self.state.needs_last_stream() {
+            let _ = self.end_stream();
+        }
+    }
+}
+
+// Reading
+////////////////////////////////////////////////////////////////////////////////
+
+// Decodes its input as it is read, so anything in the format of encode can be
+// read like the original data, from a file as well as from a pipe. The header
+// of a stream is only read by the first read that needs it, and concatenated
+// streams follow each other like in decode, with the same errors. Once
+// decoding fails, every later read fails the same way.
+//
+// Streams from before version 3 don't store where they end, so these can only
+// be the last stream of the input.
+pub struct HuffmanReader<R: BufRead> {
+    reader: R,
+    state: ReaderState,
+}
+
+// Input a ReaderState holds on to at most
+const FEED_CAPACITY: usize = DEFAULT_BUFFER_SIZE;
+
+// The most a refill of the BitReader takes, and one byte more to tell that it
+// didn't take the last one
+const REFILL_BYTES: usize = 9;
+
+// Everything of HuffmanReader but the reader. Input goes in with feed() and
+// end_input(), decoded data comes out of decode(), which asks for more input
+// through wants_input() instead of waiting for it.
+pub(crate) struct ReaderState {
+    phase: Phase,
+    // Offset of the current stream in the input
+    start: u64,
+    // Set when decode() stopped to get more input first
+    wants_input: bool,
+}
+
+enum Phase {
+    // Before the header of the stream at `start`
+    Header(Feed),
+    // The header is boxed, it would make this phase by far the largest
+    Data {
+        bit_reader: BitReader<Take<Feed>>,
+        header: Box<Header>,
+        stream: StreamDecoder,
+    },
+    // Not a stream at `start`, counted up to the end of the input
+    Garbage {
+        feed: Feed,
+        length: u64,
+    },
+    Done,
+    Failed(HuffmanError),
+}
+
+// The input that was fed and not decoded yet. It only ends once the input
+// has, the state doesn't read from it before that unless there is enough.
+struct Feed {
+    data: Vec<u8>,
+    position: usize,
+    ended: bool,
+}
+
+impl Feed {
+    fn buffered(&self) -> &[u8] {
+        &self.data[self.position..]
+    }
+}
+
+impl Read for Feed {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let read = self.buffered().len().min(buf.len());
+        buf[..read].copy_from_slice(&self.buffered()[..read]);
+        self.position += read;
+        Ok(read)
+    }
+}
+
+impl BufRead for Feed {
+    fn fill_buf(&mut self) -> IoResult<&[u8]> {
+        Ok(self.buffered())
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.position += amount;
+    }
+}
+
+// Whether the next code can be read without running out of input that is
+// still coming: the input ended, the payload does within what is there, or
+// there is enough for any refill
+fn can_decode(bit_reader: &BitReader<Take<Feed>>) -> bool {
+    let payload = bit_reader.get_ref();
+    let feed = payload.get_ref();
+    feed.ended || feed.buffered().len() >= REFILL_BYTES || payload.limit() <= feed.buffered().len() as u64
+}
+
+impl ReaderState {
+    pub(crate) fn new() -> Self {
+        let feed = Feed { data: Vec::new(), position: 0, ended: false };
+        ReaderState { phase: Phase::Header(feed), start: 0, wants_input: false }
+    }
+
+    pub(crate) fn wants_input(&self) -> bool {
+        self.wants_input
+    }
+
+    fn feed_mut(&mut self) -> Option<&mut Feed> {
+        match &mut self.phase {
+            Phase::Header(feed) | Phase::Garbage { feed, .. } => Some(feed),
+            Phase::Data { bit_reader, .. } => Some(bit_reader.get_mut().get_mut()),
+            Phase::Done | Phase::Failed(_) => None,
+        }
+    }
+
+    // Takes what fits of `input` and returns how much that was. Once the data
+    // is done it takes everything and ignores it.
+    pub(crate) fn feed(&mut self, input: &[u8]) -> usize {
+        self.wants_input = false;
+        let Some(feed) = self.feed_mut() else {
+            return input.len();
+        };
+        feed.data.drain(..feed.position);
+        feed.position = 0;
+        let taken = input.len().min(FEED_CAPACITY.saturating_sub(feed.data.len()));
+        feed.data.extend_from_slice(&input[..taken]);
+        taken
+    }
+
+    // There is no more input than what was fed
+    pub(crate) fn end_input(&mut self) {
+        self.wants_input = false;
+        if let Some(feed) = self.feed_mut() {
+            feed.ended = true;
+        }
+    }
+
+    // Decodes into `buf` and returns how many bytes, 0 at the end of the data
+    // and when wants_input() asks for more first. What was decoded before an
+    // error is returned first, the error with the next call.
+    pub(crate) fn decode(&mut self, buf: &mut [u8]) -> HuffmanResult<usize> {
+        let mut filled = 0;
+        while filled < buf.len() && !self.wants_input {
+            let result = match &mut self.phase {
+                Phase::Data { bit_reader, header, stream } => {
+                    if !can_decode(bit_reader) {
+                        self.wants_input = true;
+                        break;
+                    }
+                    match stream.next(header, bit_reader) {
+                        Ok(Some(character)) => {
+                            buf[filled] = character;
+                            filled += 1;
+                            continue;
+                        }
+                        Ok(None) => self.end_stream(),
+                        Err(e) => Err(self.in_stream(e)),
+                    }
+                }
+                Phase::Header(_) => self.start_stream(),
+                Phase::Garbage { .. } => self.count_garbage(),
+                Phase::Done => break,
+                Phase::Failed(e) => Err(e.clone()),
+            };
+            if let Err(e) = result {
+                self.phase = Phase::Failed(e.clone());
+                if filled > 0 {
+                    break;
+                }
+                return Err(e);
+            }
+        }
+        Ok(filled)
+    }
+
+    // Errors of later streams say which one failed
+    fn in_stream(&self, e: HuffmanError) -> HuffmanError {
+        match self.start {
+            0 => e,
+            start => HuffmanError::Context { context: format!("stream at offset {}", start), source: Box::new(e) },
+        }
+    }
+
+    fn start_stream(&mut self) -> HuffmanResult<()> {
+        let Phase::Header(feed) = &mut self.phase else {
+            unreachable!("only called before a header");
+        };
+        let input = feed.buffered();
+        // After the first stream, the end of the input or anything that isn't
+        // a stream can follow
+        if self.start > 0 {
+            let prefix = input.len().min(MAGIC.len());
+            if input.is_empty() && feed.ended {
+                self.phase = Phase::Done;
+                return Ok(());
+            } else if input[..prefix] != MAGIC[..prefix] || (feed.ended && input.len() < MAGIC.len()) {
+                let Phase::Header(feed) = mem::replace(&mut self.phase, Phase::Done) else { unreachable!() };
+                self.phase = Phase::Garbage { feed, length: 0 };
+                return Ok(());
+            } else if input.len() < MAGIC.len() {
+                self.wants_input = true;
+                return Ok(());
+            }
+        }
+
+        let mut rest = input;
+        let header = match read_header(&mut rest, self.start, None) {
+            Ok(header) => header,
+            Err(HuffmanError::TruncatedHeader { .. }) if !feed.ended => {
+                self.wants_input = true;
+                return Ok(());
+            }
+            Err(e) if self.start > 0 => {
+                return Err(e).with_context(|| format!("failed to read header of stream at offset {}", self.start));
+            }
+            Err(e) => return Err(e),
+        };
+        // Its codes can be longer than any refill, so it can't be decoded a
+        // bit of input at a time
+        if header.adaptive {
+            let error = HuffmanError::Usage(String::from("adaptive streams can't be read in pieces, use decode_stream"));
+            return Err(self.in_stream(error));
+        }
+        // Nothing of the block is known before all of it is decoded
+        if header.primary_index.is_some() {
+            let error = HuffmanError::Usage(String::from(
+                "Burrows-Wheeler transformed streams can't be read in pieces, use decode_stream",
+            ));
+            return Err(self.in_stream(error));
+        }
+        // The range coder reads bytes, not bits, see range.rs
+        if header.range_counts.is_some() {
+            let error = HuffmanError::Usage(String::from("range coded streams can't be read in pieces, use decode_stream"));
+            return Err(self.in_stream(error));
+        }
+        let used = input.len() - rest.len();
+        feed.consume(used);
+
+        let Phase::Header(feed) = mem::replace(&mut self.phase, Phase::Done) else { unreachable!() };
+        let stream = StreamDecoder::new(&header, true);
+        let bit_reader = BitReader::new(feed.take(header.payload_length.unwrap_or(u64::MAX)), header.padding_bits)?;
+        self.phase = Phase::Data { bit_reader, header: Box::new(header), stream };
+        Ok(())
+    }
+
+    fn end_stream(&mut self) -> HuffmanResult<()> {
+        let Phase::Data { bit_reader, header, stream } = mem::replace(&mut self.phase, Phase::Done) else {
+            unreachable!("only called after the data");
+        };
+        stream.finish(&header, &bit_reader).map_err(|e| self.in_stream(e))?;
+        // Version 0 streams have no length, they just read until the end
+        if header.original_length.is_none() {
+            return Ok(());
+        }
+
+        let used = bit_reader.bits_read.div_ceil(8);
+        let loaded = bit_reader.bytes_loaded;
+        let feed = bit_reader.into_inner().into_inner();
+        if header.payload_length.is_none() {
+            // can_decode waited for the end of the input or for what follows,
+            // which may already be in the bit buffer
+            if loaded > used || !feed.buffered().is_empty() {
+                return Err(HuffmanError::Usage(format!(
+                    "data after the version {} stream at offset {} can only be decoded from a file",
+                    header.version, self.start,
+                )));
+            }
+            return Ok(());
+        }
+        self.start += header.serialized_len() + used;
+        self.phase = Phase::Header(feed);
+        Ok(())
+    }
+
+    // Everything up to the end of the input is garbage, the error says how much
+    fn count_garbage(&mut self) -> HuffmanResult<()> {
+        let Phase::Garbage { feed, length } = &mut self.phase else {
+            unreachable!("only called after garbage");
+        };
+        *length += feed.buffered().len() as u64;
+        feed.position = feed.data.len();
+        if !feed.ended {
+            self.wants_input = true;
+            return Ok(());
+        }
+        Err(HuffmanError::TrailingGarbage { offset: self.start, length: *length })
+    }
+}
+
+impl<R: BufRead> HuffmanReader<R> {
+    pub fn new(reader: R) -> IoResult<Self> {
+        Ok(HuffmanReader { reader, state: ReaderState::new() })
+    }
+}
+
+impl<R: BufRead> Read for HuffmanReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        loop {
+            let decoded = self.state.decode(buf)?;
+            if decoded > 0 || !self.state.wants_input() {
+                return Ok(decoded);
+            }
+            let input = match self.reader.fill_buf() {
+                Ok(input) => input,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            if input.is_empty() {
+                self.state.end_input();
+            } else {
+                let taken = self.state.feed(input);
+                self.reader.consume(taken);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{decode_bytes, encode_bytes_with};
+    use crate::options::{EncoderOptions, Method};
+    use crate::frequency::calculate_frequencies;
+    use crate::table::{build_encoding_table, Code};
+    use crate::test_util::{bench, report_throughput, text_data, Rng, MIB};
+    use crate::tree::build_huffman_tree;
+    use std::io::{self, ErrorKind};
+
+    fn table_for(data: &[u8]) -> EncodingTable {
+        let frequencies = calculate_frequencies(data, DEFAULT_BUFFER_SIZE).unwrap();
+        build_encoding_table(&build_huffman_tree(&frequencies).unwrap())
+    }
+
+    #[test]
+    fn test_writer_with_table_matches_encode() {
+        let data = text_data(&mut Rng(0x57E4), 300_000);
+        let mut writer = HuffmanWriter::new(Vec::new(), table_for(&data)).unwrap();
+        io::copy(&mut &data[..], &mut writer).unwrap();
+        assert!(writer.finish().unwrap() == encode_bytes(&data).unwrap());
+    }
+
+    #[test]
+    fn test_buffered_writer_matches_encode() {
+        let data = text_data(&mut Rng(0xB0FF), 100_000);
+        let mut writer = HuffmanWriter::buffered(Vec::new());
+        for chunk in data.chunks(7) {
+            writer.write_all(chunk).unwrap();
+        }
+        assert!(writer.finish().unwrap() == encode_bytes(&data).unwrap());
+    }
+
+    #[test]
+    fn test_flush_ends_the_stream() {
+        for buffered in [false, true] {
+            let mut writer = match buffered {
+                false => HuffmanWriter::new(Vec::new(), table_for(b"first second")).unwrap(),
+                true => HuffmanWriter::buffered(Vec::new()),
+            };
+            writer.write_all(b"first ").unwrap();
+            writer.flush().unwrap();
+            let flushed = writer.output.as_ref().unwrap().clone();
+            assert_eq!(decode_bytes(&flushed).unwrap(), b"first ");
+
+            // Nothing new, nothing written
+            writer.flush().unwrap();
+            assert_eq!(writer.output.as_ref().unwrap().len(), flushed.len());
+
+            writer.write_all(b"second").unwrap();
+            let encoded = writer.finish().unwrap();
+            assert!(encoded.starts_with(&flushed));
+            assert_eq!(decode_bytes(&encoded).unwrap(), b"first second");
+        }
+    }
+
+    #[test]
+    fn test_empty_writer_gives_an_empty_stream() {
+        let encoded = HuffmanWriter::buffered(Vec::new()).finish().unwrap();
+        assert_eq!(encoded, encode_bytes(b"").unwrap());
+        let encoded = HuffmanWriter::new(Vec::new(), table_for(b"ab")).unwrap().finish().unwrap();
+        assert_eq!(decode_bytes(&encoded).unwrap(), b"");
+    }
+
+    #[test]
+    fn test_dropped_writer_is_finished() {
+        let data = b"dropped without finish";
+        let mut encoded = Vec::new();
+        let mut writer = HuffmanWriter::new(&mut encoded, table_for(data)).unwrap();
+        writer.write_all(data).unwrap();
+        drop(writer);
+        assert_eq!(decode_bytes(&encoded).unwrap(), data);
+    }
+
+    #[test]
+    fn test_writer_rejects_bytes_outside_the_table() {
+        let mut writer = HuffmanWriter::new(Vec::new(), table_for(b"ab")).unwrap();
+        assert_eq!(writer.write(b"abc").unwrap(), 2);
+        let error = writer.write_all(b"c").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert_eq!(decode_bytes(&writer.finish().unwrap()).unwrap(), b"ab");
+
+        // 'a' is a prefix of 'b', which would never be decoded
+        let overlapping: EncodingTable = [
+            (b'a', Code { bits: 0, length: 1 }),
+            (b'b', Code { bits: 0, length: 2 }),
+        ].into_iter().collect();
+        let error = HuffmanWriter::new(Vec::new(), overlapping).err().unwrap();
+        assert!(matches!(error.get_ref().unwrap().downcast_ref(), Some(HuffmanError::InvalidTable(_))));
+    }
+
+    // Small reads, odd ones and more than there is all give the same data
+    fn read_in_chunks(encoded: &[u8], chunk_size: usize) -> IoResult<Vec<u8>> {
+        let mut reader = HuffmanReader::new(encoded)?;
+        let mut decoded = Vec::new();
+        let mut buffer = vec![0u8; chunk_size];
+        loop {
+            match reader.read(&mut buffer)? {
+                0 => return Ok(decoded),
+                read => decoded.extend_from_slice(&buffer[..read]),
+            }
+        }
+    }
+
+    #[test]
+    fn test_reader_chunk_sizes_give_the_same_data() {
+        let data = text_data(&mut Rng(0x4EAD), 50_000);
+        let single = encode_bytes(&data).unwrap();
+        let blocks: Vec<u8> = data.chunks(9_000).flat_map(|block| encode_bytes(block).unwrap()).collect();
+        for encoded in [single, blocks] {
+            for chunk_size in [1, 7, 4 * MIB] {
+                assert!(read_in_chunks(&encoded, chunk_size).unwrap() == data, "{} byte reads", chunk_size);
+            }
+        }
+        assert_eq!(read_in_chunks(&encode_bytes(b"").unwrap(), 7).unwrap(), b"");
+    }
+
+    #[test]
+    fn test_reader_undoes_rle() {
+        let data = [&text_data(&mut Rng(0x4EAE), 10_000)[..], &[0; 10_000], b"end"].concat();
+        let rle = EncoderOptions::builder().rle(true).block_size(4_000).build().unwrap();
+        let encoded = [encode_bytes_with(&data, &rle).unwrap(), encode_bytes(b"plain").unwrap()].concat();
+        for chunk_size in [1, 7, 4 * MIB] {
+            assert!(read_in_chunks(&encoded, chunk_size).unwrap() == [&data[..], b"plain"].concat(), "{} byte reads", chunk_size);
+        }
+    }
+
+    #[test]
+    fn test_reader_reads_pairs() {
+        // An odd length, the last block ends with the byte left over
+        let data = text_data(&mut Rng(0x4EAF), 10_001);
+        let pairs = EncoderOptions::builder().symbol_width(2).block_size(3_000).build().unwrap();
+        let encoded = [encode_bytes_with(&data, &pairs).unwrap(), encode_bytes(b"plain").unwrap()].concat();
+        for chunk_size in [1, 7, 4 * MIB] {
+            assert!(read_in_chunks(&encoded, chunk_size).unwrap() == [&data[..], b"plain"].concat(), "{} byte reads", chunk_size);
+        }
+    }
+
+    #[test]
+    fn test_reader_reads_tokens() {
+        let data = text_data(&mut Rng(0x4EB0), 10_000);
+        let tokens = EncoderOptions::builder().tokens(true).block_size(3_000).build().unwrap();
+        let encoded = [encode_bytes_with(&data, &tokens).unwrap(), encode_bytes(b"plain").unwrap()].concat();
+        for chunk_size in [1, 7, 4 * MIB] {
+            assert!(read_in_chunks(&encoded, chunk_size).unwrap() == [&data[..], b"plain"].concat(), "{} byte reads", chunk_size);
+        }
+    }
+
+    #[test]
+    fn test_reader_switches_contexts() {
+        let data = text_data(&mut Rng(0x4EB1), 10_000);
+        let context = EncoderOptions::builder().context(true).block_size(3_000).build().unwrap();
+        let encoded = [encode_bytes_with(&data, &context).unwrap(), encode_bytes(b"plain").unwrap()].concat();
+        for chunk_size in [1, 7, 4 * MIB] {
+            assert!(read_in_chunks(&encoded, chunk_size).unwrap() == [&data[..], b"plain"].concat(), "{} byte reads", chunk_size);
+        }
+    }
+
+    #[test]
+    fn test_reader_reads_lines() {
+        let encoded = encode_bytes(b"first line\nsecond line\n").unwrap();
+        let reader = io::BufReader::new(HuffmanReader::new(&encoded[..]).unwrap());
+        let lines: Vec<String> = io::BufRead::lines(reader).map(|line| line.unwrap()).collect();
+        assert_eq!(lines, ["first line", "second line"]);
+    }
+
+    #[test]
+    fn test_reader_errors_match_decode() {
+        let encoded = [encode_bytes(b"first block").unwrap(), encode_bytes(b"second block").unwrap()].concat();
+        let mut checksum = encoded.clone();
+        // The last payload byte of the second stream
+        *checksum.last_mut().unwrap() ^= 0x80;
+        let cases = [
+            ("magic", b"HRSX".to_vec()),
+            ("empty", Vec::new()),
+            ("truncated", encoded[..encoded.len() - 3].to_vec()),
+            ("garbage", [&encoded[..], b"junk"].concat()),
+            ("checksum", checksum),
+        ];
+        for (name, input) in cases {
+            let expected = decode_bytes(&input).unwrap_err();
+            for chunk_size in [1, 7, 4 * MIB] {
+                let error = read_in_chunks(&input, chunk_size).unwrap_err();
+                assert_eq!(error.kind(), expected.kind(), "{}", name);
+                assert_eq!(error.to_string(), expected.to_string(), "{}", name);
+                // The library error comes along with it
+                let attached = error.get_ref().and_then(|e| e.downcast_ref::<HuffmanError>());
+                assert_eq!(attached.map(|e| e.to_string()), Some(expected.to_string()), "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_reader_keeps_failing_after_an_error() {
+        let encoded = [encode_bytes(b"good").unwrap(), b"junk".to_vec()].concat();
+        // Creating the reader doesn't look at the input yet
+        let mut reader = HuffmanReader::new(&encoded[..]).unwrap();
+        let mut buffer = [0u8; 16];
+        // The data before the error comes first
+        assert_eq!(reader.read(&mut buffer).unwrap(), 4);
+        assert_eq!(&buffer[..4], b"good");
+        let error = reader.read(&mut buffer).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert_eq!(reader.read(&mut buffer).unwrap_err().to_string(), error.to_string());
+    }
+
+    #[test]
+    fn test_reader_refuses_adaptive_and_transformed_streams() {
+        let adaptive = EncoderOptions::builder().adaptive(true).build().unwrap();
+        let encoded = [encode_bytes(b"first").unwrap(), encode_bytes_with(b"second", &adaptive).unwrap()].concat();
+        let error = read_in_chunks(&encoded, 7).unwrap_err();
+        assert_eq!(error.to_string(), "stream at offset 66: adaptive streams can't be read in pieces, use decode_stream");
+
+        let bwt = EncoderOptions::builder().bwt(true).build().unwrap();
+        let error = read_in_chunks(&encode_bytes_with(b"banana", &bwt).unwrap(), 7).unwrap_err();
+        assert_eq!(error.to_string(), "Burrows-Wheeler transformed streams can't be read in pieces, use decode_stream");
+
+        let range = EncoderOptions::builder().method(Method::Range).build().unwrap();
+        let error = read_in_chunks(&encode_bytes_with(b"banana", &range).unwrap(), 7).unwrap_err();
+        assert_eq!(error.to_string(), "range coded streams can't be read in pieces, use decode_stream");
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_huffman_reader() {
+        let data = text_data(&mut Rng(0x4EAD), 8 * MIB);
+        let encoded = encode_bytes(&data).unwrap();
+        let elapsed = bench(|| {
+            assert!(decode_bytes(&encoded).unwrap() == data);
+        });
+        report_throughput("decode_bytes text 8 MiB", data.len(), elapsed);
+        let elapsed = bench(|| {
+            let mut decoded = Vec::with_capacity(data.len());
+            io::copy(&mut HuffmanReader::new(&encoded[..]).unwrap(), &mut decoded).unwrap();
+            assert!(decoded == data);
+        });
+        report_throughput("  HuffmanReader", data.len(), elapsed);
+    }
+}
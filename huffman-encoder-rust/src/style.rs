@@ -0,0 +1,100 @@
+// Colors of the messages of the binary, see --color. The library never colors
+// anything, only the command line does, on stderr and on the summaries it
+// prints.
+
+use std::ffi::OsStr;
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::cli::ColorChoice;
+
+static STDERR_COLOR: AtomicBool = AtomicBool::new(false);
+static STDOUT_COLOR: AtomicBool = AtomicBool::new(false);
+
+// Whether output that goes to a terminal or not (`is_terminal`) gets colors.
+// Auto colors a terminal, unless NO_COLOR is set to anything but "", see
+// https://no-color.org.
+pub(crate) fn use_color(choice: ColorChoice, is_terminal: bool, no_color: Option<&OsStr>) -> bool {
+    match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => is_terminal && no_color.is_none_or(OsStr::is_empty),
+    }
+}
+
+// Decides for stderr and stdout, once the options are parsed
+pub fn init_colors(choice: ColorChoice) {
+    let no_color = std::env::var_os("NO_COLOR");
+    STDERR_COLOR.store(use_color(choice, std::io::stderr().is_terminal(), no_color.as_deref()), Ordering::Relaxed);
+    STDOUT_COLOR.store(use_color(choice, std::io::stdout().is_terminal(), no_color.as_deref()), Ordering::Relaxed);
+}
+
+// Puts text in a color, or leaves it as it is
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Style {
+    enabled: bool,
+}
+
+impl Style {
+    pub(crate) fn stderr() -> Self {
+        Style { enabled: STDERR_COLOR.load(Ordering::Relaxed) }
+    }
+
+    pub(crate) fn stdout() -> Self {
+        Style { enabled: STDOUT_COLOR.load(Ordering::Relaxed) }
+    }
+
+    fn paint(self, code: &str, text: &str) -> String {
+        match self.enabled {
+            true => format!("\x1b[{}m{}\x1b[0m", code, text),
+            false => text.to_string(),
+        }
+    }
+
+    // Errors and failures
+    pub(crate) fn red(self, text: &str) -> String {
+        self.paint("1;31", text)
+    }
+
+    pub(crate) fn yellow(self, text: &str) -> String {
+        self.paint("1;33", text)
+    }
+
+    // What went well
+    pub(crate) fn green(self, text: &str) -> String {
+        self.paint("32", text)
+    }
+
+    pub(crate) fn bold(self, text: &str) -> String {
+        self.paint("1", text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_use_color() {
+        for is_terminal in [false, true] {
+            assert!(use_color(ColorChoice::Always, is_terminal, Some(OsStr::new("1"))));
+            assert!(!use_color(ColorChoice::Never, is_terminal, None));
+        }
+        assert!(use_color(ColorChoice::Auto, true, None));
+        assert!(!use_color(ColorChoice::Auto, false, None));
+        assert!(!use_color(ColorChoice::Auto, true, Some(OsStr::new("1"))));
+        // An empty NO_COLOR doesn't count
+        assert!(use_color(ColorChoice::Auto, true, Some(OsStr::new(""))));
+    }
+
+    #[test]
+    fn test_style() {
+        let on = Style { enabled: true };
+        assert_eq!(on.red("FAILED"), "\x1b[1;31mFAILED\x1b[0m");
+        assert_eq!(on.green("OK"), "\x1b[32mOK\x1b[0m");
+        let off = Style { enabled: false };
+        for text in [off.red("FAILED"), off.yellow("WARNING:"), off.green("OK"), off.bold("3")] {
+            assert!(!text.contains('\x1b'), "{:?}", text);
+        }
+    }
+}
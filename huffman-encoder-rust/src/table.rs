@@ -0,0 +1,1146 @@
+// The code of every byte, for encoding, and the ways back from codes to bytes
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+
+#[cfg(feature = "serde")]
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::error::{HuffmanError, HuffmanResult};
+use crate::io::{Read, Result as IoResult, Write};
+use crate::frequency::{FrequencyTable, Symbol};
+use crate::header::{read_u32, read_u8};
+use crate::tree::{HuffmanNode, HuffmanTree};
+
+// The bits of a code are left aligned: the first bit of the code is the top bit
+// of `bits`, and the bits past `length` are zero
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Code {
+    pub bits: u32,
+    pub length: u8,
+}
+
+// The bits as text, "1011" for 0b1011 of length 4
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for i in 0..self.length as u32 {
+            f.write_str(if self.bits & 1 << (31 - i) != 0 { "1" } else { "0" })?;
+        }
+        Ok(())
+    }
+}
+
+// The other way around from Display, with the checks of a header entry
+impl FromStr for Code {
+    type Err = HuffmanError;
+
+    fn from_str(text: &str) -> HuffmanResult<Code> {
+        let mut bits = 0;
+        for (i, digit) in text.bytes().enumerate() {
+            let bit = match digit {
+                b'0' => 0,
+                b'1' => 1,
+                _ => return Err(HuffmanError::InvalidTable(format!("Invalid code: {:?}, expected 0s and 1s", text))),
+            };
+            if i < 32 {
+                bits |= bit << (31 - i);
+            }
+        }
+        check_code(text.len(), bits).map_err(HuffmanError::InvalidTable)?;
+        Ok(Code { bits, length: text.len() as u8 })
+    }
+}
+
+// What a code from outside, a header or JSON, has to pass. A zero length code
+// would decode into an endless stream of output. Bits past the length are
+// never read, so they have to be zero for a flipped bit in there to be noticed.
+fn check_code(length: usize, bits: u32) -> Result<(), String> {
+    if length == 0 || length > 32 {
+        return Err(format!("Invalid code length: {}", length));
+    }
+    if length < 32 && bits << length != 0 {
+        return Err(format!("Invalid code bits: {:#034b}", bits));
+    }
+    Ok(())
+}
+
+// The code of every character that has one, a byte or with 2 byte symbols a
+// pair of bytes. There are never more than 256 entries, or 65536 for pairs.
+#[derive(Clone, Default, PartialEq)]
+pub struct EncodingTable {
+    codes: BTreeMap<Symbol, Code>,
+    // The code of the bytes without one of their own, see with_escape
+    escape: Option<Code>,
+}
+
+// The symbol of the escape code in the decoders, one past the bytes. Only
+// tables of bytes have an escape code, so nothing else has that symbol.
+pub(crate) const ESCAPE: Symbol = 256;
+// An escape code and the byte after it are written as one code of at most 32
+// bits
+pub const MAX_ESCAPE_LENGTH: u8 = 24;
+
+// Character (1 or 2 bytes, the width of the symbols), code length (1 byte) and
+// code bits (4 bytes)
+pub(crate) fn entry_size(symbol_width: u8) -> u64 {
+    symbol_width as u64 + 5
+}
+
+impl EncodingTable {
+    pub fn new() -> Self {
+        EncodingTable { codes: BTreeMap::new(), escape: None }
+    }
+
+    // The same with an escape code: a byte without a code of its own is
+    // written as the escape code and then its 8 bits as they are, so the
+    // table can encode data with bytes it has never seen. Only for tables of
+    // bytes, see escaped_codes.
+    pub fn with_escape(self, escape: Code) -> Self {
+        EncodingTable { escape: Some(escape), ..self }
+    }
+
+    pub fn escape(&self) -> Option<Code> {
+        self.escape
+    }
+
+    pub fn insert(&mut self, character: Symbol, code: Code) {
+        self.codes.insert(character, code);
+    }
+
+    pub fn get(&self, character: Symbol) -> Option<Code> {
+        self.codes.get(&character).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.codes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.codes.is_empty()
+    }
+
+    // The codes in canonical order: shorter codes first, equal lengths by
+    // character
+    pub fn iter(&self) -> vec::IntoIter<(Symbol, Code)> {
+        let mut codes: Vec<(Symbol, Code)> = self.codes.iter().map(|(&character, &code)| (character, code)).collect();
+        codes.sort_unstable_by_key(|&(character, code)| (code.length, character));
+        codes.into_iter()
+    }
+
+    // 0 for an empty table
+    pub fn max_code_length(&self) -> u8 {
+        self.codes.values().map(|code| code.length).max().unwrap_or(0)
+    }
+
+    // Sum of count * code length, the number of bits the payload of data with
+    // these counts takes. Characters without a code take the escape code and
+    // a byte, without an escape code they are left out, encoding them fails.
+    pub fn expected_payload_bits(&self, frequencies: &FrequencyTable) -> u64 {
+        let escaped = self.escape.map(|escape| escape.length as u64 + 8);
+        frequencies.iter()
+            .filter_map(|(character, count)| Some(count * self.get(character).map(|code| code.length as u64).or(escaped)?))
+            .sum()
+    }
+
+    // Checks that data encoded with the table can be decoded: every code is 1
+    // to 32 bits long without bits set past its length, and no code is a
+    // prefix of another one. Tables read from a header aren't held to the
+    // last part, older files can have codes that overlap.
+    //
+    // The escape code is checked with the others, and it has to leave room for
+    // the byte after it in a tables of bytes.
+    pub fn validate(&self) -> HuffmanResult<()> {
+        let mut codes: Vec<(Symbol, Code)> = self.iter().collect();
+        if let Some(escape) = self.escape {
+            if let Some((character, _)) = codes.iter().find(|&&(character, _)| character >= ESCAPE) {
+                return Err(HuffmanError::InvalidTable(format!("an escape code is for tables of bytes, not of {}", character)));
+            }
+            if escape.length > MAX_ESCAPE_LENGTH {
+                return Err(HuffmanError::InvalidTable(
+                    format!("the escape code is {} bits long, more than {}", escape.length, MAX_ESCAPE_LENGTH),
+                ));
+            }
+            codes.push((ESCAPE, escape));
+        }
+        let name = |character: Symbol| match character {
+            ESCAPE if self.escape.is_some() => String::from("the escape"),
+            character => format!("{}", character),
+        };
+        for &(character, code) in &codes {
+            if code.length == 0 || code.length > 32 {
+                return Err(HuffmanError::InvalidTable(
+                    format!("code of {} has an invalid length: {}", name(character), code.length),
+                ));
+            }
+            if code.length < 32 && code.bits << code.length != 0 {
+                return Err(HuffmanError::InvalidTable(
+                    format!("code of {} has bits past its length: {:#034b}", name(character), code.bits),
+                ));
+            }
+        }
+        // In the order of their bits, a code that is a prefix of others comes
+        // right before them
+        codes.sort_unstable_by_key(|&(character, code)| (code.bits, code.length, character));
+        for pair in codes.windows(2) {
+            let ((first, prefix), (second, code)) = (pair[0], pair[1]);
+            if (code.bits ^ prefix.bits).leading_zeros() >= prefix.length as u32 {
+                return Err(HuffmanError::InvalidTable(
+                    format!("code of {} is a prefix of the code of {}", name(first), name(second)),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // Writes the entries like the header stores them, in character order so
+    // the same table always gives the same bytes
+    pub fn serialize(&self, writer: &mut impl Write) -> IoResult<()> {
+        self.write_entries(writer, 1)
+    }
+
+    // Same as serialize, with characters of `symbol_width` bytes
+    pub(crate) fn write_entries(&self, writer: &mut impl Write, symbol_width: u8) -> IoResult<()> {
+        // A BTreeMap iterates in character order already
+        for (&character, code) in &self.codes {
+            match symbol_width {
+                2 => writer.write_all(&character.to_le_bytes())?,
+                _ => writer.write_all(&[character as u8])?,
+            }
+            writer.write_all(&[code.length])?;
+            writer.write_all(&code.bits.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    // Reads `num_entries` entries written by serialize
+    pub fn deserialize(reader: &mut impl Read, num_entries: u32) -> HuffmanResult<Self> {
+        Self::read_entries(reader, &mut 0, num_entries, 1)
+    }
+
+    // Same as deserialize, for entries of `symbol_width` that start at
+    // `offset` of the input
+    pub(crate) fn read_entries(reader: &mut impl Read, offset: &mut u64, num_entries: u32, symbol_width: u8) -> HuffmanResult<Self> {
+        let mut codes = BTreeMap::new();
+        for _i in 0..num_entries {
+            let character = match symbol_width {
+                2 => u16::from_le_bytes([read_u8(reader, offset, "entries")?, read_u8(reader, offset, "entries")?]),
+                _ => read_u8(reader, offset, "entries")? as Symbol,
+            };
+            // TODO swap length with bits
+            let length = read_u8(reader, offset, "entries")?;
+            let bits = read_u32(reader, offset, "entries")?;
+            check_code(length as usize, bits).map_err(HuffmanError::CorruptHeader)?;
+            codes.insert(character, Code { bits, length });
+        }
+        Ok(EncodingTable { codes, escape: None })
+    }
+
+    // Writes the escape code like an entry without its character, nothing
+    // for a table without one
+    pub(crate) fn write_escape(&self, writer: &mut impl Write) -> IoResult<()> {
+        if let Some(escape) = self.escape {
+            writer.write_all(&[escape.length])?;
+            writer.write_all(&escape.bits.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    // Reads an escape code written by write_escape, with the checks of an
+    // entry
+    pub(crate) fn read_escape(reader: &mut impl Read, offset: &mut u64) -> HuffmanResult<Code> {
+        let length = read_u8(reader, offset, "escape code")?;
+        let bits = read_u32(reader, offset, "escape code")?;
+        check_code(length as usize, bits).map_err(HuffmanError::CorruptHeader)?;
+        if length > MAX_ESCAPE_LENGTH {
+            return Err(HuffmanError::CorruptHeader(format!("Invalid escape code length: {}", length)));
+        }
+        Ok(Code { bits, length })
+    }
+}
+
+impl IntoIterator for &EncodingTable {
+    type Item = (Symbol, Code);
+    type IntoIter = vec::IntoIter<(Symbol, Code)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl FromIterator<(Symbol, Code)> for EncodingTable {
+    fn from_iter<I: IntoIterator<Item = (Symbol, Code)>>(iter: I) -> Self {
+        EncodingTable { codes: iter.into_iter().collect(), escape: None }
+    }
+}
+
+// A table of bytes, the usual kind
+impl FromIterator<(u8, Code)> for EncodingTable {
+    fn from_iter<I: IntoIterator<Item = (u8, Code)>>(iter: I) -> Self {
+        iter.into_iter().map(|(character, code)| (character as Symbol, code)).collect()
+    }
+}
+
+impl fmt::Debug for EncodingTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).entries(self.escape.map(|escape| ("escape", escape))).finish()
+    }
+}
+
+// Text
+////////////////////////////////////////////////////////////////////////////////
+
+// One line per character with its byte in hex and its code, in canonical
+// order, and the escape code last:
+//
+//     0x61 0
+//     0x62 100
+//     escape 101
+//
+// Blank lines and lines starting with # are skipped when reading, for notes.
+impl EncodingTable {
+    pub fn to_text(&self) -> String {
+        let escape = self.escape.map(|escape| format!("escape {}\n", escape));
+        self.iter().map(|(character, code)| format!("{:#04x} {}\n", character, code)).chain(escape).collect()
+    }
+
+    // Every line gets the checks of a header entry, and the whole table those
+    // of validate(), so a table that loads can be encoded with
+    pub fn from_text(text: &str) -> HuffmanResult<Self> {
+        let mut codes = BTreeMap::new();
+        let mut escape = None;
+        for (i, line) in text.lines().enumerate().map(|(i, line)| (i + 1, line.trim())) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |message: String| HuffmanError::InvalidTable(format!("line {}: {}", i, message));
+            let (character, code) = match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [character, code] => (character, code),
+                _ => return Err(invalid(format!("expected a byte and a code, like `0x61 1011`, got {:?}", line))),
+            };
+            let code = code.parse::<Code>().map_err(|e| match e {
+                HuffmanError::InvalidTable(message) => invalid(message),
+                e => e,
+            })?;
+            if character == "escape" {
+                if escape.replace(code).is_some() {
+                    return Err(invalid(String::from("there is an escape code already")));
+                }
+                continue;
+            }
+            let character = character.strip_prefix("0x")
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .ok_or_else(|| invalid(format!("invalid byte {:?}, expected 0x00 to 0xff", character)))?;
+            if codes.insert(character as Symbol, code).is_some() {
+                return Err(invalid(format!("{:#04x} has a code already", character)));
+            }
+        }
+        let table = EncodingTable { codes, escape };
+        table.validate()?;
+        Ok(table)
+    }
+}
+
+// Serde
+////////////////////////////////////////////////////////////////////////////////
+
+// A code is its bits as a string, "1011"
+#[cfg(feature = "serde")]
+impl Serialize for Code {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Code {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct CodeVisitor;
+
+        impl de::Visitor<'_> for CodeVisitor {
+            type Value = Code;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a code of 1 to 32 bits, like \"1011\"")
+            }
+
+            fn visit_str<E: de::Error>(self, text: &str) -> Result<Code, E> {
+                text.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_str(CodeVisitor)
+    }
+}
+
+// A map from character to code, in character order like the header, so
+// {"97": "0", "98": "10", "99": "11"} in JSON
+#[cfg(feature = "serde")]
+impl Serialize for EncodingTable {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(&self.codes)
+    }
+}
+
+// Every code gets the checks of an entry read from a header. Like there, the
+// table doesn't have to be a prefix code, validate() checks that. The keys are
+// bytes, like the tables of --table-file.
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for EncodingTable {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(BTreeMap::<u8, Code>::deserialize(deserializer)?.into_iter().collect())
+    }
+}
+
+// The encoding table indexed directly by the byte, for the encode loop. A
+// table of pairs has its own, see build_pair_lookup.
+pub(crate) type CodeLookup = [Option<Code>; 256];
+
+// Bytes without a code get the escape code with the byte after it, as a single
+// code, when the table has one.
+pub(crate) fn build_code_lookup(encoding_table: &EncodingTable) -> CodeLookup {
+    let mut lookup = [None; 256];
+    if let Some(escape) = encoding_table.escape {
+        for (byte, code) in lookup.iter_mut().enumerate() {
+            let bits = escape.bits | (byte as u32) << (24 - escape.length);
+            *code = Some(Code { bits, length: escape.length + 8 });
+        }
+    }
+    for (&character, &code) in encoding_table.codes.range(..256) {
+        lookup[character as usize] = Some(code);
+    }
+    lookup
+}
+
+// The same indexed by a pair of bytes, 65536 entries
+pub(crate) fn build_pair_lookup(encoding_table: &EncodingTable) -> Vec<Option<Code>> {
+    let mut lookup = vec![None; 1 << 16];
+    for (&character, &code) in &encoding_table.codes {
+        lookup[character as usize] = Some(code);
+    }
+    lookup
+}
+
+// Walks the tree without recursion, left before right, collecting the code of
+// every leaf
+fn traverse(tree: &HuffmanTree, encoding_table: &mut EncodingTable) {
+    let mut stack = vec![(tree.nodes.len() - 1, Code { bits: 0, length: 0 })];
+    while let Some((index, code)) = stack.pop() {
+        match tree.nodes[index] {
+            HuffmanNode::Leaf { character, .. } => {
+                encoding_table.insert(character, code);
+            }
+            HuffmanNode::Parent { left, right, .. } => {
+                // Flips the next bit to '1'
+                // Example with 8 bits but it's actually 32:
+                // 1 = 0b00000001
+                // 1 << 7 = 0b10000000
+                // 1 << 6 = 0b01000000 etc..
+                let right_bits = code.bits | 1 << (31 - code.length);
+                stack.push((right as usize, Code { bits: right_bits, length: code.length + 1 }));
+                // Left just increments the length, keeping a 0
+                stack.push((left as usize, Code { bits: code.bits, length: code.length + 1 }));
+            }
+        }
+    }
+}
+
+// Encoding Table
+////////////////////////////////////////////////////////////////////////////////
+
+// The codes of the bytes of `frequencies` with an escape code for the others,
+// for a table that is used on data it wasn't made from, like the table
+// command. The escape gets the count of a byte seen once, or more when that
+// would make it longer than MAX_ESCAPE_LENGTH.
+pub fn escaped_codes(frequencies: &FrequencyTable) -> EncodingTable {
+    let mut count = 1;
+    loop {
+        let mut counts = FrequencyTable::with_symbol_width(2);
+        counts.counts[..256].copy_from_slice(&frequencies.counts[..256]);
+        counts.counts[ESCAPE as usize] = count;
+        let mut encoding_table = HuffmanTree::from_frequencies(&counts).expect("the escape is counted").codes();
+        let escape = encoding_table.codes.remove(&ESCAPE).expect("the escape has a code");
+        if escape.length <= MAX_ESCAPE_LENGTH {
+            return encoding_table.with_escape(escape);
+        }
+        count *= 2;
+    }
+}
+
+// Builds a map from character to binary code, so 'a'-> 10
+pub fn build_encoding_table(tree: &HuffmanTree) -> EncodingTable {
+    let mut encoding_table = EncodingTable::new();
+    match tree.root() {
+        // A single character would get an empty code, so it could never be
+        // written. Give it a 1 bit code instead.
+        HuffmanNode::Leaf { character, .. } => {
+            encoding_table.insert(*character, Code { bits: 0, length: 1 });
+        }
+        HuffmanNode::Parent { .. } => {
+            traverse(tree, &mut encoding_table);
+        }
+    }
+    // Only the lengths come from the tree, lengths from a tree always fit
+    canonical_codes(&encoding_table).expect("Huffman code lengths fit in a prefix code")
+}
+
+// New bits for codes of the same lengths, in canonical order: shorter codes
+// first, equal lengths by character, and every code is the previous one plus
+// one. Decoding can then work with the range of codes of each length. None
+// when there are too many codes of some length for a prefix code.
+pub(crate) fn canonical_codes(encoding_table: &EncodingTable) -> Option<EncodingTable> {
+    let mut symbols: Vec<(u8, Symbol)> = encoding_table
+        .codes
+        .iter()
+        .map(|(&character, code)| (code.length, character))
+        .collect();
+    symbols.sort();
+
+    let mut canonical = BTreeMap::new();
+    let mut next_code = 0u64;
+    let mut previous_length = 0;
+    for (length, character) in symbols {
+        next_code <<= length - previous_length;
+        if next_code >= 1 << length {
+            return None;
+        }
+        // Left aligned, like the codes from traverse
+        canonical.insert(character, Code { bits: (next_code << (32 - length)) as u32, length });
+        next_code += 1;
+        previous_length = length;
+    }
+    Some(EncodingTable { codes: canonical, escape: None })
+}
+
+// Decoding table
+////////////////////////////////////////////////////////////////////////////////
+
+// Bits the first level of the decode table is indexed with. Longer codes
+// continue in smaller tables, indexed with the bits that follow.
+const DECODE_ROOT_BITS: u8 = 10;
+const DECODE_SUB_BITS: u8 = 8;
+
+#[derive(Clone, Copy)]
+enum DecodeEntry {
+    // No code starts with these bits
+    Invalid,
+    // `length` is the full length of the code, not just the part in this level
+    Symbol { character: Symbol, length: u8 },
+    // The code is longer and continues in the table at `start`
+    Table { start: u32, bits: u8 },
+}
+
+// Finds the next code from one peek of 32 bits, instead of probing a map after
+// every bit. All levels live in one Vec, the first one at index 0.
+pub(crate) struct DecodeTable {
+    entries: Vec<DecodeEntry>,
+    root_bits: u8,
+}
+
+impl DecodeTable {
+    fn new(encoding_table: &EncodingTable) -> Self {
+        // Shortest codes first. The header doesn't guarantee a prefix code, so
+        // when codes overlap the shortest one wins like it did bit by bit, and
+        // between equal codes the lowest character.
+        let mut codes: Vec<(u32, u8, Symbol)> = encoding_table
+            .codes
+            .iter()
+            .map(|(&character, code)| (code.bits, code.length, character))
+            .collect();
+        codes.sort_by_key(|&(_, length, character)| (length, character));
+
+        let mut entries = Vec::new();
+        let root_bits = Self::build_level(&mut entries, &codes, 0);
+        DecodeTable { entries, root_bits }
+    }
+
+    // Appends the table for `codes`, which all share their first `prefix_length`
+    // bits, and returns how many bits it is indexed with
+    fn build_level(entries: &mut Vec<DecodeEntry>, codes: &[(u32, u8, Symbol)], prefix_length: u8) -> u8 {
+        let max_length = codes.iter().map(|&(_, length, _)| length).max().unwrap_or(1);
+        let width = if prefix_length == 0 { DECODE_ROOT_BITS } else { DECODE_SUB_BITS };
+        let bits = (max_length - prefix_length).clamp(1, width);
+        let start = entries.len();
+        entries.resize(start + (1 << bits), DecodeEntry::Invalid);
+
+        // The `bits` bits of a code right after the prefix. Bits past the
+        // length are zero, so for a short code this is the first entry it fills.
+        let index = |code_bits: u32| ((code_bits << prefix_length) >> (32 - bits)) as usize;
+
+        let (short_codes, mut long_codes): (Vec<_>, Vec<_>) =
+            codes.iter().copied().partition(|&(_, length, _)| length - prefix_length <= bits);
+        for (code_bits, length, character) in short_codes {
+            let first = start + index(code_bits);
+            let span = 1 << (bits - (length - prefix_length));
+            for entry in &mut entries[first..first + span] {
+                if let DecodeEntry::Invalid = entry {
+                    *entry = DecodeEntry::Symbol { character, length };
+                }
+            }
+        }
+
+        // Stable, so every group stays sorted shortest first
+        long_codes.sort_by_key(|&(code_bits, _, _)| index(code_bits));
+        for group in long_codes.chunk_by(|a, b| index(a.0) == index(b.0)) {
+            let slot = start + index(group[0].0);
+            // Already taken by a shorter code that is a prefix of these
+            if let DecodeEntry::Symbol { .. } = entries[slot] {
+                continue;
+            }
+            let sub_start = entries.len() as u32;
+            let sub_bits = Self::build_level(entries, group, prefix_length + bits);
+            entries[slot] = DecodeEntry::Table { start: sub_start, bits: sub_bits };
+        }
+        bits
+    }
+
+    // The character and code length the 32 bits of `window` start with, None
+    // when no code does
+    #[inline]
+    fn decode(&self, window: u32) -> Option<(Symbol, u8)> {
+        let mut start = 0;
+        let mut bits = self.root_bits;
+        let mut used = 0;
+        loop {
+            let index = ((window << used) >> (32 - bits)) as usize;
+            match self.entries[start + index] {
+                DecodeEntry::Symbol { character, length } => return Some((character, length)),
+                DecodeEntry::Invalid => return None,
+                DecodeEntry::Table { start: next, bits: next_bits } => {
+                    start = next as usize;
+                    used += bits;
+                    bits = next_bits;
+                }
+            }
+        }
+    }
+}
+
+// Decoding of canonical codes with the first code and the number of codes of
+// every length (Moffat and Turpin). The top `length` bits of the window are a
+// code of that length when they fall in its range, and the characters are
+// stored in code order, so no table of every bit pattern is needed.
+pub(crate) struct CanonicalDecoder {
+    // Per length, one past the last code of that length, left aligned in 32
+    // bits. The window starts with a code of the first length whose limit
+    // is above it.
+    limit: [u64; 33],
+    // Per length, the first code right aligned, and where the characters of
+    // that length start in `characters`
+    first_code: [u32; 33],
+    offset: [usize; 33],
+    characters: Vec<Symbol>,
+    min_length: u8,
+    max_length: u8,
+}
+
+impl CanonicalDecoder {
+    // None when the codes aren't canonical, like in files from before the
+    // encoder assigned canonical codes
+    fn new(encoding_table: &EncodingTable) -> Option<Self> {
+        let canonical = canonical_codes(encoding_table)?;
+        let matches = canonical == *encoding_table;
+        if !matches || encoding_table.is_empty() {
+            return None;
+        }
+
+        let mut codes: Vec<(u8, Symbol, u32)> = encoding_table
+            .iter()
+            .map(|(character, code)| (code.length, character, code.bits))
+            .collect();
+        codes.sort();
+
+        let mut decoder = CanonicalDecoder {
+            limit: [0; 33],
+            first_code: [0; 33],
+            offset: [0; 33],
+            characters: Vec::with_capacity(codes.len()),
+            min_length: codes[0].0,
+            max_length: codes[codes.len() - 1].0,
+        };
+        let mut next_code = 0u64;
+        let mut previous_length = 0;
+        for (length, character, bits) in codes {
+            for l in previous_length + 1..=length {
+                next_code <<= 1;
+                decoder.first_code[l as usize] = next_code as u32;
+                decoder.offset[l as usize] = decoder.characters.len();
+            }
+            debug_assert_eq!(bits as u64, next_code << (32 - length));
+            decoder.characters.push(character);
+            next_code += 1;
+            decoder.limit[length as usize] = next_code << (32 - length);
+            previous_length = length;
+        }
+        Some(decoder)
+    }
+
+    // Same as DecodeTable::decode
+    #[inline]
+    fn decode(&self, window: u32) -> Option<(Symbol, u8)> {
+        let mut length = self.min_length;
+        // Lengths without codes keep a limit of 0, so they are skipped
+        while window as u64 >= self.limit[length as usize] {
+            if length == self.max_length {
+                return None;
+            }
+            length += 1;
+        }
+        let l = length as usize;
+        let index = (window >> (32 - length as u32)) - self.first_code[l];
+        Some((self.characters[self.offset[l] + index as usize], length))
+    }
+}
+
+// Canonical codes are decoded from their ranges, anything else needs the table
+pub(crate) enum Decoder {
+    Canonical(Box<CanonicalDecoder>),
+    Table(DecodeTable),
+}
+
+impl Decoder {
+    // The escape code decodes to ESCAPE
+    pub(crate) fn new(encoding_table: &EncodingTable) -> Self {
+        let with_escape;
+        let encoding_table = match encoding_table.escape {
+            Some(escape) => {
+                let mut codes = encoding_table.codes.clone();
+                codes.insert(ESCAPE, escape);
+                with_escape = EncodingTable { codes, escape: None };
+                &with_escape
+            }
+            None => encoding_table,
+        };
+        match CanonicalDecoder::new(encoding_table) {
+            Some(decoder) => Decoder::Canonical(Box::new(decoder)),
+            None => Decoder::Table(DecodeTable::new(encoding_table)),
+        }
+    }
+
+    // Called for every decoded byte, from both instantiations of decode_file.
+    // Without the hint it stops being inlined and decoding gets 30% slower.
+    #[inline]
+    pub(crate) fn decode(&self, window: u32) -> Option<(Symbol, u8)> {
+        match self {
+            Decoder::Canonical(decoder) => decoder.decode(window),
+            Decoder::Table(table) => table.decode(window),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::encode_bytes;
+    #[cfg(feature = "std")]
+    use crate::estimate::entropy_bits_per_byte;
+    use crate::frequency::calculate_frequencies;
+    use crate::header::Header;
+    use crate::test_util::Rng;
+    use crate::tree::build_huffman_tree;
+
+    fn table_for(counts: &[u32]) -> (FrequencyTable, EncodingTable) {
+        let frequencies: FrequencyTable = counts.iter().enumerate()
+            .map(|(i, &count)| (i as u8, count as u64))
+            .collect();
+        let tree = build_huffman_tree(&frequencies).unwrap();
+        let encoding_table = build_encoding_table(&tree);
+        (frequencies, encoding_table)
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_huffman_codes_are_optimal() {
+        // (frequencies, optimal weighted path length worked out by hand)
+        let cases: [(&[u32], u64); 6] = [
+            (&[1; 8], 24),
+            (&[1; 5], 12),
+            (&[1, 1, 2, 4, 8, 16], 62),
+            (&[1, 1, 2, 3, 5, 8], 45),
+            (&[5, 9, 12, 13, 16, 45], 224),
+            (&[7, 1], 8),
+        ];
+
+        for (counts, optimal) in cases {
+            let (frequencies, encoding_table) = table_for(counts);
+            let wpl = encoding_table.expected_payload_bits(&frequencies);
+            assert_eq!(wpl, optimal, "frequencies {:?}", counts);
+
+            let total: u64 = counts.iter().map(|&count| count as u64).sum();
+            let average = wpl as f64 / total as f64;
+            let entropy = entropy_bits_per_byte(&frequencies);
+            assert!(average >= entropy - 1e-9, "frequencies {:?}", counts);
+            assert!(average < entropy + 1.0, "frequencies {:?}", counts);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_powers_of_two_match_entropy_exactly() {
+        let (frequencies, encoding_table) = table_for(&[1, 1, 2, 4, 8, 16]);
+        let average = encoding_table.expected_payload_bits(&frequencies) as f64 / 32.0;
+        assert!((average - entropy_bits_per_byte(&frequencies)).abs() < 1e-9);
+    }
+
+    // A complete prefix code uses up the whole code space: sum of 2^-length is 1
+    #[test]
+    fn test_kraft_equality_holds() {
+        let mut rng = Rng(0x4B52_4146);
+        let mut tables: Vec<Vec<u32>> = vec![vec![1; 256], vec![1, 1, 2, 3, 5, 8, 13, 21, 34, 55]];
+        for _ in 0..200 {
+            let symbols = 2 + rng.below(255);
+            tables.push((0..symbols).map(|_| 1 + rng.below(1000) as u32).collect());
+        }
+
+        for counts in tables {
+            let (_, encoding_table) = table_for(&counts);
+            let kraft_sum: f64 = encoding_table.iter()
+                .map(|(_, code)| 2f64.powi(-(code.length as i32)))
+                .sum();
+            assert!((kraft_sum - 1.0).abs() < 1e-9, "frequencies {:?}", counts);
+        }
+    }
+
+    #[test]
+    fn test_decode_table_finds_every_code() {
+        let mut rng = Rng(0x4C55_5421);
+        for counts in test_tables(&mut rng) {
+            let (_, encoding_table) = table_for(&counts);
+            let decode_table = DecodeTable::new(&encoding_table);
+            for (character, code) in &encoding_table {
+                // Whatever follows the code doesn't matter
+                let following = if code.length == 32 { 0 } else { rng.next() as u32 >> code.length };
+                let found = decode_table.decode(code.bits | following);
+                assert_eq!(found, Some((character, code.length)), "code {:#034b} of {} bits in {:?}", code.bits, code.length, counts);
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_table_prefers_the_shortest_code() {
+        // Not a prefix code: 'a' is a prefix of 'b' and of the 12 bit 'c', and
+        // nothing starts with 11
+        let encoding_table: EncodingTable = [
+            (b'a', Code { bits: 0, length: 1 }),
+            (b'b', Code { bits: 0b01 << 30, length: 2 }),
+            (b'c', Code { bits: 0b0101 << 28, length: 12 }),
+            (b'd', Code { bits: 0b10 << 30, length: 2 }),
+            (b'e', Code { bits: 0b10 << 30, length: 2 }),
+        ].into_iter().collect();
+        let decode_table = DecodeTable::new(&encoding_table);
+
+        assert_eq!(decode_table.decode(0b0101 << 28), Some((b'a' as Symbol, 1)));
+        assert_eq!(decode_table.decode(0b10 << 30), Some((b'd' as Symbol, 2)));
+        assert_eq!(decode_table.decode(0b11 << 30), None);
+    }
+
+    // Flat, random and Fibonacci counts, the last with codes of up to 30 bits
+    fn test_tables(rng: &mut Rng) -> Vec<Vec<u32>> {
+        let fibonacci: Vec<u32> = (0..31).scan((1, 1), |state, _| {
+            let count = state.0;
+            *state = (state.1, state.0 + state.1);
+            Some(count)
+        }).collect();
+        let mut tables = vec![vec![1; 256], vec![1, 1], vec![1], fibonacci];
+        for _ in 0..50 {
+            let symbols = 2 + rng.below(255);
+            let spread = 1 << rng.below(20);
+            tables.push((0..symbols).map(|_| 1 + rng.below(spread) as u32).collect());
+        }
+        tables
+    }
+
+    #[test]
+    fn test_canonical_decoder_finds_every_code() {
+        let mut rng = Rng(0x4341_4E4F);
+        for counts in test_tables(&mut rng) {
+            let (_, encoding_table) = table_for(&counts);
+            let decoder = CanonicalDecoder::new(&encoding_table).expect("encoder codes are canonical");
+            for (character, code) in &encoding_table {
+                let following = if code.length == 32 { 0 } else { rng.next() as u32 >> code.length };
+                let found = decoder.decode(code.bits | following);
+                assert_eq!(found, Some((character, code.length)), "code {:#034b} of {} bits in {:?}", code.bits, code.length, counts);
+            }
+        }
+    }
+
+    #[test]
+    fn test_canonical_decoder_matches_the_lookup_table() {
+        let mut rng = Rng(0x4655_5A5A);
+        for counts in test_tables(&mut rng) {
+            let (_, mut encoding_table) = table_for(&counts);
+            // Without its longest code the table is still canonical, but leaves
+            // bit patterns without a code
+            if rng.below(2) == 0 && encoding_table.len() > 1 {
+                let (longest, _) = encoding_table.iter().last().unwrap();
+                encoding_table.codes.remove(&longest);
+            }
+            let decoder = CanonicalDecoder::new(&encoding_table).unwrap();
+            let reference = DecodeTable::new(&encoding_table);
+            for _ in 0..2000 {
+                let window = rng.next() as u32;
+                assert_eq!(decoder.decode(window), reference.decode(window), "window {:#034b} in {:?}", window, counts);
+            }
+        }
+    }
+
+    #[test]
+    fn test_non_canonical_codes_use_the_lookup_table() {
+        // What the encoder used to write for "aab": 'b' on the left
+        let legacy: EncodingTable = [
+            (b'a', Code { bits: 1 << 31, length: 1 }),
+            (b'b', Code { bits: 0, length: 1 }),
+        ].into_iter().collect();
+        assert!(CanonicalDecoder::new(&legacy).is_none());
+        assert!(matches!(Decoder::new(&legacy), Decoder::Table(_)));
+
+        let (_, encoding_table) = table_for(&[2, 1]);
+        assert!(matches!(Decoder::new(&encoding_table), Decoder::Canonical(_)));
+
+        // Three codes of one bit don't fit in a prefix code
+        let oversubscribed: EncodingTable = (0..3u8).map(|c| (c, Code { bits: 0, length: 1 })).collect();
+        assert!(canonical_codes(&oversubscribed).is_none());
+    }
+
+    #[test]
+    fn test_validate_accepts_built_tables() {
+        let mut rng = Rng(0x5641_4C49);
+        for counts in test_tables(&mut rng) {
+            let (_, encoding_table) = table_for(&counts);
+            assert!(encoding_table.validate().is_ok(), "frequencies {:?}", counts);
+        }
+        assert!(EncodingTable::new().validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_rejects_bad_tables() {
+        let table = |codes: &[(u8, u32, u8)]| -> EncodingTable {
+            codes.iter().map(|&(character, bits, length)| (character, Code { bits, length })).collect()
+        };
+        let message = |encoding_table: EncodingTable| match encoding_table.validate() {
+            Err(HuffmanError::InvalidTable(message)) => message,
+            result => panic!("expected an invalid table, got {:?}", result.map(|_| ())),
+        };
+
+        assert_eq!(message(table(&[(b'a', 0, 0)])), "code of 97 has an invalid length: 0");
+        assert_eq!(message(table(&[(b'a', 0, 33)])), "code of 97 has an invalid length: 33");
+        assert!(message(table(&[(b'a', 0b11 << 30, 1)])).starts_with("code of 97 has bits past its length"));
+        // 0 is a prefix of 01, with a code in between them in bit order
+        let prefix = table(&[(b'a', 0, 1), (b'b', 0b01 << 30, 2), (b'c', 0b001 << 29, 3), (b'd', 1 << 31, 1)]);
+        assert_eq!(message(prefix), "code of 97 is a prefix of the code of 99");
+        assert_eq!(message(table(&[(b'a', 0, 2), (b'b', 0, 2)])), "code of 97 is a prefix of the code of 98");
+
+        // Not complete, but still a prefix code
+        assert!(table(&[(b'a', 0, 2), (b'b', 1 << 31, 2)]).validate().is_ok());
+        assert!(table(&[(b'a', u32::MAX, 32)]).validate().is_ok());
+    }
+
+    #[test]
+    fn test_expected_payload_bits_matches_the_encoded_size() {
+        let data = include_bytes!("../tests/fixtures/english.txt");
+        let frequencies = calculate_frequencies(&data[..], 4096).unwrap();
+        let encoding_table = build_encoding_table(&build_huffman_tree(&frequencies).unwrap());
+        let encoded = encode_bytes(data).unwrap();
+        let payload_bytes = encoded.len() as u64 - 34 - 6 * encoding_table.len() as u64;
+        assert_eq!(encoding_table.expected_payload_bits(&frequencies).div_ceil(8), payload_bytes);
+
+        // Characters without a code don't count
+        let partial: EncodingTable = [(b'a', Code { bits: 0, length: 3 })].into_iter().collect();
+        assert_eq!(partial.expected_payload_bits(&FrequencyTable::from_iter([(b'a', 5), (b'b', 7)])), 15);
+    }
+
+    #[test]
+    fn test_max_code_length() {
+        assert_eq!(EncodingTable::new().max_code_length(), 0);
+        assert_eq!(table_for(&[7]).1.max_code_length(), 1);
+        assert_eq!(table_for(&[1, 1, 2, 4, 8, 16]).1.max_code_length(), 5);
+        assert_eq!(table_for(&[1; 256]).1.max_code_length(), 8);
+    }
+
+    #[test]
+    fn test_iteration_is_in_canonical_order() {
+        let (_, encoding_table) = table_for(&[1, 16, 1, 2, 8, 4]);
+        let order: Vec<(Symbol, u8)> = encoding_table.iter().map(|(character, code)| (character, code.length)).collect();
+        assert_eq!(order, [(1, 1), (4, 2), (5, 3), (3, 4), (0, 5), (2, 5)]);
+        // Every code is the previous one plus one, shifted to its length
+        let mut expected = 0u64;
+        let mut previous_length = 0;
+        for (_, code) in &encoding_table {
+            expected <<= code.length - previous_length;
+            assert_eq!(code.bits as u64, expected << (32 - code.length));
+            expected += 1;
+            previous_length = code.length;
+        }
+    }
+
+    #[test]
+    fn test_serialize_matches_the_header_entries() {
+        let (_, encoding_table) = table_for(&[3, 1, 1]);
+        let mut bytes = Vec::new();
+        encoding_table.serialize(&mut bytes).unwrap();
+        // In character order: character, length, then the bits little endian
+        assert_eq!(bytes, [
+            0, 1, 0, 0, 0, 0x00,
+            1, 2, 0, 0, 0, 0x80,
+            2, 2, 0, 0, 0, 0xC0,
+        ]);
+        assert_eq!(EncodingTable::deserialize(&mut &bytes[..], 3).unwrap(), encoding_table);
+
+        let encoded = encode_bytes(b"abracadabra").unwrap();
+        let mut table_bytes = Vec::new();
+        let header = Header::read_from(&mut &encoded[..]).unwrap();
+        header.encoding_table.serialize(&mut table_bytes).unwrap();
+        assert_eq!(&encoded[34..34 + table_bytes.len()], &table_bytes[..]);
+    }
+
+    #[test]
+    fn test_deserialize_round_trips_and_rejects_bad_entries() {
+        let mut rng = Rng(0x5345_5249);
+        for counts in test_tables(&mut rng) {
+            let (_, encoding_table) = table_for(&counts);
+            let mut bytes = Vec::new();
+            encoding_table.serialize(&mut bytes).unwrap();
+            assert_eq!(bytes.len() as u64, entry_size(1) * encoding_table.len() as u64);
+            let decoded = EncodingTable::deserialize(&mut &bytes[..], encoding_table.len() as u32).unwrap();
+            assert_eq!(decoded, encoding_table, "frequencies {:?}", counts);
+        }
+
+        let error = |bytes: &[u8], num_entries| EncodingTable::deserialize(&mut &bytes[..], num_entries).unwrap_err().to_string();
+        assert_eq!(error(&[b'a', 0, 0, 0, 0, 0], 1), "Invalid code length: 0");
+        assert_eq!(error(&[b'a', 33, 0, 0, 0, 0], 1), "Invalid code length: 33");
+        assert!(error(&[b'a', 1, 0, 0, 0, 0xC0], 1).starts_with("Invalid code bits"));
+        assert_eq!(error(&[b'a', 1, 0, 0, 0, 0x80, b'b'], 2), "truncated header, the file ends at offset 7 in the entries");
+    }
+
+    #[test]
+    fn test_code_text_round_trips() {
+        assert_eq!(Code { bits: 0b1011 << 28, length: 4 }.to_string(), "1011");
+        assert_eq!(Code { bits: 0, length: 3 }.to_string(), "000");
+        assert_eq!(Code { bits: u32::MAX, length: 32 }.to_string(), "1".repeat(32));
+        for (_, code) in &table_for(&[5, 2, 1, 1, 2, 9, 30]).1 {
+            assert_eq!(code.to_string().parse::<Code>().unwrap(), code);
+        }
+
+        let error = |text: &str| text.parse::<Code>().unwrap_err().to_string();
+        assert_eq!(error(""), "invalid encoding table: Invalid code length: 0");
+        assert_eq!(error(&"0".repeat(33)), "invalid encoding table: Invalid code length: 33");
+        assert_eq!(error("0120"), "invalid encoding table: Invalid code: \"0120\", expected 0s and 1s");
+    }
+
+    #[test]
+    fn test_text_round_trips_through_the_binary_entries() {
+        let (_, encoding_table) = table_for(&[3, 1, 1]);
+        assert_eq!(encoding_table.to_text(), "0x00 0\n0x01 10\n0x02 11\n");
+
+        let mut rng = Rng(0x7E47);
+        for counts in test_tables(&mut rng) {
+            let (_, encoding_table) = table_for(&counts);
+            let from_text = EncodingTable::from_text(&encoding_table.to_text()).unwrap();
+            assert_eq!(from_text, encoding_table, "frequencies {:?}", counts);
+            let mut bytes = Vec::new();
+            from_text.serialize(&mut bytes).unwrap();
+            let from_binary = EncodingTable::deserialize(&mut &bytes[..], from_text.len() as u32).unwrap();
+            assert_eq!(from_binary.to_text(), encoding_table.to_text());
+        }
+
+        // Notes, blank lines, spacing and upper case hex are fine
+        let text = "# a, b and c\n\n  0x61   0\n0x62\t10\n0x6C 11";
+        assert_eq!(EncodingTable::from_text(text).unwrap().to_text(), "0x61 0\n0x62 10\n0x6c 11\n");
+    }
+
+    #[test]
+    fn test_from_text_rejects_bad_tables() {
+        let error = |text: &str| EncodingTable::from_text(text).unwrap_err().to_string();
+        assert_eq!(error("0x61 0\n0x62"), "invalid encoding table: line 2: expected a byte and a code, like `0x61 1011`, got \"0x62\"");
+        assert_eq!(error("0x61 0 1"), "invalid encoding table: line 1: expected a byte and a code, like `0x61 1011`, got \"0x61 0 1\"");
+        assert_eq!(error("a 0"), "invalid encoding table: line 1: invalid byte \"a\", expected 0x00 to 0xff");
+        assert_eq!(error("0x100 0"), "invalid encoding table: line 1: invalid byte \"0x100\", expected 0x00 to 0xff");
+        assert_eq!(error("0x61 012"), "invalid encoding table: line 1: Invalid code: \"012\", expected 0s and 1s");
+        assert_eq!(error("0x61 0\n0x62 10\n0x61 11"), "invalid encoding table: line 3: 0x61 has a code already");
+        // 1 is a prefix of 10, so 10 could be either
+        assert_eq!(error("0x61 1\n0x62 10\n0x63 0"), "invalid encoding table: code of 97 is a prefix of the code of 98");
+        assert_eq!(error("0x61 1\nescape 10"), "invalid encoding table: code of 97 is a prefix of the code of the escape");
+        assert_eq!(error("0x61 1\nescape 0\nescape 01"), "invalid encoding table: line 3: there is an escape code already");
+    }
+
+    #[test]
+    fn test_escape_codes() {
+        // The escape is counted like a byte seen once, and comes last in text
+        let frequencies = calculate_frequencies(&b"abracadabra"[..], 4096).unwrap();
+        let encoding_table = escaped_codes(&frequencies);
+        assert_eq!(encoding_table.to_text(), "0x61 0\n0x62 100\n0x72 101\n0x63 1110\n0x64 1111\nescape 110\n");
+        assert_eq!(EncodingTable::from_text(&encoding_table.to_text()).unwrap(), encoding_table);
+        encoding_table.validate().unwrap();
+
+        // A byte without a code is the escape and its 8 bits
+        let lookup = build_code_lookup(&encoding_table);
+        assert_eq!(lookup[b'a' as usize], encoding_table.get(b'a' as Symbol));
+        assert_eq!(lookup[b'z' as usize], Some(Code { bits: 0b110_01111010 << 21, length: 11 }));
+        let zeros: FrequencyTable = [(b'a', 2), (b'z', 3)].into_iter().collect();
+        assert_eq!(encoding_table.expected_payload_bits(&zeros), 2 + 3 * 11);
+
+        // Nothing counted leaves only the escape
+        let empty = escaped_codes(&FrequencyTable::new());
+        assert!(empty.is_empty());
+        assert_eq!(empty.escape(), Some(Code { bits: 0, length: 1 }));
+
+        // Counts that would make the escape too long give it a higher count
+        let mut counts = FrequencyTable::new();
+        let mut fibonacci = (1u64, 1u64);
+        for byte in 0..40u8 {
+            counts.counts[byte as usize] = fibonacci.0;
+            fibonacci = (fibonacci.1, fibonacci.0 + fibonacci.1);
+        }
+        let encoding_table = escaped_codes(&counts);
+        assert!(encoding_table.escape().unwrap().length <= MAX_ESCAPE_LENGTH);
+        encoding_table.validate().unwrap();
+
+        let too_long = EncodingTable::new().with_escape(Code { bits: 0, length: 25 });
+        assert_eq!(too_long.validate().unwrap_err().to_string(), "invalid encoding table: the escape code is 25 bits long, more than 24");
+        let pairs: EncodingTable = [(0x100 as Symbol, Code { bits: 0, length: 1 })].into_iter().collect();
+        assert!(pairs.with_escape(Code { bits: 1 << 31, length: 1 }).validate().is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_json_round_trips_through_the_header_entries() {
+        let (_, encoding_table) = table_for(&[5, 2, 1, 1, 2]);
+        let json = serde_json::to_string(&encoding_table).unwrap();
+        assert_eq!(json, r#"{"0":"0","1":"100","2":"101","3":"110","4":"111"}"#);
+
+        let mut rng = Rng(0x4A53_4F4E);
+        for counts in test_tables(&mut rng) {
+            let (_, encoding_table) = table_for(&counts);
+            let json = serde_json::to_string(&encoding_table).unwrap();
+            let from_json: EncodingTable = serde_json::from_str(&json).unwrap();
+            assert_eq!(from_json, encoding_table, "frequencies {:?}", counts);
+
+            // Through the header entries and back to the same JSON
+            let mut bytes = Vec::new();
+            from_json.serialize(&mut bytes).unwrap();
+            let from_header = EncodingTable::deserialize(&mut &bytes[..], from_json.len() as u32).unwrap();
+            assert_eq!(serde_json::to_string(&from_header).unwrap(), json);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_json_rejects_what_a_header_rejects() {
+        let error = |json: &str| serde_json::from_str::<EncodingTable>(json).unwrap_err().to_string();
+        assert!(error(r#"{"97":""}"#).starts_with("invalid encoding table: Invalid code length: 0"));
+        assert!(error(&format!(r#"{{"97":"{}"}}"#, "1".repeat(33))).starts_with("invalid encoding table: Invalid code length: 33"));
+        assert!(error(r#"{"97":"10a"}"#).starts_with("invalid encoding table: Invalid code: \"10a\""));
+        assert!(error(r#"{"97":1011}"#).starts_with("invalid type: integer `1011`, expected a code of 1 to 32 bits"));
+        assert!(error(r#"{"256":"0"}"#).contains("256"));
+
+        // Like a header, a table that isn't a prefix code is read, validate()
+        // is what rejects it
+        let overlapping: EncodingTable = serde_json::from_str(r#"{"97":"0","98":"01"}"#).unwrap();
+        assert!(overlapping.validate().is_err());
+    }
+}
@@ -0,0 +1,207 @@
+// The names of --output-template, like '{dir}/compressed/{stem}.{date}.huf'.
+// The placeholders are parts of the path of the input and what is known about
+// it when the command starts:
+//   {name}  the file name, notes.txt
+//   {stem}  the file name without its extension, notes
+//   {ext}   the extension without the dot, txt, empty when there is none
+//   {dir}   the directory of the input, . for one in the current directory
+//   {date}  the date of the run, 2024-03-01 (UTC)
+//   {size}  the size of the input in bytes
+// {{ and }} stand for { and }.
+
+use std::ffi::OsString;
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::{HuffmanError, HuffmanResult};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Name,
+    Stem,
+    Ext,
+    Dir,
+    Date,
+    Size,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OutputTemplate {
+    parts: Vec<Part>,
+}
+
+// What an input gives the placeholders besides its path
+pub(crate) struct InputFacts<'a> {
+    pub(crate) date: &'a str,
+    pub(crate) size: u64,
+}
+
+impl OutputTemplate {
+    // Fails with Usage on an unknown placeholder or a lone brace, so a typo is
+    // caught before anything is written
+    pub fn parse(template: &str) -> HuffmanResult<Self> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(HuffmanError::Usage(format!("{{{} isn't closed with }}", name))),
+                        }
+                    }
+                    let part = match name.as_str() {
+                        "name" => Part::Name,
+                        "stem" => Part::Stem,
+                        "ext" => Part::Ext,
+                        "dir" => Part::Dir,
+                        "date" => Part::Date,
+                        "size" => Part::Size,
+                        _ => {
+                            return Err(HuffmanError::Usage(format!(
+                                "unknown placeholder {{{}}}, expected {{name}}, {{stem}}, {{ext}}, {{dir}}, {{date}} or {{size}}",
+                                name,
+                            )));
+                        }
+                    };
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(part);
+                }
+                '}' => return Err(HuffmanError::Usage(String::from("a } without a {, write }} for the character"))),
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        if parts.is_empty() {
+            return Err(HuffmanError::Usage(String::from("the template is empty")));
+        }
+        Ok(OutputTemplate { parts })
+    }
+
+    // The name for `input`. The parts of its path are used as they are, so
+    // names that aren't UTF-8 survive.
+    pub(crate) fn expand(&self, input: &Path, facts: &InputFacts) -> PathBuf {
+        let mut output = OsString::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => output.push(text),
+                Part::Name => output.push(input.file_name().unwrap_or_default()),
+                Part::Stem => output.push(input.file_stem().unwrap_or_default()),
+                Part::Ext => output.push(input.extension().unwrap_or_default()),
+                Part::Dir => match input.parent() {
+                    Some(dir) if !dir.as_os_str().is_empty() => output.push(dir),
+                    _ => output.push("."),
+                },
+                Part::Date => output.push(facts.date),
+                Part::Size => output.push(facts.size.to_string()),
+            }
+        }
+        PathBuf::from(output)
+    }
+}
+
+// Today as YYYY-MM-DD, in UTC
+pub(crate) fn today() -> String {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+    date_of_day((seconds / 86400) as i64)
+}
+
+// The date `days` after 1970-01-01, by Howard Hinnant's civil_from_days
+fn date_of_day(days: i64) -> String {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+// `path` from the current directory without . and .., so two names of the
+// same file compare equal. Links aren't followed, the outputs may not exist.
+pub(crate) fn normalized(path: &Path) -> PathBuf {
+    let absolute = match path.is_absolute() {
+        true => path.to_path_buf(),
+        false => std::env::current_dir().unwrap_or_default().join(path),
+    };
+    let mut normal = PathBuf::new();
+    for component in absolute.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normal.pop();
+            }
+            component => normal.push(component),
+        }
+    }
+    normal
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(template: &str, input: &str) -> PathBuf {
+        OutputTemplate::parse(template).unwrap().expand(Path::new(input), &InputFacts { date: "2024-03-01", size: 1234 })
+    }
+
+    #[test]
+    fn test_expand() {
+        assert_eq!(expand("{stem}.{date}.huf", "logs/app.log"), Path::new("app.2024-03-01.huf"));
+        assert_eq!(expand("{dir}/compressed/{name}.huf", "logs/app.log"), Path::new("logs/compressed/app.log.huf"));
+        assert_eq!(expand("{dir}/{stem}-{size}.{ext}", "/var/app.log"), Path::new("/var/app-1234.log"));
+        // Nothing for what the input doesn't have
+        assert_eq!(expand("{dir}/{stem}[{ext}]", "README"), Path::new("./README[]"));
+        assert_eq!(expand("{{{name}}}", "a.txt"), Path::new("{a.txt}"));
+    }
+
+    #[test]
+    fn test_invalid_templates() {
+        for (template, message) in [
+            ("{stem}.{time}.huf", "unknown placeholder {time}, expected {name}, {stem}, {ext}, {dir}, {date} or {size}"),
+            ("{name", "{name isn't closed with }"),
+            ("{}", "unknown placeholder {}, expected {name}, {stem}, {ext}, {dir}, {date} or {size}"),
+            ("name}.huf", "a } without a {, write }} for the character"),
+            ("", "the template is empty"),
+        ] {
+            let error = OutputTemplate::parse(template).unwrap_err();
+            assert!(matches!(error, HuffmanError::Usage(_)), "{:?}", template);
+            assert_eq!(error.to_string(), message, "{:?}", template);
+        }
+    }
+
+    #[test]
+    fn test_date_of_day() {
+        assert_eq!(date_of_day(0), "1970-01-01");
+        assert_eq!(date_of_day(19_722), "2023-12-31");
+        assert_eq!(date_of_day(19_782), "2024-02-29");
+        assert_eq!(date_of_day(-1), "1969-12-31");
+        assert_eq!(today().len(), 10);
+    }
+
+    #[test]
+    fn test_normalized() {
+        assert_eq!(normalized(Path::new("/tmp/a/../out/./x.huf")), Path::new("/tmp/out/x.huf"));
+        assert_eq!(normalized(Path::new("x.huf")), std::env::current_dir().unwrap().join("x.huf"));
+    }
+}
@@ -0,0 +1,115 @@
+// Helpers shared by the unit tests and benchmarks of every module
+
+// Most of them are for the tests that need std
+#![cfg_attr(not(feature = "std"), allow(dead_code))]
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Unique per call, tests run in parallel
+pub(crate) fn temp_path(name: &str) -> PathBuf {
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    std::env::temp_dir().join(format!("huffman-unit-{}-{}-{}", std::process::id(), id, name))
+}
+
+// Seeded xorshift, so failures reproduce from the printed seed
+pub(crate) struct Rng(pub(crate) u64);
+
+impl Rng {
+    pub(crate) fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    pub(crate) fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+// Logs
+//
+// Tests run in parallel on one logger, so every thread collects the records
+// of its own calls.
+
+#[cfg(feature = "cli")]
+std::thread_local! {
+    static CAPTURED_LOGS: std::cell::RefCell<Option<Vec<(log::Level, String)>>> = const { std::cell::RefCell::new(None) };
+}
+
+#[cfg(feature = "cli")]
+struct TestLogger;
+
+#[cfg(feature = "cli")]
+impl log::Log for TestLogger {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        CAPTURED_LOGS.with(|captured| {
+            if let Some(records) = captured.borrow_mut().as_mut() {
+                records.push((record.level(), record.args().to_string()));
+            }
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+#[cfg(feature = "cli")]
+// Runs `f` and returns what it logged, at every level
+pub(crate) fn capture_logs<T>(f: impl FnOnce() -> T) -> (T, Vec<(log::Level, String)>) {
+    static LOGGER: TestLogger = TestLogger;
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(log::LevelFilter::Trace);
+    }
+    CAPTURED_LOGS.with(|captured| *captured.borrow_mut() = Some(Vec::new()));
+    let result = f();
+    let records = CAPTURED_LOGS.with(|captured| captured.borrow_mut().take().unwrap_or_default());
+    (result, records)
+}
+
+// Benchmarks
+//
+// Run with `cargo test --release -- --ignored bench_ --nocapture --test-threads 1`.
+// Each prints the best of a few runs, inputs come from the seeded Rng so
+// numbers are comparable between machines and between changes.
+
+const BENCH_RUNS: usize = 5;
+pub(crate) const MIB: usize = 1024 * 1024;
+
+pub(crate) fn bench(mut f: impl FnMut()) -> std::time::Duration {
+    f(); // Warm up caches and the allocator
+    (0..BENCH_RUNS)
+        .map(|_| {
+            let start = std::time::Instant::now();
+            f();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+pub(crate) fn report_throughput(name: &str, bytes: usize, elapsed: std::time::Duration) {
+    println!("{:<32} {:>8.1} MB/s", name, bytes as f64 / elapsed.as_secs_f64() / 1e6);
+}
+
+pub(crate) fn random_data(rng: &mut Rng, len: usize) -> Vec<u8> {
+    (0..len).map(|_| rng.next() as u8).collect()
+}
+
+// Words with a skewed distribution, roughly like English text
+pub(crate) fn text_data(rng: &mut Rng, len: usize) -> Vec<u8> {
+    let words = ["the", "of", "and", "to", "in", "a", "is", "that", "huffman", "encoding", "tree", "bits"];
+    let mut data = Vec::with_capacity(len + 16);
+    while data.len() < len {
+        let word = words[rng.below(words.len()).min(rng.below(words.len()))];
+        data.extend_from_slice(word.as_bytes());
+        data.push(if rng.below(10) == 0 { b'\n' } else { b' ' });
+    }
+    data.truncate(len);
+    data
+}
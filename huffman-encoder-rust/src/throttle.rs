@@ -0,0 +1,166 @@
+// --limit-rate: reads and writes of encode and decode together go no faster
+// than a number of bytes a second, so a job on a shared disk leaves some of it
+// to the others. A token bucket fills at the rate; every read or write takes
+// its bytes out, and once it is in debt the thread sleeps until the debt is
+// paid. It starts empty and holds a tenth of a second at most, so over a run
+// the rate is kept to within the last chunk.
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+pub(crate) struct TokenBucket {
+    // Bytes a second
+    rate: f64,
+    burst: f64,
+    // Below zero when in debt
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    pub(crate) fn new(rate: u64, now: Instant) -> Self {
+        let rate = rate.max(1) as f64;
+        TokenBucket { rate, burst: rate / 10.0, tokens: 0.0, last: now }
+    }
+
+    // Takes `bytes` out at `now`, and says how long to wait before going on
+    pub(crate) fn take(&mut self, bytes: u64, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.last = self.last.max(now);
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst) - bytes as f64;
+        match self.tokens < 0.0 {
+            true => Duration::from_secs_f64(-self.tokens / self.rate),
+            false => Duration::ZERO,
+        }
+    }
+}
+
+// The bucket of a command, shared by its reads and writes
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    bucket: Mutex<TokenBucket>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(rate: u64) -> Self {
+        RateLimiter { bucket: Mutex::new(TokenBucket::new(rate, Instant::now())) }
+    }
+
+    // Sleeps as long as the bucket says, without holding it
+    fn consume(&self, bytes: usize) {
+        let wait = self.bucket.lock().unwrap_or_else(PoisonError::into_inner).take(bytes as u64, Instant::now());
+        if !wait.is_zero() {
+            std::thread::sleep(wait);
+        }
+    }
+}
+
+// A reader or writer whose bytes go through the limiter, or straight through
+// without one
+pub(crate) struct Limited<'a, T> {
+    inner: T,
+    limiter: Option<&'a RateLimiter>,
+}
+
+impl<'a, T> Limited<'a, T> {
+    pub(crate) fn new(inner: T, limiter: Option<&'a RateLimiter>) -> Self {
+        Limited { inner, limiter }
+    }
+
+    pub(crate) fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    fn consume(&self, bytes: usize) {
+        if let Some(limiter) = self.limiter {
+            limiter.consume(bytes);
+        }
+    }
+}
+
+impl<T: Read> Read for Limited<'_, T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.consume(read);
+        Ok(read)
+    }
+}
+
+impl<T: Write> Write for Limited<'_, T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.consume(written);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: Seek> Seek for Limited<'_, T> {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn test_bucket_waits_for_its_debt() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(1000, start);
+        // Empty to begin with, so the first bytes already wait
+        assert_eq!(bucket.take(500, start), SECOND / 2);
+        // Having slept that long, the debt is paid
+        assert_eq!(bucket.take(0, start + SECOND / 2), Duration::ZERO);
+        assert_eq!(bucket.take(2000, start + SECOND / 2), 2 * SECOND);
+        // Taking more while in debt adds to the wait
+        assert_eq!(bucket.take(1000, start + SECOND / 2), 3 * SECOND);
+    }
+
+    #[test]
+    fn test_bucket_only_saves_up_a_tenth_of_a_second() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(1000, start);
+        // An hour idle buys 100 bytes, not 3.6 MB
+        let later = start + 3600 * SECOND;
+        assert_eq!(bucket.take(100, later), Duration::ZERO);
+        assert_eq!(bucket.take(100, later), SECOND / 10);
+    }
+
+    #[test]
+    fn test_bucket_keeps_the_rate_over_a_run() {
+        // 64 KiB chunks at 1 MiB a second for a simulated 10 seconds, each
+        // one after the wait of the one before
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(1 << 20, start);
+        let mut now = start;
+        for _ in 0..160 {
+            now += bucket.take(64 << 10, now);
+        }
+        let elapsed = (now - start).as_secs_f64();
+        assert!((elapsed - 10.0).abs() < 0.01, "{}", elapsed);
+    }
+
+    #[test]
+    fn test_limited_counts_what_goes_through() {
+        let limiter = RateLimiter::new(100);
+        let mut reader = Limited::new(&b"abracadabra"[..], Some(&limiter));
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).unwrap();
+        let mut writer = Limited::new(Vec::new(), Some(&limiter));
+        writer.write_all(&data).unwrap();
+        assert_eq!(writer.get_ref(), b"abracadabra");
+        // In debt for the 11 bytes written, at most 10 bytes were saved up
+        // while sleeping for the ones read
+        let tokens = limiter.bucket.lock().unwrap().tokens;
+        assert!(tokens < 0.0 && tokens > -22.0, "{}", tokens);
+    }
+}
@@ -0,0 +1,165 @@
+// Words as symbols, for `encode --tokens`: text is split into tokens, runs of
+// letters and digits, runs of whitespace and any other byte on its own. The
+// tokens that pay for a place in the dictionary get a symbol each, from 256
+// on, and the codes are of those and of the bytes 0 to 255. A token that
+// isn't in the dictionary, because it is rare or longer than
+// MAX_TOKEN_LENGTH, is sent as its bytes, so any data can be coded.
+//
+// Bytes from 0x80 on count as letters, which keeps the characters of UTF-8
+// text in their words.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::iter;
+
+use crate::frequency::{FrequencyTable, Symbol};
+use crate::error::HuffmanResult;
+use crate::progress::Progress;
+use crate::table::entry_size;
+use crate::tree::HuffmanTree;
+
+// The symbol of the first token of the dictionary
+pub(crate) const FIRST_TOKEN: usize = 256;
+// Every token has a 16-bit symbol
+pub(crate) const MAX_TOKENS: usize = (1 << 16) - FIRST_TOKEN;
+pub(crate) const MAX_TOKEN_LENGTH: usize = 32;
+// A token in the dictionary costs its bytes and its length in the header, and
+// an entry of 7 bytes in the table
+const TOKEN_COST: u64 = 8;
+// Roughly the bits of the code of a byte of text, and of a token
+const BYTE_BITS: u64 = 5;
+const TOKEN_BITS: u64 = 9;
+
+// The symbol of every token of a dictionary
+pub(crate) type TokenSymbols<'a> = BTreeMap<&'a [u8], Symbol>;
+
+fn is_word(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte >= 0x80
+}
+
+// The tokens of `data`, which together are all of it
+pub(crate) fn split(data: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let mut rest = data;
+    iter::from_fn(move || {
+        let first = *rest.first()?;
+        let length = match first {
+            _ if is_word(first) => rest.iter().take_while(|&&byte| is_word(byte)).count(),
+            _ if first.is_ascii_whitespace() => rest.iter().take_while(|byte| byte.is_ascii_whitespace()).count(),
+            _ => 1,
+        };
+        let (token, after) = rest.split_at(length);
+        rest = after;
+        Some(token)
+    })
+}
+
+// The dictionary of `data`: the one of build_dictionary, or none when that
+// makes the stream larger, going by the header and the codes of the counts.
+// Without tokens the codes are those of the bytes, so coding in tokens is
+// never worse than that by more than the wider entries.
+pub(crate) fn choose_dictionary(data: &[u8]) -> Vec<Vec<u8>> {
+    let dictionary = build_dictionary(data);
+    let size = |dictionary: &[Vec<u8>]| {
+        let counts = count(data, &token_symbols(dictionary));
+        let Some(tree) = HuffmanTree::from_frequencies(&counts) else {
+            return 0;
+        };
+        let table = tree.codes();
+        let tokens: u64 = dictionary.iter().map(|token| 1 + token.len() as u64).sum();
+        tokens + table.len() as u64 * entry_size(2) + table.expected_payload_bits(&counts).div_ceil(8)
+    };
+    match size(&dictionary) < size(&[]) {
+        true => dictionary,
+        false => Vec::new(),
+    }
+}
+
+// The tokens of `data` worth a symbol, the ones that save the most first.
+// Coding a token in one code instead of one per byte saves about BYTE_BITS
+// for each of its bytes but TOKEN_BITS for the code, each time it occurs, and
+// the ones that save no more than their place in the header costs are left
+// out.
+pub(crate) fn build_dictionary(data: &[u8]) -> Vec<Vec<u8>> {
+    let mut counts = BTreeMap::<&[u8], u64>::new();
+    for token in split(data).filter(|token| (2..=MAX_TOKEN_LENGTH).contains(&token.len())) {
+        *counts.entry(token).or_insert(0) += 1;
+    }
+    let mut savings: Vec<(u64, &[u8])> = counts.into_iter()
+        .map(|(token, count)| (count * (token.len() as u64 * BYTE_BITS).saturating_sub(TOKEN_BITS), token))
+        .filter(|&(saving, token)| saving > 8 * (token.len() as u64 + TOKEN_COST))
+        .collect();
+    // Equal savings in the order of the tokens, so the dictionary is the same every time
+    savings.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(b.1)));
+    savings.truncate(MAX_TOKENS);
+    savings.into_iter().map(|(_, token)| token.to_vec()).collect()
+}
+
+pub(crate) fn token_symbols(dictionary: &[Vec<u8>]) -> TokenSymbols<'_> {
+    dictionary.iter().enumerate().map(|(i, token)| (&token[..], (FIRST_TOKEN + i) as Symbol)).collect()
+}
+
+// The symbols of `data`, each with the number of bytes up to the end of its
+// token
+pub(crate) fn symbols<'a>(data: &'a [u8], token_symbols: &'a TokenSymbols) -> impl Iterator<Item = (Symbol, u64)> + 'a {
+    let mut end = 0u64;
+    split(data).flat_map(move |token| {
+        end += token.len() as u64;
+        let (symbol, bytes) = match token_symbols.get(token) {
+            Some(&symbol) => (Some(symbol), &[][..]),
+            None => (None, token),
+        };
+        let token_end = end;
+        symbol.into_iter().chain(bytes.iter().map(|&byte| byte as Symbol)).map(move |symbol| (symbol, token_end))
+    })
+}
+
+fn count(data: &[u8], token_symbols: &TokenSymbols) -> FrequencyTable {
+    let mut frequencies = FrequencyTable::with_symbol_width(2);
+    for (symbol, _) in symbols(data, token_symbols) {
+        frequencies.counts[symbol as usize] += 1;
+    }
+    frequencies
+}
+
+// Counts the symbols of `data`, like count_with_progress
+pub(crate) fn count_symbols(data: &[u8], token_symbols: &TokenSymbols, progress: &mut Progress) -> HuffmanResult<FrequencyTable> {
+    let mut frequencies = FrequencyTable::with_symbol_width(2);
+    for (symbol, end) in symbols(data, token_symbols) {
+        frequencies.counts[symbol as usize] += 1;
+        progress.update(end)?;
+    }
+    progress.end(data.len() as u64)?;
+    Ok(frequencies)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{random_data, Rng};
+
+    #[test]
+    fn test_split_into_words_spaces_and_punctuation() {
+        let tokens: Vec<&[u8]> = split("Hello,  wörld!\n\nAgain 2x".as_bytes()).collect();
+        let expected: [&[u8]; 9] = [b"Hello", b",", b"  ", "wörld".as_bytes(), b"!", b"\n\n", b"Again", b" ", b"2x"];
+        assert_eq!(tokens, expected);
+
+        let data = random_data(&mut Rng(0x70CE), 10_000);
+        assert_eq!(split(&data).flatten().copied().collect::<Vec<u8>>(), data);
+    }
+
+    #[test]
+    fn test_dictionary_keeps_the_tokens_that_pay() {
+        let data = [b"the cat and the hat and the bat ".repeat(10), [b'x'; MAX_TOKEN_LENGTH + 1].repeat(10)].concat();
+        let dictionary = build_dictionary(&data);
+        // "the" occurs 30 times and "and" 20, often enough to pay for their
+        // place, "cat" and the others only 10. The spaces are single bytes and
+        // the x's too long.
+        let expected: [&[u8]; 2] = [b"the", b"and"];
+        assert_eq!(dictionary, expected);
+
+        // Everything else goes as its bytes
+        let token_symbols = token_symbols(&dictionary);
+        let coded: Vec<(Symbol, u64)> = symbols(b"the dog and", &token_symbols).collect();
+        assert_eq!(coded, [(256, 3), (32, 4), (100, 7), (111, 7), (103, 7), (32, 8), (257, 11)]);
+    }
+}
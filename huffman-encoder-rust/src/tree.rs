@@ -0,0 +1,465 @@
+// The Huffman tree, built from the byte counts
+
+use alloc::collections::BinaryHeap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ascii;
+use core::fmt;
+
+use crate::frequency::{FrequencyTable, Symbol};
+use crate::table::{build_encoding_table, EncodingTable};
+
+// Children are indices into the nodes of the tree. With 2 byte symbols a tree
+// has up to 65536 leaves and 65535 parents, too many for a u16.
+#[derive(Clone, Copy)]
+pub(crate) enum HuffmanNode {
+    Leaf {
+        weight: u64,
+        character: Symbol,
+    },
+    Parent {
+        weight: u64,
+        left: u32,
+        right: u32,
+    }
+}
+
+impl HuffmanNode {
+    pub fn weight(&self) -> u64 {
+        match self {
+            HuffmanNode::Leaf { weight, .. } => *weight,
+            HuffmanNode::Parent { weight, .. } => *weight,
+        }
+    }
+}
+
+// All nodes in one Vec instead of a Box per node. Parents are pushed after
+// their children, so the root is the last node.
+pub struct HuffmanTree {
+    pub(crate) nodes: Vec<HuffmanNode>,
+}
+
+impl HuffmanTree {
+    fn with_capacity(leaves: usize) -> Self {
+        HuffmanTree { nodes: Vec::with_capacity(2 * leaves) }
+    }
+
+    fn push_leaf(&mut self, character: Symbol, weight: u64) -> u32 {
+        self.push(HuffmanNode::Leaf { weight, character })
+    }
+
+    fn push_parent(&mut self, left: u32, right: u32) -> u32 {
+        let weight = self.nodes[left as usize].weight() + self.nodes[right as usize].weight();
+        self.push(HuffmanNode::Parent { weight, left, right })
+    }
+
+    fn push(&mut self, node: HuffmanNode) -> u32 {
+        self.nodes.push(node);
+        (self.nodes.len() - 1) as u32
+    }
+
+    pub(crate) fn root(&self) -> &HuffmanNode {
+        self.nodes.last().expect("a tree has at least one node")
+    }
+
+    // Returns None for an empty input, which has nothing to build a tree from
+    pub fn from_frequencies(frequencies: &FrequencyTable) -> Option<Self> {
+        build_huffman_tree(frequencies)
+    }
+
+    // The code of every character, see build_encoding_table
+    pub fn codes(&self) -> EncodingTable {
+        build_encoding_table(self)
+    }
+
+    // The depth of every node, the root at 0. Children come before their
+    // parent, so going from the root down every parent has its depth already.
+    fn node_depths(&self) -> Vec<u32> {
+        let mut depths = vec![0; self.nodes.len()];
+        for index in (0..self.nodes.len()).rev() {
+            if let HuffmanNode::Parent { left, right, .. } = self.nodes[index] {
+                depths[left as usize] = depths[index] + 1;
+                depths[right as usize] = depths[index] + 1;
+            }
+        }
+        depths
+    }
+
+    // The weight and depth of every leaf
+    fn leaves(&self) -> impl Iterator<Item = (u64, u32)> + '_ {
+        self.nodes.iter().zip(self.node_depths()).filter_map(|(node, depth)| match node {
+            HuffmanNode::Leaf { weight, .. } => Some((*weight, depth)),
+            HuffmanNode::Parent { .. } => None,
+        })
+    }
+
+    // The depth of the deepest leaf, the length of the longest code. A tree of
+    // a single leaf has depth 0, even though its code gets 1 bit.
+    pub fn depth(&self) -> u32 {
+        self.leaves().map(|(_, depth)| depth).max().unwrap_or(0)
+    }
+
+    // Sum of weight * depth of the leaves, the number of bits the payload takes
+    // when there are at least two leaves
+    pub fn weighted_path_length(&self) -> u64 {
+        self.leaves().map(|(weight, depth)| weight * depth as u64).sum()
+    }
+
+    // Number of distinct characters
+    pub fn leaf_count(&self) -> usize {
+        self.nodes.iter().filter(|node| matches!(node, HuffmanNode::Leaf { .. })).count()
+    }
+
+    // The tree in Graphviz's dot language, with the weight on every node and
+    // the bit on every edge: `dot -Tsvg tree.dot -o tree.svg`
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph huffman {\n");
+        for (index, node) in self.nodes.iter().enumerate().rev() {
+            match *node {
+                HuffmanNode::Leaf { weight, character } => {
+                    let label = symbol_label(character).replace('\\', "\\\\").replace('"', "\\\"");
+                    dot += &format!("    n{} [label=\"'{}' {}\", shape=box];\n", index, label, weight);
+                }
+                HuffmanNode::Parent { weight, left, right } => {
+                    dot += &format!("    n{} [label=\"{}\"];\n", index, weight);
+                    dot += &format!("    n{} -> n{} [label=\"0\"];\n", index, left);
+                    dot += &format!("    n{} -> n{} [label=\"1\"];\n", index, right);
+                }
+            }
+        }
+        dot += "}\n";
+        dot
+    }
+
+    fn fmt_node(&self, f: &mut fmt::Formatter<'_>, index: u32) -> fmt::Result {
+        match self.nodes[index as usize] {
+            HuffmanNode::Leaf { character, .. } => {
+                write!(f, "'{}'", symbol_label(character))
+            },
+            HuffmanNode::Parent { left, right, .. } => {
+                write!(f, "(parent of ")?;
+                self.fmt_node(f, left)?;
+                write!(f, " and ")?;
+                self.fmt_node(f, right)?;
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+impl fmt::Display for HuffmanTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_node(f, (self.nodes.len() - 1) as u32)
+    }
+}
+
+// A byte escaped like in a Rust literal. The tree doesn't know how wide its
+// symbols are, those past a byte are in hex.
+pub(crate) fn symbol_label(character: Symbol) -> String {
+    match u8::try_from(character) {
+        Ok(byte) => ascii::escape_default(byte).to_string(),
+        Err(_) => format!("{:#06x}", character),
+    }
+}
+
+// A node waiting in the heap of build_huffman_tree. BinaryHeap is a max-heap,
+// so the ordering is reversed to pop the lowest weight first. Equal weights
+// pop in the order they were pushed, which keeps the tree deterministic.
+struct QueuedNode {
+    weight: u64,
+    order: usize,
+    index: u32,
+}
+
+impl PartialEq for QueuedNode {
+    fn eq(&self, other: &Self) -> bool {
+        (self.weight, self.order) == (other.weight, other.order)
+    }
+}
+
+impl Eq for QueuedNode {}
+
+impl PartialOrd for QueuedNode {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for QueuedNode {
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+        (other.weight, other.order).cmp(&(self.weight, self.order))
+    }
+}
+
+// Same as HuffmanTree::from_frequencies
+pub fn build_huffman_tree(frequencies: &FrequencyTable) -> Option<HuffmanTree> {
+    let leaves = frequencies.len();
+    if leaves == 0 {
+        return None;
+    }
+
+    // Leaves are queued by character, which is the order iter() returns them in
+    let mut tree = HuffmanTree::with_capacity(leaves);
+    let mut heap = BinaryHeap::with_capacity(leaves);
+    for (order, (byte, count)) in frequencies.iter().enumerate() {
+        let index = tree.push_leaf(byte, count);
+        heap.push(QueuedNode { weight: count, order, index });
+    }
+
+    // Continually join the two lowest nodes
+    let mut order = heap.len();
+    while heap.len() > 1 {
+        let left = heap.pop().unwrap().index;
+        let right = heap.pop().unwrap().index;
+        let index = tree.push_parent(left, right);
+        heap.push(QueuedNode { weight: tree.nodes[index as usize].weight(), order, index });
+        order += 1;
+    }
+    Some(tree)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::table::{build_encoding_table, canonical_codes, Code, EncodingTable};
+    use crate::test_util::{bench, Rng};
+
+    // The original algorithm, which sorted the whole list for every merge. Kept
+    // to check that the heap builds exactly the same trees.
+    fn build_huffman_tree_by_sorting(frequencies: &FrequencyTable) -> Option<HuffmanTree> {
+        let mut tree = HuffmanTree::with_capacity(frequencies.len());
+        let mut queue: Vec<u32> = frequencies
+            .iter()
+            .map(|(byte, count)| tree.push_leaf(byte, count))
+            .collect();
+        if queue.is_empty() {
+            return None;
+        }
+
+        while queue.len() > 1 {
+            queue.sort_by_key(|&index| tree.nodes[index as usize].weight());
+            let left = queue.remove(0);
+            let right = queue.remove(0);
+            queue.push(tree.push_parent(left, right));
+        }
+        Some(tree)
+    }
+
+    // The tree as it was before the arena, a Box per node and a recursive walk.
+    // Kept to check that the arena assigns exactly the same codes.
+    enum BoxedNode {
+        Leaf { weight: u64, character: Symbol },
+        Parent { weight: u64, left: Box<BoxedNode>, right: Box<BoxedNode> },
+    }
+
+    impl BoxedNode {
+        fn weight(&self) -> u64 {
+            match self {
+                BoxedNode::Leaf { weight, .. } | BoxedNode::Parent { weight, .. } => *weight,
+            }
+        }
+    }
+
+    fn build_boxed_tree(frequencies: &FrequencyTable) -> Option<Box<BoxedNode>> {
+        // Weight, order and node, popping the lowest weight and order first
+        let mut heap: BinaryHeap<(std::cmp::Reverse<(u64, usize)>, usize)> = BinaryHeap::new();
+        let mut nodes: Vec<Option<Box<BoxedNode>>> = Vec::new();
+        for (order, (character, weight)) in frequencies.iter().enumerate() {
+            heap.push((std::cmp::Reverse((weight, order)), nodes.len()));
+            nodes.push(Some(Box::new(BoxedNode::Leaf { weight, character })));
+        }
+
+        let mut order = heap.len();
+        while heap.len() > 1 {
+            let left = nodes[heap.pop().unwrap().1].take().unwrap();
+            let right = nodes[heap.pop().unwrap().1].take().unwrap();
+            let weight = left.weight() + right.weight();
+            heap.push((std::cmp::Reverse((weight, order)), nodes.len()));
+            nodes.push(Some(Box::new(BoxedNode::Parent { weight, left, right })));
+            order += 1;
+        }
+        heap.pop().and_then(|(_, index)| nodes[index].take())
+    }
+
+    fn traverse_boxed(node: &BoxedNode, code: Code, encoding_table: &mut EncodingTable) {
+        match node {
+            BoxedNode::Leaf { character, .. } => {
+                encoding_table.insert(*character, code);
+            }
+            BoxedNode::Parent { left, right, .. } => {
+                traverse_boxed(left, Code { bits: code.bits, length: code.length + 1 }, encoding_table);
+                let right_bits = code.bits | 1 << (31 - code.length);
+                traverse_boxed(right, Code { bits: right_bits, length: code.length + 1 }, encoding_table);
+            }
+        }
+    }
+
+    fn build_boxed_encoding_table(tree: &BoxedNode) -> EncodingTable {
+        let mut encoding_table = EncodingTable::new();
+        match tree {
+            BoxedNode::Leaf { character, .. } => {
+                encoding_table.insert(*character, Code { bits: 0, length: 1 });
+            }
+            BoxedNode::Parent { .. } => traverse_boxed(tree, Code { bits: 0, length: 0 }, &mut encoding_table),
+        }
+        canonical_codes(&encoding_table).unwrap()
+    }
+
+    #[test]
+    fn test_arena_assigns_the_same_codes_as_boxed_nodes() {
+        let mut rng = Rng(0xA4E4);
+        for _ in 0..200 {
+            let symbols = 1 + rng.below(256);
+            let max_count = [3, 20, 1_000_000][rng.below(3)];
+            let frequencies: FrequencyTable = (0..symbols)
+                .map(|byte| (byte as u8, 1 + rng.below(max_count) as u64))
+                .collect();
+
+            let table = build_encoding_table(&build_huffman_tree(&frequencies).unwrap());
+            let expected = build_boxed_encoding_table(&build_boxed_tree(&frequencies).unwrap());
+            assert_eq!(table, expected, "frequencies {:?}", frequencies);
+        }
+    }
+
+    #[test]
+    fn test_heap_builds_the_same_tree_as_sorting() {
+        let mut rng = Rng(0x4EA9);
+        for _ in 0..200 {
+            let symbols = 1 + rng.below(256);
+            // Small counts, so there are plenty of ties
+            let max_count = [3, 20, 1_000_000][rng.below(3)];
+            let frequencies: FrequencyTable = (0..symbols)
+                .map(|byte| (byte as u8, 1 + rng.below(max_count) as u64))
+                .collect();
+
+            let heap_tree = build_huffman_tree(&frequencies).unwrap();
+            let sorted_tree = build_huffman_tree_by_sorting(&frequencies).unwrap();
+            assert_eq!(heap_tree.to_string(), sorted_tree.to_string(), "frequencies {:?}", frequencies);
+
+            let heap_table = build_encoding_table(&heap_tree);
+            let sorted_table = build_encoding_table(&sorted_tree);
+            assert_eq!(heap_table, sorted_table, "frequencies {:?}", frequencies);
+        }
+    }
+
+    // 'a' on its own, 'b' and 'c' under a parent of their own:
+    //       8
+    //    a:5   3
+    //       b:2 c:1
+    fn small_tree() -> HuffmanTree {
+        let mut tree = HuffmanTree::with_capacity(3);
+        let a = tree.push_leaf(b'a'.into(), 5);
+        let b = tree.push_leaf(b'b'.into(), 2);
+        let c = tree.push_leaf(b'c'.into(), 1);
+        let bc = tree.push_parent(b, c);
+        tree.push_parent(a, bc);
+        tree
+    }
+
+    #[test]
+    fn test_depth_and_weighted_path_length_of_small_trees() {
+        let tree = small_tree();
+        assert_eq!(tree.depth(), 2);
+        assert_eq!(tree.weighted_path_length(), 5 + 2 * 2 + 2);
+        assert_eq!(tree.leaf_count(), 3);
+
+        // Every parent on the left of the next one: depths 1, 2, 3 and 3
+        let mut chain = HuffmanTree::with_capacity(4);
+        let mut parent = chain.push_leaf(b'z'.into(), 1);
+        for (character, weight) in [(b'y', 2), (b'x', 4), (b'w', 8)] {
+            let leaf = chain.push_leaf(character.into(), weight);
+            parent = chain.push_parent(parent, leaf);
+        }
+        assert_eq!(chain.depth(), 3);
+        assert_eq!(chain.weighted_path_length(), 3 + 2 * 3 + 4 * 2 + 8);
+        assert_eq!(chain.leaf_count(), 4);
+        let lengths: Vec<(u8, u8)> = chain.codes().iter().map(|(character, code)| (character as u8, code.length)).collect();
+        assert_eq!(lengths, [(b'w', 1), (b'x', 2), (b'y', 3), (b'z', 3)]);
+    }
+
+    #[test]
+    fn test_single_leaf_tree() {
+        let tree = HuffmanTree::from_frequencies(&FrequencyTable::from_iter([(b'a', 7)])).unwrap();
+        assert_eq!(tree.depth(), 0);
+        assert_eq!(tree.weighted_path_length(), 0);
+        assert_eq!(tree.leaf_count(), 1);
+        // The code still takes a bit
+        assert_eq!(tree.codes().get(b'a'.into()), Some(Code { bits: 0, length: 1 }));
+        assert_eq!(tree.to_dot(), "digraph huffman {\n    n0 [label=\"'a' 7\", shape=box];\n}\n");
+
+        assert!(HuffmanTree::from_frequencies(&FrequencyTable::new()).is_none());
+    }
+
+    #[test]
+    fn test_weighted_path_length_is_the_payload_size() {
+        let mut rng = Rng(0x5750_4C21);
+        for _ in 0..100 {
+            let symbols = 2 + rng.below(255);
+            let frequencies: FrequencyTable = (0..symbols)
+                .map(|byte| (byte as u8, 1 + rng.below(1000) as u64))
+                .collect();
+            let tree = HuffmanTree::from_frequencies(&frequencies).unwrap();
+            let codes = tree.codes();
+            assert_eq!(tree.weighted_path_length(), codes.expected_payload_bits(&frequencies));
+            assert_eq!(tree.depth(), codes.max_code_length() as u32);
+            assert_eq!(tree.leaf_count(), frequencies.len());
+        }
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let mut tree = small_tree();
+        // Quotes and backslashes are escaped for dot
+        if let HuffmanNode::Leaf { character, .. } = &mut tree.nodes[1] {
+            *character = b'"'.into();
+        }
+        assert_eq!(tree.to_dot(), [
+            "digraph huffman {",
+            "    n4 [label=\"8\"];",
+            "    n4 -> n0 [label=\"0\"];",
+            "    n4 -> n3 [label=\"1\"];",
+            "    n3 [label=\"3\"];",
+            "    n3 -> n1 [label=\"0\"];",
+            "    n3 -> n2 [label=\"1\"];",
+            "    n2 [label=\"'c' 1\", shape=box];",
+            "    n1 [label=\"'\\\\\\\"' 2\", shape=box];",
+            "    n0 [label=\"'a' 5\", shape=box];",
+            "}\n",
+        ].join("\n"));
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_build_huffman_tree() {
+        let mut rng = Rng(0x7EE);
+        let frequencies: FrequencyTable = (0..=255).map(|byte| (byte, 1 + rng.below(1_000_000) as u64)).collect();
+        type TreeBuilder = fn(&FrequencyTable) -> Option<HuffmanTree>;
+        let builders: [(&str, TreeBuilder); 2] = [
+            ("build_huffman_tree 256 symbols", build_huffman_tree),
+            ("  sorting every merge", build_huffman_tree_by_sorting),
+        ];
+        for (name, build) in builders {
+            let elapsed = bench(|| {
+                for _ in 0..100 {
+                    std::hint::black_box(build(&frequencies).unwrap());
+                }
+            });
+            println!("{:<32} {:>8.1} us", name, elapsed.as_secs_f64() * 1e6 / 100.0);
+        }
+
+        let elapsed = bench(|| {
+            for _ in 0..100 {
+                std::hint::black_box(build_encoding_table(&build_huffman_tree(&frequencies).unwrap()));
+            }
+        });
+        println!("{:<32} {:>8.1} us", "tree and table 256 symbols", elapsed.as_secs_f64() * 1e6 / 100.0);
+        let elapsed = bench(|| {
+            for _ in 0..100 {
+                std::hint::black_box(build_boxed_encoding_table(&build_boxed_tree(&frequencies).unwrap()));
+            }
+        });
+        println!("{:<32} {:>8.1} us", "  boxed nodes", elapsed.as_secs_f64() * 1e6 / 100.0);
+    }
+}
@@ -0,0 +1,154 @@
+// Exports for wasm32-unknown-unknown, on top of the ones of ffi. The module is
+// built without the cli feature, so none of the file handling is in it:
+//
+//     cargo rustc --release --lib --no-default-features --features wasm \
+//         --target wasm32-unknown-unknown --crate-type cdylib
+//
+// JavaScript can only pass numbers, so it copies the input into memory from
+// hrst_alloc and reads the output from hrst_js_output. The wrapper with
+// encode, decode and tableInfo on Uint8Arrays is examples/wasm-demo/hrst.js.
+
+// The safety requirements are the ones of ffi
+#![allow(clippy::missing_safety_doc)]
+
+use std::fmt::Write as _;
+use std::ptr;
+use std::sync::Mutex;
+
+use crate::estimate::{entropy_bits_per_byte, estimate_encoded_size};
+use crate::ffi::{hrst_decode, hrst_encode, run, HrstStatus};
+use crate::frequency::FrequencyTable;
+use crate::tree::HuffmanTree;
+
+// What the demo shows of the table encode would pick for `data`, as JSON: the
+// sizes, the entropy and the code of every byte that occurs, in canonical order
+pub fn table_info(data: &[u8]) -> String {
+    let mut frequencies = FrequencyTable::new();
+    frequencies.add(data);
+    let encoding_table = HuffmanTree::from_frequencies(&frequencies).map(|tree| tree.codes()).unwrap_or_default();
+
+    let mut json = format!(
+        "{{\"length\":{},\"encodedSize\":{},\"entropy\":{},\"codes\":[",
+        data.len(),
+        estimate_encoded_size(&frequencies, &encoding_table),
+        entropy_bits_per_byte(&frequencies),
+    );
+    for (i, (byte, code)) in encoding_table.iter().enumerate() {
+        let separator = if i == 0 { "" } else { "," };
+        let count = frequencies.get(byte);
+        let _ = write!(json, "{}{{\"byte\":{},\"count\":{},\"code\":\"{}\"}}", separator, byte, count, code);
+    }
+    json.push_str("]}");
+    json
+}
+
+// A buffer of `length` bytes for the input, released with hrst_free. Null
+// when `length` is 0 or there is no memory left.
+#[no_mangle]
+pub extern "C" fn hrst_alloc(length: usize) -> *mut u8 {
+    let mut data = Vec::<u8>::new();
+    if length == 0 || data.try_reserve_exact(length).is_err() {
+        return ptr::null_mut();
+    }
+    data.resize(length, 0);
+    Box::into_raw(data.into_boxed_slice()).cast()
+}
+
+// Same arguments as hrst_encode, the output is table_info as UTF-8
+#[no_mangle]
+pub unsafe extern "C" fn hrst_table_info(
+    input: *const u8,
+    input_length: usize,
+    output: *mut *mut u8,
+    output_length: *mut usize,
+) -> HrstStatus {
+    run(input, input_length, output, output_length, |data| Ok(table_info(data).into_bytes()))
+}
+
+// The output of the last hrst_js_* call, as a pointer and a length. It
+// belongs to the caller, who releases it with hrst_free.
+static JS_OUTPUT: Mutex<(usize, usize)> = Mutex::new((0, 0));
+
+type Export = unsafe extern "C" fn(*const u8, usize, *mut *mut u8, *mut usize) -> HrstStatus;
+
+unsafe fn call_from_js(export: Export, input: *const u8, input_length: usize) -> HrstStatus {
+    let mut output = ptr::null_mut();
+    let mut output_length = 0;
+    let status = export(input, input_length, &mut output, &mut output_length);
+    *JS_OUTPUT.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = (output as usize, output_length);
+    status
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hrst_js_encode(input: *const u8, input_length: usize) -> HrstStatus {
+    call_from_js(hrst_encode, input, input_length)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hrst_js_decode(input: *const u8, input_length: usize) -> HrstStatus {
+    call_from_js(hrst_decode, input, input_length)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hrst_js_table_info(input: *const u8, input_length: usize) -> HrstStatus {
+    call_from_js(hrst_table_info, input, input_length)
+}
+
+#[no_mangle]
+pub extern "C" fn hrst_js_output() -> *mut u8 {
+    JS_OUTPUT.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).0 as *mut u8
+}
+
+#[no_mangle]
+pub extern "C" fn hrst_js_output_length() -> usize {
+    JS_OUTPUT.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::encode_bytes;
+    use crate::ffi::hrst_free;
+    use std::slice;
+
+    #[test]
+    fn test_table_info() {
+        let encoded = encode_bytes(b"abracadabra").unwrap();
+        let json = table_info(b"abracadabra");
+        assert!(json.starts_with(&format!("{{\"length\":11,\"encodedSize\":{},\"entropy\":2.04", encoded.len())));
+        assert!(json.ends_with(concat!(
+            "\"codes\":[{\"byte\":97,\"count\":5,\"code\":\"0\"},{\"byte\":98,\"count\":2,\"code\":\"100\"},",
+            "{\"byte\":99,\"count\":1,\"code\":\"101\"},{\"byte\":100,\"count\":1,\"code\":\"110\"},",
+            "{\"byte\":114,\"count\":2,\"code\":\"111\"}]}",
+        )), "{}", json);
+        assert_eq!(table_info(b""), "{\"length\":0,\"encodedSize\":34,\"entropy\":0,\"codes\":[]}");
+    }
+
+    // The calls hrst.js makes, in the same order
+    #[test]
+    fn test_js_roundtrip() {
+        let data = include_bytes!("../tests/fixtures/english.txt");
+        let output = |status: HrstStatus| {
+            assert_eq!(status, HrstStatus::Ok);
+            let (pointer, length) = (hrst_js_output(), hrst_js_output_length());
+            let bytes = unsafe { slice::from_raw_parts(pointer, length) }.to_vec();
+            unsafe { hrst_free(pointer, length) };
+            bytes
+        };
+        unsafe {
+            let input = hrst_alloc(data.len());
+            slice::from_raw_parts_mut(input, data.len()).copy_from_slice(data);
+            let encoded = output(hrst_js_encode(input, data.len()));
+            hrst_free(input, data.len());
+            assert!(encoded == encode_bytes(data).unwrap());
+
+            let input = hrst_alloc(encoded.len());
+            slice::from_raw_parts_mut(input, encoded.len()).copy_from_slice(&encoded);
+            assert!(output(hrst_js_decode(input, encoded.len())) == data);
+            assert_eq!(hrst_js_decode(input, 3), HrstStatus::TruncatedHeader);
+            assert!(hrst_js_output().is_null() && hrst_js_output_length() == 0);
+            hrst_free(input, encoded.len());
+        }
+        assert!(hrst_alloc(0).is_null());
+    }
+}
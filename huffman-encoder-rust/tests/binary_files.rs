@@ -0,0 +1,73 @@
+// Roundtrip tests over binary (non-text) inputs
+// Compares the decoded bytes with the original byte for byte
+
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use super::common::{roundtrip, roundtrip_file, Rng, TempDir};
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn test_every_byte_value() {
+        let dir = TempDir::new();
+        let data: Vec<u8> = (0..=255).collect();
+        assert_eq!(roundtrip(&dir, "all-bytes.bin", &data), data);
+    }
+
+    #[test]
+    fn test_random_blob() {
+        let dir = TempDir::new();
+        let data = Rng::new(42).bytes(1024 * 1024);
+        assert_eq!(roundtrip(&dir, "random.bin", &data), data);
+    }
+
+    #[test]
+    fn test_nul_bytes() {
+        let dir = TempDir::new();
+        let data = vec![0u8; 4096];
+        assert_eq!(roundtrip(&dir, "nul.bin", &data), data);
+    }
+
+    #[test]
+    fn test_single_byte_files() {
+        let dir = TempDir::new();
+        for byte in [0u8, b'a', 0xFF] {
+            let name = format!("single-{}.bin", byte);
+            assert_eq!(roundtrip(&dir, &name, &[byte]), [byte]);
+        }
+    }
+
+    #[test]
+    fn test_empty_file() {
+        let dir = TempDir::new();
+        assert_eq!(roundtrip(&dir, "empty.bin", &[]), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn test_own_executable() {
+        let dir = TempDir::new();
+        let exe = Path::new(env!("CARGO_BIN_EXE_huffman-encoder"));
+        let copy = dir.join("huffman-encoder.bin");
+        fs::copy(exe, &copy).unwrap();
+        assert_eq!(roundtrip_file(&dir, &copy), fs::read(exe).unwrap());
+    }
+
+    // Two symbols get 1 bit codes, so sizes 1..=17 produce every amount of
+    // padding, including none at all.
+    #[test]
+    fn test_every_padding_length() {
+        let dir = TempDir::new();
+        let mut rng = Rng::new(7);
+        for size in 1..=17 {
+            let alternating: Vec<u8> = (0..size).map(|i| if i % 2 == 0 { 0x00 } else { 0xFF }).collect();
+            let name = format!("alternating-{}.bin", size);
+            assert_eq!(roundtrip(&dir, &name, &alternating), alternating, "size {}", size);
+
+            let random = rng.bytes(size);
+            let name = format!("random-{}.bin", size);
+            assert_eq!(roundtrip(&dir, &name, &random), random, "size {}", size);
+        }
+    }
+}
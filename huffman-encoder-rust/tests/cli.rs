@@ -0,0 +1,1287 @@
+// Tests for the command line interface
+// Exit codes and output of the binary, so CLI changes can't regress silently
+
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use super::common::{huffman, run, stderr, stdout, Rng, TempDir};
+    use std::ffi::OsStr;
+    use std::fs;
+
+    const NO_ARGS: [&str; 0] = [];
+
+    #[test]
+    fn test_no_args_prints_usage_and_fails() {
+        let output = run(NO_ARGS);
+        assert_eq!(output.status.code(), Some(255));
+        assert!(stdout(&output).contains("Usage:"));
+        assert!(stdout(&output).contains("encode"));
+        assert!(stdout(&output).contains("decode"));
+    }
+
+    #[test]
+    fn test_missing_input_argument_prints_usage_and_fails() {
+        let output = run(["encode"]);
+        assert_eq!(output.status.code(), Some(255));
+        assert!(stdout(&output).contains("Usage:"));
+    }
+
+    #[test]
+    fn test_help_prints_usage_and_succeeds() {
+        for flag in ["-h", "--help"] {
+            let output = run([flag]);
+            assert!(output.status.success());
+            assert!(stdout(&output).contains("Usage:"));
+            assert!(stdout(&output).contains("--output"));
+        }
+    }
+
+    #[test]
+    fn test_unknown_command() {
+        let dir = TempDir::new();
+        let input = dir.write("notes.txt", b"notes");
+
+        let output = run([OsStr::new("compress"), input.as_os_str()]);
+        assert_eq!(output.status.code(), Some(1));
+        assert!(stderr(&output).contains("Error: Unknown command 'compress'"), "stderr: {}", stderr(&output));
+        assert!(stdout(&output).contains("Usage:"));
+        assert!(!dir.join("notes.txt.encoded").exists());
+    }
+
+    #[test]
+    fn test_roundtrip_with_default_output_names() {
+        let dir = TempDir::new();
+        let input = dir.write("notes.txt", b"default names all the way");
+
+        let output = run([OsStr::new("encode"), input.as_os_str()]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        assert!(stderr(&output).contains("Encoding successful"), "stderr: {}", stderr(&output));
+        assert_eq!(stdout(&output), "");
+        let encoded = dir.join("notes.txt.encoded");
+        assert!(encoded.exists());
+
+        let output = run([OsStr::new("decode"), encoded.as_os_str()]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        assert!(stderr(&output).contains("Decoding successful"), "stderr: {}", stderr(&output));
+        assert_eq!(stdout(&output), "");
+        assert_eq!(fs::read(dir.join("notes.txt.encoded.decoded")).unwrap(), b"default names all the way");
+    }
+
+    #[test]
+    fn test_output_flag() {
+        let dir = TempDir::new();
+        let input = dir.write("notes.txt", b"explicit output names");
+        let encoded = dir.join("compressed.huf");
+        let decoded = dir.join("restored.txt");
+
+        for flag in ["-o", "--output"] {
+            let output = run([OsStr::new("encode"), input.as_os_str(), OsStr::new(flag), encoded.as_os_str(), OsStr::new("-f")]);
+            assert!(output.status.success(), "stderr: {}", stderr(&output));
+            let output = run([OsStr::new("decode"), encoded.as_os_str(), OsStr::new(flag), decoded.as_os_str(), OsStr::new("-f")]);
+            assert!(output.status.success(), "stderr: {}", stderr(&output));
+
+            assert_eq!(fs::read(&decoded).unwrap(), b"explicit output names");
+            assert!(!dir.join("notes.txt.encoded").exists());
+        }
+    }
+
+    #[test]
+    fn test_output_flag_before_input_options() {
+        let dir = TempDir::new();
+        let input = dir.write("notes.txt", b"flags in any order");
+        let encoded = dir.join("compressed.huf");
+
+        let output = run([
+            OsStr::new("encode"), input.as_os_str(), OsStr::new("--fsync"), OsStr::new("-o"), encoded.as_os_str(),
+        ]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        assert!(encoded.exists());
+    }
+
+    #[test]
+    fn test_missing_input_file() {
+        let dir = TempDir::new();
+        let input = dir.join("missing.txt");
+
+        for command in ["encode", "decode"] {
+            let output = run([OsStr::new(command), input.as_os_str()]);
+            assert_eq!(output.status.code(), Some(1));
+            assert!(stderr(&output).contains("Error: failed to open input"), "stderr: {}", stderr(&output));
+            assert!(stderr(&output).contains("No such file or directory"), "stderr: {}", stderr(&output));
+        }
+    }
+
+    #[test]
+    fn test_corrupt_input() {
+        let dir = TempDir::new();
+        let input = dir.write("notes.txt.encoded", b"HUFF is the C version, not this one");
+
+        let output = run([OsStr::new("decode"), input.as_os_str()]);
+        assert_eq!(output.status.code(), Some(3));
+        assert!(stderr(&output).contains("Invalid file format"), "stderr: {}", stderr(&output));
+        assert!(!dir.join("notes.txt.encoded.decoded").exists());
+    }
+
+    #[test]
+    fn test_invalid_max_output_size() {
+        let dir = TempDir::new();
+        let input = dir.write("notes.txt.encoded", b"");
+
+        let output = run([OsStr::new("decode"), input.as_os_str(), OsStr::new("--max-output-size"), OsStr::new("lots")]);
+        assert_eq!(output.status.code(), Some(1));
+        assert!(stderr(&output).contains("Invalid --max-output-size 'lots'"), "stderr: {}", stderr(&output));
+    }
+
+    #[test]
+    fn test_invalid_threads() {
+        let dir = TempDir::new();
+        let input = dir.write("notes.txt", b"notes");
+
+        for value in ["0", "many"] {
+            let output = run([OsStr::new("encode"), input.as_os_str(), OsStr::new("--threads"), OsStr::new(value)]);
+            assert_eq!(output.status.code(), Some(1));
+            assert!(stderr(&output).contains(&format!("Invalid --threads '{}'", value)), "stderr: {}", stderr(&output));
+        }
+        let output = run([OsStr::new("encode"), input.as_os_str(), OsStr::new("--threads"), OsStr::new("4")]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+    }
+
+    #[test]
+    fn test_invalid_block_size() {
+        let dir = TempDir::new();
+        let input = dir.write("notes.txt", b"notes");
+
+        for value in ["0", "big"] {
+            let output = run([OsStr::new("encode"), input.as_os_str(), OsStr::new("--block-size"), OsStr::new(value)]);
+            assert_eq!(output.status.code(), Some(1));
+            assert!(stderr(&output).contains(&format!("Invalid --block-size '{}'", value)), "stderr: {}", stderr(&output));
+        }
+    }
+
+    #[test]
+    fn test_unknown_option() {
+        let dir = TempDir::new();
+        let input = dir.write("notes.txt", b"notes");
+
+        for flag in ["--bogus", "--blocksize", "-x"] {
+            let output = run([OsStr::new("encode"), input.as_os_str(), OsStr::new(flag), OsStr::new("64K")]);
+            assert_eq!(output.status.code(), Some(1));
+            assert!(stderr(&output).contains(&format!("unknown option '{}'", flag)), "stderr: {}", stderr(&output));
+            assert!(!dir.join("notes.txt.encoded").exists());
+        }
+    }
+
+    #[test]
+    fn test_size_suffixes() {
+        let dir = TempDir::new();
+        let input = dir.write("notes.txt", &Rng::new(7).bytes(10000));
+
+        for flag in ["--block-size", "--memory-limit"] {
+            let mut encoded = Vec::new();
+            for value in ["4096", "4K", "4k"] {
+                let output_file = dir.join(&format!("notes{}.{}.encoded", flag, value));
+                let output = run([
+                    OsStr::new("encode"), input.as_os_str(), OsStr::new(flag), OsStr::new(value),
+                    OsStr::new("-o"), output_file.as_os_str(),
+                ]);
+                assert!(output.status.success(), "{} {}: {}", flag, value, stderr(&output));
+                encoded.push(fs::read(output_file).unwrap());
+            }
+            assert_eq!(encoded[0], encoded[1], "{}", flag);
+            assert_eq!(encoded[0], encoded[2], "{}", flag);
+        }
+    }
+
+    #[test]
+    fn test_invalid_memory_limit() {
+        let dir = TempDir::new();
+        let input = dir.write("notes.txt", b"notes");
+
+        for value in ["-1", "lots"] {
+            let output = run([OsStr::new("encode"), input.as_os_str(), OsStr::new("--memory-limit"), OsStr::new(value)]);
+            assert_eq!(output.status.code(), Some(1));
+            assert!(stderr(&output).contains(&format!("Invalid --memory-limit '{}'", value)), "stderr: {}", stderr(&output));
+        }
+    }
+
+    #[test]
+    fn test_invalid_buffer_size() {
+        let dir = TempDir::new();
+        let input = dir.write("notes.txt", b"notes");
+
+        for value in ["4095", "3K", "65M", "1G", "lots", "12KB"] {
+            let output = run([OsStr::new("encode"), input.as_os_str(), OsStr::new("--buffer-size"), OsStr::new(value)]);
+            assert_eq!(output.status.code(), Some(1));
+            assert!(stderr(&output).contains(&format!("Invalid --buffer-size '{}'", value)), "stderr: {}", stderr(&output));
+        }
+        for value in ["4096", "64K", "64M"] {
+            let output = run([OsStr::new("encode"), input.as_os_str(), OsStr::new("--buffer-size"), OsStr::new(value), OsStr::new("-f")]);
+            assert!(output.status.success(), "stderr: {}", stderr(&output));
+        }
+    }
+
+    #[test]
+    fn test_verbose_and_quiet_only_change_stderr() {
+        let dir = TempDir::new();
+        let input = dir.write("levels.txt", b"abracadabra");
+        let encoded = dir.join("levels.encoded");
+        let encode = |flag: Option<&str>| {
+            let mut args = vec![OsStr::new("encode"), input.as_os_str(), OsStr::new("-o"), encoded.as_os_str(), OsStr::new("-f")];
+            args.extend(flag.map(OsStr::new));
+            let output = run(args);
+            assert!(output.status.success(), "stderr: {}", stderr(&output));
+            assert_eq!(stdout(&output), "", "{:?}", flag);
+            (stderr(&output), fs::read(&encoded).unwrap())
+        };
+
+        let (default, data) = encode(None);
+        assert_eq!(default, "Encoding successful\n");
+        for flag in ["-v", "--verbose"] {
+            let (verbose, verbose_data) = encode(Some(flag));
+            assert!(verbose.contains("Char 'a' - encoding: 0b0, length: 1\n"), "stderr: {}", verbose);
+            assert!(verbose.contains("Padding bits: 1\n"), "stderr: {}", verbose);
+            assert!(verbose.ends_with("Encoding successful\n"), "stderr: {}", verbose);
+            assert!(verbose_data == data);
+        }
+        for flag in ["-q", "--quiet"] {
+            assert_eq!(encode(Some(flag)), (String::new(), data.clone()));
+        }
+
+        // Quiet still warns about data that couldn't be recovered
+        let truncated = dir.write("truncated.encoded", &data[..data.len() - 1]);
+        let decoded = dir.join("truncated.decoded");
+        let output = run([
+            OsStr::new("decode"), truncated.as_os_str(), OsStr::new("-o"), decoded.as_os_str(),
+            OsStr::new("--ignore-errors"), OsStr::new("--quiet"),
+        ]);
+        assert_eq!(output.status.code(), Some(2));
+        assert_eq!(stdout(&output), "");
+        assert!(stderr(&output).contains("WARNING: recovered only"), "stderr: {}", stderr(&output));
+        assert!(!stderr(&output).contains("successful"), "stderr: {}", stderr(&output));
+    }
+
+    #[test]
+    fn test_progress_bar_is_removed_again() {
+        let dir = TempDir::new();
+        // Text, so the input is counted and encoded in several steps
+        let contents: Vec<u8> = (0..3 * 1024 * 1024).map(|i: u32| b"the huffman tree "[(i % 17) as usize]).collect();
+        let input = dir.write("progress.txt", &contents);
+        let encoded = dir.join("progress.encoded");
+        let decoded = dir.join("progress.decoded");
+
+        let output = run([
+            OsStr::new("encode"), input.as_os_str(), OsStr::new("-o"), encoded.as_os_str(), OsStr::new("--progress"),
+        ]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        assert_eq!(stdout(&output), "");
+        let messages = stderr(&output);
+        assert!(messages.contains("\rCounting 100% [") && messages.contains("\rEncoding 100% ["), "stderr: {:?}", messages);
+        // The line ends blank, with the message after it on its own
+        assert!(messages.ends_with(" \rEncoding successful\n"), "stderr: {:?}", messages);
+
+        let output = run([
+            OsStr::new("decode"), encoded.as_os_str(), OsStr::new("-o"), decoded.as_os_str(), OsStr::new("--progress"),
+        ]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        assert!(stderr(&output).contains("\rDecoding 100% ["), "stderr: {:?}", stderr(&output));
+        assert!(stderr(&output).ends_with(" \rDecoding successful\n"), "stderr: {:?}", stderr(&output));
+        assert!(fs::read(&decoded).unwrap() == contents);
+
+        // Without the flag there is no bar
+        let output = run([OsStr::new("decode"), encoded.as_os_str(), OsStr::new("-o"), decoded.as_os_str(), OsStr::new("-f")]);
+        assert_eq!(stderr(&output), "Decoding successful\n");
+    }
+
+    #[test]
+    fn test_stats_of_encode_and_decode() {
+        let dir = TempDir::new();
+        let input = dir.write("stats.txt", b"abracadabra");
+        let encoded = dir.join("stats.encoded");
+        let decoded = dir.join("stats.decoded");
+
+        let output = run([OsStr::new("encode"), input.as_os_str(), OsStr::new("-o"), encoded.as_os_str(), OsStr::new("--stats")]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        let stats = stdout(&output);
+        let lines: Vec<&str> = stats.lines().collect();
+        assert_eq!(lines[..8], [
+            "input_bytes: 11", "output_bytes: 67", "streams: 1", "header_bytes: 64",
+            "payload_bits: 23", "padding_bits: 1", "distinct_symbols: 5", "entropy: 2.040373",
+        ]);
+        assert!(lines[8].starts_with("counting_seconds: ") && lines[9].starts_with("encoding_seconds: "), "{:?}", lines);
+        // The messages stay on stderr
+        assert_eq!(stderr(&output), "Encoding successful\n");
+
+        let output = run([OsStr::new("decode"), encoded.as_os_str(), OsStr::new("-o"), decoded.as_os_str(), OsStr::new("--json")]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        let json = stdout(&output);
+        assert!(json.starts_with(concat!(
+            r#"{"input_bytes": 67, "output_bytes": 11, "streams": 1, "header_bytes": 64, "payload_bits": 23, "#,
+            r#""padding_bits": 1, "distinct_symbols": 5, "checksum": "verified", "decoding_seconds": "#,
+        )), "{}", json);
+        assert!(json.ends_with("}\n"), "{}", json);
+    }
+
+    #[test]
+    fn test_table_files_in_both_formats() {
+        let dir = TempDir::new();
+        let training = dir.write("training.txt", b"abracadabra");
+        let message = dir.write("message.txt", b"barbara");
+
+        let output = run([OsStr::new("table"), training.as_os_str(), OsStr::new("--format"), OsStr::new("text")]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        assert_eq!(stderr(&output), "Table of 5 codes written\n");
+        let text = dir.join("training.txt.huft.txt");
+        // The id and the hash of the table first, as a comment
+        let (comment, codes) = fs::read_to_string(&text).unwrap().split_once('\n').map(|(a, b)| (a.to_string(), b.to_string())).unwrap();
+        assert!(comment.starts_with("# table ") && comment.len() == 8 + 16 + 1 + 16, "{}", comment);
+        // The escape code last, for the bytes the training data doesn't have
+        assert_eq!(codes, "0x61 0\n0x62 100\n0x72 101\n0x63 1110\n0x64 1111\nescape 110\n");
+        let output = run([OsStr::new("table"), training.as_os_str()]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        let binary = dir.join("training.txt.huft");
+        // "HUFT", the id and the hash, the count, then the entries the way a
+        // header has them, and the escape code
+        assert_eq!(fs::read(&binary).unwrap().len(), 4 + 8 + 8 + 4 + 5 * 6 + 5);
+
+        // Either file gives the same stream but for the id of the table, and
+        // it decodes like any other
+        let mut encoded = Vec::new();
+        for table in [&text, &binary] {
+            let output_path = dir.join("message.encoded");
+            let output = run([
+                OsStr::new("encode"), message.as_os_str(), OsStr::new("-o"), output_path.as_os_str(),
+                OsStr::new("--table-file"), table.as_os_str(), OsStr::new("-f"),
+            ]);
+            assert!(output.status.success(), "stderr: {}", stderr(&output));
+            encoded.push(fs::read(&output_path).unwrap());
+        }
+        assert_eq!(encoded[0][..12], encoded[1][..12]);
+        assert_ne!(encoded[0][12..20], encoded[1][12..20]);
+        assert_eq!(encoded[0][20..], encoded[1][20..]);
+        let decoded = dir.join("message.decoded");
+        let output = run([OsStr::new("decode"), dir.join("message.encoded").as_os_str(), OsStr::new("-o"), decoded.as_os_str()]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        assert_eq!(fs::read(&decoded).unwrap(), b"barbara");
+
+        // Bytes the table has no code for are escaped
+        let unseen = dir.write("unseen.txt", b"zebra!\xff\n");
+        for table in [&text, &binary] {
+            let output_path = dir.join("unseen.encoded");
+            let output = run([
+                OsStr::new("encode"), unseen.as_os_str(), OsStr::new("-o"), output_path.as_os_str(),
+                OsStr::new("--table-file"), table.as_os_str(), OsStr::new("-f"),
+            ]);
+            assert!(output.status.success(), "stderr: {}", stderr(&output));
+            let output = run([OsStr::new("decode"), output_path.as_os_str(), OsStr::new("-o"), decoded.as_os_str(), OsStr::new("-f")]);
+            assert!(output.status.success(), "stderr: {}", stderr(&output));
+            assert_eq!(fs::read(&decoded).unwrap(), b"zebra!\xff\n");
+        }
+
+        // A table that isn't a prefix code is rejected before anything is written
+        let ambiguous = dir.write("ambiguous.txt", b"0x61 1\n0x62 10\n0x72 0\n");
+        let output = run([OsStr::new("encode"), message.as_os_str(), OsStr::new("--table-file"), ambiguous.as_os_str()]);
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(stderr(&output), format!(
+            "Error: failed to read table '{}': invalid encoding table: code of 97 is a prefix of the code of 98\n",
+            ambiguous.display(),
+        ));
+        assert!(!dir.join("message.txt.encoded").exists());
+    }
+
+    #[test]
+    fn test_table_id_binds_data_to_its_table() {
+        let dir = TempDir::new();
+        let message = dir.write("message.txt", b"barbara");
+        let mut tables = Vec::new();
+        for (name, training) in [("a.txt", &b"abracadabra"[..]), ("b.txt", b"abracadabra")] {
+            let training = dir.write(name, training);
+            let output = run([OsStr::new("table"), training.as_os_str()]);
+            assert!(output.status.success(), "stderr: {}", stderr(&output));
+            tables.push(dir.join(&format!("{}.huft", name)));
+        }
+        let encoded = dir.join("message.txt.encoded");
+        let output = run([OsStr::new("encode"), message.as_os_str(), OsStr::new("--table-file"), tables[0].as_os_str()]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+
+        // The table it was encoded with
+        let decoded = dir.join("message.decoded");
+        let output = run([OsStr::new("decode"), encoded.as_os_str(), OsStr::new("-o"), decoded.as_os_str(), OsStr::new("--table-file"), tables[0].as_os_str()]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        assert_eq!(fs::read(&decoded).unwrap(), b"barbara");
+
+        // Table B has the same codes, but another id
+        let id = |table: &std::path::Path| format!("{:016x}", u64::from_le_bytes(fs::read(table).unwrap()[4..12].try_into().unwrap()));
+        let other = dir.join("other.decoded");
+        let output = run([OsStr::new("decode"), encoded.as_os_str(), OsStr::new("-o"), other.as_os_str(), OsStr::new("--table-file"), tables[1].as_os_str()]);
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(stderr(&output), format!(
+            "Error: failed to decode '{}': table mismatch: the data was encoded with table {}, '{}' is table {}\n",
+            encoded.display(), id(&tables[0]), tables[1].display(), id(&tables[1]),
+        ));
+        assert!(!other.exists());
+
+        // Data encoded without a table file, and a table changed after it was
+        // saved, which loses its id
+        let plain = dir.join("plain.huf");
+        let output = run([OsStr::new("encode"), message.as_os_str(), OsStr::new("-o"), plain.as_os_str()]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        let output = run([OsStr::new("decode"), plain.as_os_str(), OsStr::new("-o"), other.as_os_str(), OsStr::new("--table-file"), tables[0].as_os_str()]);
+        assert_eq!(output.status.code(), Some(1));
+        assert!(stderr(&output).contains("table mismatch: the data wasn't encoded with a table file"), "stderr: {}", stderr(&output));
+        let mut changed = fs::read(&tables[0]).unwrap();
+        // The hash no longer matches
+        changed[12] ^= 1;
+        let changed = dir.write("changed.huft", &changed);
+        let output = run([OsStr::new("decode"), encoded.as_os_str(), OsStr::new("-o"), other.as_os_str(), OsStr::new("--table-file"), changed.as_os_str()]);
+        assert_eq!(output.status.code(), Some(1));
+        assert!(stderr(&output).contains(&format!("table {} in '{}' was changed after it was saved", id(&tables[0]), changed.display())), "stderr: {}", stderr(&output));
+        assert!(stderr(&output).contains(&format!("table mismatch: '{}' has no table id", changed.display())), "stderr: {}", stderr(&output));
+        assert!(!other.exists());
+    }
+
+    #[test]
+    fn test_merge_files_with_the_same_table() {
+        let dir = TempDir::new();
+        let training = dir.write("training.txt", b"abracadabra");
+        let output = run([OsStr::new("table"), training.as_os_str()]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        let table = dir.join("training.txt.huft");
+        let mut encoded = Vec::new();
+        for (name, contents) in [("a.txt", &b"abracadabra"[..]), ("b.txt", b"barbara"), ("c.txt", b"cab")] {
+            let input = dir.write(name, contents);
+            let output = run([OsStr::new("encode"), input.as_os_str(), OsStr::new("--table-file"), table.as_os_str()]);
+            assert!(output.status.success(), "stderr: {}", stderr(&output));
+            encoded.push(dir.join(&format!("{}.encoded", name)));
+        }
+
+        let merged = dir.join("abc.huf");
+        let output = run([
+            OsStr::new("merge"), encoded[0].as_os_str(), encoded[1].as_os_str(), encoded[2].as_os_str(),
+            OsStr::new("-o"), merged.as_os_str(),
+        ]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        assert_eq!(stderr(&output), "Merged 3 files, which decode to 21 bytes\n");
+        let output = run([OsStr::new("decode"), merged.as_os_str()]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        assert_eq!(fs::read(dir.join("abc.huf.decoded")).unwrap(), b"abracadabrabarbaracab");
+
+        // A file with its own table can't be appended
+        let other = dir.write("other.txt", b"something else");
+        let output = run([OsStr::new("encode"), other.as_os_str()]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        let output = run([OsStr::new("merge"), encoded[0].as_os_str(), dir.join("other.txt.encoded").as_os_str()]);
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(stderr(&output), format!(
+            "Error: failed to merge into '{}.merged': input 2 has a different table than input 1\n",
+            encoded[0].display(),
+        ));
+        assert!(!dir.join("a.txt.encoded.merged").exists());
+    }
+
+    #[test]
+    fn test_rle_shrinks_runs() {
+        let dir = TempDir::new();
+        let input = dir.write("zeros.bin", &vec![0; 1_000_000]);
+        let output = run([OsStr::new("encode"), input.as_os_str(), OsStr::new("-o"), dir.join("plain.huf").as_os_str()]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        let output = run([OsStr::new("encode"), input.as_os_str(), OsStr::new("--rle"), OsStr::new("-o"), dir.join("rle.huf").as_os_str()]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        let (plain, rle) = (fs::metadata(dir.join("plain.huf")).unwrap().len(), fs::metadata(dir.join("rle.huf")).unwrap().len());
+        assert!(rle * 30 < plain, "{} bytes with --rle, {} without", rle, plain);
+
+        let output = run([OsStr::new("decode"), dir.join("rle.huf").as_os_str(), OsStr::new("-o"), dir.join("zeros.out").as_os_str()]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        assert!(fs::read(dir.join("zeros.out")).unwrap() == vec![0; 1_000_000]);
+
+        let output = run([OsStr::new("encode"), input.as_os_str(), OsStr::new("--rle"), OsStr::new("--adaptive")]);
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(stderr(&output), "Error: adaptive streams can't be run-length encoded\n");
+    }
+
+    #[test]
+    fn test_symbol_width_codes_pairs() {
+        let dir = TempDir::new();
+        let input = dir.write("utf16le.txt", include_bytes!("fixtures/utf16le.txt"));
+        let output = run([OsStr::new("encode"), input.as_os_str(), OsStr::new("-o"), dir.join("bytes.huf").as_os_str()]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        let output = run([OsStr::new("encode"), input.as_os_str(), OsStr::new("--symbol-width"), OsStr::new("2"), OsStr::new("-o"), dir.join("pairs.huf").as_os_str()]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        let (bytes, pairs) = (fs::metadata(dir.join("bytes.huf")).unwrap().len(), fs::metadata(dir.join("pairs.huf")).unwrap().len());
+        assert!(pairs * 5 < bytes * 4, "{} bytes in pairs, {} as bytes", pairs, bytes);
+
+        let output = run([OsStr::new("decode"), dir.join("pairs.huf").as_os_str(), OsStr::new("-o"), dir.join("utf16le.out").as_os_str()]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        assert!(fs::read(dir.join("utf16le.out")).unwrap() == include_bytes!("fixtures/utf16le.txt"));
+
+        let output = run([OsStr::new("encode"), input.as_os_str(), OsStr::new("--symbol-width"), OsStr::new("3")]);
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(stderr(&output), "Error: Invalid --symbol-width '3', expected 1 or 2\n");
+        let output = run([OsStr::new("encode"), input.as_os_str(), OsStr::new("--symbol-width"), OsStr::new("2"), OsStr::new("--rle")]);
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(stderr(&output), "Error: pairs of bytes can't be coded adaptively or run-length encoded\n");
+    }
+
+    #[test]
+    fn test_tokens_code_words() {
+        let dir = TempDir::new();
+        let input = dir.write("utf8.txt", include_bytes!("fixtures/utf8.txt"));
+        let output = run([OsStr::new("encode"), input.as_os_str(), OsStr::new("-o"), dir.join("bytes.huf").as_os_str()]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        let output = run([OsStr::new("encode"), input.as_os_str(), OsStr::new("--tokens"), OsStr::new("-o"), dir.join("tokens.huf").as_os_str()]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        let (bytes, tokens) = (fs::metadata(dir.join("bytes.huf")).unwrap().len(), fs::metadata(dir.join("tokens.huf")).unwrap().len());
+        assert!(tokens * 3 < bytes * 2, "{} bytes with --tokens, {} without", tokens, bytes);
+
+        let output = run([OsStr::new("decode"), dir.join("tokens.huf").as_os_str(), OsStr::new("-o"), dir.join("utf8.out").as_os_str()]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        assert!(fs::read(dir.join("utf8.out")).unwrap() == include_bytes!("fixtures/utf8.txt"));
+
+        let output = run([OsStr::new("encode"), input.as_os_str(), OsStr::new("--tokens"), OsStr::new("--adaptive")]);
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(stderr(&output), "Error: tokens can't be coded adaptively, run-length encoded or in pairs of bytes\n");
+    }
+
+    #[test]
+    fn test_bwt_transforms_blocks_first() {
+        let dir = TempDir::new();
+        let input = dir.write("english.txt", include_bytes!("fixtures/english.txt"));
+        let output = run([OsStr::new("encode"), input.as_os_str(), OsStr::new("-o"), dir.join("bytes.huf").as_os_str()]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        let output = run([OsStr::new("encode"), input.as_os_str(), OsStr::new("--bwt"), OsStr::new("-o"), dir.join("bwt.huf").as_os_str()]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        let (bytes, bwt) = (fs::metadata(dir.join("bytes.huf")).unwrap().len(), fs::metadata(dir.join("bwt.huf")).unwrap().len());
+        assert!(bwt * 3 < bytes * 2, "{} bytes with --bwt, {} without", bwt, bytes);
+
+        let output = run([OsStr::new("decode"), dir.join("bwt.huf").as_os_str(), OsStr::new("-o"), dir.join("english.out").as_os_str()]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        assert!(fs::read(dir.join("english.out")).unwrap() == include_bytes!("fixtures/english.txt"));
+
+        let output = run([OsStr::new("encode"), input.as_os_str(), OsStr::new("--bwt"), OsStr::new("--adaptive")]);
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(
+            stderr(&output),
+            "Error: Burrows-Wheeler transformed blocks can't be coded adaptively, in pairs of bytes or in tokens\n",
+        );
+    }
+
+    #[test]
+    fn test_context_codes_bytes_after_the_one_before() {
+        let dir = TempDir::new();
+        let input = dir.write("utf8.txt", include_bytes!("fixtures/utf8.txt"));
+        let output = run([OsStr::new("encode"), input.as_os_str(), OsStr::new("-o"), dir.join("bytes.huf").as_os_str()]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        let output = run([OsStr::new("encode"), input.as_os_str(), OsStr::new("--context"), OsStr::new("-o"), dir.join("context.huf").as_os_str()]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        let (bytes, context) = (fs::metadata(dir.join("bytes.huf")).unwrap().len(), fs::metadata(dir.join("context.huf")).unwrap().len());
+        assert!(context * 4 < bytes * 3, "{} bytes with --context, {} without", context, bytes);
+
+        let output = run([OsStr::new("decode"), dir.join("context.huf").as_os_str(), OsStr::new("-o"), dir.join("utf8.out").as_os_str()]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        assert!(fs::read(dir.join("utf8.out")).unwrap() == include_bytes!("fixtures/utf8.txt"));
+
+        let output = run([OsStr::new("encode"), input.as_os_str(), OsStr::new("--context"), OsStr::new("--tokens")]);
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(
+            stderr(&output),
+            "Error: tables per context can't go with adaptive codes, run-length encoding, pairs of bytes, tokens or the Burrows-Wheeler transform\n",
+        );
+    }
+
+    #[test]
+    fn test_method_range_takes_fractions_of_bits() {
+        let dir = TempDir::new();
+        let input = dir.write("sparse.bin", include_bytes!("fixtures/sparse.bin"));
+        let output = run([OsStr::new("encode"), input.as_os_str(), OsStr::new("-o"), dir.join("bytes.huf").as_os_str()]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        let output = run([
+            OsStr::new("encode"), input.as_os_str(), OsStr::new("--method"), OsStr::new("range"), OsStr::new("-o"), dir.join("range.huf").as_os_str(),
+        ]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        let (bytes, range) = (fs::metadata(dir.join("bytes.huf")).unwrap().len(), fs::metadata(dir.join("range.huf")).unwrap().len());
+        assert!(range * 3 < bytes, "{} bytes with --method range, {} without", range, bytes);
+
+        let output = run([OsStr::new("decode"), dir.join("range.huf").as_os_str(), OsStr::new("-o"), dir.join("sparse.out").as_os_str()]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        assert!(fs::read(dir.join("sparse.out")).unwrap() == include_bytes!("fixtures/sparse.bin"));
+
+        // analyze tells what it would save
+        let output = run([OsStr::new("analyze"), input.as_os_str()]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        assert!(stdout(&output).starts_with("Bytes           16384\n"), "{}", stdout(&output));
+        assert!(stdout(&output).trim_end().ends_with("% smaller"), "{}", stdout(&output));
+
+        let output = run([OsStr::new("encode"), input.as_os_str(), OsStr::new("--method"), OsStr::new("arithmetic")]);
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(stderr(&output), "Error: Invalid --method 'arithmetic', expected huffman or range\n");
+        let output = run([OsStr::new("encode"), input.as_os_str(), OsStr::new("--method"), OsStr::new("range"), OsStr::new("--rle")]);
+        assert_eq!(output.status.code(), Some(1));
+        assert!(stderr(&output).starts_with("Error: range coding can't go with adaptive codes"), "{}", stderr(&output));
+    }
+
+    #[test]
+    fn test_filter_delta_codes_the_differences_of_samples() {
+        let dir = TempDir::new();
+        // A slow sine wave in 16-bit samples
+        let pcm: Vec<u8> = (0..50_000)
+            .map(|i| (3000.0 * (i as f64 / 70.0).sin()) as i16)
+            .flat_map(i16::to_le_bytes)
+            .collect();
+        let input = dir.write("sine.pcm", &pcm);
+        let output = run([OsStr::new("encode"), input.as_os_str(), OsStr::new("-o"), dir.join("bytes.huf").as_os_str()]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        let output = run([
+            OsStr::new("encode"), input.as_os_str(), OsStr::new("--filter"), OsStr::new("delta"), OsStr::new("--stride"), OsStr::new("2"),
+            OsStr::new("-o"), dir.join("delta.huf").as_os_str(),
+        ]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        let (bytes, delta) = (fs::metadata(dir.join("bytes.huf")).unwrap().len(), fs::metadata(dir.join("delta.huf")).unwrap().len());
+        assert!(delta * 3 < bytes * 2, "{} bytes with --filter delta, {} without", delta, bytes);
+
+        let output = run([OsStr::new("decode"), dir.join("delta.huf").as_os_str(), OsStr::new("-o"), dir.join("sine.out").as_os_str()]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        assert!(fs::read(dir.join("sine.out")).unwrap() == pcm);
+
+        let output = run([OsStr::new("encode"), input.as_os_str(), OsStr::new("--filter"), OsStr::new("sub")]);
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(stderr(&output), "Error: Invalid --filter 'sub', expected delta or xor-prev\n");
+        let output = run([OsStr::new("encode"), input.as_os_str(), OsStr::new("--stride"), OsStr::new("2")]);
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(stderr(&output), "Error: a stride needs a filter\n");
+    }
+
+    #[test]
+    fn test_recursive_test_summarizes_a_tree() {
+        let dir = TempDir::new();
+        fs::create_dir_all(dir.join("2024/logs")).unwrap();
+        let encode = |name: &str, contents: &[u8]| {
+            let input = dir.write(name, contents);
+            let output = run([OsStr::new("encode"), input.as_os_str()]);
+            assert!(output.status.success(), "stderr: {}", stderr(&output));
+            fs::remove_file(input).unwrap();
+            dir.join(&format!("{}.encoded", name))
+        };
+        encode("notes.txt", b"kept for years");
+        encode("2024/logs/app.log", b"GET / 200\nGET /favicon.ico 404\n");
+        let corrupt = encode("2024/report.txt", b"the numbers of the year, all of them");
+        // Two streams, like `cat a.encoded b.encoded`
+        let first = encode("2024/part1.txt", b"the first part");
+        let second = encode("2024/part2.txt", b"and the second one");
+        let mut both = fs::read(&first).unwrap();
+        both.extend(fs::read(&second).unwrap());
+        fs::write(dir.join("2024/both.encoded"), both).unwrap();
+        fs::write(dir.join("2024/readme.md"), b"not encoded").unwrap();
+
+        let output = run([OsStr::new("test"), dir.path().as_os_str(), OsStr::new("--recursive")]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        assert_eq!(stdout(&output), "Passed: 6\nFailed: 0\nSkipped: 1 (not encoded files)\n");
+        assert!(stderr(&output).contains("[1/6] "), "stderr: {}", stderr(&output));
+        assert!(stderr(&output).contains("both.encoded: OK, 32 bytes in 2 streams"), "stderr: {}", stderr(&output));
+
+        let mut data = fs::read(&corrupt).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 0x40;
+        fs::write(&corrupt, data).unwrap();
+        let output = run([OsStr::new("test"), dir.path().as_os_str(), OsStr::new("-r")]);
+        assert_eq!(output.status.code(), Some(3));
+        assert_eq!(
+            stdout(&output),
+            format!("Passed: 5\nFailed: 1\nSkipped: 1 (not encoded files)\n  {}\n", corrupt.display()),
+        );
+        assert!(stderr(&output).contains(&format!("{}: FAILED, ", corrupt.display())), "stderr: {}", stderr(&output));
+        assert!(stderr(&output).ends_with("Error: 1 of 6 files failed the test\n"), "stderr: {}", stderr(&output));
+
+        // A single file needs no --recursive, a directory does
+        let output = run([OsStr::new("test"), first.as_os_str()]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        assert_eq!(stdout(&output), "Passed: 1\nFailed: 0\n");
+        let output = run([OsStr::new("test"), dir.path().as_os_str()]);
+        assert_eq!(output.status.code(), Some(1));
+        assert!(stderr(&output).ends_with("is a directory, test it with --recursive\n"), "stderr: {}", stderr(&output));
+    }
+
+    #[test]
+    fn test_completions_cover_the_commands_and_flags() {
+        let output = run(["completions", "bash"]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        let script = stdout(&output);
+        assert!(script.contains("complete -o filenames -F "), "{}", script);
+        let words: Vec<&str> = script.split(|c: char| c.is_whitespace() || "\"|)".contains(c)).collect();
+        for command in ["encode", "decode", "repair", "bench", "table", "merge", "analyze", "test", "ratio", "completions"] {
+            assert!(words.contains(&command), "{} isn't completed: {}", command, script);
+        }
+        for flag in ["-o", "--output", "--threads", "--block-size", "--progress", "--stats", "--adaptive", "--method", "-r", "--recursive"] {
+            assert!(words.contains(&flag), "{} isn't completed: {}", flag, script);
+        }
+        assert!(script.contains("COMPREPLY=($(compgen -W \"huffman range\" -- \"$cur\"))"), "{}", script);
+
+        // The usage comes from the same table
+        let usage = stdout(&run(["--help"]));
+        assert!(usage.contains("  completions Write the completion script of a shell"), "{}", usage);
+
+        for shell in ["zsh", "fish"] {
+            let output = run(["completions", shell]);
+            assert!(output.status.success(), "stderr: {}", stderr(&output));
+            assert!(stdout(&output).contains("recursive"), "{}", stdout(&output));
+        }
+        let output = run(["completions", "powershell"]);
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(stderr(&output), "Error: Unknown shell 'powershell', expected bash, zsh or fish\n");
+    }
+
+    #[test]
+    fn test_color_only_when_asked() {
+        let dir = TempDir::new();
+        let input = dir.write("notes.txt", b"in color");
+        let output = run([OsStr::new("encode"), input.as_os_str()]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        let encoded = dir.join("notes.txt.encoded");
+        let mut data = fs::read(&encoded).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 0x40;
+        fs::write(&encoded, data).unwrap();
+
+        // Not a terminal, so auto is the same as never
+        for color in [None, Some("never")] {
+            let mut args = vec![OsStr::new("test"), encoded.as_os_str()];
+            args.extend(color.iter().flat_map(|color| [OsStr::new("--color"), OsStr::new(color)]));
+            let output = run(args);
+            assert_eq!(output.status.code(), Some(3));
+            assert!(stderr(&output).contains(": FAILED, "), "stderr: {}", stderr(&output));
+            assert!(!stderr(&output).contains('\x1b'), "stderr: {:?}", stderr(&output));
+            assert!(!stdout(&output).contains('\x1b'), "stdout: {:?}", stdout(&output));
+        }
+
+        let output = run([OsStr::new("test"), encoded.as_os_str(), OsStr::new("--color"), OsStr::new("always")]);
+        assert_eq!(output.status.code(), Some(3));
+        assert!(stderr(&output).contains("\x1b[1;33mWARNING:\x1b[0m "), "stderr: {:?}", stderr(&output));
+        assert!(stderr(&output).contains("\x1b[1;31mFAILED\x1b[0m, "), "stderr: {:?}", stderr(&output));
+        assert!(stderr(&output).ends_with("\x1b[1;31mError:\x1b[0m 1 of 1 files failed the test\n"), "stderr: {:?}", stderr(&output));
+        assert!(stdout(&output).starts_with("Passed: \x1b[1m0\x1b[0m\nFailed: \x1b[1;31m1\x1b[0m\n"), "stdout: {:?}", stdout(&output));
+
+        let output = run([OsStr::new("test"), encoded.as_os_str(), OsStr::new("--color"), OsStr::new("sometimes")]);
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(stderr(&output), "Error: Invalid --color 'sometimes', expected auto, always or never\n");
+    }
+
+    #[test]
+    fn test_refuses_to_overwrite_when_not_on_a_terminal() {
+        let dir = TempDir::new();
+        let input = dir.write("notes.txt", b"the new notes");
+        let encoded = dir.write("notes.txt.encoded", b"the old output");
+        // stdin isn't a terminal here, so nobody is asked and the output stays
+        let output = run([OsStr::new("encode"), input.as_os_str()]);
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(stderr(&output), format!("Error: output '{}' exists already, use -f to overwrite it\n", encoded.display()));
+        assert_eq!(fs::read(&encoded).unwrap(), b"the old output");
+        assert_eq!(fs::read(&input).unwrap(), b"the new notes");
+
+        // --force replaces it, still without asking
+        for force in ["-f", "--force"] {
+            fs::write(&encoded, b"the old output").unwrap();
+            let output = run([OsStr::new("encode"), input.as_os_str(), OsStr::new(force)]);
+            assert!(output.status.success(), "stderr: {}", stderr(&output));
+            assert!(!stderr(&output).contains("overwrite"), "stderr: {}", stderr(&output));
+            assert_ne!(fs::read(&encoded).unwrap(), b"the old output");
+        }
+    }
+
+    #[test]
+    fn test_output_template_names_a_batch() {
+        let dir = TempDir::new();
+        fs::create_dir(dir.join("logs")).unwrap();
+        fs::create_dir(dir.join("notes")).unwrap();
+        let inputs = [dir.write("logs/app.log", b"started\nstopped\n"), dir.write("notes/todo.txt", b"write tests"), dir.write("README", b"read me")];
+
+        let mut args = vec![OsStr::new("encode")];
+        args.extend(inputs.iter().map(|input| input.as_os_str()));
+        args.extend([OsStr::new("--output-template"), OsStr::new("{dir}/compressed/{stem}.{ext}.{size}.huf")]);
+        let output = run(&args);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        let encoded = [dir.join("logs/compressed/app.log.16.huf"), dir.join("notes/compressed/todo.txt.11.huf"), dir.join("compressed/README..7.huf")];
+        for (input, encoded) in inputs.iter().zip(&encoded) {
+            let decoded = dir.join("decoded");
+            let output = run([OsStr::new("decode"), encoded.as_os_str(), OsStr::new("-o"), decoded.as_os_str(), OsStr::new("-f")]);
+            assert!(output.status.success(), "stderr: {}", stderr(&output));
+            assert_eq!(fs::read(decoded).unwrap(), fs::read(input).unwrap());
+        }
+
+        // Both would be encoded to out/app.huf, which is found before either is
+        let other = dir.write("notes/app.txt", b"another app");
+        let template = format!("{}/out/{{stem}}.huf", dir.path().display());
+        let output = run([OsStr::new("encode"), inputs[0].as_os_str(), other.as_os_str(), OsStr::new("--output-template"), OsStr::new(&template)]);
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(
+            stderr(&output),
+            format!("Error: '{}' and '{}' would both be written to '{}'\n", inputs[0].display(), other.display(), dir.join("out/app.huf").display()),
+        );
+        assert!(!dir.join("out").exists());
+
+        let output = run([OsStr::new("encode"), inputs[0].as_os_str(), OsStr::new("--output-template"), OsStr::new("{stem}.{time}.huf")]);
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(
+            stderr(&output),
+            "Error: Invalid --output-template '{stem}.{time}.huf', unknown placeholder {time}, expected {name}, {stem}, {ext}, {dir}, {date} or {size}\n",
+        );
+
+        let output = run([OsStr::new("encode"), inputs[0].as_os_str(), other.as_os_str(), OsStr::new("-o"), dir.join("both.huf").as_os_str()]);
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(stderr(&output), "Error: -o names the output of a single input, use --output-template for several\n");
+    }
+
+    #[test]
+    fn test_config_files_and_their_precedence() {
+        let dir = TempDir::new();
+        fs::create_dir_all(dir.join("home/.config/huff")).unwrap();
+        fs::create_dir(dir.join("project")).unwrap();
+        let input = dir.write("project/notes.txt", b"configured once, used every time");
+        let user_config = dir.write("home/.config/huff/config.toml", b"stats = true\nformat = \"text\"\nsuffix = \".huf\"\n");
+        // The user's config, and the project's in the directory the command runs in
+        let table = |args: &[&str]| {
+            let output = huffman()
+                .env("HOME", dir.join("home"))
+                .env_remove("XDG_CONFIG_HOME")
+                .env_remove("HUFF_CONFIG")
+                .current_dir(dir.join("project"))
+                .args(["table", "notes.txt"])
+                .args(args)
+                .output()
+                .unwrap();
+            let written = ["notes.txt.huft", "notes.txt.huft.txt"].into_iter().filter(|name| dir.join("project").join(name).exists()).collect::<Vec<_>>();
+            written.iter().for_each(|name| fs::remove_file(dir.join("project").join(name)).unwrap());
+            (output, written)
+        };
+
+        let (output, written) = table(&[]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        assert_eq!(written, ["notes.txt.huft.txt"]);
+        assert!(stderr(&output).contains(&format!("WARNING: {}:3: unknown key 'suffix'\n", user_config.display())), "stderr: {}", stderr(&output));
+
+        dir.write("project/.huff.toml", b"# binary tables here\nformat = 'binary'\n");
+        let (output, written) = table(&[]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        assert_eq!(written, ["notes.txt.huft"]);
+        let (_, written) = table(&["--format", "text"]);
+        assert_eq!(written, ["notes.txt.huft.txt"]);
+
+        // stats = true of the user's config still holds
+        let output = huffman().env("HOME", dir.join("home")).env_remove("XDG_CONFIG_HOME").env_remove("HUFF_CONFIG").current_dir(dir.join("project")).args(["encode", "notes.txt"]).output().unwrap();
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        assert!(stdout(&output).contains("input_bytes"), "stdout: {}", stdout(&output));
+
+        // HUFF_CONFIG takes the place of the user's config
+        let other = dir.write("other.toml", b"format = \"text\"\n");
+        let output = huffman().env("HUFF_CONFIG", &other).current_dir(dir.join("project")).args(["table", "notes.txt"]).output().unwrap();
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        fs::remove_file(dir.join("project/notes.txt.huft")).unwrap();
+        assert!(!stderr(&output).contains("suffix"), "stderr: {}", stderr(&output));
+
+        dir.write("project/.huff.toml", b"format = 'binary'\nthreads\n");
+        let (output, written) = table(&[]);
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(stderr(&output), "Error: .huff.toml:2: expected key = value\n");
+        assert!(written.is_empty());
+
+        let (output, written) = table(&["--no-config"]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        assert_eq!(written, ["notes.txt.huft"]);
+        assert!(!stderr(&output).contains("WARNING"), "stderr: {}", stderr(&output));
+        assert!(input.exists());
+    }
+
+    #[test]
+    fn test_ratio_adds_up_a_tree_from_the_headers() {
+        let dir = TempDir::new();
+        let tree = dir.join("tree");
+        fs::create_dir_all(tree.join("a/b")).unwrap();
+        fs::create_dir(tree.join("c")).unwrap();
+        let encode = |name: &str, original: usize, encoded: &str, flags: &[&str]| -> u64 {
+            let input = dir.write(name, &b"the quick brown fox jumps over the lazy dog\n".repeat(original / 44 + 1)[..original]);
+            let output = huffman().arg("encode").arg(&input).arg("-o").arg(tree.join(encoded)).args(flags).output().unwrap();
+            assert!(output.status.success(), "stderr: {}", stderr(&output));
+            fs::metadata(tree.join(encoded)).unwrap().len()
+        };
+        let x = encode("x.txt", 1000, "a/x.huf", &[]);
+        let y = encode("y.txt", 3000, "a/b/y.huf", &[]);
+        let z = encode("z.txt", 500, "c/z.huf", &[]);
+        // Adaptive files only know their size at the end
+        let adaptive = encode("adaptive.txt", 700, "c/adaptive.huf", &["--adaptive"]);
+        dir.write("tree/c/notes.txt", b"not encoded");
+        fs::write(tree.join("a/b/empty"), b"").unwrap();
+
+        let saved = |original: u64, encoded: u64| format!("{:.1}%", 100.0 - encoded as f64 * 100.0 / original as f64);
+        let line = |original: u64, encoded: u64, path: &str| format!("{:>14} {:>14} {:>7}  {}", original, encoded, saved(original, encoded), path);
+        let output = huffman().current_dir(dir.path()).args(["ratio", "tree"]).output().unwrap();
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        let expected = [
+            format!("{:>14} {:>14} {:>7}  Directory", "Original", "Encoded", "Saved"),
+            line(4500, x + y + z, "tree"),
+            line(4000, x + y, "tree/a"),
+            line(3000, y, "tree/a/b"),
+            line(500, z, "tree/c"),
+            format!("Total: 4500 bytes in 3 files encoded to {} bytes, {} saved", x + y + z, saved(4500, x + y + z)),
+            format!("Unknown original size: 1 files, {} bytes", adaptive),
+            String::from("Not encoded: 2 files"),
+        ];
+        assert_eq!(stdout(&output), expected.join("\n") + "\n");
+
+        let output = huffman().current_dir(dir.path()).args(["ratio", "tree/a/x.huf"]).output().unwrap();
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        assert!(stdout(&output).contains(&format!("{}\nTotal: 1000 bytes in 1 files", line(1000, x, "tree/a/x.huf"))), "stdout: {}", stdout(&output));
+    }
+
+    #[test]
+    fn test_paranoid_keeps_the_input_and_the_backup_until_verified() {
+        let dir = TempDir::new();
+        let input = dir.write("notes.txt", b"irreplaceable notes, irreplaceable notes");
+        let encoded = dir.write("notes.txt.encoded", b"the previous output");
+        let backup = dir.join("notes.txt.encoded.bak");
+
+        // A debug build fails the step HUFF_FAULT names
+        let output = huffman().env("HUFF_FAULT", "verify").arg("encode").arg(&input).args(["--paranoid", "--rm", "-f"]).output().unwrap();
+        assert_eq!(output.status.code(), Some(1));
+        let error = format!(
+            "Error: --paranoid stopped at verifying '{}', it is removed, the previous '{}' is kept as '{}': fault injected at verify\n",
+            encoded.display(), encoded.display(), backup.display(),
+        );
+        assert!(stderr(&output).ends_with(&error), "stderr: {}", stderr(&output));
+        assert_eq!(fs::read(&input).unwrap(), b"irreplaceable notes, irreplaceable notes");
+        assert_eq!(fs::read(&backup).unwrap(), b"the previous output");
+        assert!(!encoded.exists());
+
+        // Nor over a backup that is there already
+        fs::write(&encoded, b"the previous output").unwrap();
+        let output = huffman().arg("encode").arg(&input).args(["--paranoid", "--rm", "-f"]).output().unwrap();
+        assert_eq!(output.status.code(), Some(1));
+        assert!(stderr(&output).contains("the backup exists already"), "stderr: {}", stderr(&output));
+        assert!(input.exists());
+
+        fs::rename(&backup, &encoded).unwrap();
+        let output = huffman().arg("encode").arg(&input).args(["--paranoid", "--rm", "-f"]).output().unwrap();
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        assert!(stderr(&output).contains(&format!("Verified that '{}' decodes to '{}'", encoded.display(), input.display())), "stderr: {}", stderr(&output));
+        assert!(!input.exists());
+        assert!(!backup.exists());
+        let decoded = dir.join("decoded.txt");
+        let output = run([OsStr::new("decode"), encoded.as_os_str(), OsStr::new("-o"), decoded.as_os_str()]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        assert_eq!(fs::read(&decoded).unwrap(), b"irreplaceable notes, irreplaceable notes");
+
+        // Without --paranoid --rm removes the input right after encoding
+        let output = run([OsStr::new("encode"), decoded.as_os_str(), OsStr::new("--rm")]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        assert!(!decoded.exists());
+        assert!(dir.join("decoded.txt.encoded").exists());
+    }
+
+    #[test]
+    fn test_limit_rate_slows_encode_and_decode_down() {
+        let dir = TempDir::new();
+        let data = b"polite on a shared disk, ".repeat(2000);
+        let input = dir.write("notes.txt", &data[..48 * 1024]);
+        let encoded = dir.join("notes.txt.encoded");
+        let decoded = dir.join("notes.txt.decoded");
+
+        // 48 KiB read at 48 KiB a second take a second at least, and decoding
+        // writes as much
+        for args in [[OsStr::new("encode"), input.as_os_str(), OsStr::new("-o"), encoded.as_os_str()], [OsStr::new("decode"), encoded.as_os_str(), OsStr::new("-o"), decoded.as_os_str()]] {
+            let start = std::time::Instant::now();
+            let output = huffman().args(args).args(["--limit-rate", "48K"]).output().unwrap();
+            let elapsed = start.elapsed();
+            assert!(output.status.success(), "stderr: {}", stderr(&output));
+            assert!(elapsed >= std::time::Duration::from_secs(1), "{:?}", elapsed);
+            assert!(elapsed < std::time::Duration::from_secs(10), "{:?}", elapsed);
+        }
+        assert_eq!(fs::read(&decoded).unwrap(), fs::read(&input).unwrap());
+
+        let output = run([OsStr::new("encode"), input.as_os_str(), OsStr::new("--limit-rate"), OsStr::new("0")]);
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(stderr(&output), "Error: Invalid --limit-rate '0', expected bytes a second, like 512K or 20M\n");
+    }
+
+    // Like dd, SIGUSR1 asks for a line about how far the command got, and
+    // it goes on as before
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_sigusr1_prints_the_status() {
+        let dir = TempDir::new();
+        let data = b"how far along is it? ".repeat(100_000);
+        let input = dir.write("notes.txt", &data[..2 << 20]);
+        let encoded = dir.join("notes.txt.encoded");
+
+        // Two megabytes read from disk at one a second, twice
+        let child = huffman()
+            .args([OsStr::new("encode"), input.as_os_str(), OsStr::new("-o"), encoded.as_os_str()])
+            .args(["--memory-limit", "0", "--limit-rate", "1M"])
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(500));
+        let kill = std::process::Command::new("kill").args(["-USR1", &child.id().to_string()]).status().unwrap();
+        assert!(kill.success());
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        let errors = stderr(&output);
+        let status: Vec<&str> = errors.lines().filter(|line| line.contains(" MiB (")).collect();
+        assert_eq!(status.len(), 1, "stderr: {}", errors);
+        assert!(status[0].starts_with("Counting: ") && status[0].contains(" of 2.0 MiB (") && status[0].contains(" MiB/s, "), "{}", status[0]);
+        assert!(encoded.exists());
+    }
+
+    #[test]
+    fn test_checksum_algorithms() {
+        let dir = TempDir::new();
+        let input = dir.write("notes.txt", &b"sum it up, ".repeat(1000));
+        for algorithm in ["crc32", "xxh3", "none"] {
+            let encoded = dir.join(&format!("notes.{}.encoded", algorithm));
+            let decoded = dir.join(&format!("notes.{}.decoded", algorithm));
+            let output = run([OsStr::new("encode"), input.as_os_str(), OsStr::new("--checksum"), OsStr::new(algorithm), OsStr::new("-o"), encoded.as_os_str()]);
+            assert!(output.status.success(), "stderr: {}", stderr(&output));
+            let output = run([OsStr::new("decode"), encoded.as_os_str(), OsStr::new("-o"), decoded.as_os_str()]);
+            assert!(output.status.success(), "stderr: {}", stderr(&output));
+            assert_eq!(fs::read(&decoded).unwrap(), fs::read(&input).unwrap());
+        }
+
+        let output = run([OsStr::new("encode"), input.as_os_str(), OsStr::new("--checksum"), OsStr::new("md5")]);
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(stderr(&output), "Error: Invalid --checksum 'md5', expected crc32, xxh3 or none\n");
+    }
+
+    #[test]
+    fn test_selftest() {
+        let output = huffman().arg("selftest").output().unwrap();
+        assert!(output.status.success(), "stdout: {}", stdout(&output));
+        assert!(stdout(&output).contains("PASS text        --bwt\n"), "{}", stdout(&output));
+        assert!(stdout(&output).ends_with("Failed: 0\n"), "{}", stdout(&output));
+    }
+
+    #[test]
+    fn test_dedupe_links_the_outputs_of_duplicates() {
+        let dir = TempDir::new();
+        let photo = b"not really a photo, ".repeat(500);
+        let inputs = [
+            dir.write("a.jpg", &photo),
+            dir.write("b.jpg", b"another photo"),
+            dir.write("c.jpg", &photo),
+            dir.write("d.jpg", &photo),
+        ];
+        let mut args = vec![OsStr::new("encode")];
+        args.extend(inputs.iter().map(|input| input.as_os_str()));
+        args.push(OsStr::new("--dedupe"));
+        let output = run(args);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+
+        // Only a.jpg and b.jpg were encoded, the others got the output of a.jpg
+        let log = stderr(&output);
+        assert_eq!(log.matches("Encoding successful").count(), 2, "{}", log);
+        assert!(log.contains(&format!("'{}' is a duplicate, '{}' is linked to '{}'", inputs[2].display(), dir.join("c.jpg.encoded").display(), dir.join("a.jpg.encoded").display())), "{}", log);
+        assert!(log.ends_with(&format!("Skipped 2 duplicate inputs, {} bytes not encoded again\n", 2 * photo.len())), "{}", log);
+        for input in &inputs {
+            let encoded = dir.join(&format!("{}.encoded", input.file_name().unwrap().to_string_lossy()));
+            let decoded = dir.join(&format!("{}.decoded", input.file_name().unwrap().to_string_lossy()));
+            let output = run([OsStr::new("decode"), encoded.as_os_str(), OsStr::new("-o"), decoded.as_os_str()]);
+            assert!(output.status.success(), "stderr: {}", stderr(&output));
+            assert_eq!(fs::read(&decoded).unwrap(), fs::read(input).unwrap());
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let inode = |name: &str| fs::metadata(dir.join(name)).unwrap().ino();
+            assert_eq!(inode("c.jpg.encoded"), inode("a.jpg.encoded"));
+            assert_eq!(inode("d.jpg.encoded"), inode("a.jpg.encoded"));
+            assert_ne!(inode("b.jpg.encoded"), inode("a.jpg.encoded"));
+        }
+
+        // Without --dedupe every input is encoded
+        let output = run([OsStr::new("encode"), inputs[0].as_os_str(), inputs[2].as_os_str(), OsStr::new("--force")]);
+        assert_eq!(stderr(&output).matches("Encoding successful").count(), 2, "{}", stderr(&output));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_stdin_name_and_mtime_are_restored_by_decode() {
+        use std::io::Write;
+        use std::process::Stdio;
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let dir = TempDir::new();
+        let encoded = dir.join("piped.huf");
+        let mut child = huffman()
+            .args([OsStr::new("encode"), OsStr::new("/dev/stdin"), OsStr::new("--stdin-name"), OsStr::new("notes.txt")])
+            .args([OsStr::new("--stdin-mtime"), OsStr::new("1700000000"), OsStr::new("-o"), encoded.as_os_str()])
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(b"piped notes, piped notes").unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+
+        // decode names the output after the kept name, next to the input
+        let output = run([OsStr::new("decode"), encoded.as_os_str()]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        let restored = dir.join("notes.txt");
+        assert_eq!(fs::read(&restored).unwrap(), b"piped notes, piped notes");
+        let mtime = fs::metadata(&restored).unwrap().modified().unwrap();
+        assert_eq!(mtime, UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+
+        // -o still wins
+        let decoded = dir.join("elsewhere.txt");
+        let output = run([OsStr::new("decode"), encoded.as_os_str(), OsStr::new("-o"), decoded.as_os_str()]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        assert_eq!(fs::read(&decoded).unwrap(), b"piped notes, piped notes");
+
+        // Only the last component of a name counts
+        let input = dir.write("input.txt", b"climbing out");
+        let encoded = dir.join("sneaky.huf");
+        let output = run([OsStr::new("encode"), input.as_os_str(), OsStr::new("--stdin-name"), OsStr::new("../../escaped.txt"), OsStr::new("-o"), encoded.as_os_str()]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        let output = run([OsStr::new("decode"), encoded.as_os_str()]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        assert_eq!(fs::read(dir.join("escaped.txt")).unwrap(), b"climbing out");
+
+        let output = run([OsStr::new("encode"), input.as_os_str(), OsStr::new("--stdin-mtime"), OsStr::new("yesterday")]);
+        assert_eq!(output.status.code(), Some(1));
+        assert!(stderr(&output).contains("Error: Invalid --stdin-mtime 'yesterday', expected seconds since 1970, like 1700000000"), "stderr: {}", stderr(&output));
+        let output = run([OsStr::new("encode"), input.as_os_str(), OsStr::new("--stdin-name"), OsStr::new("")]);
+        assert_eq!(output.status.code(), Some(1));
+        assert!(stderr(&output).contains("Error: Invalid --stdin-name '', expected a name of 1 to 65535 bytes"), "stderr: {}", stderr(&output));
+    }
+
+    #[test]
+    fn test_preserve_timestamps() {
+        use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+        // Whole seconds, which every filesystem keeps
+        let seconds = |path: &std::path::Path| fs::metadata(path).unwrap().modified().unwrap()
+            .duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let set_seconds = |path: &std::path::Path, seconds: u64| fs::File::options().write(true).open(path).unwrap()
+            .set_modified(UNIX_EPOCH + Duration::from_secs(seconds)).unwrap();
+
+        let dir = TempDir::new();
+        let input = dir.write("notes.txt", b"notes from last year");
+        set_seconds(&input, 1_600_000_000);
+        let encoded = dir.join("notes.txt.encoded");
+        let output = run([OsStr::new("encode"), input.as_os_str(), OsStr::new("--preserve-timestamps")]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        assert_eq!(seconds(&encoded), 1_600_000_000);
+
+        // decode takes the time of the encoded file, which has none of its own
+        set_seconds(&encoded, 1_650_000_000);
+        let decoded = dir.join("notes.txt.decoded");
+        let output = run([OsStr::new("decode"), encoded.as_os_str(), OsStr::new("-o"), decoded.as_os_str(), OsStr::new("--preserve-timestamps")]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        assert_eq!(seconds(&decoded), 1_650_000_000);
+        assert_eq!(fs::read(&decoded).unwrap(), b"notes from last year");
+
+        // Duplicates with another time get a copy with their own instead of a
+        // link to the output of the first one, and ones with the same a link
+        let copies = [dir.write("a.txt", b"the same"), dir.write("b.txt", b"the same"), dir.write("c.txt", b"the same")];
+        for (copy, time) in copies.iter().zip([1_500_000_000, 1_550_000_000, 1_500_000_000]) {
+            set_seconds(copy, time);
+        }
+        let mut args = vec![OsStr::new("encode")];
+        args.extend(copies.iter().map(|copy| copy.as_os_str()));
+        args.extend([OsStr::new("--dedupe"), OsStr::new("--preserve-timestamps")]);
+        let output = run(args);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        let outputs = copies.map(|copy| dir.join(&format!("{}.encoded", copy.file_name().unwrap().to_string_lossy())));
+        assert_eq!(outputs.each_ref().map(|output| seconds(output)), [1_500_000_000, 1_550_000_000, 1_500_000_000]);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let inode = |path: &std::path::Path| fs::metadata(path).unwrap().ino();
+            assert_ne!(inode(&outputs[0]), inode(&outputs[1]));
+            assert_eq!(inode(&outputs[0]), inode(&outputs[2]));
+        }
+        assert_eq!(fs::read(&outputs[1]).unwrap(), fs::read(&outputs[0]).unwrap());
+
+        // Without it the outputs are as new as they are
+        let output = run([OsStr::new("encode"), input.as_os_str(), OsStr::new("--force")]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        assert!(seconds(&encoded) + 60 > now, "{} is older than {}", seconds(&encoded), now);
+
+        // A time kept in the header wins over the one of the encoded file
+        let output = run([OsStr::new("encode"), input.as_os_str(), OsStr::new("--force"), OsStr::new("--stdin-mtime"), OsStr::new("1700000000")]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        let output = run([OsStr::new("decode"), encoded.as_os_str(), OsStr::new("-o"), decoded.as_os_str(), OsStr::new("--force"), OsStr::new("--preserve-timestamps")]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        assert_eq!(seconds(&decoded), 1_700_000_000);
+    }
+
+    // --adaptive encodes a pipe as it comes: the output grows while the rest
+    // of the input hasn't even been written
+    #[cfg(unix)]
+    #[test]
+    fn test_adaptive_encodes_stdin_without_waiting_for_the_end() {
+        use std::io::Write;
+        use std::process::Stdio;
+        use std::thread;
+        use std::time::{Duration, Instant};
+
+        let dir = TempDir::new();
+        let contents: Vec<u8> = (0..2 * 1024 * 1024).map(|i: u32| b"adaptive huffman "[(i % 17) as usize]).collect();
+        let encoded = dir.join("piped.huf");
+        let mut child = huffman()
+            .args([OsStr::new("encode"), OsStr::new("/dev/stdin"), OsStr::new("--adaptive"), OsStr::new("-o"), encoded.as_os_str()])
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut stdin = child.stdin.take().unwrap();
+        let (first, rest) = contents.split_at(contents.len() / 2);
+        stdin.write_all(first).unwrap();
+
+        // The output is written to a temporary file next to piped.huf first
+        let written = || fs::read_dir(dir.path()).unwrap()
+            .map(|entry| entry.unwrap())
+            .filter(|entry| !entry.file_name().to_string_lossy().ends_with(".lock"))
+            .map(|entry| entry.metadata().unwrap().len())
+            .sum::<u64>();
+        let start = Instant::now();
+        while written() == 0 {
+            assert!(start.elapsed() < Duration::from_secs(30), "nothing written after half of the input");
+            thread::sleep(Duration::from_millis(1));
+        }
+        stdin.write_all(rest).unwrap();
+        drop(stdin);
+
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        let output = run([OsStr::new("decode"), encoded.as_os_str()]);
+        assert!(output.status.success(), "stderr: {}", stderr(&output));
+        assert!(fs::read(dir.join("piped.huf.decoded")).unwrap() == contents);
+    }
+
+    // Ctrl-C stops the command and removes what it wrote so far
+    #[cfg(unix)]
+    #[test]
+    fn test_ctrl_c_cancels_and_cleans_up() {
+        use std::process::{Command, Stdio};
+        use std::thread;
+        use std::time::Duration;
+
+        let dir = TempDir::new();
+        let contents: Vec<u8> = (0..64 * 1024 * 1024).map(|i: u32| b"the huffman tree "[(i % 17) as usize]).collect();
+        let input = dir.write("large.txt", &contents);
+        let encoded = dir.join("large.encoded");
+
+        let child = huffman()
+            .args([OsStr::new("encode"), input.as_os_str(), OsStr::new("-o"), encoded.as_os_str()])
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        while !dir.join(".large.encoded.lock").exists() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        let status = Command::new("kill").args(["-INT", &child.id().to_string()]).status().unwrap();
+        assert!(status.success());
+
+        let output = child.wait_with_output().unwrap();
+        assert_eq!(output.status.code(), Some(130), "stderr: {}", stderr(&output));
+        assert_eq!(stderr(&output), "Error: cancelled\n");
+        let left: Vec<_> = fs::read_dir(dir.path()).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        assert_eq!(left, [OsStr::new("large.txt")]);
+    }
+}
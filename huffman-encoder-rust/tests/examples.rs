@@ -0,0 +1,76 @@
+// Runs the programs in examples/ on temp data, so the public API they use
+// keeps working the way they show
+
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use super::common::{stderr, stdout, TempDir};
+    use std::ffi::OsStr;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process::{Command, Output};
+
+    const NO_ARGS: [&str; 0] = [];
+
+    // cargo test builds the examples next to the test binaries, in
+    // target/<profile>/examples
+    fn example<I, S>(name: &str, args: I) -> Output
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let deps = std::env::current_exe().unwrap().parent().unwrap().to_path_buf();
+        let path: PathBuf = deps.parent().unwrap().join("examples").join(name).with_extension(std::env::consts::EXE_EXTENSION);
+        assert!(path.exists(), "{} is not built, run cargo build --examples", path.display());
+        let output = Command::new(&path).args(args).output().expect("Failed to run example");
+        assert!(output.status.success(), "{} failed, stderr: {}", name, stderr(&output));
+        output
+    }
+
+    const LINES: &[u8] = b"lines of an example\nwith a few words\n";
+
+    fn sample_text() -> Vec<u8> {
+        LINES.iter().copied().cycle().take(200_000).collect()
+    }
+
+    #[test]
+    fn test_in_memory() {
+        let dir = TempDir::new();
+        let input = dir.write("sample.txt", &sample_text());
+        let output = example("in_memory", [&input]);
+        assert!(stdout(&output).starts_with("200000 bytes -> "), "stdout: {}", stdout(&output));
+
+        // Without a file it encodes a sentence of its own
+        let output = example("in_memory", NO_ARGS);
+        assert!(stdout(&output).contains(" bytes, ratio "), "stdout: {}", stdout(&output));
+    }
+
+    #[test]
+    fn test_stream_copy() {
+        let dir = TempDir::new();
+        let contents = sample_text();
+        let input = dir.write("sample.txt", &contents);
+        let encoded = dir.join("sample.huff");
+        let decoded = dir.join("sample.decoded");
+        let output = example("stream_copy", [&input, &encoded, &decoded]);
+        assert_eq!(fs::read(&decoded).unwrap(), contents);
+        // The same bytes as encode_bytes gives, HuffmanWriter::buffered was finished only
+        assert_eq!(fs::read(&encoded).unwrap(), huffman_encoder::encode_bytes(&contents).unwrap());
+        let encoded_size = fs::metadata(&encoded).unwrap().len();
+        assert_eq!(stdout(&output), format!("200000 bytes -> {} bytes -> 200000 bytes\n", encoded_size));
+    }
+
+    #[test]
+    fn test_custom_table() {
+        let dir = TempDir::new();
+        let contents = sample_text();
+        let input = dir.write("sample.txt", &contents);
+        let output = example("custom_table", [&input]);
+        // One message per line, with the 17 distinct bytes of the lines and
+        // the newline between them
+        let lines = contents.split(|&byte| byte == b'\n').filter(|line| !line.is_empty()).count();
+        assert!(stdout(&output).starts_with(&format!("{} messages encoded into ", lines)), "stdout: {}", stdout(&output));
+        assert!(stdout(&output).ends_with(" bytes with one table of 18 codes\n"), "stdout: {}", stdout(&output));
+    }
+}
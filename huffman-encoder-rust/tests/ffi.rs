@@ -0,0 +1,17 @@
+// The C interface from C: tests/ffi/harness.c, compiled by build.rs, calls the
+// exported functions through include/hrst.h
+
+use std::os::raw::c_int;
+
+#[link(name = "hrst_harness", kind = "static")]
+extern "C" {
+    fn hrst_harness_run() -> c_int;
+}
+
+#[test]
+fn test_c_harness() {
+    // Links the library, the harness only refers to it by symbol
+    let _ = huffman_encoder::ffi::hrst_free;
+    let failures = unsafe { hrst_harness_run() };
+    assert_eq!(failures, 0, "{} checks of harness.c failed, see above", failures);
+}
@@ -0,0 +1,84 @@
+/*
+ * Uses the library the way a C program would, through include/hrst.h only.
+ * Compiled by build.rs with the ffi feature and run from tests/ffi.rs.
+ * Returns the number of failed checks.
+ */
+
+#include <stdio.h>
+#include <string.h>
+
+#include "hrst.h"
+
+static int failures = 0;
+
+#define CHECK(condition) \
+    do { \
+        if (!(condition)) { \
+            fprintf(stderr, "harness.c:%d: check failed: %s\n", __LINE__, #condition); \
+            failures++; \
+        } \
+    } while (0)
+
+static void check_roundtrip(const uint8_t *data, size_t len) {
+    uint8_t *encoded = NULL;
+    size_t encoded_len = 0;
+    CHECK(hrst_encode(data, len, &encoded, &encoded_len) == HRST_OK);
+    CHECK(encoded != NULL && encoded_len >= 34);
+
+    uint8_t *decoded = NULL;
+    size_t decoded_len = 0;
+    CHECK(hrst_decode(encoded, encoded_len, &decoded, &decoded_len) == HRST_OK);
+    CHECK(decoded_len == len);
+    CHECK(len == 0 ? decoded == NULL : memcmp(decoded, data, len) == 0);
+
+    hrst_free(encoded, encoded_len);
+    hrst_free(decoded, decoded_len);
+}
+
+static hrst_status decode_status(const uint8_t *data, size_t len) {
+    uint8_t *out = (uint8_t *)"not touched";
+    size_t out_len = 42;
+    hrst_status status = hrst_decode(data, len, &out, &out_len);
+    if (status != HRST_OK) {
+        CHECK(out == NULL && out_len == 0);
+    }
+    hrst_free(out, out_len);
+    return status;
+}
+
+int hrst_harness_run(void) {
+    const char *text = "abracadabra";
+    check_roundtrip((const uint8_t *)text, strlen(text));
+    check_roundtrip(NULL, 0);
+    uint8_t bytes[1024];
+    for (size_t i = 0; i < sizeof bytes; i++) {
+        bytes[i] = (uint8_t)(i * 7 % 256);
+    }
+    check_roundtrip(bytes, sizeof bytes);
+
+    uint8_t *encoded = NULL;
+    size_t encoded_len = 0;
+    CHECK(hrst_encode((const uint8_t *)text, strlen(text), &encoded, &encoded_len) == HRST_OK);
+    CHECK(memcmp(encoded, "HRST", 4) == 0);
+
+    /* Each kind of damage has its own code */
+    CHECK(decode_status((const uint8_t *)"HUFF is the C version", 21) == HRST_ERROR_INVALID_MAGIC);
+    CHECK(decode_status(encoded, 20) == HRST_ERROR_TRUNCATED_HEADER);
+    CHECK(decode_status(encoded, encoded_len - 1) == HRST_ERROR_TRUNCATED_DATA);
+    encoded[17] ^= 1;
+    CHECK(decode_status(encoded, encoded_len) == HRST_ERROR_CHECKSUM_MISMATCH);
+    encoded[17] ^= 1;
+    CHECK(decode_status(encoded, encoded_len) == HRST_OK);
+
+    /* Null pointers are rejected without touching anything */
+    size_t out_len = 0;
+    uint8_t *out = NULL;
+    CHECK(hrst_encode(NULL, 3, &out, &out_len) == HRST_ERROR_NULL_POINTER);
+    CHECK(hrst_encode(encoded, encoded_len, NULL, &out_len) == HRST_ERROR_NULL_POINTER);
+    CHECK(hrst_decode(encoded, encoded_len, &out, NULL) == HRST_ERROR_NULL_POINTER);
+    CHECK(out == NULL && out_len == 0);
+    hrst_free(NULL, 0);
+
+    hrst_free(encoded, encoded_len);
+    return failures;
+}
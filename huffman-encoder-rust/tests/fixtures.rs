@@ -0,0 +1,203 @@
+// Roundtrip tests over the checked in corpus in tests/fixtures
+// Also guards the compression ratio of the text fixtures against regressions,
+// and the exact bytes on the wire against the files in tests/fixtures/golden.
+// tests/fixtures/legacy has the same files from before the codes were
+// canonical, they still have to decode, and tests/fixtures/versions has a
+// file in every version of the header, and upgrade has to bring all of them to
+// the current one.
+
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use super::common::{run, sha256, stderr, TempDir};
+    use std::fs::{self, File};
+    use std::path::Path;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    // (fixture, maximum encoded size in bytes if the ratio is guarded)
+    // The limits sit about 2% above the sizes measured when they were recorded,
+    // so a change that makes the codes worse fails here. Binary fixtures are
+    // only checked for roundtrip correctness.
+    const FIXTURES: [(&str, Option<u64>); 6] = [
+        ("english.txt", Some(2270)), // 2226 bytes when recorded
+        ("data.json", Some(5640)),   // 5529 bytes when recorded
+        ("utf8.txt", Some(3600)),    // 3529 bytes when recorded
+        ("gradient.png", None),
+        ("pattern.bin", None),
+        ("sparse.bin", None),
+    ];
+
+    #[test]
+    fn test_sha256() {
+        assert_eq!(sha256(b""), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(sha256(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        let long = b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+        assert_eq!(sha256(long), "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1");
+    }
+
+    #[test]
+    fn test_fixtures_roundtrip() {
+        let dir = TempDir::new();
+        for (name, max_encoded_size) in FIXTURES {
+            let input = Path::new("tests/fixtures").join(name);
+            let encoded = dir.join(&format!("{}.encoded", name));
+            let decoded = dir.join(&format!("{}.decoded", name));
+
+            let output = run(["encode".as_ref(), input.as_os_str(), "-o".as_ref(), encoded.as_os_str()]);
+            assert!(output.status.success(), "{}: {}", name, stderr(&output));
+            let output = run(["decode".as_ref(), encoded.as_os_str(), "-o".as_ref(), decoded.as_os_str()]);
+            assert!(output.status.success(), "{}: {}", name, stderr(&output));
+
+            let original = fs::read(&input).unwrap();
+            let restored = fs::read(&decoded).unwrap();
+            assert_eq!(sha256(&restored), sha256(&original), "{}", name);
+
+            let encoded_size = fs::metadata(&encoded).unwrap().len();
+            println!("{}: {} -> {} bytes", name, original.len(), encoded_size);
+            if let Some(max) = max_encoded_size {
+                assert!(encoded_size <= max, "{} encoded to {} bytes, more than {}", name, encoded_size, max);
+            }
+        }
+    }
+
+    // Encoded files recorded from an earlier version of the encoder. The output
+    // has to stay byte for byte the same, so changes to the bit writer can't
+    // silently change the bit order or the padding.
+    const GOLDEN: [&str; 3] = ["english.txt", "pattern.bin", "data.json"];
+
+    #[test]
+    fn test_encoding_matches_golden_files() {
+        let dir = TempDir::new();
+        for name in GOLDEN {
+            let input = Path::new("tests/fixtures").join(name);
+            let encoded = dir.join(&format!("{}.encoded", name));
+            let output = run(["encode".as_ref(), input.as_os_str(), "-o".as_ref(), encoded.as_os_str()]);
+            assert!(output.status.success(), "{}: {}", name, stderr(&output));
+
+            let golden = fs::read(Path::new("tests/fixtures/golden").join(format!("{}.encoded", name))).unwrap();
+            assert!(fs::read(&encoded).unwrap() == golden, "{} no longer encodes to its golden file", name);
+        }
+    }
+
+    #[test]
+    fn test_legacy_files_still_decode() {
+        let dir = TempDir::new();
+        for name in GOLDEN {
+            let encoded = Path::new("tests/fixtures/legacy").join(format!("{}.encoded", name));
+            let decoded = dir.join(&format!("{}.decoded", name));
+            let output = run(["decode".as_ref(), encoded.as_os_str(), "-o".as_ref(), decoded.as_os_str()]);
+            assert!(output.status.success(), "{}: {}", name, stderr(&output));
+
+            let original = fs::read(Path::new("tests/fixtures").join(name)).unwrap();
+            assert!(fs::read(&decoded).unwrap() == original, "legacy {} decodes to different data", name);
+        }
+    }
+
+    #[test]
+    fn test_every_version_still_decodes() {
+        let dir = TempDir::new();
+        for version in 0..=3 {
+            let encoded = Path::new("tests/fixtures/versions").join(format!("abracadabra.v{}.encoded", version));
+            let decoded = dir.join(&format!("v{}.decoded", version));
+            let output = run(["decode".as_ref(), encoded.as_os_str(), "-o".as_ref(), decoded.as_os_str()]);
+            assert!(output.status.success(), "version {}: {}", version, stderr(&output));
+            assert_eq!(fs::read(&decoded).unwrap(), b"abracadabra", "version {}", version);
+        }
+    }
+
+    #[test]
+    fn test_every_version_upgrades() {
+        let dir = TempDir::new();
+        let mtime = UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+        let mut files = Vec::new();
+        for version in 0..=3 {
+            let name = format!("abracadabra.v{}.encoded", version);
+            files.push((name, String::from("abracadabra")));
+        }
+        for name in GOLDEN {
+            files.push((format!("{}.encoded", name), String::from(name)));
+        }
+        let mut inputs = Vec::new();
+        for (name, _) in &files {
+            let fixture = match name.starts_with("abracadabra") {
+                true => Path::new("tests/fixtures/versions").join(name),
+                false => Path::new("tests/fixtures/legacy").join(name),
+            };
+            let input = dir.write(name, &fs::read(fixture).unwrap());
+            File::options().write(true).open(&input).unwrap().set_modified(mtime).unwrap();
+            inputs.push(input);
+        }
+
+        // In place, every file at once
+        let mut args = vec!["upgrade".as_ref()];
+        args.extend(inputs.iter().map(|input| input.as_os_str()));
+        let output = run(args);
+        assert!(output.status.success(), "{}", stderr(&output));
+        assert!(stderr(&output).contains("abracadabra.v0.encoded' from version 0 to version 3, keeping its payload"), "{}", stderr(&output));
+        assert!(stderr(&output).contains("abracadabra.v3.encoded' is version 3 already, nothing to upgrade"), "{}", stderr(&output));
+        let current = fs::read(Path::new("tests/fixtures/versions/abracadabra.v3.encoded")).unwrap();
+        for ((name, original), input) in files.iter().zip(&inputs) {
+            let upgraded = fs::read(input).unwrap();
+            assert_eq!(upgraded[4..9], [0xFF, 0xFF, 0xFF, 0xFF, 3], "{}", name);
+            assert_eq!(fs::metadata(input).unwrap().modified().unwrap(), mtime, "{}", name);
+            let decoded = dir.join(&format!("{}.decoded", name));
+            let output = run(["decode".as_ref(), input.as_os_str(), "-o".as_ref(), decoded.as_os_str()]);
+            assert!(output.status.success(), "{}: {}", name, stderr(&output));
+            let expected = match original.as_str() {
+                "abracadabra" => b"abracadabra".to_vec(),
+                name => fs::read(Path::new("tests/fixtures").join(name)).unwrap(),
+            };
+            assert_eq!(sha256(&fs::read(&decoded).unwrap()), sha256(&expected), "{}", name);
+            if name.starts_with("abracadabra") {
+                assert_eq!(upgraded, current, "{}", name);
+            }
+        }
+
+        // The payload of a legacy file is kept as it was, behind a header that
+        // has 8 bytes more for the payload length
+        let legacy = fs::read(Path::new("tests/fixtures/legacy/english.txt.encoded")).unwrap();
+        let upgraded = fs::read(dir.join("english.txt.encoded")).unwrap();
+        assert_eq!(upgraded.len(), legacy.len() + 8);
+        assert!(upgraded[upgraded.len() - 2000..] == legacy[legacy.len() - 2000..]);
+
+        // With -o the old file stays, and one in the current version is copied
+        let old = dir.write("old.encoded", &fs::read("tests/fixtures/versions/abracadabra.v1.encoded").unwrap());
+        for (input, name) in [(&old, "new.huf"), (&inputs[3], "copy.huf")] {
+            let before = fs::read(input).unwrap();
+            let output = run(["upgrade".as_ref(), input.as_os_str(), "-o".as_ref(), dir.join(name).as_os_str()]);
+            assert!(output.status.success(), "{}", stderr(&output));
+            assert_eq!(fs::read(input).unwrap(), before);
+            assert_eq!(fs::read(dir.join(name)).unwrap(), current);
+        }
+
+        // Old streams one after the other are encoded again
+        let versions = Path::new("tests/fixtures/versions");
+        let joined = [fs::read(versions.join("abracadabra.v1.encoded")).unwrap(), fs::read(versions.join("abracadabra.v2.encoded")).unwrap()].concat();
+        let joined = dir.write("joined.encoded", &joined);
+        #[cfg(unix)]
+        fs::set_permissions(&joined, std::os::unix::fs::PermissionsExt::from_mode(0o600)).unwrap();
+        let output = run(["upgrade".as_ref(), joined.as_os_str()]);
+        assert!(stderr(&output).ends_with("from version 1 to version 3, encoding it again\n"), "{}", stderr(&output));
+        // The data is decoded next to it on the way, as private as the input,
+        // and that copy is gone again
+        #[cfg(unix)]
+        assert_eq!(std::os::unix::fs::PermissionsExt::mode(&fs::metadata(&joined).unwrap().permissions()) & 0o777, 0o600);
+        let names: Vec<String> = fs::read_dir(dir.path()).unwrap().map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned()).collect();
+        assert!(names.iter().all(|name| !name.starts_with('.')), "{:?}", names);
+        let decoded = dir.join("joined.decoded");
+        let output = run(["decode".as_ref(), joined.as_os_str(), "-o".as_ref(), decoded.as_os_str()]);
+        assert!(output.status.success(), "{}", stderr(&output));
+        assert_eq!(fs::read(&decoded).unwrap(), b"abracadabraabracadabra");
+
+        // A file that doesn't decode is left as it is
+        let mut damaged = fs::read(versions.join("abracadabra.v2.encoded")).unwrap();
+        let last = damaged.len() - 1;
+        damaged[last] ^= 0x10;
+        let damaged_path = dir.write("damaged.encoded", &damaged);
+        let output = run(["upgrade".as_ref(), damaged_path.as_os_str()]);
+        assert!(!output.status.success());
+        assert!(stderr(&output).starts_with("Error: failed to decode '"), "{}", stderr(&output));
+        assert_eq!(fs::read(&damaged_path).unwrap(), damaged);
+    }
+}
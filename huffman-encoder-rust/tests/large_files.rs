@@ -0,0 +1,158 @@
+// Multi-gigabyte inputs, generated on the fly since they can't live in the repo
+// These are slow, run them with `cargo test --release -- --ignored`. Set
+// HUFFMAN_LARGE_FILE_SIZE to a number of bytes to try a different size.
+
+mod common;
+
+#[cfg(test)]
+mod tests {
+    use super::common::{run, stderr, Rng, Sha256, TempDir};
+    use std::fs::{self, File};
+    use std::io::{Read, Write};
+    use std::path::Path;
+    use std::process::Command;
+
+    // Well past what a 32-bit counter can hold
+    const DEFAULT_SIZE: u64 = 5 * 1024 * 1024 * 1024;
+    const CHUNK_SIZE: usize = 1024 * 1024;
+    // Room left over for everything else on the disk
+    const SPARE_SPACE: u64 = 256 * 1024 * 1024;
+
+    fn input_size() -> u64 {
+        match std::env::var("HUFFMAN_LARGE_FILE_SIZE") {
+            Ok(size) => size.parse().expect("HUFFMAN_LARGE_FILE_SIZE must be a number of bytes"),
+            Err(_) => DEFAULT_SIZE,
+        }
+    }
+
+    // Free bytes on the file system holding `dir`. std has no statvfs, so
+    // this asks df.
+    fn free_space(dir: &Path) -> Option<u64> {
+        let output = Command::new("df").arg("-Pk").arg(dir).output().ok()?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let available = stdout.lines().nth(1)?.split_whitespace().nth(3)?;
+        available.parse::<u64>().ok().map(|kib| kib * 1024)
+    }
+
+    // Whether `needed` bytes fit in `dir`, printing why the test is skipped
+    // otherwise
+    fn has_space(dir: &TempDir, needed: u64) -> bool {
+        match free_space(dir.path()) {
+            Some(free) if free >= needed + SPARE_SPACE => true,
+            Some(free) => {
+                eprintln!("skipped: needs {} bytes of free disk space, {} available", needed + SPARE_SPACE, free);
+                false
+            }
+            None => {
+                eprintln!("skipped: can't determine the free disk space");
+                false
+            }
+        }
+    }
+
+    fn hash_file(path: &Path) -> [u8; 32] {
+        let mut file = File::open(path).unwrap();
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0u8; CHUNK_SIZE];
+        loop {
+            let read = file.read(&mut buffer).unwrap();
+            if read == 0 {
+                return hasher.finish();
+            }
+            hasher.update(&buffer[..read]);
+        }
+    }
+
+    // The original length stored in the header of an encoded file
+    fn original_length(path: &Path) -> u64 {
+        let mut prefix = [0u8; 17];
+        File::open(path).unwrap().read_exact(&mut prefix).unwrap();
+        u64::from_le_bytes(prefix[9..17].try_into().unwrap())
+    }
+
+    // Encodes `input` and decodes it again, comparing hashes instead of
+    // holding any of the data in memory. The input is removed once encoded to
+    // make room for the decoded file. Returns the encoded size.
+    fn roundtrip_large(dir: &TempDir, input: &Path, size: u64, input_hash: [u8; 32]) -> u64 {
+        let encoded = dir.join("large.encoded");
+        let decoded = dir.join("large.decoded");
+
+        let output = run(["encode".as_ref(), input.as_os_str(), "-o".as_ref(), encoded.as_os_str()]);
+        assert!(output.status.success(), "encode failed: {}", stderr(&output));
+        fs::remove_file(input).unwrap();
+        assert_eq!(original_length(&encoded), size);
+
+        let output = run(["decode".as_ref(), encoded.as_os_str(), "-o".as_ref(), decoded.as_os_str()]);
+        assert!(output.status.success(), "decode failed: {}", stderr(&output));
+        assert_eq!(fs::metadata(&decoded).unwrap().len(), size);
+        assert!(hash_file(&decoded) == input_hash, "decoded data differs from the input");
+
+        fs::metadata(&encoded).unwrap().len()
+    }
+
+    fn assert_ratio(encoded_size: u64, size: u64, expected: f64) {
+        let ratio = encoded_size as f64 / size as f64;
+        println!("{} -> {} bytes, ratio {:.4}", size, encoded_size, ratio);
+        assert!((ratio - expected).abs() < 0.001, "ratio {} instead of about {}", ratio, expected);
+    }
+
+    // A single symbol that occurs more often than a u32 can count. The input is
+    // a sparse file, so only the encoded and decoded files take up space.
+    #[test]
+    #[ignore]
+    fn test_large_sparse_file_of_zeros() {
+        let dir = TempDir::new();
+        let size = input_size();
+        if !has_space(&dir, size + size / 8) {
+            return;
+        }
+
+        let input = dir.join("zeros.bin");
+        File::create(&input).unwrap().set_len(size).unwrap();
+        let mut hasher = Sha256::new();
+        let zeros = vec![0u8; CHUNK_SIZE];
+        let mut remaining = size;
+        while remaining > 0 {
+            let len = remaining.min(CHUNK_SIZE as u64);
+            hasher.update(&zeros[..len as usize]);
+            remaining -= len;
+        }
+
+        // One bit per byte
+        let encoded_size = roundtrip_large(&dir, &input, size, hasher.finish());
+        assert_ratio(encoded_size, size, 1.0 / 8.0);
+    }
+
+    // Seeded data over 16 equally likely symbols, which take 4 bits each
+    #[test]
+    #[ignore]
+    fn test_large_synthetic_file() {
+        let dir = TempDir::new();
+        let size = input_size();
+        if !has_space(&dir, size + size / 2) {
+            return;
+        }
+
+        let input = dir.join("synthetic.bin");
+        let mut file = File::create(&input).unwrap();
+        let mut hasher = Sha256::new();
+        let mut rng = Rng::new(0x5EED);
+        let mut chunk = vec![0u8; CHUNK_SIZE];
+        let mut remaining = size;
+        while remaining > 0 {
+            for pair in chunk.chunks_mut(2) {
+                let random = rng.next_u64();
+                pair[0] = b'a' + (random & 0xF) as u8;
+                pair[1] = b'a' + (random >> 4 & 0xF) as u8;
+            }
+            let len = remaining.min(CHUNK_SIZE as u64) as usize;
+            file.write_all(&chunk[..len]).unwrap();
+            hasher.update(&chunk[..len]);
+            remaining -= len as u64;
+        }
+        drop(file);
+
+        let encoded_size = roundtrip_large(&dir, &input, size, hasher.finish());
+        assert_ratio(encoded_size, size, 0.5);
+    }
+}